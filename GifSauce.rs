@@ -1,869 +1,3781 @@
-extern crate lzw;
-
-use lzw::{Encoder, LsbWriter};
+extern crate env_logger;
+extern crate gifsauce;
+extern crate gifsauce_core as gif;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+extern crate rayon;
+
+mod batch;
+
+use batch::{embed_batch, extract_batch, gif_files, print_batch_report};
+use gif::{
+    apply_patches, comment_patches, parse_gif, parse_gif_bytes, parse_gif_bytes_with_progress,
+    parse_gif_bytes_with_warnings, write_gif, write_gif_bytes_with_progress, Dither, GifParser,
+    ParseError, ParseWarning, ParseWarningKind, GIF,
+};
+use gifsauce::analyze::{analyze_gif, format_analysis};
+use gifsauce::archive::{
+    clear_modified_times, entry_path, name_stdin_entries, pack_archive, payload_entries,
+    read_input_files,
+};
+use gifsauce::audit::{
+    audit_input, format_history, read_audit_trail, record_operation, set_operation, AUDIT,
+    HASH_SIZE,
+};
+use gifsauce::caption::{bitmap_text, caption_frames, parse_position, Position};
+use gifsauce::channels::{
+    choose_channel, lookup_channel, parse_channel_list, ChannelChoice, ChannelKind, ChannelOptions,
+    FrameExpansion,
+};
+use gifsauce::chunk_map::format_chunk_map;
+use gifsauce::comments::{add_comment, comment_text, new_comment, remove_comment};
+use gifsauce::config::{apply_config, default_config_path, read_config};
+#[cfg(feature = "image-io")]
+use gifsauce::convert::{
+    animated_png, animated_webp, animation_format_of, parse_animation_format, AnimationFormat,
+};
+use gifsauce::crypto::{seal_payloads, seal_payloads_deterministic, sealed_length};
+use gifsauce::frames::{
+    check_frame_selection, delete_frames, extract_frames, move_frame, normalize_delays,
+    parse_frame_ranges, pingpong_frames, restore_frames, reverse_frames, set_aside_frames,
+    split_frames,
+};
+use gifsauce::generate::{generate_carrier, parse_style, Style};
+use gifsauce::hex;
+use gifsauce::icc::{read_icc_file, set_icc_profile};
+#[cfg(feature = "image-io")]
+use gifsauce::image_io::rgba_png;
+use gifsauce::inspect::{
+    block_map, format_block_map, format_frame_hashes, format_summary, frame_blocks, frame_hashes,
+};
+use gifsauce::keychain::{keychain_passphrase, keyfile_passphrase};
+use gifsauce::metadata::{format_time, parse_expiry, unix_time, PayloadMetadata};
+#[cfg(feature = "network")]
+use gifsauce::open_url;
+use gifsauce::optimize::{lossy_frames, optimize_frames, share_color_tables};
+use gifsauce::output::{check_overwrite, note_input, replace_atomic, write_atomic, FORCE, STRICT};
+use gifsauce::palette::{
+    color_table, import_palette, palette_format_of, parse_palette_format, read_palette,
+    write_palette, PaletteFormat,
+};
+use gifsauce::payload::{
+    add_checksums, deflate_body, embed_payload, encrypt_to_recipients, find_payload, fits_carrier,
+    map_payload, pad_body, padded_length, padding_room, read_legacy_payload, read_partial_payload,
+    read_payload, read_payload_with_identities, rekey_payload, remove_payload, whitened_key,
+    write_payload_body, PartialPayload, Payload, PayloadHeader, DEFAULT_PADDING, FLAG_CHECKSUMS,
+    FLAG_COMPRESSED, FLAG_ENCRYPTED, FLAG_PADDED, FLAG_RECIPIENTS, PAYLOAD_HEADER_SIZE,
+};
+use gifsauce::plain_text::render_plain_text;
+use gifsauce::polyglot::append_zip;
+use gifsauce::preview::{detect_protocol, parse_protocol, preview_gif};
+use gifsauce::progress::{disable_progress, LogWriter, StageProgress};
+use gifsauce::prompt::prompt_passphrase;
+use gifsauce::report::{
+    format_report, record_warning, record_warnings, sha256, write_report, Report, ReportTarget,
+};
+use gifsauce::sanitize::{filter_applications, sanitize_gif};
+#[cfg(feature = "server")]
+use gifsauce::server::serve;
+use gifsauce::sheet::contact_sheet;
+use gifsauce::shuffle::{derive_layout_key, ChunkKey, StealthKey};
+#[cfg(feature = "image-io")]
+use gifsauce::transcode::{animation_to_gif, still_to_gif};
+use gifsauce::transform::{crop_gif, parse_filter, resize_gif, Filter};
+use gifsauce::watermark::{
+    cell_residuals, detect_watermark, embed_watermark, verify_watermark, Detection,
+};
+use gifsauce::xmp::{delete_xmp, set_xmp, xmp_packet};
+use log::{Level, LevelFilter};
+use rayon::ThreadPoolBuilder;
 use std::env;
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Cursor, Error, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Error, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug)]
-struct GIFHeader {
-    signature: [u8; 3], // GIF
-    version: [u8; 3],   // 89a
+// function to reassemble the GIF. The file is replaced atomically, keeping
+// the previous one as a backup when asked.
+fn reassemble_gif(
+    output_file: &str,
+    gif: &mut GIF,
+    source: Option<&[u8]>,
+    input: Option<[u8; HASH_SIZE]>,
+    backup: bool,
+) -> Result<(), std::io::Error> {
+    check_version(gif)?;
+    record_operation(gif, input);
+    write_atomic(output_file, &encode_output(gif, source), backup)
 }
 
-#[derive(Debug)]
-struct LogicalScreenDescriptor {
-    width: u16,
-    height: u16,
-    packed_field: u8,
-    background_color_index: u8,
-    pixel_aspect_ratio: u8,
+// Same, for commands that write over their input when given no output
+fn reassemble_gif_in_place(
+    path: &str,
+    gif: &mut GIF,
+    source: Option<&[u8]>,
+    input: Option<[u8; HASH_SIZE]>,
+    backup: bool,
+) -> Result<(), std::io::Error> {
+    check_version(gif)?;
+    record_operation(gif, input);
+    replace_atomic(path, &encode_output(gif, source), backup)
 }
 
-#[derive(Debug, Clone)]
-struct ColorTable {
-    colors: Vec<[u8; 3]>,
+fn encode_output(gif: &GIF, source: Option<&[u8]>) -> Vec<u8> {
+    let progress = StageProgress::new(true);
+    write_gif_bytes_with_progress(gif, source, &|stage, done, total| {
+        progress.update(stage, done, total)
+    })
 }
 
-#[derive(Debug)]
-struct GraphicsControlExtension {
-    packed_field: u8,
-    delay_time: u16,
-    transparent_color_index: u8,
+fn check_version(gif: &GIF) -> Result<(), Error> {
+    if STRICT.load(Ordering::Relaxed) && gif.output_version() != gif.header.version {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The GIF87a file would need extension blocks and --strict keeps it from becoming GIF89a.",
+        ));
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct CommentExtension {
-    comments: Vec<String>,
+// What to do when the carrier already holds a payload
+#[derive(Clone, Copy, PartialEq)]
+enum ExistingPayload {
+    Replace,
+    Append, // Add the new files next to the old ones in one archive
+    Fail,
 }
 
-#[derive(Debug)]
-struct ApplicationExtension {
-    identifier: String,
-    authentication_code: String,
-    data: Vec<u8>,
+// What embed may do, in turn, with a payload that doesn't fit the carrier
+// as it is
+#[derive(Clone, Copy, PartialEq)]
+enum FitStrategy {
+    Compress,
+    Duplicate, // Let the carrier grow, adding frames as --expand says
+    Fail,
 }
 
-#[repr(C)] // Ensures the struct has the same memory layout as in C
-#[derive(Debug, Clone)]
-pub struct PlainTextExtension {
-    pub block_size: u8,
-    pub text_grid_left_position: u16,
-    pub text_grid_top_position: u16,
-    pub text_grid_width: u16,
-    pub text_grid_height: u16,
-    pub character_cell_width: u8,
-    pub character_cell_height: u8,
-    pub text_foreground_color_index: u8,
-    pub text_background_color_index: u8,
-    pub plain_text_data: Vec<u8>,
-}
-
-#[derive(Debug, Clone)]
-struct ImageDescriptor {
-    left: u16,
-    top: u16,
-    width: u16,
-    height: u16,
-    packed_field: u8,
-    local_color_table: Option<ColorTable>, // Include this field
-    lzw_minimum_code_size: u8,             // Include this field
-    image_data: Vec<u8>,                   // Include the image data field
-}
-
-#[derive(Debug)]
-struct GIF {
-    header: GIFHeader,
-    logical_screen_descriptor: LogicalScreenDescriptor,
-    global_color_table: Option<ColorTable>,
-    graphics_control_extension: Option<GraphicsControlExtension>,
-    comment_extensions: Vec<CommentExtension>,
-    application_extensions: Vec<ApplicationExtension>,
-    plain_text_extensions: Vec<PlainTextExtension>,
-    image_descriptors: Vec<ImageDescriptor>,
-}
-
-fn track_position<R: Read + Seek>(reader: &mut R, _description: &str) -> io::Result<u64> {
-    let position = reader.stream_position()?;
-    //println!("{} at byte position: {}", description, position);
-    Ok(position)
-}
-
-fn read_gif_header<R: Read + Seek>(reader: &mut R) -> Result<GIFHeader, Error> {
-    track_position(reader, "Start GIF Header")?;
-    let mut signature = [0; 3];
-    reader.read_exact(&mut signature)?;
-
-    let mut version = [0; 3];
-    reader.read_exact(&mut version)?;
-
-    track_position(reader, "End GIF Header")?;
-    Ok(GIFHeader { signature, version })
-}
-
-fn read_logical_screen_descriptor<R: Read + Seek>(
-    reader: &mut R,
-) -> Result<LogicalScreenDescriptor, Error> {
-    track_position(reader, "Start Logical Screen Descriptor")?;
-
-    let mut width = [0; 2];
-    reader.read_exact(&mut width)?;
-    let width = u16::from_le_bytes(width);
-
-    let mut height = [0; 2];
-    reader.read_exact(&mut height)?;
-    let height = u16::from_le_bytes(height);
-
-    let mut packed_field = [0; 1];
-    reader.read_exact(&mut packed_field)?;
-
-    let mut background_color_index = [0; 1];
-    reader.read_exact(&mut background_color_index)?;
-
-    let mut pixel_aspect_ratio = [0; 1];
-    reader.read_exact(&mut pixel_aspect_ratio)?;
-
-    track_position(reader, "End Logical Screen Descriptor")?;
-    Ok(LogicalScreenDescriptor {
-        width,
-        height,
-        packed_field: packed_field[0],
-        background_color_index: background_color_index[0],
-        pixel_aspect_ratio: pixel_aspect_ratio[0],
-    })
+// Everything `embed` can be asked to do besides picking the files
+#[derive(Clone)]
+struct EmbedOptions {
+    channels: Vec<ChannelKind>,
+    auto_channel: bool,         // Pick the channel to suit the carrier and payload
+    payload_files: Vec<String>, // Read from stdin when empty
+    passphrase: Option<String>,
+    recipients: Vec<String>,          // age public keys to encrypt to instead
+    decoy: Option<(Vec<u8>, String)>, // Decoy payload body and its passphrase
+    keep_decoy: Option<String>,       // Passphrase of the carrier's decoy, to seal it again
+    drop_decoy: bool,                 // Let --replace and --append lose the carrier's decoy
+    existing: ExistingPayload,
+    layout: ChannelOptions,
+    max_output_size: Option<u64>,      // Refuse to write anything larger
+    backup: bool,                      // Keep an existing output file as .bak
+    carrier_url: bool,                 // The carrier is fetched over HTTP(S)
+    compress: Option<bool>,            // Deflate the payload, or leave it to the fit strategy
+    fit_strategy: Vec<FitStrategy>,    // Taken in turn when the payload doesn't fit
+    frames: Option<Vec<Range<usize>>>, // Hide the payload in these frames only
+    lossy: Option<u32>,                // Let carrier colors drift this far to compress better
+    share_palettes: bool,              // Drop local color tables the global one can replace
+    dither: Dither,                    // For APNG and WebP carriers turned into GIFs
+    deterministic: bool,               // The same inputs always give the same output
+    nonce: Option<String>,             // Mixed into a deterministic encryption
+    padding: Option<usize>,            // Pad the payload to a multiple of this many bytes
+    checksums: bool,                   // Checksum blocks of a plain payload for extract --partial
+    if_changed: bool,                  // Leave a carrier already holding the payload as it is
+    expires: i64,                      // When extract stops handing the payload out, 0 for never
+    skip_corrupt: bool,                // Leave out frames of the carrier that fail to parse
+    map: Option<String>,               // Where to write the map of the payload's chunks
+    no_progress: bool,                 // A batch run shows its own progress
 }
 
-fn read_color_table<R: Read>(reader: &mut R, size: usize) -> Result<ColorTable, Error> {
-    let mut colors = Vec::with_capacity(size);
-    let mut buffer = [0; 3];
-
-    for _ in 0..size {
-        reader.read_exact(&mut buffer)?;
-        colors.push(buffer);
+impl Default for EmbedOptions {
+    fn default() -> Self {
+        EmbedOptions {
+            channels: vec![ChannelKind::PlainText],
+            auto_channel: false,
+            payload_files: Vec::new(),
+            passphrase: None,
+            recipients: Vec::new(),
+            decoy: None,
+            keep_decoy: None,
+            drop_decoy: false,
+            existing: ExistingPayload::Replace,
+            layout: ChannelOptions::default(),
+            max_output_size: None,
+            backup: false,
+            carrier_url: false,
+            compress: None,
+            fit_strategy: vec![FitStrategy::Compress, FitStrategy::Duplicate],
+            frames: None,
+            lossy: None,
+            share_palettes: false,
+            dither: Dither::None,
+            deterministic: false,
+            nonce: None,
+            padding: Some(DEFAULT_PADDING),
+            checksums: true,
+            if_changed: false,
+            expires: 0,
+            skip_corrupt: false,
+            map: None,
+            no_progress: false,
+        }
     }
-
-    Ok(ColorTable { colors })
 }
 
-fn read_graphics_control_extension<R: Read>(
-    reader: &mut R,
-) -> Result<GraphicsControlExtension, Error> {
-    let mut block_size = [0; 1];
-    reader.read_exact(&mut block_size)?;
-
-    if block_size[0] != 4 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid block size for graphics control extension.",
-        ));
+// Open a file for reading, "-" meaning stdin
+fn open_input(path: &str) -> Result<Box<dyn Read>, Error> {
+    if path == "-" {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        let file = File::open(path)?;
+        note_input(path);
+        Ok(Box::new(BufReader::new(file)))
     }
+}
 
-    let mut packed_field = [0; 1];
-    reader.read_exact(&mut packed_field)?;
-
-    let mut delay_time = [0; 2];
-    reader.read_exact(&mut delay_time)?;
-    let delay_time = u16::from_le_bytes(delay_time);
+// Input files are parsed straight from a memory map with the mmap feature,
+// which also lets untouched frames be copied to the output without being
+// compressed again
+#[cfg(feature = "mmap")]
+type MappedInput = memmap2::Mmap;
+#[cfg(not(feature = "mmap"))]
+type MappedInput = Vec<u8>;
+
+#[cfg(feature = "mmap")]
+fn map_input(path: &str) -> Result<Option<MappedInput>, Error> {
+    if path == "-" {
+        return Ok(None);
+    }
+    let file = File::open(path)?;
+    note_input(path);
+    if !file.metadata()?.is_file() {
+        return Ok(None);
+    }
+    // Outputs replace files by renaming, so a map of the input stays valid
+    // even when it is written in place. Other processes truncating the file
+    // meanwhile are not guarded against.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    Ok(Some(map))
+}
 
-    let mut transparent_color_index = [0; 1];
-    reader.read_exact(&mut transparent_color_index)?;
+#[cfg(not(feature = "mmap"))]
+fn map_input(_path: &str) -> Result<Option<MappedInput>, Error> {
+    Ok(None)
+}
 
-    let mut terminator = [0; 1];
-    reader.read_exact(&mut terminator)?;
+#[cfg(not(feature = "network"))]
+fn open_url(_url: &str) -> Result<(Box<dyn Read + Send>, Option<u64>), Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--input-url needs GifSauce built with the network feature.",
+    ))
+}
 
-    Ok(GraphicsControlExtension {
-        packed_field: packed_field[0],
-        delay_time,
-        transparent_color_index: transparent_color_index[0],
-    })
+#[cfg(not(feature = "image-io"))]
+fn rgba_png(_width: usize, _height: usize, _rgba: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "PNG output needs GifSauce built with the image-io feature.",
+    ))
 }
 
-fn read_comment_extension<R: Read>(reader: &mut R) -> Result<CommentExtension, Error> {
-    let mut comments = Vec::new();
+#[cfg(not(feature = "image-io"))]
+fn animation_to_gif(_bytes: &[u8], _dither: Dither) -> Result<GIF, Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "APNG and WebP carriers need GifSauce built with the image-io feature.",
+    ))
+}
 
-    loop {
-        let mut block_size = [0; 1];
-        reader.read_exact(&mut block_size)?;
-        if block_size[0] == 0 {
-            break; // End of comment blocks
-        }
+#[cfg(not(feature = "image-io"))]
+fn still_to_gif(_bytes: &[u8], _dither: Dither) -> Result<GIF, Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Importing images needs GifSauce built with the image-io feature.",
+    ))
+}
 
-        let mut data = vec![0; block_size[0] as usize];
-        reader.read_exact(&mut data)?;
-        comments.push(String::from_utf8_lossy(&data).into_owned());
-    }
+#[cfg(not(feature = "image-io"))]
+fn convert_command(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "convert needs GifSauce built with the image-io feature.",
+    )
+    .into())
+}
 
-    Ok(CommentExtension { comments })
+#[cfg(not(feature = "server"))]
+fn serve(_listen: &str) -> Result<(), Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "serve needs GifSauce built with the server feature.",
+    ))
 }
 
-fn read_application_extension<R: Read>(reader: &mut R) -> Result<ApplicationExtension, Error> {
-    let mut block_size = [0; 1];
-    reader.read_exact(&mut block_size)?;
+// Parse an input file, from its memory map when there is one
+fn parse_input(path: &str, mapped: Option<&[u8]>) -> Result<GIF, Error> {
+    let progress = StageProgress::new(true);
+    let mut gif = match mapped {
+        Some(bytes) => parse_gif_bytes_with_progress(bytes, &|stage, done, total| {
+            progress.update(stage, done, total)
+        })?,
+        None => parse_gif(open_input(path)?)?,
+    };
+    filter_input_applications(&mut gif);
+    Ok(gif)
+}
 
-    if block_size[0] != 11 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid application extension block size.",
-        ));
+// Same, for commands that write a GIF made from it: also what the audit
+// record of the operation names as its input
+fn parse_audited_input(
+    path: &str,
+    mapped: Option<&[u8]>,
+) -> Result<(GIF, Option<[u8; HASH_SIZE]>), Error> {
+    if !AUDIT.load(Ordering::Relaxed) {
+        return Ok((parse_input(path, mapped)?, None));
     }
+    // The bytes are needed to hash them
+    let read;
+    let bytes = match mapped {
+        Some(bytes) => bytes,
+        None => {
+            let mut bytes = Vec::new();
+            open_input(path)?.read_to_end(&mut bytes)?;
+            read = bytes;
+            &read
+        }
+    };
+    let mut gif = parse_gif_bytes(bytes)?;
+    filter_input_applications(&mut gif);
+    Ok((gif, audit_input(bytes)))
+}
 
-    let mut identifier = vec![0; 8];
-    reader.read_exact(&mut identifier)?;
-    let identifier = String::from_utf8_lossy(&identifier).into_owned();
-
-    let mut authentication_code = vec![0; 3];
-    reader.read_exact(&mut authentication_code)?;
-    let authentication_code = String::from_utf8_lossy(&authentication_code).into_owned();
+// Patterns from --keep-appext and --drop-appext, in that order
+static APPEXT_FILTERS: Mutex<(Vec<String>, Vec<String>)> = Mutex::new((Vec::new(), Vec::new()));
 
-    let mut data = Vec::new();
+// Drop the application extensions --drop-appext names from a GIF as it is
+// read, so whatever a command adds itself is kept
+fn filter_input_applications(gif: &mut GIF) {
+    let filters = APPEXT_FILTERS.lock().unwrap();
+    let dropped = filter_applications(gif, &filters.0, &filters.1);
+    if dropped > 0 {
+        info!("Dropped {} application extension(s)", dropped);
+    }
+}
 
-    loop {
-        let mut data_block_size = [0; 1];
-        reader.read_exact(&mut data_block_size)?;
-        if data_block_size[0] == 0 {
-            break; // End of application extension data
+// Push a GIF held in memory through a parser, one that passes over corrupt
+// blocks for --skip-corrupt, warning about each it did
+fn push_gif(
+    bytes: &[u8],
+    filename: &str,
+    skip_corrupt: bool,
+    progress: &StageProgress,
+) -> Result<GifParser, ParseError> {
+    let mut parser = match skip_corrupt {
+        true => GifParser::skipping_corrupt(),
+        false => GifParser::new(),
+    };
+    parser.push_with_progress(bytes, &|stage, done, total| {
+        progress.update(stage, done, total)
+    })?;
+    for skipped in parser.skipped() {
+        match skipped.frame {
+            Some(frame) => warn!(
+                "{}: skipped frame {} at byte {}: {}",
+                filename, frame, skipped.position, skipped.error
+            ),
+            None => warn!(
+                "{}: skipped {} bytes at byte {} that start no block",
+                filename, skipped.length, skipped.position
+            ),
         }
-
-        let mut block_data = vec![0; data_block_size[0] as usize];
-        reader.read_exact(&mut block_data)?;
-        data.extend(block_data);
     }
-
-    Ok(ApplicationExtension {
-        identifier,
-        authentication_code,
-        data,
-    })
+    Ok(parser)
 }
 
-fn read_plain_text_extension<R: Read>(reader: &mut R) -> Result<PlainTextExtension, Error> {
-    let mut block_size = [0; 1];
-    reader.read_exact(&mut block_size)?;
-    let block_size = block_size[0];
-
-    let mut text_grid_left_position = [0; 2];
-    reader.read_exact(&mut text_grid_left_position)?;
-    let text_grid_left_position = u16::from_le_bytes(text_grid_left_position);
-
-    let mut text_grid_top_position = [0; 2];
-    reader.read_exact(&mut text_grid_top_position)?;
-    let text_grid_top_position = u16::from_le_bytes(text_grid_top_position);
-
-    let mut text_grid_width = [0; 2];
-    reader.read_exact(&mut text_grid_width)?;
-    let text_grid_width = u16::from_le_bytes(text_grid_width);
-
-    let mut text_grid_height = [0; 2];
-    reader.read_exact(&mut text_grid_height)?;
-    let text_grid_height = u16::from_le_bytes(text_grid_height);
-
-    let mut character_cell_width = [0; 1];
-    reader.read_exact(&mut character_cell_width)?;
-    let character_cell_width = character_cell_width[0];
-
-    let mut character_cell_height = [0; 1];
-    reader.read_exact(&mut character_cell_height)?;
-    let character_cell_height = character_cell_height[0];
-
-    let mut text_foreground_color_index = [0; 1];
-    reader.read_exact(&mut text_foreground_color_index)?;
-    let text_foreground_color_index = text_foreground_color_index[0];
-
-    let mut text_background_color_index = [0; 1];
-    reader.read_exact(&mut text_background_color_index)?;
-    let text_background_color_index = text_background_color_index[0];
+// A carrier cut off part way, by a download that didn't finish say, is worth
+// a warning; the other oddities parsers let through are common enough in
+// the wild to only be worth a debug line
+fn log_parse_warnings(filename: &str, warnings: &[ParseWarning]) {
+    for warning in warnings {
+        match warning.kind {
+            ParseWarningKind::CutShort(length) => warn!(
+                "{} is cut off, skipped the {} bytes of its last block",
+                filename, length
+            ),
+            ref kind => debug!("{}: byte {}: {}", filename, warning.position, kind),
+        }
+    }
+}
 
-    let mut plain_text_data = Vec::new();
+// Carriers may also be APNG or animated WebP files, which are turned into
+// GIFs first. None of their frames can be copied from the input then.
+fn parse_carrier(
+    bytes: &[u8],
+    filename: &str,
+    options: &EmbedOptions,
+    progress: &StageProgress,
+) -> Result<GIF, Error> {
+    let is_png = bytes.starts_with(b"\x89PNG\r\n\x1a\n");
+    let is_webp = bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP");
+    if !is_png && !is_webp {
+        let mut outcome =
+            push_gif(bytes, filename, options.skip_corrupt, progress)?.finish_with_warnings()?;
+        log_parse_warnings(filename, &outcome.warnings);
+        filter_input_applications(&mut outcome.gif);
+        return Ok(outcome.gif);
+    }
+    let mut gif = animation_to_gif(bytes, options.dither)?;
+    filter_input_applications(&mut gif);
+    info!(
+        "Converted the {} carrier to a GIF of {} frame(s)",
+        if is_png { "PNG" } else { "WebP" },
+        gif.image_descriptors.len()
+    );
+    Ok(gif)
+}
 
-    loop {
-        let mut block_size = [0; 1];
-        reader.read_exact(&mut block_size)?;
-        if block_size[0] == 0 {
-            break; // End of text data
+// The most a carrier of `length` bytes could hold in a channel, None for the
+// channels that add to the file rather than hide in what it has. Pixels come
+// out of LZW codes of at least 3 bits, each giving at most 4096 of them, and
+// a frame with a graphic control extension takes at least 20 bytes. The
+// length is the server's word, so it may be anything up to u64::MAX.
+fn capacity_bound(length: u64, channel: ChannelKind) -> Option<u64> {
+    match channel {
+        ChannelKind::Lsb | ChannelKind::Background => {
+            Some((length.saturating_mul(8) / 3).saturating_mul(4096) / 8)
         }
+        ChannelKind::Delay => Some(length / 20 / 8),
+        _ => None,
+    }
+}
 
-        let mut block_data = vec![0; block_size[0] as usize];
-        reader.read_exact(&mut block_data)?;
-        plain_text_data.extend(block_data);
+// Check a remote carrier against its capacity, and against --max-output-size,
+// from the length the server announced and before its body is downloaded
+fn check_download(length: u64, payload: &Payload, options: &EmbedOptions) -> Result<(), Error> {
+    // Deflate shrinks data at most about a thousandfold
+    let compressible = options.compress != Some(false)
+        && (options.compress == Some(true)
+            || options.fit_strategy.contains(&FitStrategy::Compress));
+    let smallest = match compressible {
+        true => payload.data.len() as u64 / 1032,
+        false => payload.data.len() as u64,
+    } + PAYLOAD_HEADER_SIZE as u64;
+    let bounds: Option<Vec<u64>> = match options.auto_channel {
+        true => None,
+        false => options
+            .channels
+            .iter()
+            .map(|&channel| capacity_bound(length, channel))
+            .collect(),
+    };
+    if let Some(capacity) = bounds.map(|bounds| {
+        bounds
+            .iter()
+            .fold(0u64, |sum, bound| sum.saturating_add(*bound))
+    }) {
+        if capacity < smallest {
+            return Err(io::Error::other(format!(
+                "The carrier is {} bytes, which holds at most {} bytes in the chosen channels, not the {} bytes of the payload.",
+                length, capacity, smallest
+            )));
+        }
     }
 
-    Ok(PlainTextExtension {
-        block_size,
-        text_grid_left_position,
-        text_grid_top_position,
-        text_grid_width,
-        text_grid_height,
-        character_cell_width,
-        character_cell_height,
-        text_foreground_color_index,
-        text_background_color_index,
-        plain_text_data,
-    })
+    let limit = match options.max_output_size {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    // Outputs are roughly the size of their carrier, plus the payload for
+    // channels other than lsb, so this is an estimate
+    let estimate = if !options.auto_channel
+        && options
+            .channels
+            .iter()
+            .all(|channel| *channel == ChannelKind::Lsb)
+    {
+        length
+    } else {
+        length.saturating_add(payload.data.len() as u64)
+    };
+    if estimate > limit {
+        return Err(io::Error::other(format!(
+            "The carrier is {} bytes, the output would likely be about {} bytes, over the limit of {} bytes.",
+            length, estimate, limit
+        )));
+    }
+    Ok(())
 }
 
-fn read_image_descriptor<R: Read>(reader: &mut R) -> Result<ImageDescriptor, Error> {
-    // Read the image separator byte (0x2C)
-    let mut separator = [0; 1];
-    reader.read_exact(&mut separator)?;
+// Whether the carrier holds a payload of the same file already, going by the
+// digest in its header, or by the payload itself when it has none. It must
+// also be stored as this embed would store it: encrypted the same way, and
+// with the same flags where the options decide them.
+fn holds_payload(gif: &GIF, payload: &Payload, options: &EmbedOptions) -> bool {
+    // The decoy of an existing container can't be opened to compare it
+    if options.decoy.is_some() {
+        return false;
+    }
+    let passphrase = options.passphrase.as_deref();
+    let location = match find_payload(gif, passphrase) {
+        Some(location) => location,
+        None => return false,
+    };
+    let header = location.header;
+    let same_encryption = match passphrase {
+        Some(_) => header.is_encrypted(),
+        None if !options.recipients.is_empty() => header.is_for_recipients(),
+        None => !header.is_encrypted() && !header.is_for_recipients(),
+    };
+    // Compression and padding are left to the carrier unless asked for
+    let plain = passphrase.is_none() && options.recipients.is_empty();
+    let same_flags = options
+        .compress
+        .is_none_or(|compress| compress == header.is_compressed())
+        && (options.padding.is_some() || !header.is_padded())
+        && (!plain || header.is_compressed() || options.checksums == header.has_checksums());
+    if !same_encryption || !same_flags {
+        return false;
+    }
+    match header.digest {
+        Some(digest) => digest == sha256(&payload.data),
+        // Payloads for age keys can't be opened with what embed is given
+        None if header.is_for_recipients() => false,
+        None => read_payload(gif, passphrase)
+            .ok()
+            .flatten()
+            .is_some_and(|existing| {
+                existing.data == payload.data && existing.metadata.expires == options.expires
+            }),
+    }
+}
 
-    if separator[0] != 0x2C {
+// The decoy to seal along with the payload replacing or joining `header`'s,
+// when that one is a passphrase container. A container doesn't tell whether
+// it holds a decoy, so without --decoy-passphrase to keep it, or --decoy to
+// put a new one in its place, the embed is refused unless --drop-decoy says
+// a decoy may be lost.
+fn existing_decoy(
+    gif: &GIF,
+    header: &PayloadHeader,
+    options: &EmbedOptions,
+) -> Result<Option<(Vec<u8>, String)>, Error> {
+    if !header.is_encrypted() || options.decoy.is_some() {
+        return Ok(None);
+    }
+    let decoy_passphrase = match options.keep_decoy {
+        Some(ref decoy_passphrase) => decoy_passphrase,
+        None if options.drop_decoy => return Ok(None),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The carrier's payload is encrypted and may share its container with a decoy, which this embed would drop. Give --decoy-passphrase to keep the decoy, --decoy to replace it, or --drop-decoy.",
+            ))
+        }
+    };
+    if options.passphrase.as_ref() == Some(decoy_passphrase) {
         return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid image descriptor separator.",
+            io::ErrorKind::InvalidInput,
+            "The decoy's passphrase must differ from the payload's.",
         ));
     }
+    let no_decoy = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "The carrier holds no decoy for that --decoy-passphrase.",
+        )
+    };
+    match read_payload(gif, Some(decoy_passphrase)) {
+        Ok(Some(decoy)) => {
+            info!("Keeping the carrier's decoy");
+            Ok(Some((write_payload_body(&decoy), decoy_passphrase.clone())))
+        }
+        Ok(None) => Err(no_decoy()),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Err(no_decoy()),
+        Err(e) => Err(e),
+    }
+}
 
-    // Read the image descriptor fields
-    let mut left_position = [0; 2];
-    reader.read_exact(&mut left_position)?;
-
-    let mut top_position = [0; 2];
-    reader.read_exact(&mut top_position)?;
+// The output of an embed --if-changed that had nothing to do: the carrier as
+// it was, left alone when it is the output too
+fn keep_carrier(
+    filename: &str,
+    output_file: &str,
+    carrier: &[u8],
+    backup: bool,
+) -> Result<(), Error> {
+    if output_file == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(carrier)?;
+        return stdout.flush();
+    }
+    let same = match (fs::canonicalize(filename), fs::canonicalize(output_file)) {
+        (Ok(input), Ok(output)) => input == output,
+        _ => false,
+    };
+    if !same {
+        write_atomic(output_file, carrier, backup)?;
+        info!("Carrier copied to {}", output_file);
+    }
+    Ok(())
+}
 
-    let mut width = [0; 2];
-    reader.read_exact(&mut width)?;
+// A payload body made ready for its channels: compressed if asked,
+// encrypted or checksummed, and padded
+struct Sealed {
+    data: Vec<u8>,
+    flags: u8,
+    key: Option<ChunkKey>,
+    digest: Option<[u8; 32]>,
+    channels: Vec<ChannelKind>,
+    channel_choice: Option<String>, // Why --channels auto picked the channel
+    body_length: usize,             // The body after compression
+    fits: bool,                     // It goes in without the carrier growing
+}
 
-    let mut height = [0; 2];
-    reader.read_exact(&mut height)?;
+// A body as it is to be stored, its flags and its layout key
+type Encrypted = (Vec<u8>, u8, Option<ChunkKey>);
+
+// The body encrypted as the options ask, or checksummed when it stays as it
+// is
+fn encrypt_body(
+    input: &[u8],
+    decoy: Option<&(Vec<u8>, String)>,
+    flags: u8,
+    options: &EmbedOptions,
+) -> Result<Encrypted, Box<dyn std::error::Error>> {
+    Ok(match options.passphrase {
+        None if !options.recipients.is_empty() => (
+            encrypt_to_recipients(input, &options.recipients)?,
+            flags | FLAG_RECIPIENTS,
+            None,
+        ),
+        Some(ref passphrase) => {
+            let mut payloads = vec![(passphrase.as_str(), input)];
+            if let Some((decoy, decoy_passphrase)) = decoy {
+                payloads.push((decoy_passphrase.as_str(), decoy.as_slice()));
+            }
+            let container = match options.deterministic {
+                true => seal_payloads_deterministic(&payloads, options.nonce.as_deref())?,
+                false => seal_payloads(&payloads)?,
+            };
 
-    let mut packed_field = [0; 1];
-    reader.read_exact(&mut packed_field)?;
+            // The container keys the layout, which each of its passphrases
+            // then finds alike. A stealth payload's is keyed with its
+            // passphrase as well, so only the passphrase finds it.
+            let key = match options.layout.stealth {
+                true if decoy.is_some() => {
+                    return Err(Box::new(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "A stealth payload can't share its container with a decoy.",
+                    )))
+                }
+                true => StealthKey::new(passphrase)?.chunk_key(&container),
+                false => derive_layout_key(&container),
+            };
+            (container, flags | FLAG_ENCRYPTED, Some(key))
+        }
+        // Only a body stored as it is can be recovered in part, so only
+        // that one gets checksums
+        None if options.checksums && flags == 0 => (add_checksums(input), FLAG_CHECKSUMS, None),
+        None => (input.to_vec(), flags, None),
+    })
+}
 
-    // Determine the local color table size (if present)
-    let local_color_table_size = if (packed_field[0] & 0b10000000) != 0 {
-        1 << ((packed_field[0] & 0b00000111) + 1)
+fn seal_body(
+    gif: &GIF,
+    body: &[u8],
+    compress: bool,
+    payload: &Payload,
+    options: &EmbedOptions,
+) -> Result<Sealed, Box<dyn std::error::Error>> {
+    let mut input = body.to_vec();
+    let mut decoy = options.decoy.clone();
+    let flags = if compress {
+        input = deflate_body(&input)?;
+        if let Some((ref mut decoy, _)) = decoy {
+            *decoy = deflate_body(decoy)?;
+        }
+        FLAG_COMPRESSED
     } else {
         0
     };
+    let body_length = input.len();
+    let (data, flags, key) = encrypt_body(&input, decoy.as_ref(), flags, options)?;
 
-    // Read the local color table (if present)
-    let local_color_table = if local_color_table_size > 0 {
-        Some(read_color_table(reader, local_color_table_size as usize)?)
+    // Anyone can read a plain payload, so its digest gives nothing away
+    let digest = match flags & (FLAG_ENCRYPTED | FLAG_RECIPIENTS) {
+        0 => Some(sha256(&payload.data)),
+        _ => None,
+    };
+    let header_size = PAYLOAD_HEADER_SIZE + digest.map_or(0, |digest| digest.len());
+
+    let (channels, channel_choice) = if options.auto_channel {
+        let mut choice = choose_channel(gif, header_size + data.len());
+        if options.layout.stealth && choice.channel == ChannelKind::Application {
+            choice = ChannelChoice {
+                channel: ChannelKind::Comment,
+                reason: format!(
+                    "{} bytes are more than the pixels hold; --stealth rules out the appext channel, which is named after GifSauce",
+                    header_size + data.len()
+                ),
+            };
+        }
+        (vec![choice.channel], Some(choice.reason))
     } else {
-        None
+        (options.channels.clone(), None)
     };
 
-    // Read the LZW minimum code size
-    let mut lzw_minimum_code_size = [0; 1];
-    reader.read_exact(&mut lzw_minimum_code_size)?;
-
-    let lzw_minimum_code_size = lzw_minimum_code_size[0];
-
-    // Read the image data using LZW decompression
-    let image_data = read_lzw_data(reader, lzw_minimum_code_size)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-    Ok(ImageDescriptor {
-        left: u16::from_le_bytes(left_position),
-        top: u16::from_le_bytes(top_position),
-        width: u16::from_le_bytes(width),
-        height: u16::from_le_bytes(height),
-        packed_field: packed_field[0],
-        local_color_table,
-        lzw_minimum_code_size,
-        image_data,
+    // Channels of fixed capacity, and payloads that fit one extension, get
+    // what padding still fits. It goes inside any encryption, so the stored
+    // length shows no more than the cipher's own rounding does. The body is
+    // sealed again padded, and kept unpadded when that no longer fits.
+    let room = padding_room(
+        gif,
+        &channels,
+        key.as_ref(),
+        &options.layout,
+        header_size,
+        data.len(),
+    );
+    let padded = match options.padding {
+        None => None,
+        Some(bucket) if flags & (FLAG_ENCRYPTED | FLAG_RECIPIENTS) == 0 => {
+            padded_length(data.len(), bucket, room, |length| length)
+                .map(|length| (pad_body(&data, length), key.clone()))
+        }
+        Some(bucket) => {
+            // The container is sized for its longest payload, so the decoy is
+            // padded to at least the payload's length: opened, it must show
+            // the region a lone payload of its length would have
+            let decoy_length = decoy.as_ref().map(|(decoy, _)| {
+                padded_length(decoy.len(), 1, None, |length| length)
+                    .expect("padding with no room to keep to has a length")
+            });
+            let stored_length = |length: usize| match options.passphrase {
+                Some(_) => {
+                    let mut lengths = vec![length];
+                    lengths.extend(decoy_length.map(|decoy_length| decoy_length.max(length)));
+                    sealed_length(&lengths).unwrap_or(length)
+                }
+                None => length,
+            };
+            match padded_length(input.len(), bucket, room, stored_length) {
+                Some(length) => {
+                    let padded_decoy = decoy.as_ref().zip(decoy_length).map(
+                        |((decoy, decoy_passphrase), decoy_length)| {
+                            (
+                                pad_body(decoy, decoy_length.max(length)),
+                                decoy_passphrase.clone(),
+                            )
+                        },
+                    );
+                    let (sealed, _, key) = encrypt_body(
+                        &pad_body(&input, length),
+                        padded_decoy.as_ref(),
+                        flags,
+                        options,
+                    )?;
+                    room.is_none_or(|room| sealed.len() <= room)
+                        .then_some((sealed, key))
+                }
+                None => None,
+            }
+        }
+    };
+    let (data, flags, key) = match padded {
+        Some((padded, key)) => (padded, flags | FLAG_PADDED, key),
+        None => (data, flags, key),
+    };
+    let key = match (options.passphrase.as_deref(), key) {
+        (Some(passphrase), Some(key)) => {
+            Some(whitened_key(key, passphrase, decoy.is_some(), &channels)?)
+        }
+        (_, key) => key,
+    };
+    let fits = fits_carrier(
+        gif,
+        &channels,
+        key.as_ref(),
+        &options.layout,
+        header_size,
+        data.len(),
+    );
+    Ok(Sealed {
+        data,
+        flags,
+        key,
+        digest,
+        channels,
+        channel_choice,
+        body_length,
+        fits,
     })
 }
 
-fn read_lzw_data<R: Read>(reader: &mut R, minimum_code_size: u8) -> Result<Vec<u8>, Error> {
-    let mut data = Vec::new();
-    let mut block_size = [0; 1];
-
-    // Calculate clear and end-of-information codes based on minimum code size
-    let clear_code = 1 << minimum_code_size;
-    let end_of_information_code = clear_code + 1;
-
-    // Initialize dictionary with single-byte values
-    let mut dictionary: Vec<Vec<u8>> = (0..clear_code).map(|i| vec![i as u8]).collect();
-    dictionary.push(vec![]); // Add clear code entry
-    dictionary.push(vec![]); // Add EOF code entry
-    let mut next_code = clear_code + 2;
-
-    // Initialize variables for reading bit-stream
-    let mut bit_buffer: u32 = 0;
-    let mut bit_count = 0;
-    let mut current_bit_size = minimum_code_size + 1;
-
-    let mut previous_code: Option<u16> = None;
+// Embed a file (or stdin) into a carrier GIF and write the result to output_file
+fn embed_file(
+    filename: &str,
+    output_file: &str,
+    options: &EmbedOptions,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    if filename == "-" && options.payload_files.is_empty() {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The carrier is read from stdin, use --payload for the payload.",
+        )));
+    }
 
-    loop {
-        // Read block size
-        if let Err(e) = reader.read_exact(&mut block_size) {
-            if e.kind() == io::ErrorKind::UnexpectedEof {
-                return Ok(data); // Handle EOF gracefully
-            } else {
-                return Err(e); // Return other errors
-            }
+    let payload = if options.payload_files.is_empty() {
+        // Read the payload from stdin
+        let mut input = Vec::new();
+        if io::stdin().read_to_end(&mut input).is_err() {
+            error!("Failed to read from stdin");
+            std::process::exit(1);
         }
+        Payload {
+            metadata: PayloadMetadata {
+                size: input.len() as u64,
+                ..Default::default()
+            },
+            data: input,
+        }
+    } else {
+        read_input_files(&options.payload_files)?
+    };
+    let payload = match options.deterministic {
+        true => clear_modified_times(payload)?,
+        false => payload,
+    };
 
-        // Read the data block
-        let mut block_data = vec![0; block_size[0] as usize];
-        reader.read_exact(&mut block_data)?;
-
-        // Process the block data as a bitstream
-        for &byte in &block_data {
-            bit_buffer |= (byte as u32) << bit_count;
-            bit_count += 8;
-
-            // Extract codes from bit buffer
-            while bit_count >= current_bit_size {
-                let code = (bit_buffer & ((1 << current_bit_size) - 1)) as u16;
-                bit_buffer >>= current_bit_size;
-                bit_count -= current_bit_size;
+    // Open and parse the input GIF
+    let progress = StageProgress::new(!options.no_progress);
+    let mut report = Report::default();
+    // Carriers that aren't mapped are kept in `read`
+    let (mapped, read, mut gif, carrier_size) = if options.carrier_url {
+        let (mut reader, length) = open_url(filename)?;
+        if let Some(length) = length {
+            check_download(length, &payload, options)?;
+        }
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        report.carrier_sha256 = Some(sha256(&bytes));
+        let gif = parse_carrier(&bytes, filename, options, &progress)?;
+        (None, bytes, gif, length)
+    } else {
+        let mapped = map_input(filename)?;
+        let mut read = Vec::new();
+        let gif = match mapped {
+            Some(ref bytes) => {
+                report.carrier_sha256 = Some(sha256(bytes));
+                parse_carrier(bytes, filename, options, &progress)?
+            }
+            None => {
+                open_input(filename)?.read_to_end(&mut read)?;
+                report.carrier_sha256 = Some(sha256(&read));
+                parse_carrier(&read, filename, options, &progress)?
+            }
+        };
+        let size = match filename {
+            "-" => None,
+            _ => Some(fs::metadata(filename)?.len()),
+        };
+        (mapped, read, gif, size)
+    };
+    let passphrase = options.passphrase.as_deref();
+    if options.if_changed && holds_payload(&gif, &payload, options) {
+        info!("The carrier already holds this payload, leaving it as it is");
+        let carrier = mapped.as_deref().unwrap_or(&read);
+        keep_carrier(filename, output_file, carrier, options.backup)?;
+        report.payload_sha256 = Some(sha256(&payload.data));
+        report.payload_size = Some(payload.data.len());
+        report.output_size = Some(carrier.len());
+        report.unchanged = true;
+        return Ok(report);
+    }
+    // Before the payload goes in, so no embedded bit is disturbed
+    if let Some(tolerance) = options.lossy {
+        lossy_frames(&mut gif, tolerance);
+    }
+    if options.share_palettes {
+        let dropped = share_color_tables(&mut gif);
+        info!(
+            "Replaced {} local color table(s) with the global one",
+            dropped
+        );
+    }
+    let carrier_frames = gif.image_descriptors.len();
+    let set_aside = match options.frames {
+        Some(ref selection) => set_aside_frames(&mut gif, selection)?,
+        None => Vec::new(),
+    };
 
-                if code == end_of_information_code {
-                    return Ok(data); // End of data
+    // A decoy in the carrier's container is sealed again, or dropped when asked
+    let kept_options;
+    let options = match find_payload(&gif, passphrase) {
+        Some(location) if options.existing != ExistingPayload::Fail => {
+            match existing_decoy(&gif, &location.header, options)? {
+                Some(decoy) => {
+                    kept_options = EmbedOptions {
+                        decoy: Some(decoy),
+                        ..options.clone()
+                    };
+                    &kept_options
                 }
-
-                if code == clear_code {
-                    // Reset dictionary and bit size
-                    dictionary = (0..clear_code).map(|i| vec![i as u8]).collect();
-                    dictionary.push(vec![]);
-                    dictionary.push(vec![]);
-                    next_code = clear_code + 2;
-                    current_bit_size = minimum_code_size + 1;
-                    previous_code = None;
-                    continue;
+                None => options,
+            }
+        }
+        _ => options,
+    };
+    let mut payload = if find_payload(&gif, passphrase).is_some() {
+        match options.existing {
+            ExistingPayload::Fail => {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Carrier already holds a payload, use --replace or --append.",
+                )));
+            }
+            ExistingPayload::Append => {
+                let existing = read_payload(&gif, passphrase)?;
+                let mut entries = match existing {
+                    Some(existing) => payload_entries(existing)?,
+                    None => Vec::new(),
+                };
+                entries.extend(payload_entries(payload)?);
+                name_stdin_entries(&mut entries);
+                remove_payload(&mut gif, passphrase)?;
+                info!("Appending to existing payload");
+                pack_archive(String::new(), &entries)
+            }
+            ExistingPayload::Replace => {
+                remove_payload(&mut gif, passphrase)?;
+                info!("Replacing existing payload");
+                payload
+            }
+        }
+    } else {
+        payload
+    };
+    payload.metadata.expires = options.expires;
+    report.payload_sha256 = Some(sha256(&payload.data));
+    report.payload_size = Some(payload.data.len());
+    let body = write_payload_body(&payload);
+    let mut compressed = options.compress == Some(true);
+    let mut sealed = seal_body(&gif, &body, compressed, &payload, options)?;
+    // The steps of --fit-strategy, taken in turn until the payload fits
+    let mut grow = false;
+    for step in &options.fit_strategy {
+        if sealed.fits || grow {
+            break;
+        }
+        match step {
+            FitStrategy::Compress if compressed || options.compress == Some(false) => {}
+            FitStrategy::Compress if !cfg!(feature = "compression") => {
+                debug!("Built without compression, the payload can't be compressed to fit")
+            }
+            FitStrategy::Compress => {
+                compressed = true;
+                let attempt = seal_body(&gif, &body, true, &payload, options)?;
+                if attempt.body_length < body.len() {
+                    sealed = attempt;
+                } else {
+                    info!(
+                        "Compressing doesn't shrink the payload ({} to {} bytes), leaving it as it is",
+                        body.len(),
+                        attempt.body_length
+                    );
                 }
+            }
+            FitStrategy::Duplicate => grow = true,
+            FitStrategy::Fail => break,
+        }
+    }
+    if sealed.flags & FLAG_COMPRESSED != 0 {
+        let ratio = sealed.body_length as f64 / body.len() as f64;
+        info!(
+            "Compressed the payload from {} to {} bytes ({:.0}% of its size)",
+            body.len(),
+            sealed.body_length,
+            ratio * 100.0
+        );
+        report.compression_ratio = Some(ratio);
+    }
+    if !sealed.fits && !grow {
+        return Err(Box::new(io::Error::other(format!(
+            "{} bytes don't fit the carrier as it is, and --fit-strategy doesn't let it grow.",
+            sealed.data.len()
+        ))));
+    }
+    if let Some(reason) = sealed.channel_choice {
+        info!("Chose the {} channel: {}", sealed.channels[0], reason);
+        report.channel_choice = Some(reason);
+    }
+    let Sealed {
+        data,
+        flags,
+        key,
+        digest,
+        channels,
+        ..
+    } = sealed;
+    report.chunks = Some(embed_payload(
+        &mut gif,
+        &data,
+        flags,
+        digest.as_ref(),
+        &channels,
+        key.as_ref(),
+        &options.layout,
+    )?);
+    report.channels = channels;
+    restore_frames(&mut gif, set_aside);
+
+    // Reassemble in memory first so the cost of the payload can be reported
+    check_version(&gif)?;
+    record_operation(&mut gif, audit_input(mapped.as_deref().unwrap_or(&read)));
+    let output = write_gif_bytes_with_progress(&gif, mapped.as_deref(), &|stage, done, total| {
+        progress.update(stage, done, total)
+    });
+    if gif.image_descriptors.len() > carrier_frames {
+        info!(
+            "Added {} frame(s) to host the payload ({} -> {})",
+            gif.image_descriptors.len() - carrier_frames,
+            carrier_frames,
+            gif.image_descriptors.len()
+        );
+    }
+    match carrier_size {
+        Some(carrier_size) => info!(
+            "Output size: {} bytes (carrier {} bytes)",
+            output.len(),
+            carrier_size
+        ),
+        None => info!("Output size: {} bytes", output.len()),
+    }
 
-                let entry = if code < next_code {
-                    dictionary[code as usize].clone()
-                } else if let Some(prev_code) = previous_code {
-                    let mut new_entry = dictionary[prev_code as usize].clone();
-                    new_entry.push(new_entry[0]);
-                    new_entry
-                } else {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Invalid LZW code.",
-                    ));
-                };
+    if let Some(limit) = options.max_output_size {
+        if output.len() as u64 > limit {
+            return Err(Box::new(io::Error::other(format!(
+                "Output would be {} bytes, over the limit of {} bytes. Compress the payload or use a bigger carrier.",
+                output.len(),
+                limit
+            ))));
+        }
+    }
 
-                data.extend(&entry);
+    if output_file == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        info!("GIF reassembled and written to stdout");
+    } else {
+        write_atomic(output_file, &output, options.backup)?;
+        info!("GIF reassembled and saved to {}", output_file);
+    }
+    if let Some(ref path) = options.map {
+        let chunks = map_payload(&gif, key.as_ref()).ok_or_else(|| {
+            io::Error::other("The payload can't be found in the output to map it.")
+        })?;
+        write_atomic(path, format_chunk_map(&output, &chunks)?.as_bytes(), false)?;
+        info!("Map of {} chunk(s) saved to {}", chunks.len(), path);
+    }
 
-                if let Some(prev_code) = previous_code {
-                    let mut new_entry = dictionary[prev_code as usize].clone();
-                    new_entry.push(entry[0]);
-                    if next_code < (1 << 12) {
-                        dictionary.push(new_entry);
-                        next_code += 1;
-                    }
+    report.frames_added = Some(gif.image_descriptors.len().saturating_sub(carrier_frames));
+    report.output_size = Some(output.len());
+    Ok(report)
+}
 
-                    // Increase the bit size if necessary
-                    if next_code == (1 << current_bit_size) && current_bit_size < 12 {
-                        current_bit_size += 1;
-                    }
-                }
+// Directories of a batch run, given with --batch, --payload-dir and --out
+#[derive(Default)]
+struct BatchOptions {
+    input_dir: Option<PathBuf>,
+    payload_dir: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    jobs: Option<usize>, // Threads, one per core by default
+}
 
-                previous_code = Some(code);
+// Handle a batch option, returning false when `arg` isn't one
+fn batch_option<'a, I: Iterator<Item = &'a String>>(
+    batch: &mut BatchOptions,
+    args_iter: &mut I,
+    arg: &str,
+) -> bool {
+    match arg {
+        "--batch" => batch.input_dir = Some(PathBuf::from(option_value(args_iter, arg))),
+        "--payload-dir" => batch.payload_dir = Some(PathBuf::from(option_value(args_iter, arg))),
+        "--out" => batch.out_dir = Some(PathBuf::from(option_value(args_iter, arg))),
+        "--jobs" => match option_value(args_iter, arg).parse::<usize>() {
+            Ok(jobs) if jobs > 0 => batch.jobs = Some(jobs),
+            _ => {
+                error!("--jobs expects a positive number of threads");
+                exit(1);
             }
-        }
+        },
+        _ => return false,
     }
+    true
 }
-fn lzw_compress(data: &[u8], min_code_size: u8) -> Vec<u8> {
-    // Create a new cursor that will hold the compressed data
-    let mut cursor = Cursor::new(Vec::new());
-    let writer = LsbWriter::new(&mut cursor); // Use mutable reference to the cursor
 
-    // Create a new encoder with the specified minimum code size
-    let mut encoder = Encoder::new(writer, min_code_size).expect("Failed to create encoder");
-
-    // Encode the data
-    encoder
-        .encode_bytes(data)
-        .expect("Failed to write data to encoder");
-
-    // No need to call finish() or flush() here; just get the underlying Vec<u8>
-    // Retrieve the compressed data from the cursor
-    drop(encoder); // Ensure the encoder is dropped and the borrow ends
-    cursor.into_inner() // Get the underlying Vec<u8>
+// Value following an option, or exit with a message naming the option
+fn option_value<'a, I: Iterator<Item = &'a String>>(args_iter: &mut I, option: &str) -> String {
+    match args_iter.next() {
+        Some(value) => value.clone(),
+        None => {
+            error!("Expected a value after {}", option);
+            exit(1);
+        }
+    }
 }
 
-fn parse_gif<R: Read + Seek>(reader: &mut R, decode: bool) -> Result<GIF, Error> {
-    let header = read_gif_header(reader)?;
-    //println!("Header: {:?}", header);
+// The ranges of a --frames option
+fn frames_option<'a, I: Iterator<Item = &'a String>>(
+    args_iter: &mut I,
+    option: &str,
+) -> Vec<Range<usize>> {
+    match parse_frame_ranges(&option_value(args_iter, option)) {
+        Some(ranges) => ranges,
+        None => {
+            error!("{} expects frame ranges such as 3..10,20,30..", option);
+            exit(1);
+        }
+    }
+}
 
-    let logical_screen_descriptor = read_logical_screen_descriptor(reader)?;
-    //println!("Logical Screen Descriptor: {:?}", logical_screen_descriptor);
+// The passphrase a --key-id option names in the OS keychain
+fn key_id_option<'a, I: Iterator<Item = &'a String>>(args_iter: &mut I, option: &str) -> String {
+    match keychain_passphrase(&option_value(args_iter, option)) {
+        Ok(passphrase) => passphrase,
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    }
+}
 
-    // Check global color table size
-    let global_color_table_size = if (logical_screen_descriptor.packed_field & 0b111) > 0 {
-        1 << ((logical_screen_descriptor.packed_field & 0b111) + 1)
-    } else {
-        0
-    };
+// The passphrase held by the environment variable an option names
+fn env_option<'a, I: Iterator<Item = &'a String>>(args_iter: &mut I, option: &str) -> String {
+    let name = option_value(args_iter, option);
+    match env::var(&name) {
+        Ok(passphrase) if !passphrase.is_empty() => passphrase,
+        _ => {
+            error!("{} names {}, which holds no passphrase", option, name);
+            exit(1);
+        }
+    }
+}
 
-    let global_color_table = if global_color_table_size > 0 {
-        Some(read_color_table(reader, global_color_table_size)?)
-    } else {
-        None
-    };
-    //println!("Global Color Table: {:?}", global_color_table);
-
-    let mut graphics_control_extension = None;
-    let mut comment_extensions = Vec::new();
-    let mut application_extensions = Vec::new();
-    let mut plain_text_extensions = Vec::new();
-    let mut image_descriptors = Vec::new();
-
-    loop {
-        let mut block_indicator = [0; 1];
-        match reader.read_exact(&mut block_indicator) {
-            Ok(_) => {
-                track_position(reader, "Block Indicator")?;
-                //ln!("Block Indicator: {:#X}", block_indicator[0]);
-
-                if block_indicator[0] == 0x21 {
-                    // Extension Introducer
-                    let mut extension_type = [0; 1];
-                    reader.read_exact(&mut extension_type)?;
-
-                    match extension_type[0] {
-                        0xF9 => {
-                            graphics_control_extension =
-                                Some(read_graphics_control_extension(reader)?);
-                        }
-                        0xFE => {
-                            comment_extensions.push(read_comment_extension(reader)?);
-                        }
-                        0xFF => {
-                            application_extensions.push(read_application_extension(reader)?);
-                        }
-                        0x01 => {
-                            plain_text_extensions.push(read_plain_text_extension(reader)?);
-                            if decode {
-                                print!(
-                                    "{}",
-                                    String::from_utf8_lossy(
-                                        plain_text_extensions
-                                            .last()
-                                            .unwrap()
-                                            .plain_text_data
-                                            .as_slice()
-                                    )
-                                );
-                            }
-                        }
-                        _ => {
-                            // Skip unknown extensions
-                            loop {
-                                let mut block_size = [0; 1];
-                                reader.read_exact(&mut block_size)?;
-                                if block_size[0] == 0 {
-                                    break; // End of this extension block
-                                }
-                                let mut buffer = vec![0; block_size[0] as usize];
-                                reader.read_exact(&mut buffer)?;
-                            }
-                        }
-                    }
-                } else if block_indicator[0] == 0x2C {
-                    reader.seek(SeekFrom::Current(-1))?;
-                    // Image Descriptor
-                    let image_descriptor = read_image_descriptor(reader)?;
-                    image_descriptors.push(image_descriptor);
-                    reader.seek(SeekFrom::Current(1))?;
-                } else if block_indicator[0] == 0x3B {
-                    // Trailer
-                    break;
-                } else {
-                    break;
-                    //return Err(io::Error::new(
-                    //    io::ErrorKind::InvalidData,
-                    //    "Invalid GIF format.",
-                    //));
-                }
+// One of rekey's passphrases, with its keyfile mixed in, or asked for when
+// neither was given
+fn rekey_passphrase(passphrase: Option<String>, keyfile: Option<String>, confirm: bool) -> String {
+    let passphrase = match (passphrase, &keyfile) {
+        (None, None) => match prompt_passphrase(confirm) {
+            Ok(typed) => Some(typed),
+            Err(e) => {
+                error!("{}", e);
+                exit(1);
             }
+        },
+        (passphrase, _) => passphrase,
+    };
+    match keyfile {
+        Some(keyfile) => match keyfile_passphrase(&keyfile, passphrase.as_deref()) {
+            Ok(secret) => secret,
             Err(e) => {
-                if e.kind() == io::ErrorKind::UnexpectedEof {
-                    break; // Handle EOF gracefully
-                }
-                return Err(e); // Propagate other errors
+                error!("{}", e);
+                exit(1);
             }
-        }
+        },
+        None => passphrase.unwrap_or_default(),
     }
-
-    Ok(GIF {
-        header,
-        logical_screen_descriptor,
-        global_color_table,
-        graphics_control_extension,
-        comment_extensions,
-        application_extensions,
-        plain_text_extensions,
-        image_descriptors,
-    })
 }
 
-// function to reassemble the GIF
-fn reassemble_gif<R: Read + Seek>(
-    _reader: &mut R,
-    output_file: &str,
-    gif: &GIF,
-) -> Result<(), std::io::Error> {
-    let mut writer = BufWriter::new(File::create(output_file)?);
-
-    // 1. Write the GIF header
-    writer.write_all(&gif.header.signature)?;
-    writer.write_all(&gif.header.version)?;
-
-    // 2. Write the Logical Screen Descriptor
-    writer.write_all(&gif.logical_screen_descriptor.width.to_le_bytes())?;
-    writer.write_all(&gif.logical_screen_descriptor.height.to_le_bytes())?;
-    writer.write_all(&[gif.logical_screen_descriptor.packed_field])?;
-    writer.write_all(&[gif.logical_screen_descriptor.background_color_index])?;
-    writer.write_all(&[gif.logical_screen_descriptor.pixel_aspect_ratio])?;
-
-    // 3. Write the Global Color Table if present
-    if let Some(ref global_color_table) = gif.global_color_table {
-        for color in &global_color_table.colors {
-            writer.write_all(color)?;
-        }
-    }
-
-    // 4. Write any Graphics Control Extensions if present
-    if let Some(ref graphics_control_extension) = gif.graphics_control_extension {
-        writer.write_all(&[0x21, 0xF9, 0x04])?; // Graphics Control Extension introducer
-        writer.write_all(&[graphics_control_extension.packed_field])?;
-        writer.write_all(&graphics_control_extension.delay_time.to_le_bytes())?;
-        writer.write_all(&[graphics_control_extension.transparent_color_index])?;
-        writer.write_all(&[0])?; // Block terminator for Graphics Control Extension
-    }
-
-    // 5. Write comment extensions
-    for comment in &gif.comment_extensions {
-        writer.write_all(&[0x21, 0xFE])?; // Comment extension introducer
-        let comment_bytes = comment.comments.join("");
-        let comment_length = comment_bytes.len() as u8;
-        writer.write_all(&[comment_length])?;
-        writer.write_all(comment_bytes.as_bytes())?;
-        writer.write_all(&[0])?; // Block terminator
-    }
-
-    // 6. Write application extensions
-    for application in &gif.application_extensions {
-        writer.write_all(&[0x21, 0xFF, 0x0B])?; // Application extension introducer
-        writer.write_all(application.identifier.as_bytes())?;
-        writer.write_all(application.authentication_code.as_bytes())?;
-
-        for chunk in application.data.chunks(255) {
-            writer.write_all(&[chunk.len() as u8])?;
-            writer.write_all(chunk)?;
-        }
-        writer.write_all(&[0])?; // Block terminator
-    }
-
-    // 7. Write plain text extensions
-    let mut first: bool = true;
-    let mut index = 0;
-    for plain_text in &gif.plain_text_extensions {
-        index += 1;
-        if first {
-            writer.write_all(&[0x21, 0x01, plain_text.block_size])?; // Plain Text Extension introducer
-            writer.write_all(&plain_text.text_grid_left_position.to_le_bytes())?;
-            writer.write_all(&plain_text.text_grid_top_position.to_le_bytes())?;
-            writer.write_all(&plain_text.text_grid_width.to_le_bytes())?;
-            writer.write_all(&plain_text.text_grid_height.to_le_bytes())?;
-            writer.write_all(&[plain_text.character_cell_width])?;
-            writer.write_all(&[plain_text.character_cell_height])?;
-            writer.write_all(&[plain_text.text_foreground_color_index])?;
-            writer.write_all(&[plain_text.text_background_color_index])?;
-            let length = plain_text.plain_text_data.len();
-            //println!("Length: {}", length);
-            writer.write_all(&[length as u8])?; //255
-            writer.write_all(&plain_text.plain_text_data)?; //254
-            first = false;
-        } else {
-            let length = plain_text.plain_text_data.len();
-            //println!("Length: {}", length);
-            writer.write_all(&[length as u8])?; //255
-            writer.write_all(&plain_text.plain_text_data)?; //254
-        }
-        if gif.plain_text_extensions.len() == index {
-            writer.write_all(&[0])?; // Block terminator
+// The value of an option taking one byte, in decimal or as 0x followed by hex
+fn byte_option<'a, I: Iterator<Item = &'a String>>(args_iter: &mut I, option: &str) -> u8 {
+    let value = option_value(args_iter, option);
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    match parsed {
+        Ok(byte) => byte,
+        Err(_) => {
+            error!("{} expects a byte, 0 to 255 or 0x00 to 0xff", option);
+            exit(1);
         }
     }
+}
 
-    // 8. Write image descriptors
-    for image_descriptor in &gif.image_descriptors {
-        writer.write_all(&[0x2C])?; // Image separator
-        writer.write_all(&image_descriptor.left.to_le_bytes())?;
-        writer.write_all(&image_descriptor.top.to_le_bytes())?;
-        writer.write_all(&image_descriptor.width.to_le_bytes())?;
-        writer.write_all(&image_descriptor.height.to_le_bytes())?;
-        writer.write_all(&[image_descriptor.packed_field])?;
-
-        if let Some(ref local_color_table) = image_descriptor.local_color_table {
-            for color in &local_color_table.colors {
-                writer.write_all(color)?;
+// Where a --report or --report-file option sends the report. JSON is the
+// only format there is.
+fn report_option<'a, I: Iterator<Item = &'a String>>(
+    report: &mut Option<ReportTarget>,
+    args_iter: &mut I,
+    option: &str,
+) {
+    let value = option_value(args_iter, option);
+    match option {
+        "--report-file" => {
+            // Checked now rather than once the work is done
+            if let Err(e) = check_overwrite(Path::new(&value), false) {
+                error!("{}", e);
+                exit(1);
             }
+            *report = Some(ReportTarget::File(value))
         }
-
-        // Write the LZW minimum code size
-        writer.write_all(&[image_descriptor.lzw_minimum_code_size])?;
-
-        // Compress and write the image data
-        let compressed_data = lzw_compress(
-            &image_descriptor.image_data,
-            image_descriptor.lzw_minimum_code_size,
-        );
-        for chunk in compressed_data.chunks(255) {
-            writer.write_all(&[chunk.len() as u8])?;
-            writer.write_all(chunk)?;
+        _ if value == "json" => {
+            report.get_or_insert(ReportTarget::Stderr);
+        }
+        _ => {
+            error!("{} expects json", option);
+            exit(1);
         }
-        writer.write_all(&[0])?; // Block terminator
     }
-
-    // 9. Write the GIF trailer
-    writer.write_all(&[0x3B])?;
-
-    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Get input and output file names from command line arguments
-    let args: Vec<String> = env::args().collect();
-    let mut input_file = None;
-    let mut output_file = None;
-    let mut decode = false;
+// The value of a --dither option
+fn dither_option<'a, I: Iterator<Item = &'a String>>(args_iter: &mut I, option: &str) -> Dither {
+    match option_value(args_iter, option).as_str() {
+        "none" => Dither::None,
+        "ordered" => Dither::Ordered,
+        "floyd-steinberg" => Dither::FloydSteinberg,
+        _ => {
+            error!("{} expects none, ordered or floyd-steinberg", option);
+            exit(1);
+        }
+    }
+}
 
-    let mut args_iter = args.iter().skip(1); // Skip the program name
+// gifsauce embed <carrier.gif|-> <out.gif|-> [--payload FILE|DIR]...
+//                [--channels auto|plaintext,comment,appext,lsb,trailer,delay,background]
+//                [--passphrase PASS | --key-id ID | --encrypt] [--keyfile FILE]
+//                [--decoy FILE --decoy-passphrase PASS | --decoy-key-id ID]
+//                [--recipient AGE_KEY]...
+//                [--replace | --append | --fail-if-present]
+//                [--decoy-passphrase PASS | --decoy-key-id ID | --drop-decoy] [--chunk-size N]
+//                [--expand duplicate|minimal|tiny-frames] [--max-output-size BYTES]
+//                [--compress | --no-compress] [--fit-strategy compress,duplicate,fail]
+//                [--lossy N] [--share-palettes] [--frames RANGES]
+//                [--dither none|ordered|floyd-steinberg] [--deterministic [--nonce VALUE]]
+//                [--padding BYTES | --no-padding] [--no-checksums] [--no-manifest] [--stealth] [--skip-corrupt]
+//                [--if-changed] [--expires DATE] [--map FILE] [--report json] [--report-file FILE]
+//                [--backup]
+// The carrier may also be an APNG or animated WebP, which is turned into a GIF.
+// Frames --expand duplicate copies are compressed once, which saves time but
+// not bytes: every copy is still written out in full.
+// gifsauce embed --input-url <URL> <out.gif|-> [options as above]
+// gifsauce embed --batch <carriers/> --payload-dir <payloads/> --out <dir/>
+//                [--jobs N] [options as above]
+fn embed_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut options = EmbedOptions::default();
+    let mut batch = BatchOptions::default();
+    let mut decoy_file = None;
+    let mut decoy_passphrase = None;
+    let mut encrypt = false;
+    let mut keyfile = None;
+    let mut report = None;
+
+    let mut args_iter = args.iter();
     while let Some(arg) = args_iter.next() {
+        if batch_option(&mut batch, &mut args_iter, arg) {
+            continue;
+        }
         match arg.as_str() {
-            "-i" => {
-                if let Some(file) = args_iter.next() {
-                    input_file = Some(file.clone());
-                } else {
-                    eprintln!("Expected orignal carrier file after -i");
-                    std::process::exit(1);
+            "--channels" => match option_value(&mut args_iter, arg).as_str() {
+                "auto" => options.auto_channel = true,
+                list => {
+                    options.channels = parse_channel_list(list)?;
+                    options.auto_channel = false;
                 }
+            },
+            "--payload" => options
+                .payload_files
+                .push(option_value(&mut args_iter, arg)),
+            "--passphrase" => options.passphrase = Some(option_value(&mut args_iter, arg)),
+            "--key-id" => options.passphrase = Some(key_id_option(&mut args_iter, arg)),
+            "--encrypt" => encrypt = true,
+            "--no-encrypt" => encrypt = false,
+            "--keyfile" => keyfile = Some(option_value(&mut args_iter, arg)),
+            "--recipient" => options.recipients.push(option_value(&mut args_iter, arg)),
+            "--decoy" => decoy_file = Some(option_value(&mut args_iter, arg)),
+            "--decoy-passphrase" => decoy_passphrase = Some(option_value(&mut args_iter, arg)),
+            "--decoy-key-id" => decoy_passphrase = Some(key_id_option(&mut args_iter, arg)),
+            "--drop-decoy" => options.drop_decoy = true,
+            "--replace" => options.existing = ExistingPayload::Replace,
+            "--append" => options.existing = ExistingPayload::Append,
+            "--fail-if-present" => options.existing = ExistingPayload::Fail,
+            "--backup" => options.backup = true,
+            "--compress" => options.compress = Some(true),
+            "--no-compress" => options.compress = Some(false),
+            "--fit-strategy" => {
+                options.fit_strategy = option_value(&mut args_iter, arg)
+                    .split(',')
+                    .map(|step| match step.trim() {
+                        "compress" => FitStrategy::Compress,
+                        "duplicate" => FitStrategy::Duplicate,
+                        "fail" => FitStrategy::Fail,
+                        other => {
+                            error!("Unknown fit strategy: {}", other);
+                            exit(1);
+                        }
+                    })
+                    .collect()
             }
-            "-o" => {
-                if let Some(file) = args_iter.next() {
-                    output_file = Some(file.clone());
-                } else {
-                    eprintln!("Expected stegged filename file after -o");
-                    std::process::exit(1);
+            "--frames" => options.frames = Some(frames_option(&mut args_iter, arg)),
+            "--share-palettes" => options.share_palettes = true,
+            "--deterministic" => options.deterministic = true,
+            "--skip-corrupt" => options.skip_corrupt = true,
+            "--padding" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(0) => options.padding = None,
+                Ok(bucket) => options.padding = Some(bucket),
+                Err(_) => {
+                    error!("--padding expects a number of bytes, 0 for none");
+                    exit(1);
+                }
+            },
+            "--no-padding" => options.padding = None,
+            "--no-checksums" => options.checksums = false,
+            "--if-changed" => options.if_changed = true,
+            "--map" => {
+                let path = option_value(&mut args_iter, arg);
+                // Checked before the output is written, not after
+                if let Err(e) = check_overwrite(Path::new(&path), false) {
+                    error!("{}", e);
+                    exit(1);
                 }
+                options.map = Some(path);
             }
-            "-d" => loop {
-                match args_iter.next() {
-                    None => {
-                        //eprintln!("-== End Processing files ==-");
-                        exit(0)
-                    }
-                    Some(default) => {
-                        let file = default.clone();
-                        //println!("{}", file);
-                        input_file = Some(file.clone());
-                        decode = true;
-                        let file = File::open(&input_file.clone().unwrap())?;
-                        let mut reader = BufReader::new(file);
-                        let _gif = parse_gif(&mut reader, decode)?;
-                    }
+            "--expires" => match parse_expiry(&option_value(&mut args_iter, arg)) {
+                Some(expires) => options.expires = expires,
+                None => {
+                    error!("--expires expects a date such as 2025-12-31, or a time such as 2025-12-31T18:00:00Z");
+                    exit(1);
                 }
             },
-            _ => {
-                eprintln!("Unknown argument: {}", arg);
-                std::process::exit(1);
+            "--no-manifest" => options.layout.manifest = false,
+            "--stealth" => options.layout.stealth = true,
+            "--nonce" => {
+                options.nonce = Some(option_value(&mut args_iter, arg));
+                options.deterministic = true;
             }
+            "--dither" => options.dither = dither_option(&mut args_iter, arg),
+            "--report" | "--report-file" => report_option(&mut report, &mut args_iter, arg),
+            "--lossy" => match option_value(&mut args_iter, arg).parse::<u32>() {
+                Ok(tolerance) => options.lossy = Some(tolerance),
+                Err(_) => {
+                    error!("--lossy expects a color distance, e.g. 20");
+                    exit(1);
+                }
+            },
+            "--input-url" => {
+                options.carrier_url = true;
+                files.insert(0, option_value(&mut args_iter, arg));
+            }
+            "--chunk-size" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(size) if size > 0 => options.layout.chunk_size = Some(size),
+                _ => {
+                    error!("--chunk-size expects a positive number of bytes");
+                    exit(1);
+                }
+            },
+            "--max-output-size" => match option_value(&mut args_iter, arg).parse::<u64>() {
+                Ok(limit) => options.max_output_size = Some(limit),
+                Err(_) => {
+                    error!("--max-output-size expects a number of bytes");
+                    exit(1);
+                }
+            },
+            "--expand" => match option_value(&mut args_iter, arg).as_str() {
+                "duplicate" => options.layout.expansion = FrameExpansion::Duplicate,
+                "minimal" => options.layout.expansion = FrameExpansion::Minimal,
+                "tiny-frames" => options.layout.expansion = FrameExpansion::TinyFrames,
+                other => {
+                    error!("Unknown frame expansion: {}", other);
+                    exit(1);
+                }
+            },
+            _ => files.push(arg.clone()),
         }
     }
 
-    if input_file.is_none() {
-        eprintln!("Original carrier is required. Use -i <input_file>");
-        std::process::exit(1);
+    let batch_dirs = match batch {
+        BatchOptions {
+            input_dir: Some(ref input_dir),
+            payload_dir: Some(ref payload_dir),
+            out_dir: Some(ref out_dir),
+            ..
+        } if files.is_empty() && options.payload_files.is_empty() => {
+            Some((input_dir.clone(), payload_dir.clone(), out_dir.clone()))
+        }
+        _ => None,
+    };
+    if batch_dirs.is_none() && files.len() != 2 {
+        eprintln!(
+            "Usage: embed <carrier.gif|-> <out.gif|-> [--payload FILE|DIR]... [--channels auto|plaintext,comment,appext,lsb,trailer,delay,background] [--passphrase PASS | --key-id ID | --encrypt] [--keyfile FILE] [--decoy FILE --decoy-passphrase PASS | --decoy-key-id ID] [--recipient AGE_KEY]... [--replace | --append | --fail-if-present] [--decoy-passphrase PASS | --decoy-key-id ID | --drop-decoy] [--chunk-size N] [--expand duplicate|minimal|tiny-frames] [--max-output-size BYTES] [--compress | --no-compress] [--fit-strategy compress,duplicate,fail] [--lossy N] [--share-palettes] [--frames RANGES] [--dither none|ordered|floyd-steinberg] [--deterministic [--nonce VALUE]] [--padding BYTES | --no-padding] [--no-checksums] [--no-manifest] [--stealth] [--skip-corrupt] [--if-changed] [--expires DATE] [--map FILE] [--report json] [--report-file FILE] [--backup]"
+        );
+        eprintln!("       embed --input-url <URL> <out.gif|-> [options]");
+        eprintln!(
+            "       embed --batch <carriers/> --payload-dir <payloads/> --out <dir/> [--jobs N] [options]"
+        );
+        eprintln!(
+            "Frames added by --expand duplicate are compressed once but each is still written in full; minimal and tiny-frames add smaller ones."
+        );
+        exit(1);
     }
 
-    if !decode && output_file.is_none() {
-        eprintln!("Stegged filename is required unless -d is used. Use -o <output_file>");
-        std::process::exit(1);
+    // Asked for on the terminal when nothing else encrypts the payload
+    if encrypt && options.passphrase.is_none() && options.recipients.is_empty() {
+        options.passphrase = Some(prompt_passphrase(true)?);
+    }
+    if let Some(ref keyfile) = keyfile {
+        options.passphrase = Some(keyfile_passphrase(keyfile, options.passphrase.as_deref())?);
     }
 
-    let filename = input_file.unwrap();
-    let output_file = output_file.unwrap_or_else(|| String::new());
+    if options.passphrase.is_some() && !options.recipients.is_empty() {
+        error!("--recipient can't be combined with --passphrase, the payload is encrypted to one or the other");
+        exit(1);
+    }
+
+    // Anyone could edit the expiry of a payload that isn't authenticated
+    if options.expires != 0 {
+        if options.passphrase.is_none() && options.recipients.is_empty() {
+            error!("--expires needs --passphrase, --key-id, --encrypt or --recipient, only an encrypted payload keeps its expiry");
+            exit(1);
+        }
+        if options.expires <= unix_time() {
+            warn!(
+                "The payload expires at {}, which has passed already",
+                format_time(options.expires)
+            );
+        }
+    }
+
+    // An appended payload is never the same as what was there before
+    if options.if_changed && options.existing == ExistingPayload::Append {
+        error!("--if-changed can't be combined with --append");
+        exit(1);
+    }
+
+    // age draws a fresh key for every file it encrypts
+    if options.deterministic && !options.recipients.is_empty() {
+        error!("--deterministic can't be combined with --recipient, age encryption is always randomized");
+        exit(1);
+    }
+
+    // An encrypted payload, decoy or not, is cut into keyed chunks of random
+    // size
+    if options.layout.chunk_size.is_some() && options.passphrase.is_some() {
+        error!("--chunk-size can't be combined with --passphrase, its chunks are sized by the key");
+        exit(1);
+    }
+
+    // Only the passphrase's keyed layout finds a stealth payload, so a decoy
+    // sharing its container could never be found with its own
+    if options.layout.stealth && (options.passphrase.is_none() || decoy_file.is_some()) {
+        error!("--stealth needs --passphrase, and can't be combined with --decoy");
+        exit(1);
+    }
+    if options.layout.stealth && AUDIT.load(Ordering::Relaxed) {
+        error!("--stealth can't be combined with --audit, the audit trail's extension is named after GifSauce");
+        exit(1);
+    }
+
+    match (decoy_file, decoy_passphrase) {
+        (Some(decoy_file), Some(decoy_passphrase)) => {
+            if options.passphrase.is_none() {
+                error!("A decoy needs --passphrase for the real payload");
+                exit(1);
+            }
+            let mut decoy = read_input_files(&[decoy_file])?;
+            if options.deterministic {
+                decoy = clear_modified_times(decoy)?;
+            }
+            options.decoy = Some((write_payload_body(&decoy), decoy_passphrase));
+        }
+        // Without a file the decoy already in the carrier is kept
+        (None, Some(decoy_passphrase)) => {
+            if options.passphrase.is_none() {
+                error!("A decoy needs --passphrase for the real payload");
+                exit(1);
+            }
+            options.keep_decoy = Some(decoy_passphrase);
+        }
+        (None, None) => {}
+        (Some(_), None) => {
+            error!("--decoy needs --decoy-passphrase or --decoy-key-id");
+            exit(1);
+        }
+    }
+
+    match batch_dirs {
+        Some((input_dir, payload_dir, out_dir)) => {
+            if report.is_some() {
+                warn!("--report is ignored with --batch, which prints its own report");
+            }
+            if options.map.is_some() {
+                error!("--map can't be combined with --batch, it maps one output");
+                exit(1);
+            }
+            let results = embed_batch(&input_dir, &payload_dir, &out_dir, &options, batch.jobs)?;
+            if !print_batch_report(&results) {
+                exit(1);
+            }
+            Ok(())
+        }
+        None => {
+            if report.is_some() {
+                record_warnings();
+            }
+            let result = embed_file(&files[0], &files[1], &options);
+            if let Some(ref target) = report {
+                let json = format_report("embed", result.as_ref().map_err(|e| e.to_string()));
+                write_report(target, &json)?;
+            }
+            result.map(drop)
+        }
+    }
+}
+
+// Split "<files...> [--passphrase PASS | --key-id ID | --encrypt]
+// [--keyfile FILE]" command arguments
+fn files_and_passphrase(args: &[String]) -> (Vec<String>, Option<String>) {
+    let mut files = Vec::new();
+    let mut passphrase = None;
+    let mut encrypt = false;
+    let mut keyfile = None;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--passphrase" => passphrase = Some(option_value(&mut args_iter, arg)),
+            "--key-id" => passphrase = Some(key_id_option(&mut args_iter, arg)),
+            "--encrypt" => encrypt = true,
+            "--no-encrypt" => encrypt = false,
+            "--keyfile" => keyfile = Some(option_value(&mut args_iter, arg)),
+            _ => files.push(arg.clone()),
+        }
+    }
+
+    if encrypt && passphrase.is_none() {
+        match prompt_passphrase(false) {
+            Ok(typed) => passphrase = Some(typed),
+            Err(e) => {
+                error!("{}", e);
+                exit(1);
+            }
+        }
+    }
+    if let Some(keyfile) = keyfile {
+        match keyfile_passphrase(&keyfile, passphrase.as_deref()) {
+            Ok(secret) => passphrase = Some(secret),
+            Err(e) => {
+                error!("{}", e);
+                exit(1);
+            }
+        }
+    }
+    (files, passphrase)
+}
+
+#[derive(Clone, Default)]
+struct ExtractOptions {
+    passphrase: Option<String>,
+    identities: Option<String>, // Text of an age identity file
+    report: bool,               // Find the channels for a --report
+    output: Option<PathBuf>,    // Print to stdout when not set
+    list: bool,
+    file: Option<String>,              // Only this entry of an archive
+    frames: Option<Vec<Range<usize>>>, // The frames the payload was hidden in
+    no_progress: bool,                 // A batch run shows its own progress
+    partial: bool,                     // Recover what's left of a damaged payload
+    skip_corrupt: bool,                // Leave out frames that fail to parse
+    placeholder: u8,                   // What the gaps in a recovered payload are filled with
+    legacy: bool,                      // Read a payload from before the payload header
+    ignore_expiry: bool,               // Extract a payload past its expiry, with a warning
+}
+
+// gifsauce extract <file.gif|->...
+//                  [--passphrase PASS | --key-id ID | --encrypt] [--keyfile FILE]
+//                  [--identity FILE]
+//                  [-o PATH | --restore]
+//                  [--list | --file NAME] [--frames RANGES]
+//                  [--partial] [--placeholder BYTE] [--skip-corrupt] [--legacy]
+//                  [--ignore-expiry] [--report json] [--report-file FILE]
+// gifsauce extract --batch <stego/> --out <dir/> [--jobs N]
+//                  [--passphrase PASS | --key-id ID | --encrypt] [--keyfile FILE]
+//                  [--identity FILE]
+fn extract_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rest = Vec::new();
+    let mut options = ExtractOptions::default();
+    let mut batch = BatchOptions::default();
+    let mut report = None;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        if batch_option(&mut batch, &mut args_iter, arg) {
+            continue;
+        }
+        match arg.as_str() {
+            "-o" => options.output = Some(PathBuf::from(option_value(&mut args_iter, arg))),
+            "--partial" => options.partial = true,
+            "--skip-corrupt" => options.skip_corrupt = true,
+            "--legacy" => options.legacy = true,
+            "--ignore-expiry" => options.ignore_expiry = true,
+            "--placeholder" => options.placeholder = byte_option(&mut args_iter, arg),
+            // Write under the stored name into the current directory
+            "--restore" => options.output = Some(PathBuf::from(".")),
+            "--list" => options.list = true,
+            "--file" => options.file = Some(option_value(&mut args_iter, arg)),
+            "--frames" => options.frames = Some(frames_option(&mut args_iter, arg)),
+            "--report" | "--report-file" => report_option(&mut report, &mut args_iter, arg),
+            "--identity" => {
+                let mut identities = String::new();
+                open_input(&option_value(&mut args_iter, arg))?.read_to_string(&mut identities)?;
+                options.identities = Some(identities);
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    let (files, passphrase) = files_and_passphrase(&rest);
+    options.passphrase = passphrase;
+    options.report = report.is_some();
+
+    if let (Some(input_dir), Some(out_dir)) = (batch.input_dir, batch.out_dir) {
+        if files.is_empty() {
+            if report.is_some() {
+                warn!("--report is ignored with --batch, which prints its own report");
+            }
+            let results = extract_batch(&input_dir, &out_dir, &options, batch.jobs)?;
+            if !print_batch_report(&results) {
+                exit(1);
+            }
+            return Ok(());
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!(
+            "Usage: extract <file.gif|->... [--passphrase PASS | --key-id ID | --encrypt] [--keyfile FILE] [--identity FILE] [-o PATH | --restore] [--list | --file NAME] [--frames RANGES] [--partial] [--placeholder BYTE] [--skip-corrupt] [--legacy] [--ignore-expiry] [--report json] [--report-file FILE]"
+        );
+        eprintln!("       extract --batch <stego/> --out <dir/> [--jobs N] [--passphrase PASS | --key-id ID | --encrypt] [--keyfile FILE] [--identity FILE]");
+        exit(1);
+    }
+
+    // One report a file, a line each
+    let target = match report {
+        Some(target) => target,
+        None => {
+            for file in &files {
+                decode_file(file, &options)?;
+            }
+            return Ok(());
+        }
+    };
+    record_warnings();
+    let mut reports = Vec::new();
+    for file in &files {
+        let result = decode_file(file, &options);
+        reports.push(format_report(
+            "extract",
+            result.as_ref().map_err(|e| e.to_string()),
+        ));
+        if let Err(e) = result {
+            write_report(&target, &reports.join("\n"))?;
+            return Err(e);
+        }
+    }
+    write_report(&target, &reports.join("\n"))?;
+    Ok(())
+}
+
+// A payload past its expiry is refused, or extracted with a warning when
+// that is asked for
+fn check_expiry(filename: &str, metadata: &PayloadMetadata, ignore: bool) -> Result<(), Error> {
+    if metadata.expires == 0 {
+        return Ok(());
+    }
+    if unix_time() < metadata.expires {
+        info!(
+            "{}: the payload expires at {}",
+            filename,
+            format_time(metadata.expires)
+        );
+        return Ok(());
+    }
+    let expired = format!(
+        "{}: the payload expired at {}",
+        filename,
+        format_time(metadata.expires)
+    );
+    if ignore {
+        warn!("{}", expired);
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{}, --ignore-expiry extracts it anyway.", expired),
+    ))
+}
+
+// Write an extracted file to `output`. When `output` is a directory the file
+// is restored there under its stored name and modification time.
+fn restore_payload(payload: &Payload, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let path = if output.is_dir() {
+        // Stored names come from the file, so refuse any that would land
+        // outside the directory
+        let name = entry_path(&payload.metadata.name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Payload has no usable file name ({:?}), use -o FILE.",
+                    payload.metadata.name
+                ),
+            )
+        })?;
+        let path = output.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        path
+    } else {
+        output.to_path_buf()
+    };
+
+    check_overwrite(&path, false)?;
+    let mut file = File::create(&path)?;
+    file.write_all(&payload.data)?;
+    if payload.metadata.modified > 0 {
+        let modified = UNIX_EPOCH + Duration::from_secs(payload.metadata.modified as u64);
+        file.set_modified(modified)?;
+    }
+
+    info!(
+        "Restored {} ({}, {} bytes)",
+        path.display(),
+        if payload.metadata.mime_type.is_empty() {
+            "unknown type"
+        } else {
+            &payload.metadata.mime_type
+        },
+        payload.data.len()
+    );
+    Ok(())
+}
+
+// Print the embedded payload of a stegged GIF to stdout, or write it to the
+// output path
+fn decode_file(
+    filename: &str,
+    options: &ExtractOptions,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    open_input(filename)?.read_to_end(&mut bytes)?;
+    let progress = StageProgress::new(!options.no_progress);
+    // A carrier cut off part way, by a download that didn't finish say,
+    // still has its complete blocks, and the chunks of the payload in them
+    let outcome =
+        push_gif(&bytes, filename, options.skip_corrupt, &progress)?.finish_truncated()?;
+    log_parse_warnings(filename, &outcome.warnings);
+    let cut = outcome
+        .warnings
+        .iter()
+        .any(|warning| matches!(warning.kind, ParseWarningKind::CutShort(_)));
+    let mut gif = outcome.gif;
+    if let Some(ref selection) = options.frames {
+        set_aside_frames(&mut gif, selection)?;
+    }
+    let mut report = Report {
+        carrier_sha256: Some(sha256(&bytes)),
+        ..Default::default()
+    };
+
+    let passphrase = options.passphrase.as_deref();
+    let identities = options.identities.as_deref();
+    let mut stdout = io::stdout();
+    let found = if options.legacy {
+        read_legacy_payload(&gif).map(|payload| PartialPayload {
+            payload,
+            lost: Vec::new(),
+            lost_chunks: 0,
+        })
+    } else if options.partial || cut {
+        read_partial_payload(&gif, passphrase, identities, options.placeholder)?
+    } else {
+        read_payload_with_identities(&gif, passphrase, identities)?.map(|payload| PartialPayload {
+            payload,
+            lost: Vec::new(),
+            lost_chunks: 0,
+        })
+    };
+    match found {
+        Some(PartialPayload {
+            payload,
+            lost,
+            lost_chunks,
+        }) => {
+            check_expiry(filename, &payload.metadata, options.ignore_expiry)?;
+            if !lost.is_empty() {
+                let ranges: Vec<String> = lost
+                    .iter()
+                    .map(|range| format!("{}..{}", range.start, range.end))
+                    .collect();
+                let chunks = match lost_chunks {
+                    0 => String::new(),
+                    count => format!(" in {} chunk(s)", count),
+                };
+                warn!(
+                    "{}: lost {} of the payload's {} bytes{}, filled with 0x{:02x}: {}",
+                    filename,
+                    lost.iter().map(|range| range.len()).sum::<usize>(),
+                    payload.data.len(),
+                    chunks,
+                    options.placeholder,
+                    ranges.join(", ")
+                );
+                report.lost = lost;
+            }
+            report.lost_chunks = Some(lost_chunks);
+            report.payload_sha256 = Some(sha256(&payload.data));
+            report.payload_size = Some(payload.data.len());
+            // Found a second time, which only a report needs
+            if options.report {
+                if let Some(location) = find_payload(&gif, passphrase) {
+                    report.channels = location.channels;
+                }
+            }
+            let mut entries = payload_entries(payload)?;
+            if options.list {
+                for entry in &entries {
+                    println!(
+                        "{}\t{}\t{}",
+                        entry.data.len(),
+                        entry.metadata.mime_type,
+                        entry.metadata.name
+                    );
+                }
+                return Ok(report);
+            }
+
+            if let Some(ref name) = options.file {
+                entries.retain(|entry| entry.metadata.name == *name);
+                if entries.is_empty() {
+                    return Err(Box::new(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("No file named {} in the payload.", name),
+                    )));
+                }
+            }
 
-    // Open the input GIF file
+            report.output_size = Some(entries.iter().map(|entry| entry.data.len()).sum());
+            match options.output {
+                Some(ref output) => {
+                    if entries.len() > 1 && !output.is_dir() {
+                        return Err(Box::new(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Payload holds several files, -o must be a directory.",
+                        )));
+                    }
+                    for entry in &entries {
+                        restore_payload(entry, output)?;
+                    }
+                }
+                None if entries.len() > 1 => {
+                    return Err(Box::new(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Payload holds several files, use --list, --file NAME or --restore.",
+                    )));
+                }
+                None => {
+                    for entry in &entries {
+                        stdout.write_all(&entry.data)?;
+                    }
+                }
+            }
+        }
+        None if passphrase.is_some() => {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No payload found for this passphrase.",
+            )));
+        }
+        // Files written before the payload header existed carry raw plain
+        // text, which is only read when asked for
+        None if !options.legacy && !gif.plain_text_extensions.is_empty() => {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No payload found. It may be from before the payload header, try --legacy.",
+            )));
+        }
+        None => {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No payload found.",
+            )));
+        }
+    }
+    stdout.flush()?;
+    Ok(report)
+}
+
+fn detect_file(
+    filename: &str,
+    passphrase: Option<&str>,
+    frames: Option<&[Range<usize>]>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let file = File::open(filename)?;
     let mut reader = BufReader::new(file);
+    let mut gif = parse_gif(&mut reader)?;
+    if let Some(selection) = frames {
+        set_aside_frames(&mut gif, selection)?;
+    }
+
+    match find_payload(&gif, passphrase) {
+        Some(location) => println!(
+            "{}: channel={} version={} length={} encrypted={} compressed={}{}{}",
+            filename,
+            location
+                .channels
+                .iter()
+                .map(|channel| channel.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            location.header.version,
+            location.header.length,
+            if location.header.is_encrypted() {
+                "yes"
+            } else if location.header.is_for_recipients() {
+                "age"
+            } else {
+                "no"
+            },
+            if location.header.is_compressed() {
+                "yes"
+            } else {
+                "no"
+            },
+            match location.manifest {
+                Some(manifest) => format!(
+                    " chunks={} sha256={}",
+                    manifest
+                        .streams
+                        .iter()
+                        .map(|stream| stream.chunks)
+                        .sum::<usize>(),
+                    hex(&manifest.digest)
+                ),
+                None => String::new(),
+            },
+            match location.header.digest {
+                Some(digest) => format!(" file_sha256={}", hex(&digest)),
+                None => String::new(),
+            }
+        ),
+        None => println!("{}: no payload", filename),
+    }
+    Ok(())
+}
 
-    // Parse the GIF
-    let mut gif = parse_gif(&mut reader, decode)?;
+// gifsauce detect <file.gif|directory>... [--passphrase PASS | --key-id ID | --encrypt]
+//                 [--keyfile FILE] [--frames RANGES]
+fn detect_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rest = Vec::new();
+    let mut frames = None;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--frames" => frames = Some(frames_option(&mut args_iter, arg)),
+            _ => rest.push(arg.clone()),
+        }
+    }
 
-    // Read from stdin and modify the Plain Text Extensions
-    let mut input = String::new();
-    if io::stdin().read_to_string(&mut input).is_err() {
-        eprintln!("Failed to read from stdin");
-        std::process::exit(1);
+    let (args, passphrase) = files_and_passphrase(&rest);
+    if args.is_empty() {
+        eprintln!("Usage: detect <file.gif|directory>... [--passphrase PASS | --key-id ID | --encrypt] [--keyfile FILE] [--frames RANGES]");
+        exit(1);
     }
 
-    let input_chunk = 254;
-    // Adjusting to content length.
-    loop {
-        if (input.len() / input_chunk) > gif.image_descriptors.len() {
-            let descriptors_clone = gif.image_descriptors.clone();
-            gif.image_descriptors.extend(descriptors_clone);
-            println!("Extended image descriptors");
+    let mut files = Vec::new();
+    for arg in &args {
+        let path = Path::new(arg);
+        if path.is_dir() {
+            files.extend(gif_files(path)?);
         } else {
-            println!("Done extending image descriptors");
-            break;
+            files.push(path.to_path_buf());
+        }
+    }
+
+    // Keep going past unreadable files so a whole directory can be triaged
+    let mut failed = false;
+    for file in &files {
+        let filename = file.to_string_lossy();
+        if let Err(e) = detect_file(&filename, passphrase.as_deref(), frames.as_deref()) {
+            error!("{}: {}", filename, e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        exit(1);
+    }
+    Ok(())
+}
+
+// gifsauce rekey <in.gif> [out.gif]
+//                [--old-pass PASS | --old-key-id ID | --old-pass-env VAR] [--old-keyfile FILE]
+//                [--new-pass PASS | --new-key-id ID | --new-pass-env VAR] [--new-keyfile FILE]
+//                [--decoy-passphrase PASS | --decoy-key-id ID | --drop-decoy] [--backup]
+// Rewrites in.gif itself when no output is given. A passphrase given neither
+// way nor with a keyfile is asked for on the terminal, the new one twice.
+fn rekey_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut old = None;
+    let mut new = None;
+    let mut old_keyfile = None;
+    let mut new_keyfile = None;
+    let mut decoy = None;
+    let mut drop_decoy = false;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--old-pass" => old = Some(option_value(&mut args_iter, arg)),
+            "--old-key-id" => old = Some(key_id_option(&mut args_iter, arg)),
+            "--old-pass-env" => old = Some(env_option(&mut args_iter, arg)),
+            "--old-keyfile" => old_keyfile = Some(option_value(&mut args_iter, arg)),
+            "--new-pass" => new = Some(option_value(&mut args_iter, arg)),
+            "--new-key-id" => new = Some(key_id_option(&mut args_iter, arg)),
+            "--new-pass-env" => new = Some(env_option(&mut args_iter, arg)),
+            "--new-keyfile" => new_keyfile = Some(option_value(&mut args_iter, arg)),
+            "--decoy-passphrase" => decoy = Some(option_value(&mut args_iter, arg)),
+            "--decoy-key-id" => decoy = Some(key_id_option(&mut args_iter, arg)),
+            "--drop-decoy" => drop_decoy = true,
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    if !(1..=2).contains(&files.len()) {
+        eprintln!("Usage: rekey <in.gif> [out.gif] [--old-pass PASS | --old-key-id ID | --old-pass-env VAR] [--old-keyfile FILE] [--new-pass PASS | --new-key-id ID | --new-pass-env VAR] [--new-keyfile FILE] [--decoy-passphrase PASS | --decoy-key-id ID | --drop-decoy] [--backup]");
+        exit(1);
+    }
+    // Any decoy in the container goes with the old key unless it is kept
+    if decoy.is_none() && !drop_decoy {
+        error!("The payload's container may hold a decoy, which rekey would drop. Give --decoy-passphrase to keep it, or --drop-decoy.");
+        exit(1);
+    }
+    let old = rekey_passphrase(old, old_keyfile, false);
+    let new = rekey_passphrase(new, new_keyfile, true);
+    let output = files.get(1).unwrap_or(&files[0]);
+
+    // Unchanged frames are copied from the input as they are
+    let mut bytes = Vec::new();
+    open_input(&files[0])?.read_to_end(&mut bytes)?;
+    let mut gif = parse_input(&files[0], Some(&bytes))?;
+    rekey_payload(&mut gif, &old, &new, decoy.as_deref())?;
+    let input = audit_input(&bytes);
+    if files.len() == 1 {
+        reassemble_gif_in_place(output, &mut gif, Some(&bytes), input, backup)?;
+    } else {
+        reassemble_gif(output, &mut gif, Some(&bytes), input, backup)?;
+    }
+    info!(
+        "Payload sealed under the new passphrase and saved to {}",
+        output
+    );
+    Ok(())
+}
+
+// gifsauce analyze <file.gif|-> [--histograms]
+fn analyze_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut histograms = false;
+    for arg in args {
+        match arg.as_str() {
+            "--histograms" => histograms = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 1 {
+        eprintln!("Usage: analyze <file.gif|-> [--histograms]");
+        exit(1);
+    }
+
+    let gif = parse_input(&files[0], map_input(&files[0])?.as_deref())?;
+    let report = format_analysis(&analyze_gif(&gif)?, histograms);
+    io::stdout().lock().write_all(report.as_bytes())?;
+    Ok(())
+}
+
+// gifsauce inspect <file.gif|-> [--blocks] [--hash-frames] [--history]
+//                  [--frames RANGES]
+fn inspect_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut blocks = false;
+    let mut hash_frames = false;
+    let mut history = false;
+    let mut frames = None;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--blocks" => blocks = true,
+            "--hash-frames" => hash_frames = true,
+            "--history" => history = true,
+            "--frames" => frames = Some(frames_option(&mut args_iter, arg)),
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 1 {
+        eprintln!(
+            "Usage: inspect <file.gif|-> [--blocks] [--hash-frames] [--history] [--frames RANGES]"
+        );
+        exit(1);
+    }
+    let selected = |index: usize| {
+        frames
+            .as_ref()
+            .is_none_or(|ranges| ranges.iter().any(|range| range.contains(&index)))
+    };
+
+    let mut bytes = Vec::new();
+    open_input(&files[0])?.read_to_end(&mut bytes)?;
+    // The block map walks the bytes itself, so it works on files that don't
+    // parse
+    let mut report = String::new();
+    if blocks {
+        let mut map = block_map(&bytes);
+        if let Some(ref selection) = frames {
+            let frame_count = map
+                .iter()
+                .filter(|block| block.kind == "Image Data")
+                .count();
+            check_frame_selection(selection, frame_count)?;
+            map = frame_blocks(map, selection);
+        }
+        report.push_str(&format_block_map(&bytes, &map));
+    }
+    if hash_frames {
+        let gif = parse_gif_bytes(&bytes)?;
+        if let Some(ref selection) = frames {
+            check_frame_selection(selection, gif.image_descriptors.len())?;
+        }
+        // Frames are hashed as they show, after the ones before them
+        let hashes: Vec<(usize, [u8; 32])> = frame_hashes(&gif)?
+            .into_iter()
+            .enumerate()
+            .filter(|&(index, _)| selected(index))
+            .collect();
+        report.push_str(&format_frame_hashes(&hashes));
+    }
+    if history {
+        let gif = parse_gif_bytes(&bytes)?;
+        report.push_str(&format_history(&read_audit_trail(&gif)?));
+    }
+    if !blocks && !hash_frames && !history {
+        let mut outcome = parse_gif_bytes_with_warnings(&bytes)?;
+        let frame_count = outcome.gif.image_descriptors.len();
+        if let Some(ref selection) = frames {
+            set_aside_frames(&mut outcome.gif, selection)?;
+        }
+        report = format_summary(&outcome.gif, bytes.len(), frame_count, &outcome.warnings);
+    }
+    io::stdout().lock().write_all(report.as_bytes())?;
+    Ok(())
+}
+
+// gifsauce sanitize <in.gif> <out.gif> [--scrub-pixels] [--backup]
+fn sanitize_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut scrub_pixels = false;
+    let mut backup = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--scrub-pixels" => scrub_pixels = true,
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
         }
     }
-    let mut count_remove = 0;
-    let mut new_plain_text_extensions: Vec<PlainTextExtension> = gif
-        .image_descriptors
+
+    if files.len() != 2 {
+        eprintln!("Usage: sanitize <in.gif> <out.gif> [--scrub-pixels] [--backup]");
+        exit(1);
+    }
+
+    let bytes = fs::read(&files[0])?;
+    note_input(&files[0]);
+    let mut gif = parse_gif_bytes(&bytes)?;
+    filter_input_applications(&mut gif);
+
+    let report = sanitize_gif(&mut gif, scrub_pixels);
+    reassemble_gif(&files[1], &mut gif, None, audit_input(&bytes), backup)?;
+
+    info!(
+        "Removed {} plain text extension(s)",
+        report.plain_text_removed
+    );
+    info!("Removed {} comment extension(s)", report.comments_removed);
+    info!(
+        "Removed {} unknown application extension(s)",
+        report.application_removed
+    );
+    info!(
+        "Removed {} byte(s) after the trailer",
+        report.trailing_removed
+    );
+    if report.pixels_scrubbed {
+        info!("Cleared the low bits of the palettes and moved every pixel to an even index");
+    }
+    if report.colors_merged > 0 {
+        warn!(
+            "Merged {} color(s) into their closest neighbour, a palette had no room to keep them",
+            report.colors_merged
+        );
+    }
+    info!("Sanitized GIF saved to {}", files[1]);
+
+    Ok(())
+}
+
+// gifsauce comment list <file.gif>
+// gifsauce comment add <in.gif> <out.gif> (<text> | --file FILE) [--backup]
+// gifsauce comment add <file.gif> (<text> | --file FILE) --in-place
+// gifsauce comment remove <in.gif> <out.gif> (<index> | --all) [--backup]
+fn comment_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = || -> ! {
+        eprintln!("Usage: comment list <file.gif>");
+        eprintln!("       comment add <in.gif> <out.gif> (<text> | --file FILE) [--backup]");
+        eprintln!("       comment add <file.gif> (<text> | --file FILE) --in-place");
+        eprintln!("       comment remove <in.gif> <out.gif> (<index> | --all) [--backup]");
+        exit(1);
+    };
+
+    let backup = args.iter().any(|arg| arg == "--backup");
+    let in_place = args.iter().any(|arg| arg == "--in-place");
+    let args: Vec<String> = args
         .iter()
-        .enumerate()
-        .map(|(index, _)| {
-            let new_extended = PlainTextExtension {
-                block_size: 12,
-                text_grid_left_position: 0,
-                text_grid_top_position: 0,
-                text_grid_width: 0,
-                text_grid_height: 0,
-                character_cell_width: 0,
-                character_cell_height: 0,
-                text_foreground_color_index: 0,
-                text_background_color_index: 0,
-                plain_text_data: if ((index + 1) * input_chunk) <= input.len() {
-                    //println!(
-                    //   "length={}, index + 1={}",
-                    //    input.len(),
-                    //    (index + 1) * input_chunk
-                    //);
-                    input[index * input_chunk..(index + 1) * input_chunk]
-                        .as_bytes()
-                        .to_vec()
-                } else {
-                    //println!("-->index {}", index * input_chunk);
-                    let start = index * input_chunk;
-                    //println!("Start: {}", start);
-                    if start > input.len() {
-                        //println!("-->padding");
-                        count_remove += 1;
-                        vec![0u8; 254] //padding
-                    } else {
-                        //println!("Start-->bytes: {}", start);
-                        input[start..].as_bytes().to_vec()
-                    }
+        .filter(|arg| *arg != "--backup" && *arg != "--in-place")
+        .cloned()
+        .collect();
+
+    let action = args.first().map(|action| action.as_str());
+    if in_place {
+        if action != Some("add") || args.len() < 3 {
+            usage();
+        }
+        let text = match args[2].as_str() {
+            "--file" => match args.get(3) {
+                Some(path) => fs::read(path)?,
+                None => usage(),
+            },
+            text => text.as_bytes().to_vec(),
+        };
+        return add_comment_in_place(&args[1], &text);
+    }
+    let files = match action {
+        Some("list") if args.len() == 2 => &args[1..2],
+        Some("add") | Some("remove") if args.len() >= 4 => &args[1..3],
+        _ => usage(),
+    };
+
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+
+    match action {
+        Some("list") => {
+            for (index, comment) in gif.comment_extensions.iter().enumerate() {
+                println!("{}: {}", index, comment_text(comment));
+            }
+            return Ok(());
+        }
+        Some("add") => {
+            let text = match args[3].as_str() {
+                "--file" => match args.get(4) {
+                    Some(path) => fs::read(path)?,
+                    None => usage(),
                 },
+                text => text.as_bytes().to_vec(),
             };
-            new_extended
-        })
-        .collect();
+            add_comment(&mut gif, &text);
+            info!("Added a comment of {} byte(s)", text.len());
+        }
+        _ => match args[3].as_str() {
+            "--all" => {
+                info!("Removed {} comment(s)", gif.comment_extensions.len());
+                gif.comment_extensions.clear();
+            }
+            index => {
+                let index = index.parse::<usize>().unwrap_or_else(|_| usage());
+                let comment = remove_comment(&mut gif, index)?;
+                info!("Removed comment {}: {}", index, comment_text(&comment));
+            }
+        },
+    }
+
+    reassemble_gif(&files[1], &mut gif, mapped.as_deref(), input, backup)?;
+    info!("GIF saved to {}", files[1]);
+    Ok(())
+}
+
+// Add a comment by writing over the file's trailer, leaving every byte before
+// it as it is. A file with data past its trailer, or one getting an audit
+// record, is written again in full.
+fn add_comment_in_place(path: &str, text: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let bytes = fs::read(path)?;
+    let comment = new_comment(text);
+    if STRICT.load(Ordering::Relaxed) && bytes.starts_with(b"GIF87a") {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The GIF87a file would need extension blocks and --strict keeps it from becoming GIF89a.",
+        )));
+    }
 
-    // Remove the padding
-    for _ in 0..count_remove {
-        gif.image_descriptors.pop();
-        new_plain_text_extensions.pop();
+    // The audit record goes in an extension of its own, which patches can't add
+    let audit = AUDIT.load(Ordering::Relaxed);
+    let patches = match audit {
+        true => None,
+        false => comment_patches(&bytes, &comment)?,
+    };
+    match patches {
+        Some(patches) => {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            apply_patches(&mut file, &patches)?;
+        }
+        None => {
+            if !audit {
+                warn!(
+                    "{} has data past its trailer, writing it again in full",
+                    path
+                );
+            }
+            let mut gif = parse_input(path, Some(&bytes))?;
+            gif.comment_extensions.push(comment);
+            reassemble_gif_in_place(path, &mut gif, Some(&bytes), audit_input(&bytes), false)?;
+        }
     }
+    info!("Added a comment of {} byte(s) to {}", text.len(), path);
+    Ok(())
+}
+
+// gifsauce xmp get <file.gif|-> [-o FILE]
+// gifsauce xmp set <in.gif> <out.gif> (<xml> | --file FILE) [--backup]
+// gifsauce xmp delete <in.gif> <out.gif> [--backup]
+fn xmp_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = || -> ! {
+        eprintln!("Usage: xmp get <file.gif|-> [-o FILE]");
+        eprintln!("       xmp set <in.gif> <out.gif> (<xml> | --file FILE) [--backup]");
+        eprintln!("       xmp delete <in.gif> <out.gif> [--backup]");
+        exit(1);
+    };
 
-    gif.plain_text_extensions = new_plain_text_extensions;
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut xml_file = None;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut args_iter, arg)),
+            "--file" => xml_file = Some(option_value(&mut args_iter, arg)),
+            "--backup" => backup = true,
+            _ => positional.push(arg.clone()),
+        }
+    }
 
-    // Reassemble and write the modified GIF back to a file
-    reassemble_gif(&mut reader, &output_file, &gif)?;
-    println!("GIF reassembled and saved to {}", output_file);
+    let action = positional.first().map(|action| action.as_str());
+    match action {
+        Some("get") if positional.len() == 2 && xml_file.is_none() => {}
+        Some("set") if positional.len() == 3 + usize::from(xml_file.is_none()) => {}
+        Some("delete") if positional.len() == 3 && xml_file.is_none() => {}
+        _ => usage(),
+    }
+    if output.is_some() && action != Some("get") {
+        usage();
+    }
+
+    let mapped = map_input(&positional[1])?;
+    let (mut gif, input) = parse_audited_input(&positional[1], mapped.as_deref())?;
+    match action {
+        Some("get") => {
+            let xml = xmp_packet(&gif)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "The file has no XMP metadata.")
+            })?;
+            match output {
+                Some(ref path) if path != "-" => {
+                    write_atomic(path, xml.as_bytes(), false)?;
+                    info!("Wrote {} byte(s) of XMP to {}", xml.len(), path);
+                }
+                _ => io::stdout().lock().write_all(xml.as_bytes())?,
+            }
+            return Ok(());
+        }
+        Some("set") => {
+            let xml = match xml_file {
+                Some(path) => String::from_utf8(fs::read(&path)?).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} isn't UTF-8 text.", path),
+                    )
+                })?,
+                None => positional[3].clone(),
+            };
+            set_xmp(&mut gif, &xml)?;
+            info!("Set {} byte(s) of XMP", xml.len());
+        }
+        _ => {
+            let removed = delete_xmp(&mut gif);
+            if removed == 0 {
+                warn!("The file has no XMP metadata");
+            }
+            info!("Removed {} XMP block(s)", removed);
+        }
+    }
+
+    reassemble_gif(&positional[2], &mut gif, mapped.as_deref(), input, backup)?;
+    info!("GIF saved to {}", positional[2]);
+    Ok(())
+}
+
+// gifsauce frame delete <in.gif> <out.gif> <index>... [--backup]
+// gifsauce frame extract <in.gif> <out.gif> <index>... [--backup]
+// gifsauce frame move <in.gif> <out.gif> <from> <to> [--backup]
+fn frame_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = || -> ! {
+        eprintln!("Usage: frame delete <in.gif> <out.gif> <index>... [--backup]");
+        eprintln!("       frame extract <in.gif> <out.gif> <index>... [--backup]");
+        eprintln!("       frame move <in.gif> <out.gif> <from> <to> [--backup]");
+        exit(1);
+    };
+
+    let backup = args.iter().any(|arg| arg == "--backup");
+    let args: Vec<String> = args
+        .iter()
+        .filter(|arg| *arg != "--backup")
+        .cloned()
+        .collect();
+
+    let action = args.first().map(|action| action.as_str());
+    match action {
+        Some("delete") | Some("extract") if args.len() >= 4 => {}
+        Some("move") if args.len() == 5 => {}
+        _ => usage(),
+    }
+    let files = &args[1..3];
+    let indices: Vec<usize> = args[3..]
+        .iter()
+        .map(|index| index.parse().unwrap_or_else(|_| usage()))
+        .collect();
+
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    let frame_count = gif.image_descriptors.len();
+
+    match action {
+        Some("delete") => {
+            let removed = delete_frames(&mut gif, &indices)?;
+            info!("Removed {} of {} frame(s)", removed, frame_count);
+        }
+        Some("extract") => {
+            extract_frames(&mut gif, &indices)?;
+            info!("Kept {} of {} frame(s)", indices.len(), frame_count);
+        }
+        _ => {
+            move_frame(&mut gif, indices[0], indices[1])?;
+            info!("Moved frame {} to {}", indices[0], indices[1]);
+        }
+    }
+
+    reassemble_gif(&files[1], &mut gif, mapped.as_deref(), input, backup)?;
+    info!("GIF saved to {}", files[1]);
+    Ok(())
+}
+
+// gifsauce reverse <in.gif> <out.gif> [--pingpong]
+//                  [--dither none|ordered|floyd-steinberg] [--backup]
+fn reverse_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut pingpong = false;
+    let mut dither = Dither::None;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--pingpong" => pingpong = true,
+            "--dither" => dither = dither_option(&mut args_iter, arg),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 2 {
+        eprintln!(
+            "Usage: reverse <in.gif> <out.gif> [--pingpong] [--dither none|ordered|floyd-steinberg] [--backup]"
+        );
+        exit(1);
+    }
+
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    let frame_count = gif.image_descriptors.len();
+    if pingpong {
+        pingpong_frames(&mut gif, dither)?;
+        info!(
+            "Played {} frame(s) forwards then backwards in {} frame(s)",
+            frame_count,
+            gif.image_descriptors.len()
+        );
+    } else {
+        reverse_frames(&mut gif, dither)?;
+        info!("Reversed {} frame(s)", frame_count);
+    }
+
+    reassemble_gif(&files[1], &mut gif, mapped.as_deref(), input, backup)?;
+    info!("GIF saved to {}", files[1]);
+    Ok(())
+}
+
+// gifsauce split <in.gif> (--every N | --frames RANGES) [-o PATTERN]
+//                [--dither none|ordered|floyd-steinberg] [--backup]
+fn split_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut every = None;
+    let mut ranges = None;
+    let mut pattern = None;
+    let mut dither = Dither::None;
+    let mut backup = false;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--every" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(count) if count > 0 => every = Some(count),
+                _ => {
+                    error!("--every expects a positive number of frames");
+                    exit(1);
+                }
+            },
+            "--frames" => ranges = Some(frames_option(&mut args_iter, arg)),
+            "-o" => pattern = Some(option_value(&mut args_iter, arg)),
+            "--dither" => dither = dither_option(&mut args_iter, arg),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 1 || every.is_some() == ranges.is_some() {
+        eprintln!("Usage: split <in.gif> (--every N | --frames RANGES) [-o PATTERN] [--dither none|ordered|floyd-steinberg] [--backup]");
+        exit(1);
+    }
+
+    // Parts are numbered from 0 in place of %d, next to the input by default
+    let pattern = pattern.unwrap_or_else(|| {
+        let input = Path::new(&files[0]);
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        input
+            .with_file_name(format!("{}_%d.gif", stem))
+            .to_string_lossy()
+            .into_owned()
+    });
+    if !pattern.contains("%d") {
+        error!("-o expects a pattern with %d for the part number");
+        exit(1);
+    }
+
+    let mapped = map_input(&files[0])?;
+    let (gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    let frame_count = gif.image_descriptors.len();
+    let ranges: Vec<Range<usize>> = match every {
+        Some(count) => (0..frame_count)
+            .step_by(count)
+            .map(|start| start..(start + count).min(frame_count))
+            .collect(),
+        None => {
+            let ranges = ranges.unwrap_or_default();
+            // Clamped to the frames there are, these would read as empty
+            if let Some(range) = ranges.iter().find(|range| range.start >= frame_count) {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Frames {}.. start past the last frame of {} ({} frames).",
+                        range.start, files[0], frame_count
+                    ),
+                )));
+            }
+            ranges
+                .into_iter()
+                .map(|range| range.start..range.end.min(frame_count))
+                .collect()
+        }
+    };
+
+    let mut parts = split_frames(&gif, &ranges, dither)?;
+    for (number, (part, range)) in parts.iter_mut().zip(&ranges).enumerate() {
+        let path = pattern.replace("%d", &number.to_string());
+        reassemble_gif(&path, part, mapped.as_deref(), input, backup)?;
+        info!("Frames {}..{} saved to {}", range.start, range.end, path);
+    }
+    Ok(())
+}
+
+// gifsauce transform <in.gif> <out.gif> [--crop X,Y,W,H] [--resize WxH]
+//                    [--filter nearest|bilinear]
+//                    [--dither none|ordered|floyd-steinberg]
+//                    [--normalize-delays [--min-delay CS] [--fps N]] [--backup]
+fn transform_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut crop = None;
+    let mut resize = None;
+    let mut filter = Filter::Nearest;
+    let mut dither = Dither::None;
+    let mut normalize = false;
+    let mut min_delay = None;
+    let mut fps = None;
+    let mut backup = false;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--normalize-delays" => normalize = true,
+            "--min-delay" => match option_value(&mut args_iter, arg).parse::<u16>() {
+                Ok(value) if value >= 2 => min_delay = Some(value),
+                _ => {
+                    error!("--min-delay expects hundredths of a second, 2 or more");
+                    exit(1);
+                }
+            },
+            "--fps" => match option_value(&mut args_iter, arg).parse::<u16>() {
+                Ok(value) if value > 0 => fps = Some(value),
+                _ => {
+                    error!("--fps expects a positive number of frames per second");
+                    exit(1);
+                }
+            },
+            "--crop" => {
+                let value = option_value(&mut args_iter, arg);
+                let numbers: Vec<u16> = value
+                    .split(',')
+                    .filter_map(|number| number.trim().parse().ok())
+                    .collect();
+                match numbers[..] {
+                    [x, y, width, height] if value.split(',').count() == 4 => {
+                        crop = Some((x, y, width, height))
+                    }
+                    _ => {
+                        error!("--crop expects X,Y,W,H in pixels");
+                        exit(1);
+                    }
+                }
+            }
+            "--resize" => {
+                let value = option_value(&mut args_iter, arg);
+                match value
+                    .split_once('x')
+                    .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                {
+                    Some(size) => resize = Some(size),
+                    None => {
+                        error!("--resize expects WxH in pixels");
+                        exit(1);
+                    }
+                }
+            }
+            "--filter" => match parse_filter(&option_value(&mut args_iter, arg)) {
+                Some(chosen) => filter = chosen,
+                None => {
+                    error!("--filter expects nearest or bilinear");
+                    exit(1);
+                }
+            },
+            "--dither" => dither = dither_option(&mut args_iter, arg),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    let timing = min_delay.is_some() || fps.is_some();
+    if files.len() != 2
+        || (crop.is_none() && resize.is_none() && !normalize)
+        || (timing && !normalize)
+    {
+        eprintln!(
+            "Usage: transform <in.gif> <out.gif> [--crop X,Y,W,H] [--resize WxH] [--filter nearest|bilinear] [--dither none|ordered|floyd-steinberg] [--normalize-delays [--min-delay CS] [--fps N]] [--backup]"
+        );
+        exit(1);
+    }
+
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    // Cropping first leaves fewer pixels to resize
+    if let Some((x, y, width, height)) = crop {
+        crop_gif(&mut gif, x, y, width, height)?;
+        info!("Cropped to {}x{} from {},{}", width, height, x, y);
+    }
+    if let Some((width, height)) = resize {
+        resize_gif(&mut gif, width, height, filter, dither)?;
+        info!("Resized to {}x{}", width, height);
+    }
+    if normalize {
+        let changed = normalize_delays(&mut gif, min_delay.unwrap_or(10), fps);
+        info!("Normalized the delay of {} frame(s)", changed);
+    }
+
+    reassemble_gif(&files[1], &mut gif, mapped.as_deref(), input, backup)?;
+    info!("GIF saved to {}", files[1]);
+    Ok(())
+}
+
+// gifsauce palette export <in.gif> [-o FILE] [--frame N] [--format gpl|act|json]
+// gifsauce palette import <in.gif> <colors> <out.gif> [--frame N]
+//                         [--format gpl|act|json] [--backup]
+fn palette_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = || -> ! {
+        eprintln!("Usage: palette export <in.gif> [-o FILE] [--frame N] [--format gpl|act|json]");
+        eprintln!(
+            "       palette import <in.gif> <colors> <out.gif> [--frame N] [--format gpl|act|json] [--backup]"
+        );
+        exit(1);
+    };
+
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut frame = None;
+    let mut format = None;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut args_iter, arg)),
+            "--frame" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(index) => frame = Some(index),
+                Err(_) => {
+                    error!("--frame expects a frame index");
+                    exit(1);
+                }
+            },
+            "--format" => match parse_palette_format(&option_value(&mut args_iter, arg)) {
+                Some(chosen) => format = Some(chosen),
+                None => {
+                    error!("--format expects gpl, act or json");
+                    exit(1);
+                }
+            },
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+
+    match files.first().map(|action| action.as_str()) {
+        Some("export") if files.len() == 2 => {
+            let gif = parse_input(&files[1], map_input(&files[1])?.as_deref())?;
+            let table = color_table(&gif, frame)?;
+            // Named by --format, then by the output's extension
+            let format = format
+                .or(output.as_deref().and_then(palette_format_of))
+                .unwrap_or(PaletteFormat::Gpl);
+            let bytes = write_palette(&table.colors, format);
+            match output {
+                Some(ref path) if path != "-" => {
+                    write_atomic(path, &bytes, backup)?;
+                    info!("Wrote {} color(s) to {}", table.colors.len(), path);
+                }
+                _ => io::stdout().lock().write_all(&bytes)?,
+            }
+            Ok(())
+        }
+        Some("import") if files.len() == 4 => {
+            let format = match format.or(palette_format_of(&files[2])) {
+                Some(format) => format,
+                None => {
+                    error!(
+                        "Can't tell the palette format of {}, use --format",
+                        files[2]
+                    );
+                    exit(1);
+                }
+            };
+            let colors = read_palette(&fs::read(&files[2])?, format)?;
+            let mapped = map_input(&files[1])?;
+            let (mut gif, input) = parse_audited_input(&files[1], mapped.as_deref())?;
+            import_palette(&mut gif, frame, &colors)?;
+            info!("Imported {} color(s) from {}", colors.len(), files[2]);
+
+            reassemble_gif(&files[3], &mut gif, mapped.as_deref(), input, backup)?;
+            info!("GIF saved to {}", files[3]);
+            Ok(())
+        }
+        _ => usage(),
+    }
+}
+
+// gifsauce render-text <in.gif> <out.gif> [--backup]
+fn render_text_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut backup = false;
+    for arg in args {
+        match arg.as_str() {
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 2 {
+        eprintln!("Usage: render-text <in.gif> <out.gif> [--backup]");
+        exit(1);
+    }
+
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    let rendered = render_plain_text(&mut gif)?;
+    info!("Drew {} plain text block(s) as frames", rendered);
+
+    reassemble_gif(&files[1], &mut gif, mapped.as_deref(), input, backup)?;
+    info!("GIF saved to {}", files[1]);
+    Ok(())
+}
+
+// gifsauce caption <in.gif> <out.gif> --text TEXT [--pos top|center|bottom]
+//                  [--font-size N] [--font FILE.ttf] [--frames RANGES]
+//                  [--dither none|ordered|floyd-steinberg] [--backup]
+fn caption_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut text = None;
+    let mut position = Position::Bottom;
+    let mut font_size = 16;
+    let mut font = None;
+    let mut frames = None;
+    let mut dither = Dither::None;
+    let mut backup = false;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--text" => text = Some(option_value(&mut args_iter, arg)),
+            "--pos" => match parse_position(&option_value(&mut args_iter, arg)) {
+                Some(chosen) => position = chosen,
+                None => {
+                    error!("--pos expects top, center or bottom");
+                    exit(1);
+                }
+            },
+            "--font-size" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(size @ 1..=1024) => font_size = size,
+                _ => {
+                    error!("--font-size expects a height in pixels up to 1024");
+                    exit(1);
+                }
+            },
+            "--font" => font = Some(option_value(&mut args_iter, arg)),
+            "--frames" => frames = Some(frames_option(&mut args_iter, arg)),
+            "--dither" => dither = dither_option(&mut args_iter, arg),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    let text = match text {
+        Some(text) if files.len() == 2 => text,
+        _ => {
+            eprintln!(
+                "Usage: caption <in.gif> <out.gif> --text TEXT [--pos top|center|bottom] [--font-size N] [--font FILE.ttf] [--frames RANGES] [--dither none|ordered|floyd-steinberg] [--backup]"
+            );
+            exit(1);
+        }
+    };
+
+    let mask = match font {
+        #[cfg(feature = "fontdue")]
+        Some(path) => gifsauce::caption::truetype_text(&fs::read(path)?, &text, font_size)?,
+        #[cfg(not(feature = "fontdue"))]
+        Some(_) => {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "--font needs GifSauce built with the fontdue feature.",
+            )))
+        }
+        None => bitmap_text(&text, font_size),
+    };
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    let captioned = caption_frames(&mut gif, &mask, position, frames.as_deref(), dither)?;
+    info!("Captioned {} frame(s)", captioned);
+
+    reassemble_gif(&files[1], &mut gif, mapped.as_deref(), input, backup)?;
+    info!("GIF saved to {}", files[1]);
+    Ok(())
+}
+
+// gifsauce preview <file.gif|-> [--protocol kitty|sixel|blocks] [--width COLUMNS]
+//                  [--loop]
+fn preview_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut protocol = None;
+    let mut columns = None;
+    let mut repeat = false;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--protocol" => match parse_protocol(&option_value(&mut args_iter, arg)) {
+                Some(chosen) => protocol = Some(chosen),
+                None => {
+                    error!("--protocol expects kitty, sixel or blocks");
+                    exit(1);
+                }
+            },
+            "--width" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(width) if width > 0 => columns = Some(width),
+                _ => {
+                    error!("--width expects a number of columns");
+                    exit(1);
+                }
+            },
+            "--loop" => repeat = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 1 {
+        eprintln!(
+            "Usage: preview <file.gif|-> [--protocol kitty|sixel|blocks] [--width COLUMNS] [--loop]"
+        );
+        exit(1);
+    }
+
+    let gif = parse_input(&files[0], map_input(&files[0])?.as_deref())?;
+    let protocol = protocol.unwrap_or_else(detect_protocol);
+    // Shells export COLUMNS for the terminal's width
+    let columns = columns
+        .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(80);
+    preview_gif(&gif, protocol, columns, repeat, io::stdout().lock())?;
+    Ok(())
+}
+
+// gifsauce sheet <file.gif|-> -o <sheet.png|-> [--columns N] [--every N]
+fn sheet_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut columns = 8;
+    let mut every = 1;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut args_iter, arg)),
+            "--columns" | "--every" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(number) if number > 0 && arg == "--columns" => columns = number,
+                Ok(number) if number > 0 => every = number,
+                _ => {
+                    error!("{} expects a positive number", arg);
+                    exit(1);
+                }
+            },
+            _ => files.push(arg.clone()),
+        }
+    }
+    let output = match output {
+        Some(output) if files.len() == 1 => output,
+        _ => {
+            eprintln!("Usage: sheet <file.gif|-> -o <sheet.png|-> [--columns N] [--every N]");
+            exit(1);
+        }
+    };
+
+    let gif = parse_input(&files[0], map_input(&files[0])?.as_deref())?;
+    let (width, height, rgba) = contact_sheet(&gif, every, columns)?;
+    let png = rgba_png(width, height, &rgba)?;
+    if output == "-" {
+        io::stdout().lock().write_all(&png)?;
+    } else {
+        write_atomic(&output, &png, false)?;
+        info!("Saved a {}x{} sheet to {}", width, height, output);
+    }
+    Ok(())
+}
+
+// gifsauce convert <file.gif|-> -o <out.png|out.webp|-> [--format apng|webp] [--icc FILE]
+#[cfg(feature = "image-io")]
+fn convert_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut format = None;
+    let mut icc = None;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut args_iter, arg)),
+            "--icc" => icc = Some(read_icc_file(&option_value(&mut args_iter, arg))?),
+            "--format" => {
+                let name = option_value(&mut args_iter, arg);
+                format = Some(parse_animation_format(&name).unwrap_or_else(|| {
+                    error!("Unknown animation format: {}", name);
+                    exit(1);
+                }));
+            }
+            _ => files.push(arg.clone()),
+        }
+    }
+    let output = match output {
+        Some(output) if files.len() == 1 => output,
+        _ => {
+            eprintln!(
+                "Usage: convert <file.gif|-> -o <out.png|out.webp|-> [--format apng|webp] [--icc FILE]"
+            );
+            exit(1);
+        }
+    };
+    let format = match format.or_else(|| animation_format_of(&output)) {
+        Some(format) => format,
+        None => {
+            error!(
+                "Can't tell the format of {}, pass --format apng|webp",
+                output
+            );
+            exit(1);
+        }
+    };
+
+    let mut gif = parse_input(&files[0], map_input(&files[0])?.as_deref())?;
+    if let Some(profile) = icc {
+        set_icc_profile(&mut gif, profile);
+    }
+    let converted = match format {
+        AnimationFormat::Apng => animated_png(&gif)?,
+        AnimationFormat::Webp => animated_webp(&gif)?,
+    };
+    if output == "-" {
+        io::stdout().lock().write_all(&converted)?;
+    } else {
+        write_atomic(&output, &converted, false)?;
+        info!(
+            "Converted {} frame(s) to {}",
+            gif.image_descriptors.len(),
+            output
+        );
+    }
+    Ok(())
+}
+
+// gifsauce carrier from-image <image.jpg|png|webp|-> -o <carrier.gif> [--capacity BYTES] [--dither MODE] [--icc FILE] [--backup]
+// gifsauce carrier generate -o <carrier.gif> [--size WxH] [--frames N] [--style noise|gradient|plasma] [--seed N] [--delay CS] [--capacity BYTES] [--dither MODE] [--icc FILE] [--backup]
+// The image's own ICC profile is kept unless --icc replaces it.
+fn carrier_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = || -> ! {
+        eprintln!(
+            "Usage: carrier from-image <image.jpg|png|webp|-> -o <carrier.gif> [--capacity BYTES] [--dither none|ordered|floyd-steinberg] [--icc FILE] [--backup]"
+        );
+        eprintln!(
+            "       carrier generate -o <carrier.gif> [--size WxH] [--frames N] [--style noise|gradient|plasma] [--seed N] [--delay CS] [--capacity BYTES] [--dither none|ordered|floyd-steinberg] [--icc FILE] [--backup]"
+        );
+        exit(1);
+    };
+
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut capacity = None;
+    let mut size = (256, 256);
+    let mut frames = 10;
+    let mut style = Style::Noise;
+    let mut seed = None;
+    let mut delay = 10;
+    let mut dither = Dither::None;
+    let mut icc = None;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut args_iter, arg)),
+            "--icc" => icc = Some(read_icc_file(&option_value(&mut args_iter, arg))?),
+            "--capacity" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(bytes) => capacity = Some(bytes),
+                Err(_) => {
+                    error!("--capacity expects a number of bytes");
+                    exit(1);
+                }
+            },
+            "--size" => {
+                let value = option_value(&mut args_iter, arg);
+                match value
+                    .split_once('x')
+                    .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                {
+                    Some(chosen) => size = chosen,
+                    None => {
+                        error!("--size expects WxH in pixels");
+                        exit(1);
+                    }
+                }
+            }
+            "--frames" => match option_value(&mut args_iter, arg).parse::<usize>() {
+                Ok(count) if count > 0 => frames = count,
+                _ => {
+                    error!("--frames expects a number of frames");
+                    exit(1);
+                }
+            },
+            "--style" => match parse_style(&option_value(&mut args_iter, arg)) {
+                Some(chosen) => style = chosen,
+                None => {
+                    error!("--style expects noise, gradient or plasma");
+                    exit(1);
+                }
+            },
+            "--seed" => match option_value(&mut args_iter, arg).parse::<u64>() {
+                Ok(value) => seed = Some(value),
+                Err(_) => {
+                    error!("--seed expects a number");
+                    exit(1);
+                }
+            },
+            "--delay" => match option_value(&mut args_iter, arg).parse::<u16>() {
+                Ok(value) => delay = value,
+                Err(_) => {
+                    error!("--delay expects hundredths of a second");
+                    exit(1);
+                }
+            },
+            "--dither" => dither = dither_option(&mut args_iter, arg),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    let output = match output {
+        Some(output) => output,
+        None => usage(),
+    };
+
+    let lsb = lookup_channel(ChannelKind::Lsb);
+    let mut gif = match files.first().map(|action| action.as_str()) {
+        Some("from-image") if files.len() == 2 => {
+            let mut bytes = Vec::new();
+            open_input(&files[1])?.read_to_end(&mut bytes)?;
+            let mut gif = still_to_gif(&bytes, dither)?;
+
+            // Repeat the frame until the lsb channel holds --capacity bytes.
+            // Every copy holds as much as the first.
+            let per_frame = lsb.capacity(&gif).unwrap_or(0);
+            if let Some(capacity) = capacity {
+                if per_frame == 0 {
+                    error!("The image is too small to hold anything in the lsb channel");
+                    exit(1);
+                }
+                let frame = gif.image_descriptors[0].clone();
+                gif.image_descriptors = vec![frame; capacity.div_ceil(per_frame).max(1)];
+            }
+            gif
+        }
+        Some("generate") if files.len() == 1 => {
+            // A seed from the clock unless given, so carriers made without
+            // one don't all look alike
+            let seed = seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+            });
+            // Generated frames have no transparent pixels, so each holds a
+            // bit a pixel in the lsb channel
+            let (width, height) = size;
+            if let Some(capacity) = capacity {
+                frames = frames.max((capacity * 8).div_ceil((width * height).max(1)));
+            }
+            info!("Generating {} frame(s) with seed {}", frames, seed);
+            generate_carrier(width, height, frames, style, seed, delay, dither)?
+        }
+        _ => usage(),
+    };
+    if let Some(profile) = icc {
+        set_icc_profile(&mut gif, profile);
+    }
+
+    reassemble_gif(&output, &mut gif, None, None, backup)?;
+    info!(
+        "Wrote a carrier of {}x{} pixels and {} frame(s) to {}, holding {} byte(s) in the lsb channel",
+        gif.logical_screen_descriptor.width,
+        gif.logical_screen_descriptor.height,
+        gif.image_descriptors.len(),
+        output,
+        lsb.capacity(&gif).unwrap_or(0)
+    );
+    Ok(())
+}
+
+// gifsauce optimize <in.gif> <out.gif> [--backup]
+fn optimize_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut backup = false;
+    for arg in args {
+        match arg.as_str() {
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 2 {
+        eprintln!("Usage: optimize <in.gif> <out.gif> [--backup]");
+        exit(1);
+    }
+
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    let mut before = Vec::new();
+    write_gif(&mut before, &gif, mapped.as_deref())?;
+    optimize_frames(&mut gif)?;
+    share_color_tables(&mut gif);
+    check_version(&gif)?;
+    record_operation(&mut gif, input);
+    let mut output = Vec::new();
+    write_gif(&mut output, &gif, None)?;
+
+    info!(
+        "Optimized {} frame(s): {} -> {} bytes",
+        gif.image_descriptors.len(),
+        before.len(),
+        output.len()
+    );
+    write_atomic(&files[1], &output, backup)?;
+    info!("GIF saved to {}", files[1]);
+    Ok(())
+}
+
+// Set up logging from -q / -v / -vv, removing those flags from the
+// arguments. Diagnostics go to stderr, stdout is left for extracted data and
+// reports. RUST_LOG still overrides the level. --quiet and --no-progress also
+// hide the progress bars.
+fn init_logging(args: Vec<String>) -> Vec<String> {
+    let mut level = LevelFilter::Info;
+    let args = args
+        .into_iter()
+        .filter(|arg| {
+            match arg.as_str() {
+                "-q" | "--quiet" => {
+                    level = LevelFilter::Error;
+                    disable_progress();
+                }
+                "--no-progress" => disable_progress(),
+                "-v" | "--verbose" => level = LevelFilter::Debug,
+                "-vv" => level = LevelFilter::Trace,
+                _ => return true,
+            }
+            false
+        })
+        .collect();
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .target(env_logger::Target::Pipe(Box::new(LogWriter)))
+        .format(|buf, record| match record.level() {
+            Level::Info => writeln!(buf, "{}", record.args()),
+            Level::Warn => {
+                record_warning(record.args().to_string());
+                writeln!(buf, "warn: {}", record.args())
+            }
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+    args
+}
+
+// Size the thread pool used for frame compression from --threads N,
+// removing the option from the arguments. Rayon picks one per core otherwise.
+fn init_threads(args: Vec<String>) -> Vec<String> {
+    let mut remaining = Vec::with_capacity(args.len());
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        if arg != "--threads" {
+            remaining.push(arg.clone());
+            continue;
+        }
+        match option_value(&mut args_iter, arg).parse::<usize>() {
+            Ok(threads) if threads > 0 => {
+                if let Err(e) = ThreadPoolBuilder::new().num_threads(threads).build_global() {
+                    warn!("Can't size the thread pool: {}", e);
+                }
+            }
+            _ => {
+                error!("--threads expects a positive number of threads");
+                exit(1);
+            }
+        }
+    }
+    remaining
+}
+
+// Collect the application extension filters of --keep-appext and
+// --drop-appext, removing them from the arguments. Each takes an identifier
+// and authentication code such as NETSCAPE2.0, where * and ? are wildcards,
+// and keeping wins over dropping.
+fn init_appext_filters(args: Vec<String>) -> Vec<String> {
+    let mut filters = APPEXT_FILTERS.lock().unwrap();
+    let mut remaining = Vec::with_capacity(args.len());
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--keep-appext" => filters.0.push(option_value(&mut args_iter, arg)),
+            "--drop-appext" => filters.1.push(option_value(&mut args_iter, arg)),
+            _ => remaining.push(arg.clone()),
+        }
+    }
+    remaining
+}
+
+// Record each operation in the audit trail of the GIFs it writes from
+// --audit, removing the flag from the arguments. The operation is the
+// command, embed for the old -i and -o form.
+fn init_audit(args: Vec<String>) -> Vec<String> {
+    let args: Vec<String> = args
+        .into_iter()
+        .filter(|arg| {
+            if arg == "--audit" {
+                AUDIT.store(true, Ordering::Relaxed);
+                return false;
+            }
+            true
+        })
+        .collect();
+    match args.get(1) {
+        Some(command) if !command.starts_with('-') => set_operation(command),
+        _ => set_operation("embed"),
+    }
+    args
+}
+
+// Let outputs replace existing files from --force, removing the flag from the
+// arguments
+fn init_force(args: Vec<String>) -> Vec<String> {
+    args.into_iter()
+        .filter(|arg| {
+            if arg == "--force" {
+                FORCE.store(true, Ordering::Relaxed);
+                return false;
+            }
+            true
+        })
+        .collect()
+}
+
+// Turn on strict version handling from --strict, removing the flag from the
+// arguments
+fn init_strict(args: Vec<String>) -> Vec<String> {
+    args.into_iter()
+        .filter(|arg| {
+            if arg == "--strict" {
+                STRICT.store(true, Ordering::Relaxed);
+                return false;
+            }
+            true
+        })
+        .collect()
+}
+
+// Put the defaults of the config file, from --config PATH or the default
+// location, in front of the command's options. A missing default file is no
+// config at all.
+fn init_config(args: Vec<String>) -> Vec<String> {
+    let mut remaining = Vec::with_capacity(args.len());
+    let mut path = None;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--config" => path = Some(PathBuf::from(option_value(&mut args_iter, arg))),
+            _ => remaining.push(arg.clone()),
+        }
+    }
+    let path_given = path.is_some();
+
+    let path = match path.or_else(default_config_path) {
+        Some(path) if path.is_file() || path_given => path,
+        _ => return remaining,
+    };
+    match read_config(&path).and_then(|config| apply_config(&config, remaining)) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}: {}", path.display(), e);
+            exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args = init_config(init_audit(init_appext_filters(init_force(init_strict(
+        init_threads(init_logging(env::args().collect())),
+    )))));
+    if let Err(e) = run(&args) {
+        error!("{}", e);
+        exit(1);
+    }
+}
+
+// gifsauce polyglot <carrier.gif|-> <archive.zip|-> -o <out.gif> [--backup]
+fn polyglot_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut args_iter, arg)),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    let output = match output {
+        Some(output) if files.len() == 2 => output,
+        _ => {
+            eprintln!("Usage: polyglot <carrier.gif|-> <archive.zip|-> -o <out.gif> [--backup]");
+            exit(1);
+        }
+    };
+
+    let mapped = map_input(&files[0])?;
+    let (mut gif, input) = parse_audited_input(&files[0], mapped.as_deref())?;
+    if !gif.trailing_data.is_empty() {
+        warn!(
+            "Dropping the {} byte(s) the carrier had after its trailer",
+            gif.trailing_data.len()
+        );
+        gif.trailing_data.clear();
+    }
+    let mut zip = Vec::new();
+    open_input(&files[1])?.read_to_end(&mut zip)?;
+
+    check_version(&gif)?;
+    record_operation(&mut gif, input);
+    let mut bytes = Vec::new();
+    write_gif(&mut bytes, &gif, mapped.as_deref())?;
+    write_atomic(&output, &append_zip(bytes, &zip)?, backup)?;
+    info!("GIF/ZIP polyglot saved to {}", output);
+    Ok(())
+}
+
+// gifsauce watermark embed <in.gif|-> <out.gif|-> --id ID [--key KEY] [--strength LEVELS] [--dither MODE] [--backup]
+// gifsauce watermark verify <image|-> --id ID [--key KEY]
+fn watermark_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = || -> ! {
+        eprintln!(
+            "Usage: watermark embed <in.gif|-> <out.gif|-> --id ID [--key KEY] [--strength LEVELS] [--dither none|ordered|floyd-steinberg] [--backup]"
+        );
+        eprintln!("       watermark verify <image.gif|jpg|png|webp|-> --id ID [--key KEY]");
+        exit(1);
+    };
+
+    let mut files = Vec::new();
+    let mut id = None;
+    let mut key = String::new();
+    let mut strength = 4;
+    let mut dither = Dither::FloydSteinberg;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--id" => id = Some(option_value(&mut args_iter, arg)),
+            "--key" => key = option_value(&mut args_iter, arg),
+            "--strength" => match option_value(&mut args_iter, arg).parse::<u8>() {
+                Ok(levels) if levels > 0 => strength = levels,
+                _ => {
+                    error!("--strength expects a number of levels from 1 to 255");
+                    exit(1);
+                }
+            },
+            "--dither" => dither = dither_option(&mut args_iter, arg),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    let id = match id {
+        Some(id) if !id.is_empty() => id,
+        _ => usage(),
+    };
+
+    match files.first().map(|action| action.as_str()) {
+        Some("embed") if files.len() == 3 => {
+            let mapped = map_input(&files[1])?;
+            let (mut gif, input) = parse_audited_input(&files[1], mapped.as_deref())?;
+            embed_watermark(&mut gif, &id, &key, strength, dither)?;
+            reassemble_gif(&files[2], &mut gif, None, input, backup)?;
+            info!("Watermarked GIF saved to {}", files[2]);
+        }
+        Some("verify") if files.len() == 2 => {
+            let detection = verify_watermark(&read_marked_image(&files[1])?, &id, &key)?;
+            println!(
+                "{}: watermark {} {} (score {:.2})",
+                files[1],
+                id,
+                if detection.found {
+                    "found"
+                } else {
+                    "not found"
+                },
+                detection.score
+            );
+        }
+        _ => usage(),
+    }
+    Ok(())
+}
+
+// A GIF, or a still image a watermarked GIF may have been converted to
+fn read_marked_image(path: &str) -> Result<GIF, Error> {
+    let mut bytes = Vec::new();
+    open_input(path)?.read_to_end(&mut bytes)?;
+    match bytes.starts_with(b"GIF") {
+        true => Ok(parse_gif_bytes(&bytes)?),
+        false => still_to_gif(&bytes, Dither::None),
+    }
+}
+
+// One recipient a line. Blank lines and lines starting with # are skipped.
+fn read_recipients(path: &str) -> Result<Vec<String>, Error> {
+    let mut recipients: Vec<String> = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let recipient = line.trim();
+        if recipient.is_empty() || recipient.starts_with('#') {
+            continue;
+        }
+        if recipients.iter().any(|known| known == recipient) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is listed twice, their copies couldn't be told apart.",
+                    recipient
+                ),
+            ));
+        }
+        recipients.push(recipient.to_string());
+    }
+    if recipients.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} lists no recipients.", path),
+        ));
+    }
+    Ok(recipients)
+}
+
+// gifsauce fingerprint <in.gif|-> --recipients FILE [-o PATTERN] [--key KEY]
+//                      [--strength LEVELS] [--dither MODE] [--backup]
+fn fingerprint_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut recipients = None;
+    let mut pattern = None;
+    let mut key = String::new();
+    let mut strength = 4;
+    let mut dither = Dither::FloydSteinberg;
+    let mut backup = false;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--recipients" => recipients = Some(option_value(&mut args_iter, arg)),
+            "-o" => pattern = Some(option_value(&mut args_iter, arg)),
+            "--key" => key = option_value(&mut args_iter, arg),
+            "--strength" => match option_value(&mut args_iter, arg).parse::<u8>() {
+                Ok(levels) if levels > 0 => strength = levels,
+                _ => {
+                    error!("--strength expects a number of levels from 1 to 255");
+                    exit(1);
+                }
+            },
+            "--dither" => dither = dither_option(&mut args_iter, arg),
+            "--backup" => backup = true,
+            _ => files.push(arg.clone()),
+        }
+    }
+    let recipients = match recipients {
+        Some(recipients) if files.len() == 1 => read_recipients(&recipients)?,
+        _ => {
+            eprintln!("Usage: fingerprint <in.gif|-> --recipients FILE [-o PATTERN] [--key KEY] [--strength LEVELS] [--dither none|ordered|floyd-steinberg] [--backup]");
+            exit(1);
+        }
+    };
+
+    // Copies are named after their recipient in place of %s, next to the
+    // input by default
+    let pattern = pattern.unwrap_or_else(|| {
+        let input = Path::new(&files[0]);
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        input
+            .with_file_name(format!("{}_%s.gif", stem))
+            .to_string_lossy()
+            .into_owned()
+    });
+    if !pattern.contains("%s") {
+        error!("-o expects a pattern with %s for the recipient");
+        exit(1);
+    }
+
+    // Each copy starts from its own parse of the input
+    let mut bytes = Vec::new();
+    open_input(&files[0])?.read_to_end(&mut bytes)?;
+    for recipient in &recipients {
+        let name: String = recipient
+            .chars()
+            .map(|c| match c.is_alphanumeric() || c == '-' || c == '.' {
+                true => c,
+                false => '_',
+            })
+            .collect();
+        let path = pattern.replace("%s", &name);
+        let mut copy = parse_gif_bytes(&bytes)?;
+        filter_input_applications(&mut copy);
+        embed_watermark(&mut copy, recipient, &key, strength, dither)?;
+        reassemble_gif(&path, &mut copy, None, audit_input(&bytes), backup)?;
+        info!("Copy for {} saved to {}", recipient, path);
+    }
+    Ok(())
+}
+
+// gifsauce trace <image|-> --recipients FILE [--key KEY]
+fn trace_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut recipients = None;
+    let mut key = String::new();
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--recipients" => recipients = Some(option_value(&mut args_iter, arg)),
+            "--key" => key = option_value(&mut args_iter, arg),
+            _ => files.push(arg.clone()),
+        }
+    }
+    let recipients = match recipients {
+        Some(recipients) if files.len() == 1 => read_recipients(&recipients)?,
+        _ => {
+            eprintln!("Usage: trace <image.gif|jpg|png|webp|-> --recipients FILE [--key KEY]");
+            exit(1);
+        }
+    };
+
+    let residuals = cell_residuals(&read_marked_image(&files[0])?)?;
+    let mut detections: Vec<(&String, Detection)> = recipients
+        .iter()
+        .map(|recipient| (recipient, detect_watermark(&residuals, recipient, &key)))
+        .collect();
+    detections.sort_by(|a, b| b.1.score.total_cmp(&a.1.score));
+    for (recipient, detection) in &detections {
+        debug!("{}: score {:.2}", recipient, detection.score);
+    }
+
+    let (recipient, best) = &detections[0];
+    if !best.found {
+        println!(
+            "{}: no recipient's watermark found (closest {}, score {:.2})",
+            files[0], recipient, best.score
+        );
+        return Ok(());
+    }
+    // Copies mixed together can carry more than one
+    let others = detections[1..]
+        .iter()
+        .filter(|(_, detection)| detection.found)
+        .count();
+    if others > 0 {
+        warn!(
+            "{} more recipient(s) also match, the copy may be made from several",
+            others
+        );
+    }
+    println!(
+        "{}: copy of {} (score {:.2})",
+        files[0], recipient, best.score
+    );
+    Ok(())
+}
+
+// gifsauce serve [--listen ADDR]
+fn serve_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut listen = "127.0.0.1:8080".to_string();
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match (arg.as_str(), args_iter.next()) {
+            ("--listen", Some(address)) => listen = address.clone(),
+            _ => {
+                eprintln!("Usage: serve [--listen ADDR]");
+                exit(1);
+            }
+        }
+    }
+    Ok(serve(&listen)?)
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(command) = args.get(1) {
+        match command.as_str() {
+            "embed" => return embed_command(&args[2..]),
+            "extract" => return extract_command(&args[2..]),
+            "detect" => return detect_command(&args[2..]),
+            "rekey" => return rekey_command(&args[2..]),
+            "inspect" => return inspect_command(&args[2..]),
+            "analyze" => return analyze_command(&args[2..]),
+            "sanitize" => return sanitize_command(&args[2..]),
+            "comment" => return comment_command(&args[2..]),
+            "xmp" => return xmp_command(&args[2..]),
+            "frame" => return frame_command(&args[2..]),
+            "reverse" => return reverse_command(&args[2..]),
+            "split" => return split_command(&args[2..]),
+            "transform" => return transform_command(&args[2..]),
+            "optimize" => return optimize_command(&args[2..]),
+            "palette" => return palette_command(&args[2..]),
+            "render-text" => return render_text_command(&args[2..]),
+            "caption" => return caption_command(&args[2..]),
+            "preview" => return preview_command(&args[2..]),
+            "sheet" => return sheet_command(&args[2..]),
+            "convert" => return convert_command(&args[2..]),
+            "carrier" => return carrier_command(&args[2..]),
+            "polyglot" => return polyglot_command(&args[2..]),
+            "watermark" => return watermark_command(&args[2..]),
+            "fingerprint" => return fingerprint_command(&args[2..]),
+            "trace" => return trace_command(&args[2..]),
+            "serve" => return serve_command(&args[2..]),
+            _ => {}
+        }
+    }
+
+    let mut input_file = None;
+    let mut output_file = None;
+
+    let mut args_iter = args.iter().skip(1); // Skip the program name
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "-i" => {
+                if let Some(file) = args_iter.next() {
+                    input_file = Some(file.clone());
+                } else {
+                    error!("Expected orignal carrier file after -i");
+                    std::process::exit(1);
+                }
+            }
+            "-o" => {
+                if let Some(file) = args_iter.next() {
+                    output_file = Some(file.clone());
+                } else {
+                    error!("Expected stegged filename file after -o");
+                    std::process::exit(1);
+                }
+            }
+            "-d" => {
+                for file in args_iter.by_ref() {
+                    decode_file(file, &ExtractOptions::default())?;
+                }
+                exit(0)
+            }
+            _ => {
+                error!("Unknown argument: {}", arg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if input_file.is_none() {
+        error!("Original carrier is required. Use -i <input_file>");
+        std::process::exit(1);
+    }
+
+    if output_file.is_none() {
+        error!("Stegged filename is required unless -d is used. Use -o <output_file>");
+        std::process::exit(1);
+    }
+
+    let filename = input_file.unwrap();
+    let output_file = output_file.unwrap_or_default();
 
+    embed_file(&filename, &output_file, &EmbedOptions::default())?;
     Ok(())
 }
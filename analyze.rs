@@ -0,0 +1,467 @@
+use std::fmt::Write;
+use std::io::{self, Error};
+
+use crate::frames::is_loop_extension;
+use crate::gif::{KnownApplication, TrailingKind, GIF};
+use crate::polyglot::{find_zip, ZipArchive};
+
+// Statistics steganalysis tools look at. Pixels are counted as stored, frame
+// after frame, leaving out transparent ones. The LSB test is the chi-square
+// attack of Westfeld and Pfitzmann on palette indices: embedding random bits
+// in the low bit of indices evens out the counts of 2k and 2k+1, so a p close
+// to 1 points at embedding. As embedding starts at the first pixel, the test
+// is also run on the first part of the pixels alone.
+
+// Share of the pixels the LSB test is also run on
+const PREFIXES: [usize; 3] = [10, 25, 50];
+
+// p from the LSB test above which the pixels look embedded in
+const LSB_SUSPICIOUS: f64 = 0.95;
+
+// Metadata longer than this with more bits per byte than ENTROPY_SUSPICIOUS
+// looks compressed or encrypted. Text stays under 5 bits, base64 under 6.
+const ENTROPY_MINIMUM_LENGTH: usize = 256;
+const ENTROPY_SUSPICIOUS: f64 = 7.0;
+
+// Colors this close in every component count as a near-identical pair
+const CLOSE_COLORS: u8 = 4;
+
+pub struct LsbTest {
+    pub chi_square: f64,
+    pub degrees_of_freedom: usize,
+    pub p: f64,
+}
+
+pub struct TableUsage {
+    pub name: String,
+    pub colors: Vec<[u8; 3]>,
+    pub counts: Vec<u64>,   // Pixels using each entry
+    pub close_pairs: usize, // Entries 2k and 2k+1 of near-identical colors
+}
+
+pub struct Analysis {
+    pub pixels: usize,
+    pub entropy: [f64; 4], // Red, green, blue and palette index, bits a pixel
+    pub ones: f64,         // Share of indices with the low bit set
+    pub lsb: Option<LsbTest>,
+    pub lsb_prefixes: Vec<(usize, Option<LsbTest>)>, // Percent of the pixels, test
+    pub tables: Vec<TableUsage>,
+    pub metadata: Vec<(String, usize, f64)>, // Block, length, bits a byte
+    pub zip: Option<ZipArchive>,             // A ZIP archive after the trailer
+    pub findings: Vec<String>,
+}
+
+// Shannon entropy of a histogram, in bits
+fn entropy(counts: &[u64]) -> f64 {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let share = count as f64 / total as f64;
+            -share * share.log2()
+        })
+        .sum()
+}
+
+fn byte_entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0u64; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    entropy(&counts)
+}
+
+// ln Γ(x) for x > 0, Lanczos approximation with g = 7
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+// The upper regularized incomplete gamma function Q(a, x), by its series
+// below a + 1 and its continued fraction above
+fn upper_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut term, mut sum) = (1.0 / a, 1.0 / a);
+        for n in 1..1000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        return (1.0 - sum * front).clamp(0.0, 1.0);
+    }
+    // Lentz's method
+    let tiny = 1e-300;
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / tiny;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for i in 1..1000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < tiny {
+            d = tiny;
+        }
+        c = b + an / c;
+        if c.abs() < tiny {
+            c = tiny;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    (front * h).clamp(0.0, 1.0)
+}
+
+// The chi-square attack on a histogram of indices. Pairs seen fewer than
+// ten times are too thin to count. None when no two pairs are left.
+fn lsb_test(counts: &[u64; 256]) -> Option<LsbTest> {
+    let mut chi_square = 0.0;
+    let mut pairs = 0;
+    for pair in counts.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected < 5.0 {
+            continue;
+        }
+        chi_square += (pair[0] as f64 - expected).powi(2) / expected;
+        pairs += 1;
+    }
+    if pairs < 2 {
+        return None;
+    }
+    let degrees_of_freedom = pairs - 1;
+    Some(LsbTest {
+        chi_square,
+        degrees_of_freedom,
+        p: upper_gamma(degrees_of_freedom as f64 / 2.0, chi_square / 2.0),
+    })
+}
+
+pub fn analyze_gif(gif: &GIF) -> Result<Analysis, Error> {
+    let mut channels = [[0u64; 256]; 4];
+    let mut indices = Vec::new();
+    let mut tables = Vec::new();
+    let mut global_counts = vec![0u64; 256];
+
+    for (number, frame) in gif.image_descriptors.iter().enumerate() {
+        let (colors, counts) = match frame.local_color_table {
+            Some(ref table) => {
+                tables.push(TableUsage {
+                    name: format!("Frame {} local color table", number),
+                    colors: table.colors.clone(),
+                    counts: vec![0; 256],
+                    close_pairs: 0,
+                });
+                let usage = tables.last_mut().unwrap();
+                (&table.colors, &mut usage.counts)
+            }
+            None => match gif.global_color_table {
+                Some(ref table) => (&table.colors, &mut global_counts),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Frame {} has no color table.", number),
+                    ))
+                }
+            },
+        };
+        let transparent = frame.transparent_color_index();
+        for &index in &frame.image_data {
+            if Some(index) == transparent {
+                continue;
+            }
+            counts[index as usize] += 1;
+            let color = colors.get(index as usize).copied().unwrap_or([0; 3]);
+            for (channel, value) in color.iter().enumerate() {
+                channels[channel][*value as usize] += 1;
+            }
+            channels[3][index as usize] += 1;
+            indices.push(index);
+        }
+    }
+    if let Some(ref table) = gif.global_color_table {
+        tables.insert(
+            0,
+            TableUsage {
+                name: "Global color table".to_string(),
+                colors: table.colors.clone(),
+                counts: global_counts,
+                close_pairs: 0,
+            },
+        );
+    }
+    for table in &mut tables {
+        table.counts.truncate(table.colors.len());
+        table.close_pairs = table
+            .colors
+            .chunks_exact(2)
+            .filter(|pair| (0..3).all(|c| pair[0][c].abs_diff(pair[1][c]) <= CLOSE_COLORS))
+            .count();
+    }
+
+    let histogram = |indices: &[u8]| {
+        let mut counts = [0u64; 256];
+        for &index in indices {
+            counts[index as usize] += 1;
+        }
+        counts
+    };
+    let lsb = lsb_test(&histogram(&indices));
+    let lsb_prefixes = PREFIXES
+        .iter()
+        .map(|&percent| {
+            let end = indices.len() * percent / 100;
+            (percent, lsb_test(&histogram(&indices[..end])))
+        })
+        .collect();
+
+    // Comments and plain text are taken together, as payloads are spread
+    // over many of them
+    let mut metadata = Vec::new();
+    let comments: Vec<u8> = gif
+        .comment_extensions
+        .iter()
+        .flat_map(|comment| comment.comments.concat())
+        .collect();
+    if !gif.comment_extensions.is_empty() {
+        let name = format!("{} comment(s)", gif.comment_extensions.len());
+        metadata.push((name, comments.len(), byte_entropy(&comments)));
+    }
+    for application in &gif.application_extensions {
+        if is_loop_extension(application) {
+            continue;
+        }
+        // The trailer after an XMP packet has every byte value once
+        let data = match application.known() {
+            Some(KnownApplication::Xmp(packet)) => packet.xml.into_bytes(),
+            _ => application.data.clone(),
+        };
+        metadata.push((
+            format!(
+                "Application extension {}{}",
+                application.identifier.escape_debug(),
+                application.authentication_code.escape_debug()
+            ),
+            data.len(),
+            byte_entropy(&data),
+        ));
+    }
+    let plain_text: Vec<u8> = gif
+        .plain_text_extensions
+        .iter()
+        .flat_map(|plain_text| plain_text.plain_text_data.iter().copied())
+        .collect();
+    if !gif.plain_text_extensions.is_empty() {
+        let name = format!("{} plain text block(s)", gif.plain_text_extensions.len());
+        metadata.push((name, plain_text.len(), byte_entropy(&plain_text)));
+    }
+    if !gif.trailing_data.is_empty() {
+        metadata.push((
+            "Data after the trailer".to_string(),
+            gif.trailing_data.len(),
+            byte_entropy(&gif.trailing_data),
+        ));
+    }
+
+    let ones = match indices.len() {
+        0 => 0.0,
+        total => indices.iter().filter(|&&index| index & 1 == 1).count() as f64 / total as f64,
+    };
+    let mut analysis = Analysis {
+        pixels: indices.len(),
+        entropy: channels.map(|counts| entropy(&counts)),
+        ones,
+        lsb,
+        lsb_prefixes,
+        tables,
+        metadata,
+        zip: find_zip(&gif.trailing_data),
+        findings: Vec::new(),
+    };
+    analysis.findings = findings(&analysis);
+    for application in &gif.application_extensions {
+        // Known ones holding something else than they should count as unknown
+        if application.known().is_none() {
+            analysis.findings.push(format!(
+                "Application extension {}{} is none that players or editors write",
+                application.identifier.escape_debug(),
+                application.authentication_code.escape_debug()
+            ));
+        }
+    }
+    for segment in gif.trailing_segments() {
+        if let TrailingKind::Gif { frames } = segment.kind {
+            analysis.findings.push(format!(
+                "A second GIF of {} frame(s) follows the trailer, which decoders never show",
+                frames
+            ));
+        }
+    }
+    match analysis.zip {
+        Some(ref zip) if zip.zip64 => analysis.findings.push(
+            "A ZIP64 archive follows the trailer, the file is a GIF/ZIP polyglot".to_string(),
+        ),
+        Some(ref zip) => analysis.findings.push(format!(
+            "A ZIP archive of {} entries follows the trailer, the file is a GIF/ZIP polyglot",
+            zip.entries
+        )),
+        None if !gif.trailing_data.is_empty() => analysis.findings.push(format!(
+            "{} byte(s) follow the trailer, where no decoder looks",
+            gif.trailing_data.len()
+        )),
+        None => {}
+    }
+    Ok(analysis)
+}
+
+// What would make a steganalysis tool look closer
+fn findings(analysis: &Analysis) -> Vec<String> {
+    let mut findings = Vec::new();
+    let suspicious = |test: &Option<LsbTest>| test.as_ref().is_some_and(|t| t.p > LSB_SUSPICIOUS);
+    if suspicious(&analysis.lsb) {
+        findings.push(
+            "Index pairs are evened out across the image, as LSB embedding leaves them".to_string(),
+        );
+    } else if let Some((percent, _)) = analysis
+        .lsb_prefixes
+        .iter()
+        .find(|(_, test)| suspicious(test))
+    {
+        findings.push(format!(
+            "Index pairs are evened out in the first {}% of the pixels, as LSB embedding leaves them",
+            percent
+        ));
+    }
+    for table in &analysis.tables {
+        let pairs = table.colors.len() / 2;
+        if table.close_pairs >= 4 && table.close_pairs * 4 >= pairs {
+            findings.push(format!(
+                "{} has {} of {} entry pairs in near-identical colors, as palette embedding makes them",
+                table.name, table.close_pairs, pairs
+            ));
+        }
+    }
+    for (name, length, entropy) in &analysis.metadata {
+        if *length >= ENTROPY_MINIMUM_LENGTH && *entropy > ENTROPY_SUSPICIOUS {
+            findings.push(format!(
+                "{}: {} bytes at {:.2} bits a byte, which looks compressed or encrypted",
+                name, length, entropy
+            ));
+        }
+    }
+    findings
+}
+
+fn format_test(test: &Option<LsbTest>) -> String {
+    match test {
+        Some(test) => format!(
+            "chi-square {:.2}, {} degrees of freedom, p = {:.4}",
+            test.chi_square, test.degrees_of_freedom, test.p
+        ),
+        None => "too few pixels".to_string(),
+    }
+}
+
+// The analysis as text. Histograms of every palette entry are left out
+// unless asked for.
+pub fn format_analysis(analysis: &Analysis, histograms: bool) -> String {
+    let mut output = String::new();
+    let [red, green, blue, index] = analysis.entropy;
+    let _ = writeln!(output, "Pixels: {}", analysis.pixels);
+    let _ = writeln!(
+        output,
+        "Entropy (bits): red {:.3}, green {:.3}, blue {:.3}, index {:.3}",
+        red, green, blue, index
+    );
+    let _ = writeln!(output, "Index low bits set: {:.2}%", analysis.ones * 100.0);
+    let _ = writeln!(output, "LSB test: {}", format_test(&analysis.lsb));
+    for (percent, test) in &analysis.lsb_prefixes {
+        let _ = writeln!(output, "  first {:>2}%: {}", percent, format_test(test));
+    }
+
+    for table in &analysis.tables {
+        let used = table.counts.iter().filter(|&&count| count > 0).count();
+        let _ = writeln!(
+            output,
+            "{}: {} of {} entries used, {} near-identical pairs",
+            table.name,
+            used,
+            table.colors.len(),
+            table.close_pairs
+        );
+        if !histograms {
+            continue;
+        }
+        let most = table.counts.iter().copied().max().unwrap_or(0).max(1);
+        for (entry, (color, count)) in table.colors.iter().zip(&table.counts).enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let _ = writeln!(
+                output,
+                "  {:>3} #{:02x}{:02x}{:02x} {:>9} {}",
+                entry,
+                color[0],
+                color[1],
+                color[2],
+                count,
+                "#".repeat(((*count * 40).div_ceil(most)) as usize)
+            );
+        }
+    }
+
+    for (name, length, entropy) in &analysis.metadata {
+        let _ = writeln!(
+            output,
+            "{}: {} bytes, {:.3} bits a byte",
+            name, length, entropy
+        );
+    }
+
+    match analysis.findings.len() {
+        0 => output.push_str("Nothing looks suspicious\n"),
+        _ => {
+            output.push_str("Suspicious:\n");
+            for finding in &analysis.findings {
+                let _ = writeln!(output, "  {}", finding);
+            }
+        }
+    }
+    output
+}
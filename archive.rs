@@ -0,0 +1,203 @@
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Component, Path, PathBuf};
+
+use crate::metadata::{file_metadata, read_metadata, write_metadata, PayloadMetadata};
+use crate::payload::Payload;
+
+// Several files travel as one payload whose MIME type marks it as an archive:
+//
+//   entry count (4) | metadata record per entry | entry data, in index order
+//
+// Entry names are relative paths separated by '/'.
+pub const ARCHIVE_MIME_TYPE: &str = "application/x-gifsauce-archive";
+
+fn join_name(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+// Walk an input, descending into directories in name order
+fn collect_files(
+    path: &Path,
+    name: String,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), Error> {
+    if !path.is_dir() {
+        files.push((name, path.to_path_buf()));
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        let entry_name = entry
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        collect_files(&entry, join_name(&name, &entry_name), files)?;
+    }
+    Ok(())
+}
+
+fn input_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Read the files to embed. A single file is embedded as is, anything else is
+// packed into an archive.
+pub fn read_input_files(paths: &[String]) -> Result<Payload, Error> {
+    if paths.len() == 1 && !Path::new(&paths[0]).is_dir() {
+        let path = Path::new(&paths[0]);
+        let data = fs::read(path)?;
+        return Ok(Payload {
+            metadata: file_metadata(path, &data),
+            data,
+        });
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        collect_files(path, input_name(path), &mut files)?;
+    }
+
+    let mut entries = Vec::with_capacity(files.len());
+    for (name, path) in files {
+        let data = fs::read(&path)?;
+        let mut metadata = file_metadata(&path, &data);
+        metadata.name = name;
+        entries.push(Payload { metadata, data });
+    }
+
+    // A lone directory keeps its name, loose files have none in common
+    let name = match paths {
+        [path] => input_name(Path::new(path)),
+        _ => String::new(),
+    };
+    Ok(pack_archive(name, &entries))
+}
+
+pub fn pack_archive(name: String, entries: &[Payload]) -> Payload {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for entry in entries {
+        data.extend_from_slice(&write_metadata(&entry.metadata));
+    }
+    for entry in entries {
+        data.extend_from_slice(&entry.data);
+    }
+
+    Payload {
+        metadata: PayloadMetadata {
+            name,
+            mime_type: ARCHIVE_MIME_TYPE.to_string(),
+            size: data.len() as u64,
+            modified: 0,
+            expires: 0,
+        },
+        data,
+    }
+}
+
+// The payload with every modification time cleared, archive entries included,
+// for output that doesn't change with the files' timestamps
+pub fn clear_modified_times(payload: Payload) -> Result<Payload, Error> {
+    if payload.metadata.mime_type != ARCHIVE_MIME_TYPE {
+        return Ok(Payload {
+            metadata: PayloadMetadata {
+                modified: 0,
+                ..payload.metadata
+            },
+            data: payload.data,
+        });
+    }
+    let name = payload.metadata.name.clone();
+    let mut entries = payload_entries(payload)?;
+    for entry in &mut entries {
+        entry.metadata.modified = 0;
+    }
+    Ok(pack_archive(name, &entries))
+}
+
+// The files held by a payload: the entries of an archive, or the payload itself
+pub fn payload_entries(payload: Payload) -> Result<Vec<Payload>, Error> {
+    if payload.metadata.mime_type != ARCHIVE_MIME_TYPE {
+        return Ok(vec![payload]);
+    }
+
+    let truncated = || io::Error::new(io::ErrorKind::InvalidData, "Archive is truncated.");
+    let data = &payload.data;
+    let mut count = [0; 4];
+    count.copy_from_slice(data.get(0..4).ok_or_else(truncated)?);
+    let count = u32::from_le_bytes(count) as usize;
+
+    let mut metadata = Vec::new();
+    let mut rest = &data[4..];
+    for _ in 0..count {
+        let (entry, remaining) = read_metadata(rest)?;
+        metadata.push(entry);
+        rest = remaining;
+    }
+
+    let mut entries = Vec::new();
+    for entry in metadata {
+        let size = entry.size as usize;
+        let contents = rest.get(..size).ok_or_else(truncated)?;
+        rest = &rest[size..];
+        entries.push(Payload {
+            metadata: entry,
+            data: contents.to_vec(),
+        });
+    }
+    Ok(entries)
+}
+
+// Archive entries read from stdin have no name of their own. They get this
+// one, numbered from the second on: stdin, stdin-2, stdin-3...
+const STDIN_NAME: &str = "stdin";
+
+pub fn name_stdin_entries(entries: &mut [Payload]) {
+    let mut number = 0;
+    for index in 0..entries.len() {
+        if !entries[index].metadata.name.is_empty() {
+            continue;
+        }
+        let name = loop {
+            number += 1;
+            let name = match number {
+                1 => STDIN_NAME.to_string(),
+                _ => format!("{}-{}", STDIN_NAME, number),
+            };
+            if !entries.iter().any(|entry| entry.metadata.name == name) {
+                break name;
+            }
+        };
+        entries[index].metadata.name = name;
+    }
+}
+
+// Where an entry is written below the output directory. Names that are
+// absolute or climb out with ".." are refused.
+pub fn entry_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
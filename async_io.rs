@@ -0,0 +1,40 @@
+use std::io::{self, Error};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::gif::{write_gif_bytes, GifParser, GIF};
+
+// parse_gif for async readers, e.g. an upload streaming into a web service.
+// Only the reads wait on the runtime; blocks are decoded on the calling task
+// as they complete.
+pub async fn parse_gif_async<R: AsyncRead + Unpin>(mut reader: R) -> Result<GIF, Error> {
+    let mut parser = GifParser::new();
+    let mut buffer = Vec::new();
+    while !parser.is_done() {
+        buffer.resize(parser.wanted(), 0);
+        let count = match reader.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        parser.push(&buffer[..count])?;
+    }
+    if parser.is_done() {
+        buffer.clear();
+        reader.read_to_end(&mut buffer).await?;
+        parser.push(&buffer)?;
+    }
+    Ok(parser.finish()?)
+}
+
+// Serialize a GIF to an async writer. The GIF is assembled in memory first,
+// with source as for write_gif, so the writer only sees whole buffers.
+pub async fn reassemble_gif_async<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    gif: &GIF,
+    source: Option<&[u8]>,
+) -> Result<(), Error> {
+    writer.write_all(&write_gif_bytes(gif, source)).await?;
+    writer.flush().await
+}
@@ -0,0 +1,193 @@
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::io::{self, Error};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::channels::APPLICATION_IDENTIFIER;
+use crate::gif::{ApplicationExtension, GIF};
+use crate::hex;
+use crate::metadata::format_time;
+use crate::output::STRICT;
+
+// With --audit every GIF written gets a record of the operation added to an
+// application extension of its own, which so holds the file's history,
+// oldest first. Each record is:
+//
+//   version (1) | time (8, little endian) | tool length (1) | tool
+//   | operation length (1) | operation | input (8) | previous (8)
+//
+// The time is in seconds since the Unix epoch. input is the start of the
+// SHA-256 of the GIF the operation read, zeros when it read none, and
+// previous the start of the SHA-256 of the record before, zeros for the
+// first. A record changed or taken out breaks the link of the one after it.
+const AUDIT_AUTHENTICATION_CODE: &str = "AUD";
+const AUDIT_VERSION: u8 = 1;
+pub const HASH_SIZE: usize = 8;
+
+pub static AUDIT: AtomicBool = AtomicBool::new(false);
+
+// The command being run
+static OPERATION: Mutex<String> = Mutex::new(String::new());
+
+pub struct AuditRecord {
+    pub time: i64,
+    pub tool: String,
+    pub operation: String,
+    pub input: [u8; HASH_SIZE],
+    pub previous: [u8; HASH_SIZE],
+    pub hash: [u8; HASH_SIZE], // Of the record itself, what the next one links to
+}
+
+fn short_hash(bytes: &[u8]) -> [u8; HASH_SIZE] {
+    Sha256::digest(bytes)[..HASH_SIZE].try_into().unwrap()
+}
+
+pub fn set_operation(operation: &str) {
+    *OPERATION.lock().unwrap() = operation.to_string();
+}
+
+// What the record of an operation on the GIF `bytes` names as its input.
+// None without --audit, so nothing is hashed for nothing.
+pub fn audit_input(bytes: &[u8]) -> Option<[u8; HASH_SIZE]> {
+    AUDIT.load(Ordering::Relaxed).then(|| short_hash(bytes))
+}
+
+fn is_audit_trail(application: &ApplicationExtension) -> bool {
+    application.identifier == APPLICATION_IDENTIFIER
+        && application.authentication_code == AUDIT_AUTHENTICATION_CODE
+}
+
+// The records of the audit trail, none when the GIF has no trail
+pub fn read_audit_trail(gif: &GIF) -> Result<Vec<AuditRecord>, Error> {
+    let data = match gif
+        .application_extensions
+        .iter()
+        .find(|a| is_audit_trail(a))
+    {
+        Some(application) => &application.data[..],
+        None => return Ok(Vec::new()),
+    };
+    let damaged = |count: usize| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The audit trail is damaged after record {}.", count),
+        )
+    };
+
+    let mut records = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let start = position;
+        let mut take = |count: usize| {
+            let field = data.get(position..position + count);
+            position += count;
+            field.ok_or_else(|| damaged(records.len()))
+        };
+        if take(1)?[0] != AUDIT_VERSION {
+            return Err(damaged(records.len()));
+        }
+        let time = i64::from_le_bytes(take(8)?.try_into().unwrap());
+        let length = take(1)?[0] as usize;
+        let tool = String::from_utf8_lossy(take(length)?).into_owned();
+        let length = take(1)?[0] as usize;
+        let operation = String::from_utf8_lossy(take(length)?).into_owned();
+        let input = take(HASH_SIZE)?.try_into().unwrap();
+        let previous = take(HASH_SIZE)?.try_into().unwrap();
+        records.push(AuditRecord {
+            time,
+            tool,
+            operation,
+            input,
+            previous,
+            hash: short_hash(&data[start..position]),
+        });
+    }
+    Ok(records)
+}
+
+// Add a record of this run to the GIF's audit trail, when --audit is on.
+// `input` is from audit_input, of the GIF the operation made this one from.
+pub fn record_operation(gif: &mut GIF, input: Option<[u8; HASH_SIZE]>) {
+    if !AUDIT.load(Ordering::Relaxed) {
+        return;
+    }
+    if STRICT.load(Ordering::Relaxed) && gif.output_version() == *b"87a" {
+        warn!(
+            "Not recording the operation, --strict keeps the GIF87a file from holding extensions"
+        );
+        return;
+    }
+    // A damaged trail is kept, the new record's link to it broken
+    let previous = match read_audit_trail(gif) {
+        Ok(records) => records.last().map_or([0; HASH_SIZE], |record| record.hash),
+        Err(e) => {
+            warn!("{} The new record can't link to it.", e);
+            [0; HASH_SIZE]
+        }
+    };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let tool = format!("GifSauce {}", env!("CARGO_PKG_VERSION"));
+    let operation = OPERATION.lock().unwrap().clone();
+
+    let mut record = vec![AUDIT_VERSION];
+    record.extend_from_slice(&time.to_le_bytes());
+    for text in [&tool, &operation] {
+        let text = &text.as_bytes()[..text.len().min(255)];
+        record.push(text.len() as u8);
+        record.extend_from_slice(text);
+    }
+    record.extend_from_slice(&input.unwrap_or([0; HASH_SIZE]));
+    record.extend_from_slice(&previous);
+
+    match gif
+        .application_extensions
+        .iter_mut()
+        .find(|application| is_audit_trail(application))
+    {
+        Some(application) => application.data.extend_from_slice(&record),
+        None => gif.application_extensions.push(ApplicationExtension {
+            identifier: APPLICATION_IDENTIFIER.to_string(),
+            authentication_code: AUDIT_AUTHENTICATION_CODE.to_string(),
+            data: record,
+        }),
+    }
+}
+
+// The trail as a table, each record marked by whether it links to the one
+// before
+pub fn format_history(records: &[AuditRecord]) -> String {
+    if records.is_empty() {
+        return "No audit trail\n".to_string();
+    }
+    let mut output = format!(
+        "{:>4}  {:<23}  {:<16}  {:<12}  {:<16}  link\n",
+        "#", "time", "tool", "operation", "input"
+    );
+    for (index, record) in records.iter().enumerate() {
+        let link = match index {
+            0 if record.previous == [0; HASH_SIZE] => "first",
+            0 => "broken, records before it are missing",
+            _ if record.previous == records[index - 1].hash => "ok",
+            _ => "broken",
+        };
+        let input = match record.input {
+            [0, 0, 0, 0, 0, 0, 0, 0] => "none".to_string(),
+            input => hex(&input),
+        };
+        let _ = writeln!(
+            output,
+            "{:>4}  {:<23}  {:<16}  {:<12}  {:<16}  {}",
+            index + 1,
+            format_time(record.time),
+            record.tool,
+            record.operation,
+            input,
+            link
+        );
+    }
+    output
+}
@@ -0,0 +1,232 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Error};
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use gifsauce::channels::{lookup_channel, ChannelKind};
+use gifsauce::frames::set_aside_frames;
+use gifsauce::progress::batch_progress;
+
+use crate::gif::{parse_gif, GIF};
+use crate::{decode_file, embed_file, EmbedOptions, ExtractOptions};
+
+// Outcome of one file of a batch run
+pub struct BatchResult {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub payload: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+fn sorted_entries(dir: &Path, keep: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, Error> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|entry| keep(entry))
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+// The .gif files directly inside a directory, in name order
+pub fn gif_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    sorted_entries(dir, |entry| {
+        entry
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+    })
+}
+
+// Ranks carriers: the room left in bounded channels when every channel is
+// bounded, otherwise the pixel count, since bigger images hide more.
+fn carrier_capacity(gif: &GIF, channels: &[ChannelKind]) -> usize {
+    let bounded: Option<usize> = channels
+        .iter()
+        .map(|channel| lookup_channel(*channel).capacity(gif))
+        .sum();
+    bounded.unwrap_or_else(|| {
+        gif.image_descriptors
+            .iter()
+            .map(|image_descriptor| image_descriptor.image_data.len())
+            .sum()
+    })
+}
+
+fn run_in_pool<T: Send>(jobs: Option<usize>, work: impl FnOnce() -> T + Send) -> Result<T, Error> {
+    let mut builder = ThreadPoolBuilder::new();
+    if let Some(jobs) = jobs {
+        builder = builder.num_threads(jobs);
+    }
+    let pool = builder.build().map_err(Error::other)?;
+    Ok(pool.install(work))
+}
+
+fn output_path(out_dir: &Path, input: &Path) -> PathBuf {
+    out_dir.join(input.file_name().unwrap_or_default())
+}
+
+// Embed every file of payload_dir into its own carrier from carrier_dir. The
+// largest payloads go to the roomiest carriers.
+pub fn embed_batch(
+    carrier_dir: &Path,
+    payload_dir: &Path,
+    out_dir: &Path,
+    options: &EmbedOptions,
+    jobs: Option<usize>,
+) -> Result<Vec<BatchResult>, Error> {
+    fs::create_dir_all(out_dir)?;
+    let carriers = gif_files(carrier_dir)?;
+    let payloads = sorted_entries(payload_dir, |entry| entry.is_file())?;
+
+    run_in_pool(jobs, || {
+        let mut results = Vec::new();
+
+        // Carriers that can't be read are reported and left out of the pairing
+        let mut ranked: Vec<(PathBuf, usize)> = Vec::new();
+        let parsed: Vec<(PathBuf, Result<usize, String>)> = carriers
+            .into_par_iter()
+            .map(|carrier| {
+                let capacity = File::open(&carrier)
+                    .and_then(|file| parse_gif(BufReader::new(file)))
+                    .and_then(|mut gif| {
+                        if let Some(ref selection) = options.frames {
+                            set_aside_frames(&mut gif, selection)?;
+                        }
+                        Ok(carrier_capacity(&gif, &options.channels))
+                    })
+                    .map_err(|e| e.to_string());
+                (carrier, capacity)
+            })
+            .collect();
+        for (carrier, capacity) in parsed {
+            match capacity {
+                Ok(capacity) => ranked.push((carrier, capacity)),
+                Err(error) => results.push(BatchResult {
+                    input: carrier,
+                    output: None,
+                    payload: None,
+                    error: Some(error),
+                }),
+            }
+        }
+        ranked.sort_by_key(|carrier| std::cmp::Reverse(carrier.1));
+
+        let mut sized: Vec<(PathBuf, u64)> = payloads
+            .into_iter()
+            .map(|payload| {
+                let size = fs::metadata(&payload).map(|m| m.len()).unwrap_or(0);
+                (payload, size)
+            })
+            .collect();
+        sized.sort_by_key(|payload| std::cmp::Reverse(payload.1));
+
+        for (payload, _) in sized.iter().skip(ranked.len()) {
+            results.push(BatchResult {
+                input: payload.clone(),
+                output: None,
+                payload: Some(payload.clone()),
+                error: Some("No carrier left for this payload".to_string()),
+            });
+        }
+
+        let progress = batch_progress(ranked.len().min(sized.len()));
+        progress.set_message("embed");
+        let embedded: Vec<BatchResult> = ranked
+            .par_iter()
+            .zip(sized.par_iter())
+            .map(|((carrier, _), (payload, _))| {
+                let output = output_path(out_dir, carrier);
+                let mut payload_options = options.clone();
+                payload_options.payload_files = vec![payload.to_string_lossy().into_owned()];
+                payload_options.no_progress = true;
+                let error = embed_file(
+                    &carrier.to_string_lossy(),
+                    &output.to_string_lossy(),
+                    &payload_options,
+                )
+                .err()
+                .map(|e| e.to_string());
+                progress.inc(1);
+                BatchResult {
+                    input: carrier.clone(),
+                    output: Some(output),
+                    payload: Some(payload.clone()),
+                    error,
+                }
+            })
+            .collect();
+        progress.finish_and_clear();
+        results.extend(embedded);
+        results
+    })
+}
+
+// Restore the payload of every GIF in carrier_dir into a directory of its own
+// under out_dir.
+pub fn extract_batch(
+    carrier_dir: &Path,
+    out_dir: &Path,
+    options: &ExtractOptions,
+    jobs: Option<usize>,
+) -> Result<Vec<BatchResult>, Error> {
+    let carriers = gif_files(carrier_dir)?;
+
+    run_in_pool(jobs, || {
+        let progress = batch_progress(carriers.len());
+        progress.set_message("extract");
+        let results = carriers
+            .into_par_iter()
+            .map(|carrier| {
+                let output = out_dir.join(carrier.file_stem().unwrap_or_default());
+                let mut carrier_options = options.clone();
+                carrier_options.output = Some(output.clone());
+                carrier_options.no_progress = true;
+                let error = fs::create_dir_all(&output)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| {
+                        decode_file(&carrier.to_string_lossy(), &carrier_options)
+                            .map_err(|e| e.to_string())
+                    })
+                    .err();
+                progress.inc(1);
+                BatchResult {
+                    input: carrier,
+                    output: Some(output),
+                    payload: None,
+                    error,
+                }
+            })
+            .collect();
+        progress.finish_and_clear();
+        results
+    })
+}
+
+// Print one line per file and a total. Returns whether everything succeeded.
+pub fn print_batch_report(results: &[BatchResult]) -> bool {
+    let mut failed = 0;
+    for result in results {
+        match result.error {
+            Some(ref error) => {
+                failed += 1;
+                println!("failed  {}: {}", result.input.display(), error);
+            }
+            None => match result.payload {
+                Some(ref payload) => println!(
+                    "ok      {} <- {} -> {}",
+                    result.input.display(),
+                    payload.display(),
+                    result.output.as_deref().unwrap_or(Path::new("")).display()
+                ),
+                None => println!(
+                    "ok      {} -> {}",
+                    result.input.display(),
+                    result.output.as_deref().unwrap_or(Path::new("")).display()
+                ),
+            },
+        }
+    }
+    println!("{} succeeded, {} failed", results.len() - failed, failed);
+    failed == 0
+}
@@ -0,0 +1,272 @@
+use std::io::{self, Error};
+use std::ops::Range;
+
+use crate::frames::standalone_frames;
+use crate::gif::{
+    closest_color, quantize_dithered, Dither, GraphicsControlExtension, ImageDescriptor, GIF,
+};
+use crate::plain_text::glyph_pixel;
+
+// Captions are drawn in white with a black outline, centered across the
+// screen. Frames are first made to stand alone so every one covers the
+// screen. A frame whose palette has nothing close to white or black is
+// quantized again with the caption drawn in.
+
+const FILL: [u8; 3] = [0xFF, 0xFF, 0xFF];
+const OUTLINE: [u8; 3] = [0, 0, 0];
+// How far a palette color may be from the caption colors, squared
+const CLOSE_ENOUGH: u32 = 32 * 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    Top,
+    Center,
+    Bottom,
+}
+
+pub fn parse_position(name: &str) -> Option<Position> {
+    match name {
+        "top" => Some(Position::Top),
+        "center" => Some(Position::Center),
+        "bottom" => Some(Position::Bottom),
+        _ => None,
+    }
+}
+
+// Which pixels of a rectangle the text covers
+pub struct TextMask {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>,
+}
+
+impl TextMask {
+    fn get(&self, x: isize, y: isize) -> bool {
+        x >= 0
+            && y >= 0
+            && (x as usize) < self.width
+            && (y as usize) < self.height
+            && self.pixels[y as usize * self.width + x as usize]
+    }
+}
+
+// Lines of text in the built-in font, each character cell `size` pixels high
+pub fn bitmap_text(text: &str, size: usize) -> TextMask {
+    let cell_height = size.max(8);
+    let cell_width = (cell_height * 6 / 8).max(1);
+    let lines: Vec<&[u8]> = text.lines().map(str::as_bytes).collect();
+    let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+    let width = columns * cell_width;
+    let height = lines.len() * cell_height;
+
+    let mut pixels = vec![false; width * height];
+    for (row, line) in lines.iter().enumerate() {
+        for (column, &character) in line.iter().enumerate() {
+            for y in 0..cell_height {
+                for x in 0..cell_width {
+                    if glyph_pixel(character, x, y, cell_width, cell_height) {
+                        let position = (row * cell_height + y) * width + column * cell_width + x;
+                        pixels[position] = true;
+                    }
+                }
+            }
+        }
+    }
+    TextMask {
+        width,
+        height,
+        pixels,
+    }
+}
+
+// Lines of text in a TrueType or OpenType font, `size` pixels high. Pixels
+// the glyphs cover at least half of are set.
+#[cfg(feature = "fontdue")]
+pub fn truetype_text(font: &[u8], text: &str, size: usize) -> Result<TextMask, Error> {
+    let font = fontdue::Font::from_bytes(font, fontdue::FontSettings::default())
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))?;
+    let size = size.max(1) as f32;
+    let (ascent, line_height) = font
+        .horizontal_line_metrics(size)
+        .map_or((size, size), |metrics| {
+            (metrics.ascent, metrics.new_line_size)
+        });
+    let line_height = line_height.ceil() as usize;
+
+    // Glyphs with their place, then the rectangle they all fit in
+    let mut glyphs = Vec::new();
+    for (row, line) in text.lines().enumerate() {
+        let mut pen = 0.0;
+        for character in line.chars() {
+            let (metrics, coverage) = font.rasterize(character, size);
+            let left = (pen + metrics.xmin as f32).round() as isize;
+            let top = (row * line_height) as isize + ascent.round() as isize
+                - metrics.height as isize
+                - metrics.ymin as isize;
+            glyphs.push((left, top, metrics.width, coverage));
+            pen += metrics.advance_width;
+        }
+    }
+    let width = glyphs
+        .iter()
+        .map(|(left, _, width, _)| (left + *width as isize).max(0) as usize)
+        .max()
+        .unwrap_or(0);
+    let height = text.lines().count() * line_height;
+
+    let mut pixels = vec![false; width * height];
+    for (left, top, glyph_width, coverage) in glyphs {
+        for (index, &value) in coverage.iter().enumerate() {
+            let x = left + (index % glyph_width.max(1)) as isize;
+            let y = top + (index / glyph_width.max(1)) as isize;
+            if value >= 128 && x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+                pixels[y as usize * width + x as usize] = true;
+            }
+        }
+    }
+    Ok(TextMask {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// What a pixel of the caption shows, a frame of one pixel around the text
+// giving the outline
+fn caption_color(mask: &TextMask, x: isize, y: isize) -> Option<[u8; 3]> {
+    if mask.get(x, y) {
+        return Some(FILL);
+    }
+    let outlined = (-1..=1).any(|dy| (-1..=1).any(|dx| mask.get(x + dx, y + dy)));
+    outlined.then_some(OUTLINE)
+}
+
+// Draw the caption onto a frame covering the whole screen, its top left
+// corner, outline included, at (left, top)
+fn draw_caption(
+    frame: &mut ImageDescriptor,
+    global: Option<&[[u8; 3]]>,
+    mask: &TextMask,
+    (left, top): (isize, isize),
+    dither: Dither,
+) {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let transparent_index = frame.transparent_color_index();
+    let colors: Vec<[u8; 3]> = match frame.local_color_table {
+        Some(ref table) => table.colors.clone(),
+        None => global.unwrap_or(&[]).to_vec(),
+    };
+    let mut pixels = frame.pixels_in_row_order();
+    pixels.resize(width * height, transparent_index.unwrap_or(0));
+    frame.packed_field &= !0b0100_0000; // Rows are written in order
+
+    // Caption pixels with the screen position they go to
+    let caption: Vec<(usize, [u8; 3])> = (-1..mask.height as isize + 1)
+        .flat_map(|y| (-1..mask.width as isize + 1).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            let color = caption_color(mask, x, y)?;
+            let (column, row) = (left + 1 + x, top + 1 + y);
+            let on_screen =
+                column >= 0 && row >= 0 && (column as usize) < width && (row as usize) < height;
+            on_screen.then(|| (row as usize * width + column as usize, color))
+        })
+        .collect();
+
+    // The palette's own colors when they are close enough
+    let distance = |index: u8, color: [u8; 3]| -> u32 {
+        let entry = colors.get(index as usize).copied().unwrap_or([0; 3]);
+        (0..3)
+            .map(|i| (entry[i] as i32 - color[i] as i32).unsigned_abs().pow(2))
+            .sum()
+    };
+    let fill = closest_color(&colors, FILL, transparent_index);
+    let outline = closest_color(&colors, OUTLINE, transparent_index);
+    if !colors.is_empty()
+        && distance(fill, FILL) <= CLOSE_ENOUGH
+        && distance(outline, OUTLINE) <= CLOSE_ENOUGH
+    {
+        for (position, color) in caption {
+            pixels[position] = if color == FILL { fill } else { outline };
+        }
+        frame.image_data = pixels;
+        frame.compressed_range = None;
+        return;
+    }
+
+    let mut rgba: Vec<[u8; 4]> = pixels
+        .iter()
+        .map(|&pixel| match Some(pixel) == transparent_index {
+            true => [0; 4],
+            false => {
+                let [red, green, blue] = colors.get(pixel as usize).copied().unwrap_or([0; 3]);
+                [red, green, blue, 0xFF]
+            }
+        })
+        .collect();
+    for (position, [red, green, blue]) in caption {
+        rgba[position] = [red, green, blue, 0xFF];
+    }
+    let quantized = quantize_dithered(&rgba, width, 256, dither);
+    frame.packed_field = (frame.packed_field & 0b0001_1000) | 0b1000_0000 | quantized.size_bits;
+    frame.lzw_minimum_code_size = quantized.lzw_minimum_code_size();
+    frame.local_color_table = Some(quantized.color_table);
+    frame.image_data = quantized.indices;
+    frame.compressed_range = None;
+    if let Some(transparent_index) = quantized.transparent_index {
+        let gce = frame
+            .graphics_control_extension
+            .get_or_insert(GraphicsControlExtension {
+                packed_field: 0,
+                delay_time: 0,
+                transparent_color_index: 0,
+            });
+        gce.packed_field |= 0b1;
+        gce.transparent_color_index = transparent_index;
+    } else if let Some(ref mut gce) = frame.graphics_control_extension {
+        gce.packed_field &= !0b1;
+    }
+}
+
+// Burn a caption into the selected frames, or all of them
+pub fn caption_frames(
+    gif: &mut GIF,
+    mask: &TextMask,
+    position: Position,
+    selection: Option<&[Range<usize>]>,
+    dither: Dither,
+) -> Result<usize, Error> {
+    let screen_width = gif.logical_screen_descriptor.width as isize;
+    let screen_height = gif.logical_screen_descriptor.height as isize;
+    if mask.width == 0 || mask.height == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The caption has no text.",
+        ));
+    }
+    // The caption with its outline, kept clear of the edge
+    let (width, height) = (mask.width as isize + 2, mask.height as isize + 2);
+    let margin = height / 4;
+    let left = (screen_width - width) / 2;
+    let top = match position {
+        Position::Top => margin,
+        Position::Center => (screen_height - height) / 2,
+        Position::Bottom => screen_height - height - margin,
+    };
+
+    let mut frames = standalone_frames(gif, dither)?;
+    let global = gif
+        .global_color_table
+        .as_ref()
+        .map(|table| &table.colors[..]);
+    let mut captioned = 0;
+    for (index, frame) in frames.iter_mut().enumerate() {
+        if selection.is_some_and(|ranges| !ranges.iter().any(|range| range.contains(&index))) {
+            continue;
+        }
+        draw_caption(frame, global, mask, (left, top), dither);
+        captioned += 1;
+    }
+    gif.image_descriptors = frames;
+    Ok(captioned)
+}
@@ -0,0 +1,1035 @@
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Error};
+use std::sync::RwLock;
+
+use crate::gif::{
+    ApplicationExtension, ColorTable, CommentExtension, DisposalMethod, GraphicsControlExtension,
+    ImageDescriptor, PlainTextExtension, GIF,
+};
+use crate::shuffle::{chunk_count, shuffle_chunks, whitening_rng, ChunkKey};
+
+// Identifier of the application extension used by the appext channel, and
+// of the one describing the payload
+pub const APPLICATION_IDENTIFIER: &str = "GIFSAUCE";
+pub const APPLICATION_AUTHENTICATION_CODE: &str = "DAT";
+
+// Default unkeyed chunk sizes of the chunked channels. Larger chunks are
+// written as several sub-blocks of one extension.
+const PLAIN_TEXT_CHUNK_SIZE: usize = 254;
+const COMMENT_CHUNK_SIZE: usize = 255;
+
+// Streams up to this size go whole into one extension, as two sub-blocks at
+// most, keyed or not, so a small payload never needs frames added for it
+pub const SINGLE_EXTENSION_SIZE: usize = 2 * 255;
+
+// The places inside a GIF where payload bytes can live.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelKind {
+    PlainText,
+    Comment,
+    Application,
+    Lsb,
+    Trailer,
+    Delay,
+    Background,
+    Custom(u8), // Added with register_channel, by the id files record it under
+}
+
+// Ids from here up are left to registered channels
+pub const FIRST_CUSTOM_CHANNEL_ID: u8 = 128;
+
+pub const ALL_CHANNELS: [ChannelKind; 7] = [
+    ChannelKind::PlainText,
+    ChannelKind::Comment,
+    ChannelKind::Application,
+    ChannelKind::Lsb,
+    ChannelKind::Trailer,
+    ChannelKind::Delay,
+    ChannelKind::Background,
+];
+
+impl fmt::Display for ChannelKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", lookup_channel(*self).name())
+    }
+}
+
+// How frames are added when plain text chunks outnumber them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameExpansion {
+    Duplicate,  // Copies of the carrier's frames, in order, each as big as the one copied
+    Minimal,    // 1x1 frames repeating the pixel already shown at the origin
+    TinyFrames, // 1x1 fully transparent frames
+}
+
+// Layout choices for the channels, besides the chunk key
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelOptions {
+    pub chunk_size: Option<usize>, // Overrides the default unkeyed chunk size
+    pub expansion: FrameExpansion,
+    pub manifest: bool, // Describe the payload in an application extension of its own
+    pub stealth: bool,  // Leave no marker, only the key finds the payload
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        ChannelOptions {
+            chunk_size: None,
+            expansion: FrameExpansion::Duplicate,
+            manifest: true,
+            stealth: false,
+        }
+    }
+}
+
+// A place payload bytes can live, e.g. the plain text extensions. Channels
+// made of many small extensions also take their data as chunks, which is how
+// keyed layouts shuffle them.
+pub trait Channel: Sync {
+    fn kind(&self) -> ChannelKind;
+
+    // Name used by --channels and in reports
+    fn name(&self) -> &'static str;
+
+    // Number of bytes the channel can hold, or None when it grows with the payload
+    fn capacity(&self, _gif: &GIF) -> Option<usize> {
+        None
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error>;
+
+    // The data the channel holds, None when it holds nothing
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>>;
+
+    // Unchunked channels that mix their data with the key take it here
+    fn embed_keyed(
+        &self,
+        gif: &mut GIF,
+        data: &[u8],
+        _key: Option<&ChunkKey>,
+    ) -> Result<(), Error> {
+        self.embed(gif, data)
+    }
+
+    fn extract_keyed(&self, gif: &GIF, _key: Option<&ChunkKey>) -> Option<Vec<u8>> {
+        self.extract(gif)
+    }
+
+    // Undo embed for a stream of `length` bytes
+    fn clear(&self, gif: &mut GIF, length: usize) -> Result<(), Error>;
+
+    // Default unkeyed chunk size of chunked channels, None for the others
+    fn chunk_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn embed_chunks(
+        &self,
+        gif: &mut GIF,
+        chunks: Vec<Vec<u8>>,
+        _options: &ChannelOptions,
+    ) -> Result<(), Error> {
+        self.embed(gif, &chunks.concat())
+    }
+
+    // Data of each extension of a chunked channel, in file order
+    fn chunks(&self, gif: &GIF) -> Vec<Vec<u8>> {
+        self.extract(gif).into_iter().collect()
+    }
+}
+
+// Every channel, in the order of ALL_CHANNELS
+static REGISTRY: [&dyn Channel; 7] = [
+    &PlainTextChannel,
+    &CommentChannel,
+    &ApplicationChannel,
+    &LsbChannel,
+    &TrailerChannel,
+    &DelayChannel,
+    &BackgroundChannel,
+];
+
+// Channels added at run time, in the order they were registered
+static CUSTOM_REGISTRY: RwLock<Vec<&'static dyn Channel>> = RwLock::new(Vec::new());
+
+// Add a channel of your own. Its kind must be ChannelKind::Custom with an id
+// from FIRST_CUSTOM_CHANNEL_ID up, and neither the id nor the name may be
+// taken. The id is stored in manifests, so keep it the same from run to run.
+pub fn register_channel(channel: Box<dyn Channel>) -> Result<ChannelKind, Error> {
+    let kind = channel.kind();
+    if !matches!(kind, ChannelKind::Custom(id) if id >= FIRST_CUSTOM_CHANNEL_ID) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "A registered channel's kind must be ChannelKind::Custom({}) or above.",
+                FIRST_CUSTOM_CHANNEL_ID
+            ),
+        ));
+    }
+    let mut custom = CUSTOM_REGISTRY.write().unwrap();
+    let taken = REGISTRY
+        .iter()
+        .chain(custom.iter())
+        .any(|registered| registered.kind() == kind || registered.name() == channel.name());
+    if taken {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "A channel named {} or with its id is already registered.",
+                channel.name()
+            ),
+        ));
+    }
+    custom.push(Box::leak(channel));
+    Ok(kind)
+}
+
+// The built-in channels followed by the registered ones
+pub fn all_channels() -> Vec<ChannelKind> {
+    let custom = CUSTOM_REGISTRY.read().unwrap();
+    ALL_CHANNELS
+        .iter()
+        .copied()
+        .chain(custom.iter().map(|channel| channel.kind()))
+        .collect()
+}
+
+// Custom kinds are only made by registering, parsing a registered name or
+// reading a registered id, so one that isn't found is a bug
+pub fn lookup_channel(kind: ChannelKind) -> &'static dyn Channel {
+    match kind {
+        ChannelKind::Custom(id) => *CUSTOM_REGISTRY
+            .read()
+            .unwrap()
+            .iter()
+            .find(|channel| channel.kind() == kind)
+            .unwrap_or_else(|| panic!("Channel {} isn't registered", id)),
+        _ => REGISTRY[channel_id(kind) as usize],
+    }
+}
+
+pub fn parse_channel(name: &str) -> Option<ChannelKind> {
+    let custom = CUSTOM_REGISTRY.read().unwrap();
+    REGISTRY
+        .iter()
+        .chain(custom.iter())
+        .find(|channel| channel.name() == name)
+        .map(|channel| channel.kind())
+}
+
+// Parse a comma separated list such as "plaintext,comment,lsb"
+pub fn parse_channel_list(list: &str) -> Result<Vec<ChannelKind>, Error> {
+    let mut channels = Vec::new();
+    for name in list.split(',') {
+        let channel = parse_channel(name.trim()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown channel: {}", name),
+            )
+        })?;
+        if !channels.contains(&channel) {
+            channels.push(channel);
+        }
+    }
+    Ok(channels)
+}
+
+// The channel --channels auto picked for a payload, and why
+pub struct ChannelChoice {
+    pub channel: ChannelKind,
+    pub reason: String,
+}
+
+// Channels of fixed room, most likely to survive re-processing first.
+// Optimizers and upload pipelines commonly strip comments and unknown
+// extensions and drop whatever follows the trailer, but keep pixels and
+// frame timing as long as they don't re-quantize the frames.
+const ROBUST_CHANNELS: [ChannelKind; 3] = [
+    ChannelKind::Lsb,
+    ChannelKind::Delay,
+    ChannelKind::Background,
+];
+
+// Pick the channel for a stream of `size` bytes: the most robust one with
+// room for it, or else an extension, which grows to fit
+pub fn choose_channel(gif: &GIF, size: usize) -> ChannelChoice {
+    for channel in ROBUST_CHANNELS {
+        let room = lookup_channel(channel).capacity(gif).unwrap_or(0);
+        if room >= size {
+            let place = match channel {
+                ChannelKind::Lsb => "the low bits of the carrier's pixels",
+                ChannelKind::Delay => "the carrier's frame delays",
+                _ => "the carrier's background pixels",
+            };
+            return ChannelChoice {
+                channel,
+                reason: format!(
+                    "{} bytes fit in {}, with room for {}, which outlast tools that strip extensions",
+                    size, place, room
+                ),
+            };
+        }
+    }
+
+    let lsb_room = lookup_channel(ChannelKind::Lsb).capacity(gif).unwrap_or(0);
+    // Plain text would take the carrier's frames, or add frames of its own,
+    // and the trailer is lost to any tool that rewrites the file
+    let (channel, why) = match gif.comment_extensions.len() {
+        0 => (
+            ChannelKind::Application,
+            "an application extension is kept by most tools, which hold on to the looping one, and adds no frames".to_string(),
+        ),
+        comments => (
+            ChannelKind::Comment,
+            format!(
+                "the carrier already has {} comment(s) for one more to blend in with",
+                comments
+            ),
+        ),
+    };
+    ChannelChoice {
+        channel,
+        reason: format!(
+            "{} bytes are more than the pixels hold ({} bytes); {}",
+            size, lsb_room, why
+        ),
+    }
+}
+
+// Stable numbering used when a channel has to be recorded in the file
+pub fn channel_id(channel: ChannelKind) -> u8 {
+    match channel {
+        ChannelKind::Custom(id) => id,
+        _ => ALL_CHANNELS.iter().position(|c| *c == channel).unwrap() as u8,
+    }
+}
+
+pub fn channel_from_id(id: u8) -> Option<ChannelKind> {
+    if id < FIRST_CUSTOM_CHANNEL_ID {
+        return ALL_CHANNELS.get(id as usize).cloned();
+    }
+    let custom = CUSTOM_REGISTRY.read().unwrap();
+    custom
+        .iter()
+        .map(|channel| channel.kind())
+        .find(|kind| *kind == ChannelKind::Custom(id))
+}
+
+// Returns how many blocks the data went into, one for channels that aren't
+// chunked
+pub fn embed_channel(
+    gif: &mut GIF,
+    kind: ChannelKind,
+    data: &[u8],
+    key: Option<&ChunkKey>,
+    options: &ChannelOptions,
+) -> Result<usize, Error> {
+    let channel = lookup_channel(kind);
+    let default_size = match channel.chunk_size() {
+        Some(size) => size,
+        None => return channel.embed_keyed(gif, data, key).map(|()| 1),
+    };
+    let chunks = match key {
+        Some(key) => shuffle_chunks(data, key, kind),
+        None if options.chunk_size.is_none() && data.len() <= SINGLE_EXTENSION_SIZE => {
+            vec![data.to_vec()]
+        }
+        None => data
+            .chunks(options.chunk_size.unwrap_or(default_size))
+            .map(|chunk| chunk.to_vec())
+            .collect(),
+    };
+    let count = chunks.len();
+    channel.embed_chunks(gif, chunks, options)?;
+    Ok(count)
+}
+
+// How many blocks embed_channel puts `length` bytes into
+pub fn chunks_needed(
+    kind: ChannelKind,
+    length: usize,
+    key: Option<&ChunkKey>,
+    options: &ChannelOptions,
+) -> usize {
+    let default_size = match lookup_channel(kind).chunk_size() {
+        Some(size) => size,
+        None => return 1,
+    };
+    match key {
+        Some(key) => chunk_count(length, key, kind),
+        None if options.chunk_size.is_none() && length <= SINGLE_EXTENSION_SIZE => 1,
+        None => length.div_ceil(options.chunk_size.unwrap_or(default_size)),
+    }
+}
+
+// The graphic control extension of a frame that shows nothing: its one
+// pixel, in colour `index`, is see-through and left in place for no time
+fn invisible_control(index: u8) -> GraphicsControlExtension {
+    let mut graphics_control_extension = GraphicsControlExtension {
+        packed_field: 0b1, // Transparent
+        delay_time: 0,
+        transparent_color_index: index,
+    };
+    graphics_control_extension.set_disposal_method(DisposalMethod::DoNotDispose);
+    graphics_control_extension
+}
+
+// A frame that changes nothing on screen: one pixel at the origin, with the
+// colour table and code size of the last frame covering the origin. Its pixel
+// is transparent, so whatever the frames before left there shows through.
+fn minimal_frame(gif: &GIF) -> ImageDescriptor {
+    let source = gif.image_descriptors.iter().rev().find(|image_descriptor| {
+        image_descriptor.left == 0
+            && image_descriptor.top == 0
+            && !image_descriptor.image_data.is_empty()
+    });
+
+    match source {
+        Some(source) => ImageDescriptor {
+            left: 0,
+            top: 0,
+            width: 1,
+            height: 1,
+            packed_field: source.packed_field & 0b1000_0111, // Keep only the local color table
+            graphics_control_extension: Some(invisible_control(source.image_data[0])),
+            local_color_table: source.local_color_table.clone(),
+            lzw_minimum_code_size: source.lzw_minimum_code_size,
+            image_data: vec![source.image_data[0]],
+            compressed_range: None,
+        },
+        None => {
+            let index = gif.logical_screen_descriptor.background_color_index;
+            ImageDescriptor {
+                left: 0,
+                top: 0,
+                width: 1,
+                height: 1,
+                packed_field: 0,
+                graphics_control_extension: Some(invisible_control(index)),
+                local_color_table: None,
+                lzw_minimum_code_size: 8,
+                image_data: vec![index],
+                compressed_range: None,
+            }
+        }
+    }
+}
+
+// A frame that shows nothing: one pixel at the origin in a see-through
+// colour, left in place for no time. The global color table lends it its
+// first colour; without one it brings a local table of its own.
+fn tiny_frame(gif: &GIF) -> ImageDescriptor {
+    let (packed_field, local_color_table) = match gif.global_color_table {
+        Some(_) => (0, None),
+        None => (
+            0b1000_0000, // A local color table of two colors
+            Some(ColorTable {
+                colors: vec![[0; 3]; 2],
+            }),
+        ),
+    };
+    ImageDescriptor {
+        left: 0,
+        top: 0,
+        width: 1,
+        height: 1,
+        packed_field,
+        graphics_control_extension: Some(invisible_control(0)),
+        local_color_table,
+        lzw_minimum_code_size: 2,
+        image_data: vec![0],
+        compressed_range: None,
+    }
+}
+
+// Add just enough frames for `needed` plain text extensions to each have one
+fn expand_frames(gif: &mut GIF, needed: usize, expansion: FrameExpansion) {
+    let original_frames = gif.image_descriptors.len();
+    if needed <= original_frames || original_frames == 0 {
+        return;
+    }
+
+    for index in 0..needed - original_frames {
+        let frame = match expansion {
+            FrameExpansion::Duplicate => {
+                // Shown for no time, so the animation keeps its timing
+                let mut frame = gif.image_descriptors[index % original_frames].clone();
+                if let Some(ref mut gce) = frame.graphics_control_extension {
+                    gce.delay_time = 0;
+                }
+                frame
+            }
+            FrameExpansion::Minimal => minimal_frame(gif),
+            FrameExpansion::TinyFrames => tiny_frame(gif),
+        };
+        gif.image_descriptors.push(frame);
+    }
+}
+
+// One Plain Text Extension per chunk, adding frames to host them. Frames
+// duplicated for them are kept when the channel is cleared, the next embed
+// reuses them.
+struct PlainTextChannel;
+
+impl Channel for PlainTextChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::PlainText
+    }
+
+    fn name(&self) -> &'static str {
+        "plaintext"
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error> {
+        let chunks = data
+            .chunks(PLAIN_TEXT_CHUNK_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        self.embed_chunks(gif, chunks, &ChannelOptions::default())
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        Some(self.chunks(gif).concat()).filter(|data| !data.is_empty())
+    }
+
+    fn clear(&self, gif: &mut GIF, _length: usize) -> Result<(), Error> {
+        gif.plain_text_extensions.clear();
+        Ok(())
+    }
+
+    fn chunk_size(&self) -> Option<usize> {
+        Some(PLAIN_TEXT_CHUNK_SIZE)
+    }
+
+    fn embed_chunks(
+        &self,
+        gif: &mut GIF,
+        chunks: Vec<Vec<u8>>,
+        options: &ChannelOptions,
+    ) -> Result<(), Error> {
+        expand_frames(gif, chunks.len(), options.expansion);
+
+        gif.plain_text_extensions = chunks
+            .into_iter()
+            .map(|plain_text_data| PlainTextExtension {
+                block_size: 12,
+                text_grid_left_position: 0,
+                text_grid_top_position: 0,
+                text_grid_width: 0,
+                text_grid_height: 0,
+                character_cell_width: 0,
+                character_cell_height: 0,
+                text_foreground_color_index: 0,
+                text_background_color_index: 0,
+                plain_text_data,
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn chunks(&self, gif: &GIF) -> Vec<Vec<u8>> {
+        gif.plain_text_extensions
+            .iter()
+            .map(|plain_text| plain_text.plain_text_data.clone())
+            .collect()
+    }
+}
+
+// Comments written by the carrier's author are kept, but ours go first so the
+// channel data starts with the payload.
+struct CommentChannel;
+
+impl Channel for CommentChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Comment
+    }
+
+    fn name(&self) -> &'static str {
+        "comment"
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error> {
+        let chunks = data
+            .chunks(COMMENT_CHUNK_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        self.embed_chunks(gif, chunks, &ChannelOptions::default())
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        Some(self.chunks(gif).concat()).filter(|data| !data.is_empty())
+    }
+
+    fn clear(&self, gif: &mut GIF, length: usize) -> Result<(), Error> {
+        // Our chunks lead the comments, whatever size they were cut to
+        let mut covered = 0;
+        let mut count = 0;
+        for comment in &gif.comment_extensions {
+            if covered >= length {
+                break;
+            }
+            covered += comment
+                .comments
+                .iter()
+                .map(|block| block.len())
+                .sum::<usize>();
+            count += 1;
+        }
+        gif.comment_extensions.drain(..count);
+        Ok(())
+    }
+
+    fn chunk_size(&self) -> Option<usize> {
+        Some(COMMENT_CHUNK_SIZE)
+    }
+
+    fn embed_chunks(
+        &self,
+        gif: &mut GIF,
+        chunks: Vec<Vec<u8>>,
+        _options: &ChannelOptions,
+    ) -> Result<(), Error> {
+        let comments: Vec<CommentExtension> = chunks
+            .into_iter()
+            .map(|chunk| CommentExtension {
+                comments: vec![chunk],
+            })
+            .collect();
+        gif.comment_extensions.splice(0..0, comments);
+        Ok(())
+    }
+
+    fn chunks(&self, gif: &GIF) -> Vec<Vec<u8>> {
+        gif.comment_extensions
+            .iter()
+            .map(|comment| comment.comments.concat())
+            .collect()
+    }
+}
+
+// One application extension of our own, replaced on every embed
+struct ApplicationChannel;
+
+impl Channel for ApplicationChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Application
+    }
+
+    fn name(&self) -> &'static str {
+        "appext"
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error> {
+        gif.application_extensions
+            .retain(|application| !is_payload_application(application));
+        gif.application_extensions.push(ApplicationExtension {
+            identifier: APPLICATION_IDENTIFIER.to_string(),
+            authentication_code: APPLICATION_AUTHENTICATION_CODE.to_string(),
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        let data: Vec<u8> = gif
+            .application_extensions
+            .iter()
+            .filter(|application| is_payload_application(application))
+            .flat_map(|application| application.data.iter().cloned())
+            .collect();
+        Some(data).filter(|data| !data.is_empty())
+    }
+
+    fn clear(&self, gif: &mut GIF, _length: usize) -> Result<(), Error> {
+        gif.application_extensions
+            .retain(|application| !is_payload_application(application));
+        Ok(())
+    }
+}
+
+fn is_payload_application(application: &ApplicationExtension) -> bool {
+    application.identifier == APPLICATION_IDENTIFIER
+        && application.authentication_code == APPLICATION_AUTHENTICATION_CODE
+}
+
+// The transparent index and its LSB neighbour are never used for payload
+// bits, otherwise flipping a bit would change which pixels are see-through.
+// Each frame reserves the index of its own graphic control extension.
+fn lsb_reserved(image_descriptor: &ImageDescriptor) -> Option<u8> {
+    image_descriptor
+        .transparent_color_index()
+        .map(|index| index & 0xFE)
+}
+
+fn lsb_pixel_count(gif: &GIF) -> usize {
+    gif.image_descriptors
+        .iter()
+        .map(|image_descriptor| {
+            let reserved = lsb_reserved(image_descriptor);
+            image_descriptor
+                .image_data
+                .iter()
+                .filter(|pixel| Some(**pixel & 0xFE) != reserved)
+                .count()
+        })
+        .sum()
+}
+
+// The low bit of every pixel index, frame after frame
+struct LsbChannel;
+
+impl Channel for LsbChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Lsb
+    }
+
+    fn name(&self) -> &'static str {
+        "lsb"
+    }
+
+    fn capacity(&self, gif: &GIF) -> Option<usize> {
+        Some(lsb_pixel_count(gif) / 8)
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error> {
+        if data.len() > lsb_pixel_count(gif) / 8 {
+            return Err(io::Error::other("Payload does not fit in the lsb channel."));
+        }
+
+        let mut bits = data
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1));
+
+        for image_descriptor in &mut gif.image_descriptors {
+            image_descriptor.compressed_range = None;
+            let reserved = lsb_reserved(image_descriptor);
+            for pixel in &mut image_descriptor.image_data {
+                if Some(*pixel & 0xFE) == reserved {
+                    continue;
+                }
+                match bits.next() {
+                    Some(bit) => *pixel = (*pixel & 0xFE) | bit,
+                    None => return Ok(()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut byte = 0u8;
+        let mut bit_count = 0;
+
+        for image_descriptor in &gif.image_descriptors {
+            let reserved = lsb_reserved(image_descriptor);
+            for pixel in &image_descriptor.image_data {
+                if Some(*pixel & 0xFE) == reserved {
+                    continue;
+                }
+                byte |= (pixel & 1) << bit_count;
+                bit_count += 1;
+                if bit_count == 8 {
+                    data.push(byte);
+                    byte = 0;
+                    bit_count = 0;
+                }
+            }
+        }
+        Some(data).filter(|data| !data.is_empty())
+    }
+
+    fn clear(&self, gif: &mut GIF, length: usize) -> Result<(), Error> {
+        self.embed(gif, &vec![0; length])
+    }
+}
+
+// The bytes after the trailer, which decoders never read. Whatever the carrier
+// already had there is kept after ours.
+struct TrailerChannel;
+
+impl Channel for TrailerChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Trailer
+    }
+
+    fn name(&self) -> &'static str {
+        "trailer"
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error> {
+        warn!("Many editors and re-encoders drop everything after the trailer, payload included");
+        gif.trailing_data.splice(0..0, data.iter().cloned());
+        Ok(())
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        Some(gif.trailing_data.clone()).filter(|data| !data.is_empty())
+    }
+
+    fn clear(&self, gif: &mut GIF, length: usize) -> Result<(), Error> {
+        let length = length.min(gif.trailing_data.len());
+        gif.trailing_data.drain(..length);
+        Ok(())
+    }
+}
+
+// Frames whose delay can carry a bit. Delays of 0 and 1 are left alone, as
+// browsers stretch both to a tenth of a second and a bit could move a frame
+// between them and 2.
+fn delay_frames(gif: &GIF) -> impl Iterator<Item = &GraphicsControlExtension> {
+    gif.image_descriptors
+        .iter()
+        .filter_map(|image_descriptor| image_descriptor.graphics_control_extension.as_ref())
+        .filter(|gce| gce.delay_time >= 2)
+}
+
+fn delay_frames_mut(gif: &mut GIF) -> impl Iterator<Item = &mut GraphicsControlExtension> {
+    gif.image_descriptors
+        .iter_mut()
+        .filter_map(|image_descriptor| image_descriptor.graphics_control_extension.as_mut())
+        .filter(|gce| gce.delay_time >= 2)
+}
+
+// The low bit of each frame's delay, a hundredth of a second either way. The
+// bits are mixed with a stream from the passphrase, so even a run of equal
+// bytes comes out as the uneven 4, 5, 4, 4 an encoder rounding its timing
+// leaves, and without the passphrase there is no telling it from that.
+struct DelayChannel;
+
+// The delays rounded down to even, which the channel leaves as they were
+fn even_delays(gif: &GIF) -> Vec<u8> {
+    delay_frames(gif)
+        .flat_map(|gce| (gce.delay_time & !1).to_le_bytes())
+        .collect()
+}
+
+impl Channel for DelayChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Delay
+    }
+
+    fn name(&self) -> &'static str {
+        "delay"
+    }
+
+    fn capacity(&self, gif: &GIF) -> Option<usize> {
+        Some(delay_frames(gif).count() / 8)
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error> {
+        self.embed_keyed(gif, data, None)
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        self.extract_keyed(gif, None)
+    }
+
+    fn embed_keyed(&self, gif: &mut GIF, data: &[u8], key: Option<&ChunkKey>) -> Result<(), Error> {
+        if data.len() > delay_frames(gif).count() / 8 {
+            return Err(io::Error::other(
+                "Payload does not fit in the delay channel.",
+            ));
+        }
+
+        let mut rng = whitening_rng(key, ChannelKind::Delay, &even_delays(gif));
+        let bits = data
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1));
+        for (gce, bit) in delay_frames_mut(gif).zip(bits) {
+            gce.delay_time = (gce.delay_time & !1) | (bit ^ (rng.gen::<u8>() & 1)) as u16;
+        }
+        Ok(())
+    }
+
+    fn extract_keyed(&self, gif: &GIF, key: Option<&ChunkKey>) -> Option<Vec<u8>> {
+        let mut rng = whitening_rng(key, ChannelKind::Delay, &even_delays(gif));
+        let bits: Vec<u8> = delay_frames(gif)
+            .map(|gce| (gce.delay_time & 1) as u8 ^ (rng.gen::<u8>() & 1))
+            .collect();
+        let data: Vec<u8> = bits
+            .chunks_exact(8)
+            .map(|byte| byte.iter().rev().fold(0, |value, bit| value << 1 | bit))
+            .collect();
+        Some(data).filter(|data| !data.is_empty())
+    }
+
+    // The delays are rounded down to even, which drops the jitter
+    fn clear(&self, gif: &mut GIF, length: usize) -> Result<(), Error> {
+        for gce in delay_frames_mut(gif).take(length * 8) {
+            gce.delay_time &= !1;
+        }
+        Ok(())
+    }
+}
+
+// The entries of frame `index` that show its background, taken to be the
+// color most of its opaque pixels show: the first two entries with that
+// color, or the first and where a copy would go when there is one. The
+// transparent index is never one of them, as a frame has just the one and
+// transparent pixels couldn't switch entries without showing. Choosing
+// between the two leaves how many pixels show each color alone, so the same
+// entries are found again after embedding. None when the frame has no table
+// or a full one without a copy.
+fn background_twins(gif: &GIF, index: usize) -> Option<(u8, u8, bool)> {
+    let frame = &gif.image_descriptors[index];
+    let table = frame
+        .local_color_table
+        .as_ref()
+        .or(gif.global_color_table.as_ref())?;
+    let transparent = frame.transparent_color_index().map(|index| index as usize);
+
+    let mut counts = [0usize; 256];
+    for &pixel in &frame.image_data {
+        counts[pixel as usize] += 1;
+    }
+    let mut colors: HashMap<[u8; 3], (usize, usize)> = HashMap::new(); // Pixels, first entry
+    for (entry, color) in table.colors.iter().enumerate() {
+        if Some(entry) != transparent {
+            let shown = colors.entry(*color).or_insert((0, entry));
+            shown.0 += counts[entry];
+        }
+    }
+    let (color, _) = colors
+        .into_iter()
+        .max_by_key(|&(_, (pixels, first))| (pixels, Reverse(first)))?;
+
+    let mut twins = (0..table.colors.len())
+        .filter(|&entry| table.colors[entry] == color && Some(entry) != transparent);
+    let first = twins.next()? as u8;
+    match twins.next() {
+        Some(second) => Some((first, second as u8, true)),
+        None if table.colors.len() < 256 => Some((first, table.colors.len() as u8, false)),
+        None => None,
+    }
+}
+
+fn background_pixel_count(gif: &GIF) -> usize {
+    (0..gif.image_descriptors.len())
+        .filter_map(|index| Some((index, background_twins(gif, index)?)))
+        .map(|(index, (first, second, exists))| {
+            gif.image_descriptors[index]
+                .image_data
+                .iter()
+                .filter(|&&pixel| pixel == first || (exists && pixel == second))
+                .count()
+        })
+        .sum()
+}
+
+// Copy `color` into the next entry of a table, growing it to the next power
+// of two when it is full. Returns the bits of the packed field's size.
+fn add_twin(table: &mut ColorTable, color: [u8; 3]) -> u8 {
+    table.colors.push(color);
+    let size = table.colors.len().next_power_of_two().max(2);
+    table.colors.resize(size, [0; 3]);
+    (size.trailing_zeros() - 1) as u8
+}
+
+// Background pixels pick one of two entries of the same color, the first for
+// a 0 bit and the second for a 1, so the frames look just as they did. Tables
+// without a second entry get one.
+struct BackgroundChannel;
+
+impl Channel for BackgroundChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Background
+    }
+
+    fn name(&self) -> &'static str {
+        "background"
+    }
+
+    fn capacity(&self, gif: &GIF) -> Option<usize> {
+        Some(background_pixel_count(gif) / 8)
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), Error> {
+        if data.len() > background_pixel_count(gif) / 8 {
+            return Err(io::Error::other(
+                "Payload does not fit in the background channel.",
+            ));
+        }
+
+        let mut bits = data
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1))
+            .peekable();
+        for index in 0..gif.image_descriptors.len() {
+            if bits.peek().is_none() {
+                break;
+            }
+            let (first, second, exists) = match background_twins(gif, index) {
+                Some(twins) => twins,
+                None => continue,
+            };
+            let frame = &gif.image_descriptors[index];
+            if !frame.image_data.contains(&first) && !exists {
+                continue;
+            }
+            if !exists {
+                let frame = &mut gif.image_descriptors[index];
+                let (table, packed_field) = match frame.local_color_table {
+                    Some(ref mut table) => (table, &mut frame.packed_field),
+                    // The frame has a table, so this is it
+                    None => (
+                        gif.global_color_table.as_mut().unwrap(),
+                        &mut gif.logical_screen_descriptor.packed_field,
+                    ),
+                };
+                let color = table.colors[first as usize];
+                *packed_field = (*packed_field & !0b111) | add_twin(table, color);
+            }
+
+            let frame = &mut gif.image_descriptors[index];
+            // Codes must be wide enough for the second entry
+            let needed = (u8::BITS - second.leading_zeros()) as u8;
+            frame.lzw_minimum_code_size = frame.lzw_minimum_code_size.max(needed).max(2);
+            frame.compressed_range = None;
+            for pixel in &mut frame.image_data {
+                if *pixel != first && *pixel != second {
+                    continue;
+                }
+                match bits.next() {
+                    Some(0) => *pixel = first,
+                    Some(_) => *pixel = second,
+                    None => break,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut byte = 0u8;
+        let mut bit_count = 0;
+
+        for index in 0..gif.image_descriptors.len() {
+            let (first, second) = match background_twins(gif, index) {
+                Some((first, second, true)) => (first, second),
+                _ => continue,
+            };
+            for &pixel in &gif.image_descriptors[index].image_data {
+                if pixel != first && pixel != second {
+                    continue;
+                }
+                byte |= ((pixel == second) as u8) << bit_count;
+                bit_count += 1;
+                if bit_count == 8 {
+                    data.push(byte);
+                    byte = 0;
+                    bit_count = 0;
+                }
+            }
+        }
+        Some(data).filter(|data| !data.is_empty())
+    }
+
+    fn clear(&self, gif: &mut GIF, length: usize) -> Result<(), Error> {
+        self.embed(gif, &vec![0; length])
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt::Write;
+use std::io::{self, Error};
+
+use crate::channels::{ChannelKind, APPLICATION_AUTHENTICATION_CODE, APPLICATION_IDENTIFIER};
+use crate::gif::{parse_events_bytes, FrameStart, GifVisitor, SubBlocks};
+use crate::payload::MappedChunk;
+
+// embed --map: where each piece of the stored payload landed in the output,
+// as JSON. Ranges are of the stored payload, header and body as written,
+// encrypted or compressed if it was. Each chunk names the extension or frame
+// holding it and the file offset of that extension's introducer, so a
+// damaged frame can be traced to the bytes it took with it:
+//
+//   {"length":N,"chunks":[{"channel":"plaintext","start":0,"end":254,
+//     "block":0,"frame":0,"offset":1234},...]}
+//
+// block, frame and offset are null where they don't apply, such as for the
+// pixels of the lsb channel.
+
+const PLAIN_TEXT_LABEL: u8 = 0x01;
+const COMMENT_LABEL: u8 = 0xFE;
+const APPLICATION_LABEL: u8 = 0xFF;
+
+// Where the extensions that can hold payload chunks sit in the file
+#[derive(Default)]
+struct Layout {
+    plain_texts: Vec<usize>,
+    comments: Vec<usize>,
+    payload_extension: Option<usize>, // The appext channel's
+    frames: usize,
+    trailer: Option<usize>,
+}
+
+impl GifVisitor for Layout {
+    fn on_extension(&mut self, position: usize, label: u8, mut sub_blocks: SubBlocks) {
+        match label {
+            PLAIN_TEXT_LABEL => self.plain_texts.push(position),
+            COMMENT_LABEL => self.comments.push(position),
+            APPLICATION_LABEL => {
+                let is_payload = sub_blocks.next().is_some_and(|header| {
+                    header
+                        == [APPLICATION_IDENTIFIER, APPLICATION_AUTHENTICATION_CODE]
+                            .concat()
+                            .as_bytes()
+                });
+                if is_payload && self.payload_extension.is_none() {
+                    self.payload_extension = Some(position);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_frame_start(&mut self, _frame: &FrameStart) {
+        self.frames += 1;
+    }
+
+    fn on_trailer(&mut self, position: usize) {
+        self.trailer = Some(position);
+    }
+}
+
+fn json_number(number: Option<usize>) -> String {
+    number.map_or("null".to_string(), |number| number.to_string())
+}
+
+// The map of `chunks` in the GIF written as `output`
+pub fn format_chunk_map(output: &[u8], chunks: &[MappedChunk]) -> Result<String, Error> {
+    let mut layout = Layout::default();
+    parse_events_bytes(output, &mut layout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}", e)))?;
+
+    let length = chunks
+        .iter()
+        .map(|chunk| chunk.range.end)
+        .max()
+        .unwrap_or(0);
+    let mut entries = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        // Plain text extension n is written ahead of frame n
+        let (frame, offset) = match (chunk.channel, chunk.block) {
+            (ChannelKind::PlainText, Some(block)) => (
+                (block < layout.frames).then_some(block),
+                layout.plain_texts.get(block).copied(),
+            ),
+            (ChannelKind::Comment, Some(block)) => (None, layout.comments.get(block).copied()),
+            (ChannelKind::Application, _) => (None, layout.payload_extension),
+            (ChannelKind::Trailer, _) => (None, layout.trailer.map(|trailer| trailer + 1)),
+            _ => (None, None),
+        };
+        let mut entry = String::new();
+        let _ = write!(
+            entry,
+            "{{\"channel\":\"{}\",\"start\":{},\"end\":{},\"block\":{},\"frame\":{},\"offset\":{}}}",
+            chunk.channel,
+            chunk.range.start,
+            chunk.range.end,
+            json_number(chunk.block),
+            json_number(frame),
+            json_number(offset)
+        );
+        entries.push(entry);
+    }
+    Ok(format!(
+        "{{\"length\":{},\"chunks\":[{}]}}\n",
+        length,
+        entries.join(",")
+    ))
+}
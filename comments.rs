@@ -0,0 +1,38 @@
+use std::io::{self, Error};
+
+use crate::gif::{CommentExtension, GIF};
+
+// Text of a comment for display. Comments holding binary data, such as
+// payload chunks, are summarised instead of printed.
+pub fn comment_text(comment: &CommentExtension) -> String {
+    let data = comment.comments.concat();
+    match String::from_utf8(data) {
+        Ok(text) => text,
+        Err(error) => format!("[{} bytes of binary data]", error.as_bytes().len()),
+    }
+}
+
+// A comment split into sub-blocks of at most 255 bytes
+pub fn new_comment(text: &[u8]) -> CommentExtension {
+    CommentExtension {
+        comments: text.chunks(255).map(|chunk| chunk.to_vec()).collect(),
+    }
+}
+
+pub fn add_comment(gif: &mut GIF, text: &[u8]) {
+    gif.comment_extensions.push(new_comment(text));
+}
+
+pub fn remove_comment(gif: &mut GIF, index: usize) -> Result<CommentExtension, Error> {
+    if index >= gif.comment_extensions.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No comment #{}, the file has {} comment(s).",
+                index,
+                gif.comment_extensions.len()
+            ),
+        ));
+    }
+    Ok(gif.comment_extensions.remove(index))
+}
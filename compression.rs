@@ -0,0 +1,22 @@
+use std::io::{self, Error};
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+const LEVEL: u8 = 9;
+
+// A compressed payload never inflates past this, however small it is
+const MAX_INFLATED_SIZE: usize = 1 << 30;
+
+pub fn deflate_body(body: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(compress_to_vec(body, LEVEL))
+}
+
+pub fn inflate_body(data: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_to_vec_with_limit(data, MAX_INFLATED_SIZE).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Payload fails to decompress: {}", e),
+        )
+    })
+}
@@ -0,0 +1,115 @@
+use std::env;
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+// Default options from a TOML file, one table a command. Every key is one of
+// the command's long options without its dashes, and its value the option's
+// value: true for a flag, a list for an option that can be repeated. A table
+// inside a command's table is for one of its subcommands.
+//
+//   [embed]
+//   channels = "comment,lsb"
+//   compress = true
+//   chunk-size = 4096
+//   max-output-size = 5000000
+//   encrypt = true
+//
+//   [watermark.embed]
+//   strength = 6
+//
+// The defaults go in front of the options on the command line, which win
+// where both set the same thing.
+
+// $XDG_CONFIG_HOME/gifsauce/config.toml, ~/.config/gifsauce/config.toml when
+// that isn't set, or %APPDATA%\gifsauce\config.toml on Windows
+pub fn default_config_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|path| !path.is_empty()) {
+        Some(path) => PathBuf::from(path),
+        None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("gifsauce").join("config.toml"))
+}
+
+fn invalid(message: String) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn read_config(path: &Path) -> Result<Value, Error> {
+    let text = fs::read_to_string(path)?;
+    let config: Value = text.parse().map_err(|e| invalid(format!("{}", e)))?;
+    if !config.is_table() {
+        return Err(invalid("Not a TOML table.".to_string()));
+    }
+    Ok(config)
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, Error> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Datetime(_) => Ok(value.to_string()),
+        _ => Err(invalid(format!(
+            "Setting {} should be a string, a number or true.",
+            key
+        ))),
+    }
+}
+
+// The options a command table stands for, without those of its subcommands
+fn table_options(name: &str, table: &toml::value::Table) -> Result<Vec<String>, Error> {
+    let mut options = Vec::new();
+    for (key, value) in table {
+        let option = format!("--{}", key);
+        match value {
+            Value::Boolean(true) => options.push(option),
+            Value::Boolean(false) | Value::Table(_) => {}
+            Value::Array(values) => {
+                for value in values {
+                    options.push(option.clone());
+                    options.push(scalar(&format!("{}.{}", name, key), value)?);
+                }
+            }
+            value => {
+                options.push(option);
+                options.push(scalar(&format!("{}.{}", name, key), value)?);
+            }
+        }
+    }
+    Ok(options)
+}
+
+// The command line with the defaults of its command, and subcommand, put in
+// front of its own options. `args` starts with the program name.
+pub fn apply_config(config: &Value, mut args: Vec<String>) -> Result<Vec<String>, Error> {
+    let command = match args.get(1) {
+        Some(command) => command.clone(),
+        None => return Ok(args),
+    };
+    let table = match config.get(&command) {
+        Some(Value::Table(table)) => table,
+        Some(_) => {
+            return Err(invalid(format!(
+                "Setting {} should be a table of options.",
+                command
+            )))
+        }
+        None => return Ok(args),
+    };
+
+    // Subcommands parse the options after their name, so the defaults go
+    // there, those of the subcommand after those of the command
+    let mut defaults = table_options(&command, table)?;
+    let mut position = 2;
+    if let Some(Value::Table(subtable)) = args.get(2).and_then(|name| table.get(name)) {
+        defaults.extend(table_options(
+            &format!("{}.{}", command, args[2]),
+            subtable,
+        )?);
+        position = 3;
+    }
+    args.splice(position..position, defaults);
+    Ok(args)
+}
@@ -0,0 +1,226 @@
+use std::io::{self, Error};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::Sha256;
+
+use crate::crypto::PayloadCipher;
+
+// Encrypted payloads are stored as a container with room for two payloads:
+//
+//   salt (16) | slot header 0 (24) | slot header 1 (24) | region
+//
+// A slot header is the sealed (offset u32, length u32) of its payload's
+// ciphertext inside the region, which wraps around at its end. Unused slots
+// and the rest of the region are random bytes, so a container with one
+// payload looks exactly like one with two until the second passphrase is
+// used. Nor does opening one of two tell there is another: every payload
+// starts anywhere in the region, and the region is sized for two of the
+// longest payload, one or two of them.
+const SALT_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+const SLOT_HEADER_SIZE: usize = 8 + TAG_SIZE;
+const SLOT_COUNT: usize = 2;
+const CONTAINER_HEADER_SIZE: usize = SALT_SIZE + SLOT_COUNT * SLOT_HEADER_SIZE;
+const REGION_STEP: usize = 64;
+
+fn derive_key_bytes(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], Error> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(key)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, Error> {
+    let key = derive_key_bytes(passphrase, salt)?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+// The seed seal_seeded draws from: `seed` keyed with what Argon2 derives from
+// every passphrase over a salt taken from it. The salt drawn from this is
+// stored in clear, so checking a guessed passphrase against it takes Argon2
+// even when the payload is known.
+fn keyed_seed(payloads: &[(&str, &[u8])], seed: [u8; 32]) -> Result<[u8; 32], Error> {
+    let mut keys = Vec::with_capacity(payloads.len() * 32);
+    for &(passphrase, _) in payloads {
+        keys.extend_from_slice(&derive_key_bytes(passphrase, &seed[..SALT_SIZE])?);
+    }
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(&keys).expect("HMAC takes keys of any length");
+    mac.update(&seed);
+    Ok(mac.finalize().into_bytes().into())
+}
+
+// Every key seals at most one slot header and one payload per container, so
+// nonces only have to differ between those two uses and between slots.
+fn slot_nonce(slot: usize, part: u8) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[0] = slot as u8;
+    nonce[1] = part;
+    *Nonce::from_slice(&nonce)
+}
+
+// The built-in cipher: Argon2 derives a ChaCha20-Poly1305 key per passphrase
+pub struct ContainerCipher;
+
+impl PayloadCipher for ContainerCipher {
+    fn seal(&self, payloads: &[(&str, &[u8])]) -> Result<Vec<u8>, Error> {
+        seal_container(payloads, &mut OsRng)
+    }
+
+    fn seal_seeded(&self, payloads: &[(&str, &[u8])], seed: [u8; 32]) -> Result<Vec<u8>, Error> {
+        let seed = keyed_seed(payloads, seed)?;
+        seal_container(payloads, &mut ChaCha20Rng::from_seed(seed))
+    }
+
+    fn open(&self, container: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+        open_container(container, passphrase)
+    }
+
+    fn sealed_length(&self, lengths: &[usize]) -> Option<usize> {
+        let longest = lengths.iter().map(|length| length + TAG_SIZE).max()?;
+        Some(CONTAINER_HEADER_SIZE + region_size(longest))
+    }
+
+    fn stretch(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; 32], Error> {
+        derive_key_bytes(passphrase, salt)
+    }
+}
+
+// Regions hold two of the longest sealed payload, whether they hold one or
+// two, so their size says nothing about how many payloads they hold. It is
+// rounded up to a multiple of REGION_STEP; padding the payloads is what
+// hides their length.
+fn region_size(longest: usize) -> usize {
+    2 * longest.max(1).next_multiple_of(REGION_STEP)
+}
+
+// Seal one or two (passphrase, data) payloads into a container. The salt,
+// layout and padding come from `rng`.
+fn seal_container<R: RngCore>(payloads: &[(&str, &[u8])], rng: &mut R) -> Result<Vec<u8>, Error> {
+    if payloads.is_empty() || payloads.len() > SLOT_COUNT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A container holds one or two payloads.",
+        ));
+    }
+    if payloads.len() == 2 && payloads[0].0 == payloads[1].0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Both payloads need different passphrases.",
+        ));
+    }
+
+    let mut salt = [0u8; SALT_SIZE];
+    rng.fill_bytes(&mut salt);
+
+    // Neither the slot nor the position in the region gives away which
+    // payload is which
+    let mut slots: Vec<usize> = (0..SLOT_COUNT).collect();
+    slots.shuffle(rng);
+    let mut order: Vec<usize> = (0..payloads.len()).collect();
+    order.shuffle(rng);
+
+    let mut ciphers = Vec::with_capacity(payloads.len());
+    let mut sealed = vec![Vec::new(); payloads.len()];
+    for (index, &(passphrase, data)) in payloads.iter().enumerate() {
+        let cipher = derive_key(passphrase, &salt)?;
+        sealed[index] = cipher
+            .encrypt(&slot_nonce(slots[index], 1), data)
+            .map_err(|_| io::Error::other("Failed to encrypt payload."))?;
+        ciphers.push(cipher);
+    }
+
+    // The first payload starts anywhere, the second anywhere in the gap after
+    // it, both wrapping around the end of the region. Either one, on its
+    // own, starts anywhere, as a lone payload does.
+    let size = region_size(sealed.iter().map(Vec::len).max().unwrap_or(0));
+    let mut region = vec![0u8; size];
+    rng.fill_bytes(&mut region);
+    let mut offsets = vec![0; payloads.len()];
+    let mut offset = rng.gen_range(0..size);
+    let gap = size - sealed.iter().map(Vec::len).sum::<usize>();
+    for &index in &order {
+        offsets[index] = offset;
+        for (position, &byte) in sealed[index].iter().enumerate() {
+            region[(offset + position) % size] = byte;
+        }
+        offset = (offset + sealed[index].len() + rng.gen_range(0..=gap)) % size;
+    }
+
+    let mut headers = vec![0u8; SLOT_COUNT * SLOT_HEADER_SIZE];
+    rng.fill_bytes(&mut headers);
+    for (index, cipher) in ciphers.iter().enumerate() {
+        let mut location = Vec::with_capacity(8);
+        location.extend_from_slice(&(offsets[index] as u32).to_le_bytes());
+        location.extend_from_slice(&(sealed[index].len() as u32).to_le_bytes());
+        let header = cipher
+            .encrypt(&slot_nonce(slots[index], 0), location.as_slice())
+            .map_err(|_| io::Error::other("Failed to encrypt payload."))?;
+        let start = slots[index] * SLOT_HEADER_SIZE;
+        headers[start..start + SLOT_HEADER_SIZE].copy_from_slice(&header);
+    }
+
+    let mut container = Vec::with_capacity(CONTAINER_HEADER_SIZE + region.len());
+    container.extend_from_slice(&salt);
+    container.extend_from_slice(&headers);
+    container.extend_from_slice(&region);
+    Ok(container)
+}
+
+// Open whichever payload in the container belongs to this passphrase.
+fn open_container(container: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    if container.len() < CONTAINER_HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Encrypted payload is truncated.",
+        ));
+    }
+
+    let cipher = derive_key(passphrase, &container[..SALT_SIZE])?;
+    let region = &container[CONTAINER_HEADER_SIZE..];
+
+    for slot in 0..SLOT_COUNT {
+        let start = SALT_SIZE + slot * SLOT_HEADER_SIZE;
+        let header = &container[start..start + SLOT_HEADER_SIZE];
+        let location = match cipher.decrypt(&slot_nonce(slot, 0), header) {
+            Ok(location) => location,
+            Err(_) => continue,
+        };
+
+        let mut offset = [0; 4];
+        offset.copy_from_slice(&location[0..4]);
+        let mut length = [0; 4];
+        length.copy_from_slice(&location[4..8]);
+        let offset = u32::from_le_bytes(offset) as usize;
+        let length = u32::from_le_bytes(length) as usize;
+
+        if offset >= region.len() || length > region.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Encrypted payload is truncated.",
+            ));
+        }
+        let sealed: Vec<u8> = region[offset..]
+            .iter()
+            .chain(region)
+            .take(length)
+            .copied()
+            .collect();
+        return cipher
+            .decrypt(&slot_nonce(slot, 1), sealed.as_slice())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Payload is corrupted."));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Wrong passphrase.",
+    ))
+}
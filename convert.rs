@@ -0,0 +1,532 @@
+use std::borrow::Cow;
+use std::io::{self, Error};
+
+use crate::frames::{composite_frames, is_loop_extension};
+use crate::gif::{KnownApplication, GIF};
+
+// The animation is re-encoded from what a browser shows: every frame covers
+// the whole screen, drawn over nothing, so no disposal or blending carries
+// over. Delays under 20ms are played at 100ms as browsers do, and the loop
+// count and ICC profile are kept.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimationFormat {
+    Apng,
+    Webp,
+}
+
+pub fn parse_animation_format(name: &str) -> Option<AnimationFormat> {
+    match name.to_ascii_lowercase().as_str() {
+        "apng" | "png" => Some(AnimationFormat::Apng),
+        "webp" => Some(AnimationFormat::Webp),
+        _ => None,
+    }
+}
+
+// The format a file name's extension stands for
+pub fn animation_format_of(path: &str) -> Option<AnimationFormat> {
+    parse_animation_format(path.rsplit_once('.')?.1)
+}
+
+// Each frame as RGBA pixels, with its delay in hundredths of a second
+fn rgba_frames(gif: &GIF) -> Result<Vec<(Vec<u8>, u16)>, Error> {
+    let canvases = composite_frames(gif)?;
+    if canvases.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The GIF has no frames to convert.",
+        ));
+    }
+    Ok(canvases
+        .iter()
+        .zip(&gif.image_descriptors)
+        .map(|(canvas, frame)| {
+            let rgba = canvas
+                .iter()
+                .flat_map(|pixel| match pixel {
+                    Some([red, green, blue]) => [*red, *green, *blue, 0xFF],
+                    None => [0; 4],
+                })
+                .collect();
+            let delay = frame
+                .graphics_control_extension
+                .as_ref()
+                .map_or(0, |gce| gce.delay_time);
+            (rgba, if delay < 2 { 10 } else { delay })
+        })
+        .collect())
+}
+
+// How many times to play in all, 0 for forever. The loop count of a GIF
+// counts the repeats after the first time.
+fn plays(gif: &GIF) -> u32 {
+    let loop_block = gif
+        .application_extensions
+        .iter()
+        .find(|application| is_loop_extension(application));
+    match loop_block.map(|application| application.known()) {
+        Some(Some(KnownApplication::Loop(looping))) if looping.repeats > 0 => {
+            looping.repeats as u32 + 1
+        }
+        Some(_) => 0,
+        None => 1,
+    }
+}
+
+// The GIF's ICC profile, which goes along into the converted file
+fn icc_profile(gif: &GIF) -> Option<Vec<u8>> {
+    gif.application_extensions
+        .iter()
+        .find_map(|application| match application.known() {
+            Some(KnownApplication::IccProfile(profile)) => Some(profile.profile),
+            _ => None,
+        })
+}
+
+pub fn animated_png(gif: &GIF) -> Result<Vec<u8>, Error> {
+    let width = gif.logical_screen_descriptor.width as u32;
+    let height = gif.logical_screen_descriptor.height as u32;
+    let frames = rgba_frames(gif)?;
+
+    let mut output = Vec::new();
+    let mut info = png::Info::with_size(width, height);
+    info.icc_profile = icc_profile(gif).map(Cow::Owned);
+    let mut encoder = png::Encoder::with_info(&mut output, info).map_err(io::Error::other)?;
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, plays(gif))
+        .map_err(io::Error::other)?;
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    for (rgba, delay) in frames {
+        writer
+            .set_frame_delay(delay, 100)
+            .map_err(io::Error::other)?;
+        writer.write_image_data(&rgba).map_err(io::Error::other)?;
+    }
+    writer.finish().map_err(io::Error::other)?;
+    Ok(output)
+}
+
+// Bits are packed from the least significant bit of each byte up
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go in from their most significant bit
+    fn write_code(&mut self, (code, length): (u32, u32)) {
+        for bit in (0..length).rev() {
+            self.write((code >> bit) & 1, 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+// Code lengths of at most `limit` bits for symbols counted `counts` times.
+// Rare symbols are counted as more common until the tree is shallow enough.
+fn code_lengths(counts: &[u32], limit: u8) -> Vec<u8> {
+    let mut minimum = 1;
+    loop {
+        // Leaves, then the nodes merging them, with their parents
+        let mut weights: Vec<(u64, usize)> = counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(symbol, &count)| (count.max(minimum) as u64, symbol))
+            .collect();
+        let mut lengths = vec![0u8; counts.len()];
+        if weights.len() == 1 {
+            lengths[weights[0].1] = 1;
+            return lengths;
+        }
+        let leaves = weights.len();
+        let mut parents = vec![0usize; 2 * leaves];
+        let mut queue: Vec<(u64, usize)> = weights
+            .iter()
+            .enumerate()
+            .map(|(node, &(weight, _))| (weight, node))
+            .collect();
+        let mut next = leaves;
+        while queue.len() > 1 {
+            queue.sort_unstable_by(|a, b| b.cmp(a));
+            let (first, a) = queue.pop().unwrap();
+            let (second, b) = queue.pop().unwrap();
+            parents[a] = next;
+            parents[b] = next;
+            queue.push((first + second, next));
+            next += 1;
+        }
+        let root = next - 1;
+        let mut deepest = 0;
+        for (node, (_, symbol)) in weights.drain(..).enumerate() {
+            let mut depth = 0;
+            let mut at = node;
+            while at != root {
+                at = parents[at];
+                depth += 1;
+            }
+            lengths[symbol] = depth;
+            deepest = deepest.max(depth);
+        }
+        if deepest <= limit {
+            return lengths;
+        }
+        minimum *= 2;
+    }
+}
+
+// Canonical codes for the lengths, shortest first and in symbol order
+fn canonical_codes(lengths: &[u8]) -> Vec<(u32, u32)> {
+    let mut codes = vec![(0, 0); lengths.len()];
+    let mut code = 0u32;
+    for length in 1..=15u8 {
+        for (symbol, _) in lengths.iter().enumerate().filter(|&(_, &l)| l == length) {
+            codes[symbol] = (code, length as u32);
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+// The order code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// Store the prefix code for symbols counted `counts` times and return its
+// codes. Up to two symbols below 256 fit the simple form.
+fn write_prefix_code(writer: &mut BitWriter, counts: &[u32]) -> Vec<(u32, u32)> {
+    let used: Vec<usize> = (0..counts.len()).filter(|&s| counts[s] > 0).collect();
+    if used.len() <= 2 && used.iter().all(|&symbol| symbol < 256) {
+        writer.write(1, 1);
+        writer.write(used.len().max(1) as u32 - 1, 1);
+        let first = used.first().copied().unwrap_or(0) as u32;
+        if first < 2 {
+            writer.write(0, 1);
+            writer.write(first, 1);
+        } else {
+            writer.write(1, 1);
+            writer.write(first, 8);
+        }
+        let mut codes = vec![(0, 0); counts.len()];
+        if let Some(&second) = used.get(1) {
+            writer.write(second as u32, 8);
+            codes[used[0]] = (0, 1);
+            codes[second] = (1, 1);
+        }
+        return codes;
+    }
+
+    let lengths = code_lengths(counts, 15);
+    // Lengths in turn, runs of zeros shortened with symbols 17 and 18
+    let mut tokens: Vec<(u8, u32)> = Vec::new();
+    let mut symbol = 0;
+    while symbol < lengths.len() {
+        let zeros = lengths[symbol..]
+            .iter()
+            .take(138)
+            .take_while(|&&length| length == 0)
+            .count();
+        match zeros {
+            11.. => tokens.push((18, zeros as u32 - 11)),
+            3.. => tokens.push((17, zeros as u32 - 3)),
+            _ => {
+                tokens.push((lengths[symbol], 0));
+                symbol += 1;
+                continue;
+            }
+        }
+        symbol += zeros;
+    }
+    let mut length_counts = [0u32; 19];
+    for &(token, _) in &tokens {
+        length_counts[token as usize] += 1;
+    }
+    // A code with one symbol would be zero bits long
+    if length_counts.iter().filter(|&&count| count > 0).count() < 2 {
+        let spare = length_counts.iter().position(|&count| count == 0).unwrap();
+        length_counts[spare] = 1;
+    }
+    let length_lengths = code_lengths(&length_counts, 7);
+    let length_codes = canonical_codes(&length_lengths);
+
+    let stored = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&symbol| length_lengths[symbol] > 0)
+        .map_or(4, |last| (last + 1).max(4));
+    writer.write(0, 1);
+    writer.write(stored as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..stored] {
+        writer.write(length_lengths[symbol] as u32, 3);
+    }
+    writer.write(0, 1); // Every symbol has a length
+    for (token, extra) in tokens {
+        writer.write_code(length_codes[token as usize]);
+        match token {
+            17 => writer.write(extra, 3),
+            18 => writer.write(extra, 7),
+            _ => {}
+        }
+    }
+    canonical_codes(&lengths)
+}
+
+// A length or distance as a prefix symbol with extra bits
+fn prefix_value(value: u32) -> (usize, u32, u32) {
+    let value = value - 1;
+    if value < 4 {
+        return (value as usize, 0, 0);
+    }
+    let highest = 31 - value.leading_zeros();
+    let second = (value >> (highest - 1)) & 1;
+    let extra_bits = highest - 1;
+    (
+        (2 * highest + second) as usize,
+        extra_bits,
+        value & ((1 << extra_bits) - 1),
+    )
+}
+
+enum Symbol {
+    Literal([u8; 4]),
+    // A run copying the pixel before or the one above, by distance code
+    Copy(u32, u32),
+}
+
+// Runs repeating the pixel to the left or the one above are copied, any other
+// pixel is stored as it is
+fn lossless_symbols(pixels: &[[u8; 4]], width: usize) -> Vec<Symbol> {
+    let run = |position: usize, distance: usize| {
+        (position..pixels.len())
+            .take(4096)
+            .take_while(|&at| at >= distance && pixels[at] == pixels[at - distance])
+            .count()
+    };
+    let mut symbols = Vec::new();
+    let mut position = 0;
+    while position < pixels.len() {
+        // Distance codes 1 and 2 stand for the pixel above and the one before
+        let above = run(position, width);
+        let before = run(position, 1);
+        let (length, code) = if above > before {
+            (above, 1)
+        } else {
+            (before, 2)
+        };
+        if length >= 3 {
+            symbols.push(Symbol::Copy(length as u32, code));
+            position += length;
+        } else {
+            symbols.push(Symbol::Literal(pixels[position]));
+            position += 1;
+        }
+    }
+    symbols
+}
+
+// The pixels' prefix codes and the pixels with them, without a color cache.
+// Only the main image may say whether it has more than one set of codes.
+fn write_entropy_image(writer: &mut BitWriter, pixels: &[[u8; 4]], width: usize, main: bool) {
+    let symbols = lossless_symbols(pixels, width);
+
+    // Green with the lengths after it, red, blue, alpha and distance
+    let mut counts = [
+        vec![0u32; 256 + 24],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; 40],
+    ];
+    for symbol in &symbols {
+        match *symbol {
+            Symbol::Literal([red, green, blue, alpha]) => {
+                counts[0][green as usize] += 1;
+                counts[1][red as usize] += 1;
+                counts[2][blue as usize] += 1;
+                counts[3][alpha as usize] += 1;
+            }
+            Symbol::Copy(length, code) => {
+                counts[0][256 + prefix_value(length).0] += 1;
+                counts[4][prefix_value(code).0] += 1;
+            }
+        }
+    }
+
+    writer.write(0, 1); // No color cache
+    if main {
+        writer.write(0, 1); // No meta prefix codes
+    }
+    let codes: Vec<Vec<(u32, u32)>> = counts
+        .iter()
+        .map(|counts| write_prefix_code(writer, counts))
+        .collect();
+    for symbol in symbols {
+        match symbol {
+            Symbol::Literal([red, green, blue, alpha]) => {
+                writer.write_code(codes[0][green as usize]);
+                writer.write_code(codes[1][red as usize]);
+                writer.write_code(codes[2][blue as usize]);
+                writer.write_code(codes[3][alpha as usize]);
+            }
+            Symbol::Copy(length, code) => {
+                let (prefix, extra_bits, extra) = prefix_value(length);
+                writer.write_code(codes[0][256 + prefix]);
+                writer.write(extra, extra_bits);
+                let (prefix, extra_bits, extra) = prefix_value(code);
+                writer.write_code(codes[4][prefix]);
+                writer.write(extra, extra_bits);
+            }
+        }
+    }
+}
+
+// A VP8L bitstream with one set of prefix codes for the whole image. Images
+// of up to 256 colors, as GIF frames mostly are, are stored as indices into a
+// palette, several to a pixel when there are 16 colors or fewer.
+fn lossless_image(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut pixels: Vec<[u8; 4]> = rgba
+        .chunks(4)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+        .collect();
+    let mut writer = BitWriter::new();
+    writer.write(0x2F, 8);
+    writer.write(width as u32 - 1, 14);
+    writer.write(height as u32 - 1, 14);
+    writer.write(u32::from(pixels.iter().any(|pixel| pixel[3] != 0xFF)), 1);
+    writer.write(0, 3); // Version
+
+    let mut palette = pixels.clone();
+    palette.sort_unstable();
+    palette.dedup();
+    let mut coded_width = width;
+    if palette.len() <= 256 {
+        writer.write(1, 1);
+        writer.write(3, 2); // Color indexing
+        writer.write(palette.len() as u32 - 1, 8);
+        // Each color stored as its difference from the one before
+        let deltas: Vec<[u8; 4]> = palette
+            .iter()
+            .scan([0u8; 4], |previous, color| {
+                let delta: [u8; 4] = std::array::from_fn(|i| color[i].wrapping_sub(previous[i]));
+                *previous = *color;
+                Some(delta)
+            })
+            .collect();
+        write_entropy_image(&mut writer, &deltas, palette.len(), false);
+
+        let width_bits = match palette.len() {
+            0..=2 => 3,
+            3..=4 => 2,
+            5..=16 => 1,
+            _ => 0,
+        };
+        let bits_per_index = 8 >> width_bits;
+        coded_width = width.div_ceil(1 << width_bits);
+        let mut packed = vec![[0, 0, 0, 0xFF]; coded_width * height];
+        for (position, pixel) in pixels.iter().enumerate() {
+            let (x, y) = (position % width, position / width);
+            let index = palette.binary_search(pixel).unwrap() as u8;
+            let shift = (x & ((1 << width_bits) - 1)) * bits_per_index;
+            // Indices are held in green
+            packed[y * coded_width + (x >> width_bits)][1] |= index << shift;
+        }
+        pixels = packed;
+    }
+    writer.write(0, 1); // No more transforms
+    write_entropy_image(&mut writer, &pixels, coded_width, true);
+    writer.finish()
+}
+
+// A RIFF chunk, padded to an even length
+fn chunk(output: &mut Vec<u8>, name: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(name);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let [low, middle, high, _] = value.to_le_bytes();
+    [low, middle, high]
+}
+
+// An animated WebP, each frame stored losslessly
+pub fn animated_webp(gif: &GIF) -> Result<Vec<u8>, Error> {
+    let width = gif.logical_screen_descriptor.width as usize;
+    let height = gif.logical_screen_descriptor.height as usize;
+    if width == 0 || height == 0 || width > 16384 || height > 16384 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("WebP can't hold a {}x{} screen.", width, height),
+        ));
+    }
+    let frames = rgba_frames(gif)?;
+    let transparent = frames
+        .iter()
+        .any(|(rgba, _)| rgba.chunks(4).any(|pixel| pixel[3] != 0xFF));
+
+    let profile = icc_profile(gif);
+
+    let mut body = b"WEBP".to_vec();
+    let mut flags = 0x02 | if transparent { 0x10 } else { 0 };
+    if profile.is_some() {
+        flags |= 0x20;
+    }
+    let mut header = vec![flags, 0, 0, 0];
+    header.extend_from_slice(&u24(width as u32 - 1));
+    header.extend_from_slice(&u24(height as u32 - 1));
+    chunk(&mut body, b"VP8X", &header);
+    if let Some(ref profile) = profile {
+        chunk(&mut body, b"ICCP", profile);
+    }
+    let mut animation = vec![0; 4]; // Transparent background
+    animation.extend_from_slice(&(plays(gif).min(u16::MAX as u32) as u16).to_le_bytes());
+    chunk(&mut body, b"ANIM", &animation);
+
+    for (rgba, delay) in frames {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&u24(0)); // Left and top, halved
+        frame.extend_from_slice(&u24(0));
+        frame.extend_from_slice(&u24(width as u32 - 1));
+        frame.extend_from_slice(&u24(height as u32 - 1));
+        frame.extend_from_slice(&u24(delay as u32 * 10));
+        frame.push(0b10); // Drawn without blending, not disposed of
+        chunk(&mut frame, b"VP8L", &lossless_image(&rgba, width, height));
+        chunk(&mut body, b"ANMF", &frame);
+    }
+
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend_from_slice(&body);
+    Ok(output)
+}
@@ -0,0 +1,166 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::gif::{ApplicationExtension, GIF};
+
+// Application extensions other tools agree on, told apart by their
+// identifier and authentication code. Any other stays an ApplicationExtension
+// with its bytes as they were.
+
+// Identifier and authentication code of each known application extension
+const NETSCAPE_LOOP: (&str, &str) = ("NETSCAPE", "2.0");
+const ANIMEXTS_LOOP: (&str, &str) = ("ANIMEXTS", "1.0");
+const XMP_DATA: (&str, &str) = ("XMP Data", "XMP");
+const ICC_PROFILE: (&str, &str) = ("ICCRGBG1", "012");
+
+// The sub-block holding a loop count, in front of it
+const LOOP_SUB_BLOCK: u8 = 1;
+
+// Bytes after an XMP packet. It is stored as it is, so the bytes of the packet
+// double as sub-block sizes, and wherever they lead into this ramp of
+// descending sizes, it ends at its zero.
+const XMP_TRAILER_SIZE: usize = 257;
+
+// Where an ICC profile states its signature, "acsp"
+const ICC_SIGNATURE: core::ops::Range<usize> = 36..40;
+const ICC_HEADER_SIZE: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KnownApplication {
+    Loop(LoopExtension),
+    Xmp(XmpPacket),
+    IccProfile(IccProfile),
+}
+
+// How many times an animation repeats after it has played once, 0 for forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoopExtension {
+    pub repeats: u16,
+}
+
+// XMP metadata, an XML document in UTF-8
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XmpPacket {
+    pub xml: String,
+}
+
+// A color profile for the palette colors, in the format of the ICC
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IccProfile {
+    pub profile: Vec<u8>,
+}
+
+impl IccProfile {
+    // The color space of the profile, such as "RGB " or "GRAY"
+    pub fn color_space(&self) -> &[u8] {
+        &self.profile[16..20]
+    }
+}
+
+fn is(application: &ApplicationExtension, (identifier, code): (&str, &str)) -> bool {
+    application.identifier == identifier && application.authentication_code == code
+}
+
+pub(crate) fn is_xmp(identifier: &str, authentication_code: &str) -> bool {
+    (identifier, authentication_code) == XMP_DATA
+}
+
+fn xmp_trailer() -> impl Iterator<Item = u8> {
+    core::iter::once(1).chain((0..=255).rev())
+}
+
+// Whether stored bytes read as data sub-blocks end exactly where they do,
+// which is what lets them be written out as they are
+pub(crate) fn fits_sub_blocks(data: &[u8]) -> bool {
+    let mut position = 0;
+    while position < data.len() {
+        if data[position] == 0 {
+            return false;
+        }
+        position += 1 + data[position] as usize;
+    }
+    position == data.len()
+}
+
+impl ApplicationExtension {
+    // The typed content of a known application extension. None for any other,
+    // and for a known one whose data doesn't hold what it should.
+    pub fn known(&self) -> Option<KnownApplication> {
+        if is(self, NETSCAPE_LOOP) || is(self, ANIMEXTS_LOOP) {
+            // Other sub-blocks, such as a buffer size, may come after it
+            return match self.data[..] {
+                [LOOP_SUB_BLOCK, low, high, ..] => Some(KnownApplication::Loop(LoopExtension {
+                    repeats: u16::from_le_bytes([low, high]),
+                })),
+                _ => None,
+            };
+        }
+        if is(self, XMP_DATA) {
+            let end = self.data.len().checked_sub(XMP_TRAILER_SIZE)?;
+            if !self.data[end..].iter().copied().eq(xmp_trailer()) {
+                return None;
+            }
+            let xml = core::str::from_utf8(&self.data[..end]).ok()?;
+            return Some(KnownApplication::Xmp(XmpPacket {
+                xml: xml.to_string(),
+            }));
+        }
+        if is(self, ICC_PROFILE) {
+            if self.data.len() < ICC_HEADER_SIZE || &self.data[ICC_SIGNATURE] != b"acsp" {
+                return None;
+            }
+            return Some(KnownApplication::IccProfile(IccProfile {
+                profile: self.data.clone(),
+            }));
+        }
+        None
+    }
+}
+
+impl From<&KnownApplication> for ApplicationExtension {
+    fn from(known: &KnownApplication) -> ApplicationExtension {
+        let ((identifier, authentication_code), data) = match known {
+            KnownApplication::Loop(looping) => {
+                let [low, high] = looping.repeats.to_le_bytes();
+                (NETSCAPE_LOOP, alloc::vec![LOOP_SUB_BLOCK, low, high])
+            }
+            KnownApplication::Xmp(packet) => {
+                let mut data = packet.xml.as_bytes().to_vec();
+                data.extend(xmp_trailer());
+                (XMP_DATA, data)
+            }
+            KnownApplication::IccProfile(profile) => (ICC_PROFILE, profile.profile.clone()),
+        };
+        ApplicationExtension {
+            identifier: identifier.to_string(),
+            authentication_code: authentication_code.to_string(),
+            data,
+        }
+    }
+}
+
+impl GIF {
+    // Put the application extension in place of the first one with its
+    // identifier and authentication code, dropping any others, or add it
+    pub fn set_application(&mut self, application: ApplicationExtension) {
+        let same = |other: &ApplicationExtension| {
+            other.identifier == application.identifier
+                && other.authentication_code == application.authentication_code
+        };
+        match self.application_extensions.iter().position(same) {
+            Some(first) => {
+                let mut index = 0;
+                self.application_extensions.retain(|other| {
+                    index += 1;
+                    index - 1 <= first || !same(other)
+                });
+                self.application_extensions[first] = application;
+            }
+            None => self.application_extensions.push(application),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+
+use crate::gif::{
+    global_color_table_size, local_color_table_size, read_color_table, read_gif_header,
+    read_logical_screen_descriptor, BlockLabel, ByteReader, ColorTable, GIFHeader,
+    LogicalScreenDescriptor, ParseError,
+};
+use crate::sub_blocks::{BlockSource, SubBlockReader};
+
+// Parsing as a stream of events, for scanners that look at every block once
+// and have no use for the GIF model. Nothing is kept between events but the
+// block at hand, and image data is handed over still compressed.
+
+// Callbacks of parse_events, each given the stream offset of what it reports.
+// All of them do nothing unless overridden.
+pub trait GifVisitor {
+    // The header, logical screen descriptor and global color table
+    fn on_header(
+        &mut self,
+        _header: &GIFHeader,
+        _screen: &LogicalScreenDescriptor,
+        _global_color_table: Option<&ColorTable>,
+    ) {
+    }
+
+    // Any extension with its label, the graphic control extension ahead of
+    // a frame included
+    fn on_extension(&mut self, _position: usize, _label: u8, _sub_blocks: SubBlocks) {}
+
+    // An image descriptor, before its data
+    fn on_frame_start(&mut self, _frame: &FrameStart) {}
+
+    // One sub-block of a frame's LZW data
+    fn on_image_data_chunk(&mut self, _position: usize, _chunk: &[u8]) {}
+
+    // The end of a frame's data, just past its block terminator
+    fn on_frame_end(&mut self, _position: usize) {}
+
+    // The trailer, or a byte no block starts with, which ends the stream all
+    // the same. Nothing after it is read.
+    fn on_trailer(&mut self, _position: usize) {}
+}
+
+// The data sub-blocks of an extension, each as it was stored
+#[derive(Debug, Clone)]
+pub struct SubBlocks<'a> {
+    data: &'a [u8], // Sub-blocks with their size bytes, without the terminator
+}
+
+impl<'a> Iterator for SubBlocks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let (&size, rest) = self.data.split_first()?;
+        let (block, rest) = rest.split_at(size as usize);
+        self.data = rest;
+        Some(block)
+    }
+}
+
+// An image descriptor with its local color table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameStart {
+    pub position: usize, // Stream offset of the image separator
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+    pub packed_field: u8,
+    pub local_color_table: Option<ColorTable>,
+    pub lzw_minimum_code_size: u8,
+}
+
+// Where events are read from
+trait Input: BlockSource {
+    // None at the end of the input
+    fn next_byte(&mut self) -> Result<Option<u8>, Self::Error>;
+
+    fn read_u8(&mut self) -> Result<u8, Self::Error> {
+        let mut byte = [0];
+        self.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+impl Input for ByteReader<'_> {
+    fn next_byte(&mut self) -> Result<Option<u8>, ParseError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.read_u8()?))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Input for R {
+    fn next_byte(&mut self) -> Result<Option<u8>, io::Error> {
+        let mut byte = [0];
+        loop {
+            match self.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn read_table<I: Input>(
+    input: &mut I,
+    size: usize,
+    position: &mut usize,
+) -> Result<Option<ColorTable>, I::Error> {
+    if size == 0 {
+        return Ok(None);
+    }
+    let mut bytes = vec![0; 3 * size];
+    input.read_exact(&mut bytes)?;
+    let reader = &mut ByteReader {
+        data: &bytes,
+        position: *position,
+    };
+    *position += bytes.len();
+    Ok(Some(read_color_table(reader, size)?))
+}
+
+fn visit<I: Input, V: GifVisitor + ?Sized>(mut input: I, visitor: &mut V) -> Result<(), I::Error> {
+    let mut start = [0; 13];
+    input.read_exact(&mut start)?;
+    let reader = &mut ByteReader {
+        data: &start,
+        position: 0,
+    };
+    let header = read_gif_header(reader)?;
+    let screen = read_logical_screen_descriptor(reader)?;
+    let mut position = start.len();
+    let global_color_table = read_table(
+        &mut input,
+        global_color_table_size(screen.packed_field),
+        &mut position,
+    )?;
+    visitor.on_header(&header, &screen, global_color_table.as_ref());
+
+    // Reused from extension to extension
+    let mut sub_blocks = Vec::new();
+    loop {
+        let start = position;
+        let introducer = match input.next_byte()? {
+            Some(introducer) => introducer,
+            None => return Ok(()), // No trailer
+        };
+        position += 1;
+        match BlockLabel::introducer(introducer) {
+            Some(BlockLabel::Extension) => {
+                let label = input.read_u8()?;
+                sub_blocks.clear();
+                let mut blocks = SubBlockReader::new(input);
+                while let Some(block) = blocks.next_block()? {
+                    sub_blocks.push(block.len() as u8);
+                    sub_blocks.extend_from_slice(block);
+                }
+                input = blocks.into_inner();
+                position += 2 + sub_blocks.len();
+                visitor.on_extension(start, label, SubBlocks { data: &sub_blocks });
+            }
+            Some(BlockLabel::Image) => {
+                let mut fields = [0; 9];
+                input.read_exact(&mut fields)?;
+                position += fields.len();
+                let local_color_table =
+                    read_table(&mut input, local_color_table_size(fields[8]), &mut position)?;
+                let lzw_minimum_code_size = input.read_u8()?;
+                position += 1;
+                visitor.on_frame_start(&FrameStart {
+                    position: start,
+                    left: u16::from_le_bytes([fields[0], fields[1]]),
+                    top: u16::from_le_bytes([fields[2], fields[3]]),
+                    width: u16::from_le_bytes([fields[4], fields[5]]),
+                    height: u16::from_le_bytes([fields[6], fields[7]]),
+                    packed_field: fields[8],
+                    local_color_table,
+                    lzw_minimum_code_size,
+                });
+                let mut blocks = SubBlockReader::new(input);
+                while let Some(chunk) = blocks.next_block()? {
+                    visitor.on_image_data_chunk(position + 1, chunk);
+                    position += 1 + chunk.len();
+                }
+                input = blocks.into_inner();
+                position += 1; // Block terminator
+                visitor.on_frame_end(position);
+            }
+            _ => {
+                visitor.on_trailer(start);
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Parse a GIF from any reader into events for `visitor`. Between blocks the
+// reader is read a byte at a time, so an unbuffered one is best wrapped in a
+// BufReader.
+#[cfg(feature = "std")]
+pub fn parse_events<R: Read, V: GifVisitor + ?Sized>(
+    reader: R,
+    visitor: &mut V,
+) -> Result<(), io::Error> {
+    visit(reader, visitor)
+}
+
+// parse_events for a GIF held in memory
+pub fn parse_events_bytes<V: GifVisitor + ?Sized>(
+    data: &[u8],
+    visitor: &mut V,
+) -> Result<(), ParseError> {
+    visit(ByteReader { data, position: 0 }, visitor)
+}
@@ -0,0 +1,1417 @@
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use crate::application::{fits_sub_blocks, is_xmp};
+use crate::lzw::{lzw_compress, read_lzw_blocks};
+use crate::sub_blocks::{Memory, SubBlockReader, SubBlockWriter};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GIFHeader {
+    pub signature: [u8; 3], // GIF
+    pub version: [u8; 3],   // 87a or 89a
+}
+
+// The two versions of the format. 87a has no extension blocks; readers skip
+// them, but a file holding them should say 89a.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GifVersion {
+    Gif87a,
+    Gif89a,
+}
+
+impl GIFHeader {
+    // The version the header names, None for any other
+    pub fn gif_version(&self) -> Option<GifVersion> {
+        match &self.version {
+            b"87a" => Some(GifVersion::Gif87a),
+            b"89a" => Some(GifVersion::Gif89a),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogicalScreenDescriptor {
+    pub width: u16,
+    pub height: u16,
+    pub packed_field: u8,
+    pub background_color_index: u8,
+    pub pixel_aspect_ratio: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColorTable {
+    pub colors: Vec<[u8; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GraphicsControlExtension {
+    pub packed_field: u8,
+    pub delay_time: u16,
+    pub transparent_color_index: u8,
+}
+
+impl GraphicsControlExtension {
+    pub fn disposal_method(&self) -> DisposalMethod {
+        DisposalMethod::from_packed_field(self.packed_field)
+    }
+
+    pub fn set_disposal_method(&mut self, disposal_method: DisposalMethod) {
+        self.packed_field = (self.packed_field & !DISPOSAL_BITS) | disposal_method.packed_bits();
+    }
+}
+
+// Bits 2 to 4 of a graphic control extension's packed field
+const DISPOSAL_BITS: u8 = 0b0001_1100;
+
+// What happens to a frame once its delay is over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisposalMethod {
+    None,              // Up to the decoder, which leaves it in place
+    DoNotDispose,      // Left in place
+    RestoreBackground, // Its area is cleared
+    RestorePrevious,   // What was there before comes back
+}
+
+impl DisposalMethod {
+    // The reserved values 4 to 7 read as None
+    pub fn from_packed_field(packed_field: u8) -> DisposalMethod {
+        match (packed_field & DISPOSAL_BITS) >> 2 {
+            1 => DisposalMethod::DoNotDispose,
+            2 => DisposalMethod::RestoreBackground,
+            3 => DisposalMethod::RestorePrevious,
+            _ => DisposalMethod::None,
+        }
+    }
+
+    // The method in place in a packed field, the other bits clear
+    pub fn packed_bits(self) -> u8 {
+        let value = match self {
+            DisposalMethod::None => 0,
+            DisposalMethod::DoNotDispose => 1,
+            DisposalMethod::RestoreBackground => 2,
+            DisposalMethod::RestorePrevious => 3,
+        };
+        value << 2
+    }
+}
+
+// The bytes blocks start with: an introducer, followed by a label for
+// extensions
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockLabel {
+    Extension = 0x21, // The introducer of every extension
+    GraphicControl = 0xF9,
+    Comment = 0xFE,
+    Application = 0xFF,
+    PlainText = 0x01,
+    Image = 0x2C, // The image separator
+    Trailer = 0x3B,
+}
+
+impl BlockLabel {
+    // The block a byte between blocks starts
+    pub fn introducer(byte: u8) -> Option<BlockLabel> {
+        [
+            BlockLabel::Extension,
+            BlockLabel::Image,
+            BlockLabel::Trailer,
+        ]
+        .into_iter()
+        .find(|label| *label as u8 == byte)
+    }
+
+    // The extension a label after the introducer stands for
+    pub fn extension(byte: u8) -> Option<BlockLabel> {
+        [
+            BlockLabel::GraphicControl,
+            BlockLabel::Comment,
+            BlockLabel::Application,
+            BlockLabel::PlainText,
+        ]
+        .into_iter()
+        .find(|label| *label as u8 == byte)
+    }
+}
+
+impl From<BlockLabel> for u8 {
+    fn from(label: BlockLabel) -> u8 {
+        label as u8
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommentExtension {
+    pub comments: Vec<Vec<u8>>, // Raw sub-blocks, comments may carry binary data
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ApplicationExtension {
+    pub identifier: String,
+    pub authentication_code: String,
+    pub data: Vec<u8>,
+}
+
+#[repr(C)] // Ensures the struct has the same memory layout as in C
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlainTextExtension {
+    pub block_size: u8,
+    pub text_grid_left_position: u16,
+    pub text_grid_top_position: u16,
+    pub text_grid_width: u16,
+    pub text_grid_height: u16,
+    pub character_cell_width: u8,
+    pub character_cell_height: u8,
+    pub text_foreground_color_index: u8,
+    pub text_background_color_index: u8,
+    pub plain_text_data: Vec<u8>,
+}
+
+// Rows of an interlaced frame are stored in four passes
+const INTERLACE_PASSES: [(usize, usize); 4] = [(0, 8), (4, 8), (2, 4), (1, 2)];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImageDescriptor {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+    pub packed_field: u8,
+    // The graphic control extension in front of the frame, with its delay,
+    // disposal and transparent index
+    pub graphics_control_extension: Option<GraphicsControlExtension>,
+    pub local_color_table: Option<ColorTable>, // Include this field
+    pub lzw_minimum_code_size: u8,             // Include this field
+    pub image_data: Vec<u8>,                   // Include the image data field
+    // Where the compressed sub-blocks sit in the source file. Whoever changes
+    // image_data clears it, so the writer knows it can't copy them verbatim.
+    pub compressed_range: Option<Range<usize>>,
+}
+
+impl ImageDescriptor {
+    // The palette index its graphic control extension makes see-through
+    pub fn transparent_color_index(&self) -> Option<u8> {
+        self.graphics_control_extension
+            .as_ref()
+            .filter(|gce| gce.packed_field & 0b1 != 0)
+            .map(|gce| gce.transparent_color_index)
+    }
+
+    // What happens to the frame once its delay is over
+    pub fn disposal_method(&self) -> DisposalMethod {
+        self.graphics_control_extension
+            .as_ref()
+            .map_or(DisposalMethod::None, |gce| gce.disposal_method())
+    }
+
+    // The pixels row by row from the top. Interlaced frames store their rows
+    // in four passes. Missing pixels are left out, and so are the stored rows
+    // of a short interlaced frame that belong past them.
+    pub fn pixels_in_row_order(&self) -> Vec<u8> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let stored = &self.image_data[..self.image_data.len().min(width * height)];
+        if self.packed_field & 0b0100_0000 == 0 {
+            return stored.to_vec();
+        }
+
+        let mut rows = INTERLACE_PASSES
+            .iter()
+            .flat_map(|&(start, step)| (start..height).step_by(step));
+        let mut pixels = vec![0; stored.len()];
+        for stored_row in stored.chunks(width.max(1)) {
+            if let Some(row) = rows.next() {
+                let start = row * width;
+                if start >= pixels.len() {
+                    continue;
+                }
+                let end = (start + stored_row.len()).min(pixels.len());
+                pixels[start..end].copy_from_slice(&stored_row[..end - start]);
+            }
+        }
+        pixels
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GIF {
+    pub header: GIFHeader,
+    pub logical_screen_descriptor: LogicalScreenDescriptor,
+    pub global_color_table: Option<ColorTable>,
+    pub comment_extensions: Vec<CommentExtension>,
+    pub application_extensions: Vec<ApplicationExtension>,
+    pub plain_text_extensions: Vec<PlainTextExtension>,
+    pub image_descriptors: Vec<ImageDescriptor>,
+    pub trailing_data: Vec<u8>, // Bytes after the trailer, which decoders ignore
+}
+
+impl GIF {
+    // Whether it holds blocks only GIF89a defines: graphic control, comment,
+    // application or plain text extensions
+    pub fn needs_89a(&self) -> bool {
+        !self.comment_extensions.is_empty()
+            || !self.application_extensions.is_empty()
+            || !self.plain_text_extensions.is_empty()
+            || self
+                .image_descriptors
+                .iter()
+                .any(|frame| frame.graphics_control_extension.is_some())
+    }
+
+    // The version bytes the writer puts in the header: those stored, unless
+    // a 87a GIF has gained blocks it can't hold, which makes it 89a
+    pub fn output_version(&self) -> [u8; 3] {
+        match self.header.gif_version() {
+            Some(GifVersion::Gif87a) if self.needs_89a() => *b"89a",
+            _ => self.header.version,
+        }
+    }
+}
+
+// Why a GIF failed to parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedEnd,         // The input stops inside a block
+    Truncated,             // The input stops before the logical screen descriptor
+    Invalid(&'static str), // A block that breaks the format
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => f.write_str("GIF ends in the middle of a block."),
+            ParseError::Truncated => f.write_str("GIF is truncated."),
+            ParseError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+#[cfg(feature = "std")]
+impl From<ParseError> for io::Error {
+    fn from(error: ParseError) -> io::Error {
+        let kind = match error {
+            ParseError::UnexpectedEnd | ParseError::Truncated => io::ErrorKind::UnexpectedEof,
+            ParseError::Invalid(_) => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+// Reads through a block held in memory. It counts the bytes taken so
+// positions in the stream can be traced and recorded.
+#[derive(Clone)]
+pub(crate) struct ByteReader<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) position: usize, // Stream offset of data[0]
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], ParseError> {
+        if self.data.len() < count {
+            return Err(ParseError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, ParseError> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], ParseError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+}
+
+fn track_position(reader: &ByteReader, description: &str) -> usize {
+    trace!("{} at byte position: {}", description, reader.position);
+    reader.position
+}
+
+pub(crate) fn read_gif_header(reader: &mut ByteReader) -> Result<GIFHeader, ParseError> {
+    track_position(reader, "Start GIF Header");
+    let signature = reader.read_array()?;
+    let version = reader.read_array()?;
+    if &signature != b"GIF" {
+        return Err(ParseError::Invalid("Not a GIF file."));
+    }
+
+    track_position(reader, "End GIF Header");
+    let header = GIFHeader { signature, version };
+    if header.gif_version().is_none() {
+        // Other versions were never defined, read them as 89a
+        warn!(
+            "Unknown GIF version {}, reading it as 89a",
+            String::from_utf8_lossy(&version)
+        );
+    }
+    Ok(header)
+}
+
+pub(crate) fn read_logical_screen_descriptor(
+    reader: &mut ByteReader,
+) -> Result<LogicalScreenDescriptor, ParseError> {
+    track_position(reader, "Start Logical Screen Descriptor");
+    let width = reader.read_u16()?;
+    let height = reader.read_u16()?;
+    let packed_field = reader.read_u8()?;
+    let background_color_index = reader.read_u8()?;
+    let pixel_aspect_ratio = reader.read_u8()?;
+
+    track_position(reader, "End Logical Screen Descriptor");
+    Ok(LogicalScreenDescriptor {
+        width,
+        height,
+        packed_field,
+        background_color_index,
+        pixel_aspect_ratio,
+    })
+}
+
+pub(crate) fn read_color_table(
+    reader: &mut ByteReader,
+    size: usize,
+) -> Result<ColorTable, ParseError> {
+    let mut colors = Vec::with_capacity(size);
+    for _ in 0..size {
+        colors.push(reader.read_array()?);
+    }
+
+    Ok(ColorTable { colors })
+}
+
+fn read_graphics_control_extension(
+    reader: &mut ByteReader,
+) -> Result<GraphicsControlExtension, ParseError> {
+    if reader.read_u8()? != 4 {
+        return Err(ParseError::Invalid(
+            "Invalid block size for graphics control extension.",
+        ));
+    }
+
+    let packed_field = reader.read_u8()?;
+    let delay_time = reader.read_u16()?;
+    let transparent_color_index = reader.read_u8()?;
+    reader.read_u8()?; // Block terminator
+
+    Ok(GraphicsControlExtension {
+        packed_field,
+        delay_time,
+        transparent_color_index,
+    })
+}
+
+// Data sub-blocks up to the block terminator, each as it was stored
+fn read_sub_blocks(reader: &mut ByteReader) -> Result<Vec<Vec<u8>>, ParseError> {
+    let mut sub_blocks = SubBlockReader::new(reader.clone());
+    let mut blocks = Vec::new();
+    while let Some(block) = sub_blocks.next_block()? {
+        blocks.push(block.to_vec());
+    }
+    *reader = sub_blocks.into_inner();
+    Ok(blocks)
+}
+
+fn read_comment_extension(reader: &mut ByteReader) -> Result<CommentExtension, ParseError> {
+    Ok(CommentExtension {
+        comments: read_sub_blocks(reader)?,
+    })
+}
+
+fn read_application_extension(reader: &mut ByteReader) -> Result<ApplicationExtension, ParseError> {
+    if reader.read_u8()? != 11 {
+        return Err(ParseError::Invalid(
+            "Invalid application extension block size.",
+        ));
+    }
+
+    let identifier = String::from_utf8_lossy(reader.read_bytes(8)?).into_owned();
+    let authentication_code = String::from_utf8_lossy(reader.read_bytes(3)?).into_owned();
+    let blocks = read_sub_blocks(reader)?;
+    // XMP is stored raw, its bytes doubling as sub-block sizes, so they are
+    // kept with their sizes
+    let data = if is_xmp(&identifier, &authentication_code) {
+        blocks
+            .iter()
+            .flat_map(|block| core::iter::once(block.len() as u8).chain(block.iter().copied()))
+            .collect()
+    } else {
+        blocks.concat()
+    };
+
+    Ok(ApplicationExtension {
+        identifier,
+        authentication_code,
+        data,
+    })
+}
+
+fn read_plain_text_extension(reader: &mut ByteReader) -> Result<PlainTextExtension, ParseError> {
+    Ok(PlainTextExtension {
+        block_size: reader.read_u8()?,
+        text_grid_left_position: reader.read_u16()?,
+        text_grid_top_position: reader.read_u16()?,
+        text_grid_width: reader.read_u16()?,
+        text_grid_height: reader.read_u16()?,
+        character_cell_width: reader.read_u8()?,
+        character_cell_height: reader.read_u8()?,
+        text_foreground_color_index: reader.read_u8()?,
+        text_background_color_index: reader.read_u8()?,
+        plain_text_data: read_sub_blocks(reader)?.concat(),
+    })
+}
+
+// The image separator has already been read by the caller
+fn read_image_descriptor(reader: &mut ByteReader) -> Result<ImageDescriptor, ParseError> {
+    // Read the image descriptor fields
+    let left = reader.read_u16()?;
+    let top = reader.read_u16()?;
+    let width = reader.read_u16()?;
+    let height = reader.read_u16()?;
+    let packed_field = reader.read_u8()?;
+
+    // Determine the local color table size (if present)
+    let local_color_table_size = local_color_table_size(packed_field);
+
+    // Read the local color table (if present)
+    let local_color_table = if local_color_table_size > 0 {
+        Some(read_color_table(reader, local_color_table_size)?)
+    } else {
+        None
+    };
+
+    // Read the LZW minimum code size
+    let lzw_minimum_code_size = reader.read_u8()?;
+
+    // Read the image data using LZW decompression
+    let compressed_start = reader.position;
+    let pixels = width as usize * height as usize;
+    let image_data = read_lzw_blocks(reader, lzw_minimum_code_size, pixels)?;
+    let compressed_range = compressed_start..reader.position;
+
+    Ok(ImageDescriptor {
+        left,
+        top,
+        width,
+        height,
+        packed_field,
+        graphics_control_extension: None,
+        local_color_table,
+        lzw_minimum_code_size,
+        image_data,
+        compressed_range: Some(compressed_range),
+    })
+}
+
+// Skip data sub-blocks up to and including the block terminator
+pub(crate) fn skip_sub_blocks(reader: &mut ByteReader) -> Result<(), ParseError> {
+    let mut sub_blocks = SubBlockReader::new(reader.clone());
+    sub_blocks.skip_to_end()?;
+    *reader = sub_blocks.into_inner();
+    Ok(())
+}
+
+// Entries in the global color table, 0 when there is none
+pub(crate) fn global_color_table_size(packed_field: u8) -> usize {
+    if (packed_field & 0b10000000) != 0 {
+        1 << ((packed_field & 0b00000111) + 1)
+    } else {
+        0
+    }
+}
+
+// Entries in the local color table of a frame, 0 when there is none
+pub(crate) fn local_color_table_size(packed_field: u8) -> usize {
+    if (packed_field & 0b10000000) != 0 {
+        1 << ((packed_field & 0b00000111) + 1)
+    } else {
+        0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ParserState {
+    Header, // Header, logical screen descriptor and global color table
+    Blocks,
+    Done,
+}
+
+// Something odd about a GIF that still parses, found at `position` in the
+// input
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseWarning {
+    pub position: usize,
+    pub kind: ParseWarningKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParseWarningKind {
+    UnknownVersion,      // Read as 89a
+    TrailingData(usize), // Bytes after the trailer
+    MissingTrailer,      // The input ends without one
+    UnexpectedByte(u8),  // A byte no block starts with, taken for the trailer
+    CutShort(usize),     // Bytes of a block the input ends inside, dropped
+    BlockSize {
+        found: usize,
+        expected: usize,
+    }, // A block of a fixed size that isn't
+    IndexOverrun {
+        frame: usize,
+        index: u8,
+        entries: usize,
+    }, // A pixel past the color table
+}
+
+impl fmt::Display for ParseWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseWarningKind::UnknownVersion => f.write_str("unknown GIF version, read as 89a"),
+            ParseWarningKind::TrailingData(length) => {
+                write!(f, "{} bytes after the trailer", length)
+            }
+            ParseWarningKind::MissingTrailer => f.write_str("no trailer"),
+            ParseWarningKind::UnexpectedByte(byte) => {
+                write!(
+                    f,
+                    "byte 0x{:02x} starts no block, taken for the trailer",
+                    byte
+                )
+            }
+            ParseWarningKind::CutShort(length) => {
+                write!(
+                    f,
+                    "the input ends {} bytes into a block, which was dropped",
+                    length
+                )
+            }
+            ParseWarningKind::BlockSize { found, expected } => {
+                write!(
+                    f,
+                    "block of {} bytes where {} are standard",
+                    found, expected
+                )
+            }
+            ParseWarningKind::IndexOverrun {
+                frame,
+                index,
+                entries,
+            } => write!(
+                f,
+                "frame {} uses color {} of a table of {}",
+                frame, index, entries
+            ),
+        }
+    }
+}
+
+// A GIF with what was odd about its input
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseOutcome {
+    pub gif: GIF,
+    pub warnings: Vec<ParseWarning>,
+}
+
+// A block passed over by a parser from GifParser::skipping_corrupt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedBlock {
+    pub position: usize, // Stream offset of its first byte
+    pub length: usize,
+    pub frame: Option<usize>, // The frame it was, counting skipped ones, if it was one
+    pub error: ParseError,
+}
+
+// GIF parser that does no I/O of its own: bytes are pushed in as they arrive
+// and every block is parsed once it is complete. The blocking, async and
+// in-memory fronts all drive it.
+pub struct GifParser {
+    state: ParserState,
+    gif: Option<GIF>,
+    buffer: Vec<u8>,   // Start of a block that hasn't fully arrived
+    position: usize,   // Stream offset of the first unparsed byte
+    needed: usize,     // Least length the buffered block can have
+    sub_blocks: usize, // How far the sub-blocks of the buffered block are checked
+    // Read ahead of the frame it belongs to. One that no frame follows is
+    // dropped.
+    graphics_control_extension: Option<GraphicsControlExtension>,
+    skip_corrupt: bool,
+    skipped: Vec<SkippedBlock>,
+    warnings: Vec<ParseWarning>,
+}
+
+impl GifParser {
+    pub fn new() -> GifParser {
+        GifParser {
+            state: ParserState::Header,
+            gif: None,
+            buffer: Vec::new(),
+            position: 0,
+            needed: 13,
+            sub_blocks: 0,
+            graphics_control_extension: None,
+            skip_corrupt: false,
+            skipped: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    // A parser that passes over blocks that fail to parse, frames with a
+    // broken LZW stream say, instead of failing, and over bytes between
+    // blocks up to the next block introducer. What it passed over is listed
+    // by skipped().
+    pub fn skipping_corrupt() -> GifParser {
+        GifParser {
+            skip_corrupt: true,
+            ..GifParser::new()
+        }
+    }
+
+    pub fn skipped(&self) -> &[SkippedBlock] {
+        &self.skipped
+    }
+
+    // How many more bytes to push before the parser can make progress. Reading
+    // no more than this never takes bytes past the trailer. 0 once done.
+    pub fn wanted(&self) -> usize {
+        self.needed.saturating_sub(self.buffer.len())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == ParserState::Done
+    }
+
+    // Bytes pushed past the trailer are kept as the GIF's trailing data
+    pub fn push(&mut self, data: &[u8]) -> Result<(), ParseError> {
+        if self.buffer.is_empty() {
+            // Whole blocks are parsed straight from the input
+            let consumed = self.parse_blocks(data)?;
+            self.keep(&data[consumed..]);
+        } else {
+            let mut buffer = core::mem::take(&mut self.buffer);
+            buffer.extend_from_slice(data);
+            let consumed = self.parse_blocks(&buffer)?;
+            buffer.drain(..consumed);
+            if !self.is_done() {
+                self.buffer = buffer;
+            } else {
+                self.keep(&buffer);
+            }
+        }
+        Ok(())
+    }
+
+    // Hold on to input no block has taken yet
+    fn keep(&mut self, rest: &[u8]) {
+        if !self.is_done() {
+            self.buffer.extend_from_slice(rest);
+        } else if let Some(ref mut gif) = self.gif {
+            gif.trailing_data.extend_from_slice(rest);
+        }
+    }
+
+    // Call at the end of the input. A block cut short is parsed as far as it
+    // goes, the way a reader reaching the end of a file would.
+    pub fn finish(self) -> Result<GIF, ParseError> {
+        Ok(self.finish_with_warnings()?.gif)
+    }
+
+    // finish, with what was odd about the input
+    pub fn finish_with_warnings(mut self) -> Result<ParseOutcome, ParseError> {
+        if self.state == ParserState::Header || !self.buffer.is_empty() {
+            let buffer = core::mem::take(&mut self.buffer);
+            self.parse_block(&buffer)?;
+            self.position += buffer.len();
+        }
+        self.outcome()
+    }
+
+    // finish for input that may have been cut off, by a download that
+    // stopped part way say. A block cut short that can't be parsed as far as
+    // it goes is dropped, with a CutShort warning, so what is left is the
+    // complete blocks.
+    pub fn finish_truncated(mut self) -> Result<ParseOutcome, ParseError> {
+        if self.state == ParserState::Header {
+            return self.finish_with_warnings();
+        }
+        let buffer = core::mem::take(&mut self.buffer);
+        match self.parse_block(&buffer) {
+            Ok(()) => {}
+            Err(ParseError::UnexpectedEnd) => self.warn(ParseWarningKind::CutShort(buffer.len())),
+            Err(error) => return Err(error),
+        }
+        self.position += buffer.len();
+        self.outcome()
+    }
+
+    // What is left once the input has all been parsed
+    fn outcome(mut self) -> Result<ParseOutcome, ParseError> {
+        let gif = self.gif.take().ok_or(ParseError::Truncated)?;
+        if self.state != ParserState::Done {
+            self.warn(ParseWarningKind::MissingTrailer);
+        } else if !gif.trailing_data.is_empty() {
+            // Trailing data starts where the trailer ends
+            self.warn(ParseWarningKind::TrailingData(gif.trailing_data.len()));
+        }
+        Ok(ParseOutcome {
+            gif,
+            warnings: self.warnings,
+        })
+    }
+
+    // Note something odd about the block at the current position
+    fn warn(&mut self, kind: ParseWarningKind) {
+        self.warnings.push(ParseWarning {
+            position: self.position,
+            kind,
+        });
+    }
+
+    // push for a whole input held in memory, calling progress with
+    // (Stage::Parsing, bytes parsed, total bytes) as it goes. Blocks are
+    // still parsed where they lie; a step only ends where a block does.
+    pub fn push_with_progress(
+        &mut self,
+        data: &[u8],
+        progress: &(dyn Fn(Stage, usize, usize) + Sync),
+    ) -> Result<(), ParseError> {
+        let mut consumed = 0;
+        progress(Stage::Parsing, 0, data.len());
+        while !self.is_done() && consumed < data.len() {
+            let end = data.len().min(consumed + PROGRESS_STEP.max(self.wanted()));
+            consumed += self.parse_blocks(&data[consumed..end])?;
+            progress(Stage::Parsing, consumed, data.len());
+            if end == data.len() {
+                break;
+            }
+        }
+        // What is left is trailing data, or a block cut short by the end of
+        // input
+        self.push(&data[consumed..])?;
+        progress(Stage::Parsing, data.len(), data.len());
+        Ok(())
+    }
+
+    // Parse the complete blocks at the start of data, returning how many bytes
+    // they took
+    fn parse_blocks(&mut self, data: &[u8]) -> Result<usize, ParseError> {
+        let mut consumed = 0;
+        while !self.is_done() {
+            match self.block_length(&data[consumed..]) {
+                Ok(length) => {
+                    let block = &data[consumed..consumed + length];
+                    let skipping = self.skip_corrupt && self.state == ParserState::Blocks;
+                    if skipping && BlockLabel::introducer(block[0]).is_none() {
+                        self.skip(
+                            block,
+                            None,
+                            ParseError::Invalid("Bytes that start no block."),
+                        );
+                    } else if let Err(error) = self.parse_block(block) {
+                        if !skipping {
+                            return Err(error);
+                        }
+                        let image = BlockLabel::introducer(block[0]) == Some(BlockLabel::Image);
+                        let frame = image.then(|| {
+                            let parsed = self
+                                .gif
+                                .as_ref()
+                                .map_or(0, |gif| gif.image_descriptors.len());
+                            parsed
+                                + self
+                                    .skipped
+                                    .iter()
+                                    .filter(|skipped| skipped.frame.is_some())
+                                    .count()
+                        });
+                        self.skip(block, frame, error);
+                    }
+                    consumed += length;
+                    self.position += length;
+                }
+                Err(needed) => {
+                    self.needed = needed;
+                    return Ok(consumed);
+                }
+            }
+        }
+        self.needed = 0;
+        Ok(consumed)
+    }
+
+    fn skip(&mut self, block: &[u8], frame: Option<usize>, error: ParseError) {
+        debug!(
+            "Skipping {} bytes at {}: {}",
+            block.len(),
+            self.position,
+            error
+        );
+        if frame.is_some() {
+            // It belonged to the frame
+            self.graphics_control_extension = None;
+        }
+        self.skipped.push(SkippedBlock {
+            position: self.position,
+            length: block.len(),
+            frame,
+            error,
+        });
+    }
+
+    // Length of the block data starts with, or the least length it can have
+    // when it is incomplete
+    fn block_length(&mut self, data: &[u8]) -> Result<usize, usize> {
+        let start = match self.state {
+            ParserState::Header => {
+                if data.len() < 13 {
+                    return Err(13);
+                }
+                let length = 13 + 3 * global_color_table_size(data[10]);
+                return if data.len() < length {
+                    Err(length)
+                } else {
+                    Ok(length)
+                };
+            }
+            ParserState::Blocks => match data.first().map(|&byte| BlockLabel::introducer(byte)) {
+                None => return Err(1),
+                Some(Some(BlockLabel::Image)) => {
+                    if data.len() < 10 {
+                        return Err(10);
+                    }
+                    // Descriptor, local color table and LZW minimum code size
+                    10 + 3 * local_color_table_size(data[9]) + 1
+                }
+                Some(Some(BlockLabel::Extension)) => 2,
+                Some(Some(_)) => return Ok(1), // Trailer
+                // Up to the next byte that starts a block
+                Some(None) if self.skip_corrupt => {
+                    return match data
+                        .iter()
+                        .position(|&byte| BlockLabel::introducer(byte).is_some())
+                    {
+                        Some(length) => Ok(length),
+                        None => Err(data.len() + 1),
+                    };
+                }
+                Some(None) => return Ok(1), // Anything else ends the stream too
+            },
+            ParserState::Done => return Ok(0),
+        };
+
+        // Walk the sub-blocks, resuming where the previous call stopped
+        let mut position = start.max(self.sub_blocks);
+        loop {
+            match data.get(position) {
+                None => {
+                    self.sub_blocks = position;
+                    return Err(position + 1);
+                }
+                Some(0) => {
+                    self.sub_blocks = 0;
+                    return Ok(position + 1);
+                }
+                Some(&size) => position += 1 + size as usize,
+            }
+        }
+    }
+
+    fn parse_block(&mut self, block: &[u8]) -> Result<(), ParseError> {
+        let reader = &mut ByteReader {
+            data: block,
+            position: self.position,
+        };
+
+        if self.state == ParserState::Header {
+            let header = read_gif_header(reader)?;
+            debug!("Header: {:?}", header);
+
+            let logical_screen_descriptor = read_logical_screen_descriptor(reader)?;
+            debug!("Logical Screen Descriptor: {:?}", logical_screen_descriptor);
+
+            let global_color_table_size =
+                global_color_table_size(logical_screen_descriptor.packed_field);
+            let global_color_table = if global_color_table_size > 0 {
+                Some(read_color_table(reader, global_color_table_size)?)
+            } else {
+                None
+            };
+            trace!("Global Color Table: {:?}", global_color_table);
+
+            if header.gif_version().is_none() {
+                self.warn(ParseWarningKind::UnknownVersion);
+            }
+            self.gif = Some(GIF {
+                header,
+                logical_screen_descriptor,
+                global_color_table,
+                comment_extensions: Vec::new(),
+                application_extensions: Vec::new(),
+                plain_text_extensions: Vec::new(),
+                image_descriptors: Vec::new(),
+                trailing_data: Vec::new(),
+            });
+            self.state = ParserState::Blocks;
+            return Ok(());
+        }
+
+        let gif = match self.gif {
+            Some(ref mut gif) => gif,
+            None => return Ok(()),
+        };
+        let block_indicator = match reader.read_u8() {
+            Ok(block_indicator) => block_indicator,
+            Err(_) => return Ok(()), // Nothing arrived after the last block
+        };
+        track_position(reader, "Block Indicator");
+
+        let mut warning = None;
+        match BlockLabel::introducer(block_indicator) {
+            Some(BlockLabel::Extension) => match BlockLabel::extension(reader.read_u8()?) {
+                Some(BlockLabel::GraphicControl) => {
+                    self.graphics_control_extension =
+                        Some(read_graphics_control_extension(reader)?);
+                    // Introducer, label, size, 4 bytes of fields and a
+                    // terminator, with no data sub-blocks after them
+                    if block.len() != 8 {
+                        warning = Some(ParseWarningKind::BlockSize {
+                            found: block.len(),
+                            expected: 8,
+                        });
+                    }
+                }
+                Some(BlockLabel::Comment) => {
+                    gif.comment_extensions.push(read_comment_extension(reader)?);
+                }
+                Some(BlockLabel::Application) => {
+                    gif.application_extensions
+                        .push(read_application_extension(reader)?);
+                }
+                Some(BlockLabel::PlainText) => {
+                    let plain_text = read_plain_text_extension(reader)?;
+                    if plain_text.block_size != 12 {
+                        warning = Some(ParseWarningKind::BlockSize {
+                            found: plain_text.block_size as usize,
+                            expected: 12,
+                        });
+                    }
+                    gif.plain_text_extensions.push(plain_text);
+                }
+                _ => skip_sub_blocks(reader)?, // Skip unknown extensions
+            },
+            Some(BlockLabel::Image) => {
+                let mut image_descriptor = read_image_descriptor(reader)?;
+                image_descriptor.graphics_control_extension =
+                    self.graphics_control_extension.take();
+                // Frames without any color table use one of the decoder's own
+                let entries = image_descriptor
+                    .local_color_table
+                    .as_ref()
+                    .or(gif.global_color_table.as_ref())
+                    .map(|table| table.colors.len());
+                let highest = image_descriptor.image_data.iter().max();
+                if let (Some(entries), Some(&index)) = (entries, highest) {
+                    if index as usize >= entries {
+                        warning = Some(ParseWarningKind::IndexOverrun {
+                            frame: gif.image_descriptors.len(),
+                            index,
+                            entries,
+                        });
+                    }
+                }
+                gif.image_descriptors.push(image_descriptor);
+            }
+            Some(BlockLabel::Trailer) => self.state = ParserState::Done,
+            _ => {
+                // A byte no block starts with ends the stream all the same
+                warning = Some(ParseWarningKind::UnexpectedByte(block_indicator));
+                self.state = ParserState::Done;
+            }
+        }
+        if let Some(kind) = warning {
+            self.warn(kind);
+        }
+        Ok(())
+    }
+}
+
+impl Default for GifParser {
+    fn default() -> Self {
+        GifParser::new()
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn parse_gif<R: Read>(mut reader: R) -> Result<GIF, io::Error> {
+    let mut parser = GifParser::new();
+    let mut buffer = Vec::new();
+    while !parser.is_done() {
+        buffer.resize(parser.wanted(), 0);
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        parser.push(&buffer[..count])?;
+    }
+    if parser.is_done() {
+        buffer.clear();
+        reader.read_to_end(&mut buffer)?;
+        parser.push(&buffer)?;
+    }
+    Ok(parser.finish()?)
+}
+
+// Parse a GIF held in memory. Blocks are parsed where they lie, without
+// being copied first.
+pub fn parse_gif_bytes(data: &[u8]) -> Result<GIF, ParseError> {
+    let mut parser = GifParser::new();
+    parser.push(data)?;
+    parser.finish()
+}
+
+// parse_gif_bytes, with what was odd about the input
+pub fn parse_gif_bytes_with_warnings(data: &[u8]) -> Result<ParseOutcome, ParseError> {
+    let mut parser = GifParser::new();
+    parser.push(data)?;
+    parser.finish_with_warnings()
+}
+
+// parse_gif_bytes for a GIF that may have been cut off, as
+// GifParser::finish_truncated has it
+pub fn parse_truncated_gif_bytes(data: &[u8]) -> Result<ParseOutcome, ParseError> {
+    let mut parser = GifParser::new();
+    parser.push(data)?;
+    parser.finish_truncated()
+}
+
+// The long-running parts of parsing and writing, as reported to progress
+// callbacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Parsing,  // Counts bytes of input
+    Encoding, // Counts frames written
+}
+
+// How much input GifParser::push_with_progress parses between two reports
+const PROGRESS_STEP: usize = 1 << 16;
+
+// parse_gif_bytes, reporting progress as GifParser::push_with_progress does
+pub fn parse_gif_bytes_with_progress(
+    data: &[u8],
+    progress: &(dyn Fn(Stage, usize, usize) + Sync),
+) -> Result<GIF, ParseError> {
+    let mut parser = GifParser::new();
+    parser.push_with_progress(data, progress)?;
+    parser.finish()
+}
+
+// Write data sub-blocks followed by the block terminator. Blocks as read from
+// a file are written back unchanged, longer ones are split at 255 bytes and
+// empty ones are dropped since a zero length would end the sequence.
+fn write_sub_blocks(output: &mut Vec<u8>, blocks: &[Vec<u8>]) {
+    let mut writer = SubBlockWriter::new(Memory(output));
+    for block in blocks {
+        let Ok(()) = writer.write_data(block);
+        let Ok(()) = writer.end_block();
+    }
+    let Ok(_) = writer.finish();
+}
+
+// Data split into sub-blocks of 255 bytes, then the block terminator
+fn write_data_sub_blocks(output: &mut Vec<u8>, data: &[u8]) {
+    let mut writer = SubBlockWriter::new(Memory(output));
+    let Ok(()) = writer.write_data(data);
+    let Ok(_) = writer.finish();
+}
+
+// Header, Logical Screen Descriptor and Global Color Table
+pub(crate) fn write_screen(
+    output: &mut Vec<u8>,
+    header: &GIFHeader,
+    logical_screen_descriptor: &LogicalScreenDescriptor,
+    global_color_table: Option<&ColorTable>,
+) {
+    output.extend_from_slice(&header.signature);
+    output.extend_from_slice(&header.version);
+    output.extend_from_slice(&logical_screen_descriptor.width.to_le_bytes());
+    output.extend_from_slice(&logical_screen_descriptor.height.to_le_bytes());
+    output.push(logical_screen_descriptor.packed_field);
+    output.push(logical_screen_descriptor.background_color_index);
+    output.push(logical_screen_descriptor.pixel_aspect_ratio);
+    if let Some(global_color_table) = global_color_table {
+        for color in &global_color_table.colors {
+            output.extend_from_slice(color);
+        }
+    }
+}
+
+pub(crate) fn write_comment_extension(output: &mut Vec<u8>, comment: &CommentExtension) {
+    output.extend_from_slice(&[BlockLabel::Extension.into(), BlockLabel::Comment.into()]);
+    write_sub_blocks(output, &comment.comments);
+}
+
+pub(crate) fn write_application_extension(
+    output: &mut Vec<u8>,
+    application: &ApplicationExtension,
+) {
+    output.extend_from_slice(&[
+        BlockLabel::Extension.into(),
+        BlockLabel::Application.into(),
+        11, // Block size
+    ]);
+    output.extend_from_slice(application.identifier.as_bytes());
+    output.extend_from_slice(application.authentication_code.as_bytes());
+    if is_xmp(&application.identifier, &application.authentication_code)
+        && fits_sub_blocks(&application.data)
+    {
+        output.extend_from_slice(&application.data);
+        output.push(0); // Block terminator
+    } else {
+        write_data_sub_blocks(output, &application.data);
+    }
+}
+
+// A frame's graphic control extension if it has one, then its descriptor,
+// local color table and LZW minimum code size, all but its data
+pub(crate) fn write_image_descriptor(output: &mut Vec<u8>, image_descriptor: &ImageDescriptor) {
+    if let Some(ref graphics_control_extension) = image_descriptor.graphics_control_extension {
+        output.extend_from_slice(&[
+            BlockLabel::Extension.into(),
+            BlockLabel::GraphicControl.into(),
+            4, // Block size
+        ]);
+        output.push(graphics_control_extension.packed_field);
+        output.extend_from_slice(&graphics_control_extension.delay_time.to_le_bytes());
+        output.push(graphics_control_extension.transparent_color_index);
+        output.push(0); // Block terminator for Graphics Control Extension
+    }
+
+    output.push(BlockLabel::Image.into());
+    output.extend_from_slice(&image_descriptor.left.to_le_bytes());
+    output.extend_from_slice(&image_descriptor.top.to_le_bytes());
+    output.extend_from_slice(&image_descriptor.width.to_le_bytes());
+    output.extend_from_slice(&image_descriptor.height.to_le_bytes());
+    output.push(image_descriptor.packed_field);
+
+    if let Some(ref local_color_table) = image_descriptor.local_color_table {
+        for color in &local_color_table.colors {
+            output.extend_from_slice(color);
+        }
+    }
+
+    // Write the LZW minimum code size
+    output.push(image_descriptor.lzw_minimum_code_size);
+}
+
+// A frame's pixels compressed into sub-blocks, with the block terminator
+pub(crate) fn compressed_sub_blocks(image_descriptor: &ImageDescriptor) -> Vec<u8> {
+    let compressed = lzw_compress(
+        &image_descriptor.image_data,
+        image_descriptor.lzw_minimum_code_size,
+    );
+    let mut blocks = Vec::with_capacity(compressed.len() + compressed.len() / 255 + 2);
+    write_data_sub_blocks(&mut blocks, &compressed);
+    blocks
+}
+
+// A complete Plain Text Extension: introducer, 12 byte header, text
+// sub-blocks and terminator. Each is written on its own so the boundaries
+// between them survive a round trip.
+pub(crate) fn write_plain_text_extension(output: &mut Vec<u8>, plain_text: &PlainTextExtension) {
+    output.extend_from_slice(&[
+        BlockLabel::Extension.into(),
+        BlockLabel::PlainText.into(),
+        12,
+    ]);
+    output.extend_from_slice(&plain_text.text_grid_left_position.to_le_bytes());
+    output.extend_from_slice(&plain_text.text_grid_top_position.to_le_bytes());
+    output.extend_from_slice(&plain_text.text_grid_width.to_le_bytes());
+    output.extend_from_slice(&plain_text.text_grid_height.to_le_bytes());
+    output.push(plain_text.character_cell_width);
+    output.push(plain_text.character_cell_height);
+    output.push(plain_text.text_foreground_color_index);
+    output.push(plain_text.text_background_color_index);
+    write_data_sub_blocks(output, &plain_text.plain_text_data);
+}
+
+// Serialize a GIF to any writer. When the bytes the GIF was parsed from are
+// at hand, frames whose pixels are unchanged are copied from there instead
+// of being compressed again.
+#[cfg(feature = "std")]
+pub fn write_gif<W: Write>(
+    mut writer: W,
+    gif: &GIF,
+    source: Option<&[u8]>,
+) -> Result<(), io::Error> {
+    writer.write_all(&write_gif_bytes(gif, source))
+}
+
+// Serialize a GIF into memory, as write_gif does
+pub fn write_gif_bytes(gif: &GIF, source: Option<&[u8]>) -> Vec<u8> {
+    write_gif_bytes_with_progress(gif, source, &|_, _, _| {})
+}
+
+// write_gif_bytes, calling progress with (Stage::Encoding, frames done, total
+// frames) as each frame is compressed or copied. With the parallel feature
+// the calls come from several threads, in no particular order.
+pub fn write_gif_bytes_with_progress(
+    gif: &GIF,
+    source: Option<&[u8]>,
+    progress: &(dyn Fn(Stage, usize, usize) + Sync),
+) -> Vec<u8> {
+    let mut output = Vec::new();
+
+    // 1. Write the GIF header, as 89a when a 87a GIF needs it
+    let version = gif.output_version();
+    if version != gif.header.version {
+        warn!("The GIF87a file holds extension blocks, writing it as GIF89a");
+    }
+    let header = GIFHeader {
+        signature: gif.header.signature,
+        version,
+    };
+
+    // 2. and 3. Write the Logical Screen Descriptor and the Global Color
+    // Table if present
+    write_screen(
+        &mut output,
+        &header,
+        &gif.logical_screen_descriptor,
+        gif.global_color_table.as_ref(),
+    );
+
+    // 4. Write comment extensions
+    for comment in &gif.comment_extensions {
+        write_comment_extension(&mut output, comment);
+    }
+
+    // 5. Write application extensions
+    for application in &gif.application_extensions {
+        write_application_extension(&mut output, application);
+    }
+
+    // Compressing the frames dominates writing, so it is done up front, in
+    // parallel with the parallel feature, and the results are written in
+    // order. Frames with the same pixels, such as the copies added to host a
+    // payload, are compressed once and their blocks written for each of them.
+    // That saves CPU only. GIF has no way for one image descriptor to point
+    // at another's data, so every frame carries its own sub-blocks and the
+    // output is byte for byte what compressing each frame would give, no
+    // smaller. Only smaller frames make a smaller file.
+    let frames = gif.image_descriptors.len();
+    let done = AtomicUsize::new(0);
+    progress(Stage::Encoding, 0, frames);
+    let raw_blocks = |image_descriptor: &ImageDescriptor| {
+        image_descriptor
+            .compressed_range
+            .as_ref()
+            .and_then(|range| source?.get(range.clone()))
+    };
+    // Each entry is a frame to compress or copy and how many frames use it
+    let mut unique: Vec<(&ImageDescriptor, usize)> = Vec::new();
+    let mut seen: BTreeMap<(u8, &[u8]), usize> = BTreeMap::new();
+    let mut slots = Vec::with_capacity(frames);
+    for image_descriptor in &gif.image_descriptors {
+        let pixels = (
+            image_descriptor.lzw_minimum_code_size,
+            &image_descriptor.image_data[..],
+        );
+        let copied = raw_blocks(image_descriptor).is_some();
+        let slot = match seen.get(&pixels) {
+            Some(&slot) if !copied => slot,
+            _ => {
+                if !copied {
+                    seen.insert(pixels, unique.len());
+                }
+                unique.push((image_descriptor, 0));
+                unique.len() - 1
+            }
+        };
+        unique[slot].1 += 1;
+        slots.push(slot);
+    }
+    // Each entry holds the sub-blocks and terminator of one unique frame
+    let compress_frame = |&(image_descriptor, uses): &(&ImageDescriptor, usize)| {
+        let blocks = match raw_blocks(image_descriptor) {
+            Some(raw) => Cow::Borrowed(raw),
+            None => Cow::Owned(compressed_sub_blocks(image_descriptor)),
+        };
+        for _ in 0..uses {
+            progress(
+                Stage::Encoding,
+                done.fetch_add(1, Ordering::Relaxed) + 1,
+                frames,
+            );
+        }
+        blocks
+    };
+    #[cfg(feature = "parallel")]
+    let compressed_frames: Vec<Cow<[u8]>> = unique.par_iter().map(compress_frame).collect();
+    #[cfg(not(feature = "parallel"))]
+    let compressed_frames: Vec<Cow<[u8]>> = unique.iter().map(compress_frame).collect();
+
+    // 6. Write image descriptors, each preceded by the plain text extension
+    // it hosts and its own graphic control extension. Plain text beyond the
+    // last frame follows the frames.
+    for (index, image_descriptor) in gif.image_descriptors.iter().enumerate() {
+        if let Some(plain_text) = gif.plain_text_extensions.get(index) {
+            write_plain_text_extension(&mut output, plain_text);
+        }
+
+        write_image_descriptor(&mut output, image_descriptor);
+
+        // Write the compressed image data
+        output.extend_from_slice(&compressed_frames[slots[index]]);
+    }
+
+    // 7. Write the plain text extensions no frame was left for
+    for plain_text in gif
+        .plain_text_extensions
+        .iter()
+        .skip(gif.image_descriptors.len())
+    {
+        write_plain_text_extension(&mut output, plain_text);
+    }
+
+    // 8. Write the GIF trailer, and whatever followed it
+    output.push(BlockLabel::Trailer.into());
+    output.extend_from_slice(&gif.trailing_data);
+    output
+}
@@ -0,0 +1,46 @@
+// The GIF model with its block parser, LZW codec and writer, shared by the
+// gifsauce library and the GifSauce tool. It only needs alloc; the std
+// feature, on by default, adds fronts for io::Read and io::Write and the
+// streaming GifWriter. The serde feature makes the model serializable, and
+// the testkit feature adds random GIFs for tests.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "testkit")]
+extern crate rand;
+#[cfg(feature = "testkit")]
+extern crate rand_chacha;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+mod application;
+mod events;
+mod gif;
+mod lzw;
+mod patch;
+mod quantize;
+mod sub_blocks;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+mod trailing;
+#[cfg(feature = "std")]
+mod writer;
+
+pub use application::{IccProfile, KnownApplication, LoopExtension, XmpPacket};
+#[cfg(feature = "std")]
+pub use events::parse_events;
+pub use events::{parse_events_bytes, FrameStart, GifVisitor, SubBlocks};
+pub use gif::*;
+pub use lzw::{lzw_compress, lzw_lossy, read_lzw_data};
+#[cfg(feature = "std")]
+pub use patch::apply_patches;
+pub use patch::{application_patches, comment_patches, Patch};
+pub use quantize::{closest_color, map_to_palette, quantize, quantize_dithered, Dither, Quantized};
+pub use sub_blocks::{BlockSink, BlockSource, SubBlockReader, SubBlockWriter};
+pub use trailing::{trailing_segments, TrailingKind, TrailingSegment};
+#[cfg(feature = "std")]
+pub use writer::{Extension, GifWriter};
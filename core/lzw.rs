@@ -0,0 +1,321 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::gif::{ByteReader, ParseError};
+use crate::sub_blocks::SubBlockReader;
+
+// Codes never grow past 12 bits, which caps the dictionary at 4096 entries
+const MAX_CODE_SIZE: u8 = 12;
+const MAX_ENTRIES: usize = 1 << MAX_CODE_SIZE;
+
+// Decompress the data sub-blocks of a frame, e.g. a compressed_range of the
+// file it was parsed from. At most `limit` pixels are kept.
+pub fn read_lzw_data(
+    data: &[u8],
+    minimum_code_size: u8,
+    limit: usize,
+) -> Result<Vec<u8>, ParseError> {
+    let reader = &mut ByteReader { data, position: 0 };
+    read_lzw_blocks(reader, minimum_code_size, limit)
+}
+
+// Decompress sub-blocks up to the end code or the block terminator. Pixels
+// past the limit, the width times height of the frame, are dropped unread so
+// a few bytes can't expand into gigabytes.
+pub(crate) fn read_lzw_blocks(
+    reader: &mut ByteReader,
+    minimum_code_size: u8,
+    limit: usize,
+) -> Result<Vec<u8>, ParseError> {
+    let mut blocks = SubBlockReader::new(reader.clone());
+    let data = decode_blocks(&mut blocks, minimum_code_size, limit)?;
+    *reader = blocks.into_inner();
+    Ok(data)
+}
+
+fn decode_blocks(
+    blocks: &mut SubBlockReader<ByteReader>,
+    minimum_code_size: u8,
+    limit: usize,
+) -> Result<Vec<u8>, ParseError> {
+    // Larger sizes leave no room in 12 bit codes
+    if minimum_code_size >= MAX_CODE_SIZE {
+        return Err(ParseError::Invalid("Invalid LZW minimum code size."));
+    }
+    let mut data = Vec::new();
+
+    // Calculate clear and end-of-information codes based on minimum code size
+    let clear_code = 1 << minimum_code_size;
+    let end_of_information_code = clear_code + 1;
+
+    // Initialize dictionary with single-byte values
+    let mut dictionary: Vec<Vec<u8>> = (0..clear_code).map(|i| vec![i as u8]).collect();
+    dictionary.push(vec![]); // Add clear code entry
+    dictionary.push(vec![]); // Add EOF code entry
+    let mut next_code = clear_code + 2;
+
+    // Initialize variables for reading bit-stream
+    let mut bit_buffer: u32 = 0;
+    let mut bit_count = 0;
+    let mut current_bit_size = minimum_code_size + 1;
+
+    let mut previous_code: Option<u16> = None;
+
+    'blocks: loop {
+        // An input ending before the next block ends the frame
+        if blocks.get_ref().data.is_empty() {
+            return Ok(data);
+        }
+        let block = match blocks.next_block()? {
+            Some(block) => block,
+            None => return Ok(data), // Block terminator without an end code
+        };
+
+        // Process the block data as a bitstream
+        for &byte in block {
+            bit_buffer |= (byte as u32) << bit_count;
+            bit_count += 8;
+
+            // Extract codes from bit buffer
+            while bit_count >= current_bit_size {
+                let code = (bit_buffer & ((1 << current_bit_size) - 1)) as u16;
+                bit_buffer >>= current_bit_size;
+                bit_count -= current_bit_size;
+
+                if code == end_of_information_code {
+                    break 'blocks; // End of data
+                }
+
+                if code == clear_code {
+                    // Reset dictionary and bit size
+                    dictionary = (0..clear_code).map(|i| vec![i as u8]).collect();
+                    dictionary.push(vec![]);
+                    dictionary.push(vec![]);
+                    next_code = clear_code + 2;
+                    current_bit_size = minimum_code_size + 1;
+                    previous_code = None;
+                    continue;
+                }
+
+                let entry = if code < next_code {
+                    dictionary[code as usize].clone()
+                } else if let Some(prev_code) = previous_code.filter(|_| code == next_code) {
+                    // The code being defined: the previous string plus its first byte
+                    let mut new_entry = dictionary[prev_code as usize].clone();
+                    new_entry.push(new_entry[0]);
+                    new_entry
+                } else {
+                    return Err(ParseError::Invalid("Invalid LZW code."));
+                };
+
+                data.extend(&entry);
+                if data.len() >= limit {
+                    data.truncate(limit);
+                    break 'blocks;
+                }
+
+                if let Some(prev_code) = previous_code {
+                    let mut new_entry = dictionary[prev_code as usize].clone();
+                    new_entry.push(entry[0]);
+                    if (next_code as usize) < MAX_ENTRIES {
+                        dictionary.push(new_entry);
+                        next_code += 1;
+                    }
+
+                    // Increase the bit size if necessary
+                    if next_code == (1 << current_bit_size) && current_bit_size < MAX_CODE_SIZE {
+                        current_bit_size += 1;
+                    }
+                }
+
+                previous_code = Some(code);
+            }
+        }
+    }
+
+    // Skip whatever follows up to the block terminator
+    blocks.skip_to_end()?;
+    Ok(data)
+}
+
+// Packs codes into bytes, least significant bit first
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    // Pad the last code out to a byte. As with the lzw crate this encoder
+    // replaced, a whole zero byte follows codes that end on a byte boundary,
+    // which keeps frames compressing to the same bytes as before.
+    fn finish(mut self) -> Vec<u8> {
+        self.write(0, 8 - self.bit_count);
+        self.output
+    }
+}
+
+// A dictionary entry: the string of its parent code plus one byte. The
+// children of a code are kept as a linked list.
+#[derive(Clone, Copy)]
+struct Node {
+    byte: u8,
+    first_child: u16, // 0 for none, code 0 is never a child
+    next_sibling: u16,
+}
+
+fn initial_dictionary(clear_code: u16) -> Vec<Node> {
+    let mut dictionary = Vec::with_capacity(MAX_ENTRIES + 1);
+    // The single bytes, then the entries of the clear and end codes
+    dictionary.extend((0..clear_code + 2).map(|i| Node {
+        byte: i as u8,
+        first_child: 0,
+        next_sibling: 0,
+    }));
+    dictionary
+}
+
+// The code for the string of prefix followed by byte, adding it to the
+// dictionary when it isn't there yet
+fn find_or_insert(dictionary: &mut Vec<Node>, prefix: u16, byte: u8) -> Option<u16> {
+    let mut child = dictionary[prefix as usize].first_child;
+    while child != 0 {
+        if dictionary[child as usize].byte == byte {
+            return Some(child);
+        }
+        child = dictionary[child as usize].next_sibling;
+    }
+    let code = dictionary.len() as u16;
+    dictionary.push(Node {
+        byte,
+        first_child: 0,
+        next_sibling: dictionary[prefix as usize].first_child,
+    });
+    dictionary[prefix as usize].first_child = code;
+    None
+}
+
+// Compress pixels as read_lzw_data expects them, with a minimum code size
+// the pixel values fit in
+pub fn lzw_compress(data: &[u8], minimum_code_size: u8) -> Vec<u8> {
+    let clear_code = 1u16 << minimum_code_size;
+    let end_of_information_code = clear_code + 1;
+    let mut dictionary = initial_dictionary(clear_code);
+    let mut writer = BitWriter {
+        output: Vec::with_capacity(data.len() / 2),
+        buffer: 0,
+        bit_count: 0,
+    };
+    let mut code_size = minimum_code_size + 1;
+    writer.write(clear_code, code_size);
+
+    // Code of the longest string matched so far
+    let mut current: Option<u16> = None;
+    for &byte in data {
+        current = match current {
+            None => Some(byte as u16),
+            Some(prefix) => match find_or_insert(&mut dictionary, prefix, byte) {
+                Some(code) => Some(code),
+                None => {
+                    writer.write(prefix, code_size);
+                    Some(byte as u16)
+                }
+            },
+        };
+
+        let next_code = dictionary.len();
+        if next_code > (1 << code_size) && code_size < MAX_CODE_SIZE {
+            code_size += 1;
+        }
+        if next_code > MAX_ENTRIES {
+            dictionary = initial_dictionary(clear_code);
+            writer.write(clear_code, code_size);
+            code_size = minimum_code_size + 1;
+        }
+    }
+
+    if let Some(code) = current {
+        writer.write(code, code_size);
+    }
+    writer.write(end_of_information_code, code_size);
+    writer.finish()
+}
+
+// Nudge pixels so they compress better, gifsicle style. Where the string
+// being matched can't go on with the next pixel, a string that goes on with
+// a color no further than `tolerance` from it is taken instead. Transparent
+// pixels are never changed or swapped in. The pixels that come out compress
+// with lzw_compress like any others.
+pub fn lzw_lossy(
+    data: &[u8],
+    minimum_code_size: u8,
+    colors: &[[u8; 3]],
+    transparent_index: Option<u8>,
+    tolerance: u32,
+) -> Vec<u8> {
+    let clear_code = 1u16 << minimum_code_size;
+    let limit = tolerance.saturating_mul(tolerance);
+    let distance = |a: u8, b: u8| -> Option<u32> {
+        if Some(a) == transparent_index || Some(b) == transparent_index {
+            return None;
+        }
+        let (a, b) = (colors.get(a as usize)?, colors.get(b as usize)?);
+        Some(
+            (0..3)
+                .map(|i| (a[i] as i32 - b[i] as i32).unsigned_abs().pow(2))
+                .sum(),
+        )
+    };
+
+    let mut dictionary = initial_dictionary(clear_code);
+    let mut output = Vec::with_capacity(data.len());
+    let mut current: Option<u16> = None;
+    for &byte in data {
+        current = match current {
+            None => {
+                output.push(byte);
+                Some(byte as u16)
+            }
+            Some(prefix) => {
+                // The closest way to go on, the pixel itself when it can
+                let mut closest: Option<(u8, u32)> = None;
+                let mut child = dictionary[prefix as usize].first_child;
+                while child != 0 {
+                    let node = dictionary[child as usize];
+                    let error = match node.byte == byte {
+                        true => Some(0),
+                        false => distance(node.byte, byte),
+                    };
+                    if let Some(error) = error.filter(|&error| error <= limit) {
+                        if closest.is_none_or(|(_, best)| error < best) {
+                            closest = Some((node.byte, error));
+                        }
+                    }
+                    child = node.next_sibling;
+                }
+                let chosen = closest.map_or(byte, |(chosen, _)| chosen);
+                output.push(chosen);
+                match find_or_insert(&mut dictionary, prefix, chosen) {
+                    Some(code) => Some(code),
+                    None => Some(chosen as u16),
+                }
+            }
+        };
+
+        // Start over where lzw_compress does, so the dictionaries stay the same
+        if dictionary.len() > MAX_ENTRIES {
+            dictionary = initial_dictionary(clear_code);
+        }
+    }
+    output
+}
@@ -0,0 +1,193 @@
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::application::is_xmp;
+use crate::events::{parse_events_bytes, GifVisitor, SubBlocks};
+use crate::gif::{
+    write_comment_extension, ApplicationExtension, BlockLabel, ColorTable, CommentExtension,
+    GIFHeader, GifVersion, LogicalScreenDescriptor, ParseError,
+};
+use crate::sub_blocks::{Memory, SubBlockWriter};
+
+// Edits that touch a few blocks of a GIF, made by writing over the bytes of
+// those blocks instead of writing the whole file again. Frames are neither
+// decoded nor encoded, so on a big file only the bytes that change are
+// written.
+
+// Bytes to write over a GIF at an offset, past its end when it grows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+// Where the extensions and the trailer of a GIF are
+#[derive(Default)]
+struct Layout {
+    gif87a: bool,
+    extensions: Vec<(usize, usize, u8)>, // Start, end and label
+    trailer: Option<usize>,
+}
+
+impl GifVisitor for Layout {
+    fn on_header(
+        &mut self,
+        header: &GIFHeader,
+        _screen: &LogicalScreenDescriptor,
+        _global_color_table: Option<&ColorTable>,
+    ) {
+        self.gif87a = header.gif_version() == Some(GifVersion::Gif87a);
+    }
+
+    fn on_extension(&mut self, position: usize, label: u8, sub_blocks: SubBlocks) {
+        // Introducer, label, the sub-blocks and their terminator
+        let length = 2 + sub_blocks.map(|block| 1 + block.len()).sum::<usize>() + 1;
+        self.extensions.push((position, position + length, label));
+    }
+
+    fn on_trailer(&mut self, position: usize) {
+        self.trailer = Some(position);
+    }
+}
+
+fn layout(data: &[u8]) -> Result<Layout, ParseError> {
+    let mut layout = Layout::default();
+    parse_events_bytes(data, &mut layout)?;
+    Ok(layout)
+}
+
+// Patches adding a comment after every other block: it goes where the
+// trailer was, and the trailer after it. A GIF87a header becomes GIF89a.
+// None when there is anything past the trailer, which would be overwritten.
+pub fn comment_patches(
+    data: &[u8],
+    comment: &CommentExtension,
+) -> Result<Option<Vec<Patch>>, ParseError> {
+    let layout = layout(data)?;
+    let end = match layout.trailer {
+        Some(trailer)
+            if data[trailer] == BlockLabel::Trailer.into() && trailer + 1 == data.len() =>
+        {
+            trailer
+        }
+        Some(_) => return Ok(None),
+        None => data.len(), // No trailer, the comment is added at the end all the same
+    };
+
+    let mut bytes = Vec::new();
+    write_comment_extension(&mut bytes, comment);
+    bytes.push(BlockLabel::Trailer.into());
+    let mut patches = Vec::new();
+    if layout.gif87a {
+        patches.push(Patch {
+            offset: 3,
+            bytes: b"89a".to_vec(),
+        });
+    }
+    patches.push(Patch { offset: end, bytes });
+    Ok(Some(patches))
+}
+
+// Patches putting `application` in place of the application extension at
+// `index`, counted in file order. When it is shorter, its data is spread
+// over more sub-blocks and a comment of spaces fills what is left, so the
+// file keeps its size. None when it is longer, when `index` is past the last
+// application extension, or when a gap of one, two or four bytes can't be
+// closed, and for XMP.
+pub fn application_patches(
+    data: &[u8],
+    index: usize,
+    application: &ApplicationExtension,
+) -> Result<Option<Vec<Patch>>, ParseError> {
+    // XMP is written raw, not spread over sub-blocks
+    if is_xmp(&application.identifier, &application.authentication_code) {
+        return Ok(None);
+    }
+    let layout = layout(data)?;
+    let (start, end) = match layout
+        .extensions
+        .iter()
+        .filter(|extension| extension.2 == BlockLabel::Application.into())
+        .nth(index)
+    {
+        Some(&(start, end, _)) => (start, end),
+        None => return Ok(None),
+    };
+
+    // Header, identifier block, data sub-blocks and terminator
+    let length = application.data.len();
+    let blocks = length.div_ceil(255);
+    let size = |blocks: usize| 3 + 11 + length + blocks + 1;
+    if size(blocks) > end - start {
+        return Ok(None);
+    }
+    // A comment filler takes three bytes, with no data, or five and more
+    let gap = end - start - size(blocks);
+    // Each sub-block more takes a byte, so up to two of them close the gaps
+    // a filler can't
+    let extra = match (0..=2.min(gap))
+        .find(|&extra| blocks + extra <= length && matches!(gap - extra, 0 | 3 | 5..))
+    {
+        Some(extra) => extra,
+        None => return Ok(None),
+    };
+
+    let mut bytes = Vec::with_capacity(end - start);
+    bytes.extend_from_slice(&[
+        BlockLabel::Extension.into(),
+        BlockLabel::Application.into(),
+        11, // Block size
+    ]);
+    bytes.extend_from_slice(application.identifier.as_bytes());
+    bytes.extend_from_slice(application.authentication_code.as_bytes());
+    write_spread(&mut bytes, &application.data, blocks + extra);
+    write_filler(&mut bytes, gap - extra);
+    Ok(Some(vec![Patch {
+        offset: start,
+        bytes,
+    }]))
+}
+
+// Data over exactly `blocks` sub-blocks, as even as they come
+fn write_spread(output: &mut Vec<u8>, mut data: &[u8], blocks: usize) {
+    let mut writer = SubBlockWriter::new(Memory(output));
+    for left in (1..=blocks).rev() {
+        let (block, rest) = data.split_at(data.len().div_ceil(left));
+        let Ok(()) = writer.write_data(block);
+        let Ok(()) = writer.end_block();
+        data = rest;
+    }
+    let Ok(_) = writer.finish();
+}
+
+// A comment extension of spaces exactly `length` bytes long, nothing for 0
+fn write_filler(output: &mut Vec<u8>, length: usize) {
+    if length == 0 {
+        return;
+    }
+    output.extend_from_slice(&[BlockLabel::Extension.into(), BlockLabel::Comment.into()]);
+    let mut left = length - 3;
+    while left > 0 {
+        // A sub-block takes its size byte too, and can't leave one byte over
+        let mut block = (left - 1).min(255);
+        if left - (block + 1) == 1 {
+            block -= 1;
+        }
+        output.push(block as u8);
+        output.resize(output.len() + block, b' ');
+        left -= block + 1;
+    }
+    output.push(0);
+}
+
+// Write the patches over a file, which grows when one goes past its end
+#[cfg(feature = "std")]
+pub fn apply_patches<W: Write + Seek>(writer: &mut W, patches: &[Patch]) -> Result<(), io::Error> {
+    for patch in patches {
+        writer.seek(SeekFrom::Start(patch.offset as u64))?;
+        writer.write_all(&patch.bytes)?;
+    }
+    writer.flush()
+}
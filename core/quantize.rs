@@ -0,0 +1,259 @@
+// Median cut color quantization. The distinct colors of an image are put in
+// one box, and the box spanning the widest range of a channel is split at
+// the median pixel of that channel until there are enough boxes. Each box
+// becomes the average of its colors. Pixels can then be dithered onto the
+// palette. Only integer math is used, so it works without std.
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::gif::ColorTable;
+
+// Pixels with less alpha than this are transparent
+const ALPHA_THRESHOLD: u8 = 128;
+
+// An image reduced to a palette a GIF frame can use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quantized {
+    // 2 to 256 entries, a power of two, padded with black
+    pub color_table: ColorTable,
+    // The size bits of a packed field announcing the table
+    pub size_bits: u8,
+    // The entry transparent pixels point at, after the colors
+    pub transparent_index: Option<u8>,
+    pub indices: Vec<u8>,
+}
+
+impl Quantized {
+    // The smallest LZW minimum code size the indices fit in
+    pub fn lzw_minimum_code_size(&self) -> u8 {
+        (self.size_bits + 1).max(2)
+    }
+}
+
+// Distinct colors with how many pixels have them
+struct ColorBox {
+    colors: Vec<([u8; 3], u32)>,
+}
+
+impl ColorBox {
+    // The channel with the widest range and that range
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..3)
+            .map(|channel| {
+                let values = self.colors.iter().map(|(color, _)| color[channel]);
+                let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                (channel, range)
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    }
+
+    // Split at the median pixel of the widest channel. Boxes of one color
+    // can't be split.
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel();
+        self.colors
+            .sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = self.colors.iter().map(|&(_, count)| count as u64).sum();
+        let mut seen = 0;
+        let mut median = self.colors.len() - 1;
+        for (index, &(_, count)) in self.colors.iter().enumerate() {
+            seen += count as u64;
+            if seen * 2 >= total {
+                median = index;
+                break;
+            }
+        }
+        // Both halves keep at least one color
+        let at = (median + 1).clamp(1, self.colors.len() - 1);
+        let upper = self.colors.split_off(at);
+        (self, ColorBox { colors: upper })
+    }
+
+    // The average color of its pixels
+    fn average(&self) -> [u8; 3] {
+        let total: u64 = self.colors.iter().map(|&(_, count)| count as u64).sum();
+        let mut sum = [0u64; 3];
+        for &(color, count) in &self.colors {
+            for (sum, component) in sum.iter_mut().zip(color) {
+                *sum += component as u64 * count as u64;
+            }
+        }
+        sum.map(|sum| ((sum + total / 2) / total.max(1)) as u8)
+    }
+}
+
+// Reduce RGBA pixels to at most max_colors colors, clamped to 2..=256. A
+// transparent pixel takes one of those colors.
+pub fn quantize(pixels: &[[u8; 4]], max_colors: usize) -> Quantized {
+    let max_colors = max_colors.clamp(2, 256);
+    let transparent = pixels.iter().any(|pixel| pixel[3] < ALPHA_THRESHOLD);
+    let max_boxes = max_colors - usize::from(transparent);
+
+    let mut counts: BTreeMap<[u8; 3], u32> = BTreeMap::new();
+    for pixel in pixels.iter().filter(|pixel| pixel[3] >= ALPHA_THRESHOLD) {
+        *counts.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0) += 1;
+    }
+
+    let mut boxes = Vec::new();
+    if !counts.is_empty() {
+        boxes.push(ColorBox {
+            colors: counts.into_iter().collect(),
+        });
+    }
+    while boxes.len() < max_boxes {
+        // Split the box spanning the widest range
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.colors.len() > 1)
+            .max_by_key(|(_, color_box)| color_box.widest_channel().1);
+        let index = match widest {
+            Some((index, _)) => index,
+            None => break, // Every box holds one color
+        };
+        let (lower, upper) = boxes.swap_remove(index).split();
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    let mut colors = Vec::with_capacity(max_colors);
+    let mut lookup: BTreeMap<[u8; 3], u8> = BTreeMap::new();
+    for color_box in &boxes {
+        let index = colors.len() as u8;
+        colors.push(color_box.average());
+        for &(color, _) in &color_box.colors {
+            lookup.insert(color, index);
+        }
+    }
+    let transparent_index = transparent.then(|| {
+        colors.push([0; 3]);
+        (colors.len() - 1) as u8
+    });
+
+    let indices = pixels
+        .iter()
+        .map(|pixel| match transparent_index {
+            Some(index) if pixel[3] < ALPHA_THRESHOLD => index,
+            _ => lookup[&[pixel[0], pixel[1], pixel[2]]],
+        })
+        .collect();
+
+    let size_bits = (colors.len().max(2).next_power_of_two().trailing_zeros() - 1) as u8;
+    colors.resize(2 << size_bits, [0; 3]);
+    Quantized {
+        color_table: ColorTable { colors },
+        size_bits,
+        transparent_index,
+        indices,
+    }
+}
+
+// The palette entry closest to a color, for mapping pixels onto a palette
+// that already exists
+pub fn closest_color(colors: &[[u8; 3]], color: [u8; 3], skip: Option<u8>) -> u8 {
+    let distance = |entry: &[u8; 3]| -> u32 {
+        (0..3)
+            .map(|i| (entry[i] as i32 - color[i] as i32).unsigned_abs().pow(2))
+            .sum()
+    };
+    colors
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| Some(*index as u8) != skip)
+        .min_by_key(|(_, entry)| distance(entry))
+        .map_or(0, |(index, _)| index as u8)
+}
+
+// How colors missing from a palette are approximated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    None,           // The closest color
+    Ordered,        // A 4x4 Bayer pattern nudges each pixel before picking
+    FloydSteinberg, // The error of each pixel spreads to its neighbours
+}
+
+// Thresholds of the ordered dither, one per position in a 4x4 tile
+const BAYER: [[i32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// Map RGBA pixels, rows of `width`, onto an existing palette. Transparent
+// pixels get the transparent index, which no other pixel gets.
+pub fn map_to_palette(
+    pixels: &[[u8; 4]],
+    width: usize,
+    colors: &[[u8; 3]],
+    transparent_index: Option<u8>,
+    dither: Dither,
+) -> Vec<u8> {
+    let width = width.max(1);
+    let mut cache: BTreeMap<[u8; 3], u8> = BTreeMap::new();
+    let mut closest = |color: [i32; 3]| -> u8 {
+        let color = color.map(|component| component.clamp(0, 255) as u8);
+        *cache
+            .entry(color)
+            .or_insert_with(|| closest_color(colors, color, transparent_index))
+    };
+
+    // Errors carried to this row and the next, with a column of margin on
+    // either side
+    let mut errors = vec![[0i32; 3]; width + 2];
+    let mut next_errors = vec![[0i32; 3]; width + 2];
+    let mut indices = Vec::with_capacity(pixels.len());
+    for (position, pixel) in pixels.iter().enumerate() {
+        let (x, y) = (position % width, position / width);
+        if x == 0 && y > 0 {
+            core::mem::swap(&mut errors, &mut next_errors);
+            next_errors.fill([0; 3]);
+        }
+        if let Some(index) = transparent_index.filter(|_| pixel[3] < ALPHA_THRESHOLD) {
+            indices.push(index);
+            continue;
+        }
+
+        let color = [pixel[0] as i32, pixel[1] as i32, pixel[2] as i32];
+        let index = match dither {
+            Dither::None => closest(color),
+            Dither::Ordered => {
+                let offset = BAYER[y % 4][x % 4] * 2 - 15;
+                closest(color.map(|component| component + offset))
+            }
+            Dither::FloydSteinberg => {
+                let wanted = [0, 1, 2].map(|i| color[i] + errors[x + 1][i] / 16);
+                let index = closest(wanted);
+                let got = colors.get(index as usize).copied().unwrap_or([0; 3]);
+                for i in 0..3 {
+                    let error = wanted[i].clamp(0, 255) - got[i] as i32;
+                    errors[x + 2][i] += error * 7;
+                    next_errors[x][i] += error * 3;
+                    next_errors[x + 1][i] += error * 5;
+                    next_errors[x + 2][i] += error;
+                }
+                index
+            }
+        };
+        indices.push(index);
+    }
+    indices
+}
+
+// Quantize as quantize does, then map the pixels onto the palette with
+// dithering. Without dithering this is quantize.
+pub fn quantize_dithered(
+    pixels: &[[u8; 4]],
+    width: usize,
+    max_colors: usize,
+    dither: Dither,
+) -> Quantized {
+    let mut quantized = quantize(pixels, max_colors);
+    if dither != Dither::None {
+        quantized.indices = map_to_palette(
+            pixels,
+            width,
+            &quantized.color_table.colors,
+            quantized.transparent_index,
+            dither,
+        );
+    }
+    quantized
+}
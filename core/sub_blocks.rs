@@ -0,0 +1,215 @@
+use alloc::vec::Vec;
+use core::convert::Infallible;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use crate::gif::{ByteReader, ParseError};
+
+// Data sub-blocks, the framing of everything past the fixed fields of a
+// block: a size byte and up to 255 bytes of data at a time, then a size of
+// 0, the block terminator. SubBlockReader takes the framing off and
+// SubBlockWriter puts it on, over blocks in memory and, with std, over any
+// io::Read and io::Write.
+
+// What a SubBlockReader reads from
+pub trait BlockSource {
+    type Error: From<ParseError>;
+
+    // Fails with UnexpectedEnd when the input ends first
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+impl BlockSource for ByteReader<'_> {
+    type Error = ParseError;
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), ParseError> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> BlockSource for R {
+    type Error = io::Error;
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), io::Error> {
+        Read::read_exact(self, buffer).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => ParseError::UnexpectedEnd.into(),
+            _ => e,
+        })
+    }
+}
+
+// What a SubBlockWriter writes to
+pub trait BlockSink {
+    type Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> BlockSink for W {
+    type Error = io::Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        Write::write_all(self, bytes)
+    }
+}
+
+// A Vec to write to, which never fails
+pub(crate) struct Memory<'a>(pub(crate) &'a mut Vec<u8>);
+
+impl BlockSink for Memory<'_> {
+    type Error = Infallible;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+        self.0.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+// The data of a run of sub-blocks, read up to its terminator and no further
+pub struct SubBlockReader<R> {
+    inner: R,
+    block: [u8; 255],
+    length: usize, // Bytes in block
+    taken: usize,  // Of them, how many were read
+    done: bool,    // Whether the terminator was read
+}
+
+impl<R: BlockSource> SubBlockReader<R> {
+    pub fn new(inner: R) -> SubBlockReader<R> {
+        SubBlockReader {
+            inner,
+            block: [0; 255],
+            length: 0,
+            taken: 0,
+            done: false,
+        }
+    }
+
+    // The next sub-block as it was stored, None once the terminator is read.
+    // What read left of the one before is dropped.
+    pub fn next_block(&mut self) -> Result<Option<&[u8]>, R::Error> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        self.taken = self.length;
+        Ok(Some(&self.block[..self.length]))
+    }
+
+    // Reads on past the terminator
+    pub fn skip_to_end(&mut self) -> Result<(), R::Error> {
+        while self.fill()? {}
+        Ok(())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    // The reader, just past the terminator when is_done
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Reads the next sub-block, false at the terminator
+    fn fill(&mut self) -> Result<bool, R::Error> {
+        if self.done {
+            return Ok(false);
+        }
+        let mut size = [0];
+        self.inner.read_exact(&mut size)?;
+        if size[0] == 0 {
+            self.done = true;
+            return Ok(false);
+        }
+        self.length = size[0] as usize;
+        self.taken = 0;
+        self.inner.read_exact(&mut self.block[..self.length])?;
+        Ok(true)
+    }
+}
+
+// The data of the sub-blocks run together, ending at the terminator
+#[cfg(feature = "std")]
+impl<R: Read> Read for SubBlockReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        while self.taken == self.length {
+            if buffer.is_empty() || !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let count = buffer.len().min(self.length - self.taken);
+        buffer[..count].copy_from_slice(&self.block[self.taken..self.taken + count]);
+        self.taken += count;
+        Ok(count)
+    }
+}
+
+// Frames data into sub-blocks. Each goes out as soon as it holds 255 bytes;
+// finish writes the last one and the terminator.
+pub struct SubBlockWriter<W> {
+    inner: W,
+    block: [u8; 256], // Size byte, then data
+    length: usize,    // Bytes of data in block
+}
+
+impl<W: BlockSink> SubBlockWriter<W> {
+    pub fn new(inner: W) -> SubBlockWriter<W> {
+        SubBlockWriter {
+            inner,
+            block: [0; 256],
+            length: 0,
+        }
+    }
+
+    pub fn write_data(&mut self, mut data: &[u8]) -> Result<(), W::Error> {
+        while !data.is_empty() {
+            let count = data.len().min(255 - self.length);
+            self.block[1 + self.length..1 + self.length + count].copy_from_slice(&data[..count]);
+            self.length += count;
+            data = &data[count..];
+            if self.length == 255 {
+                self.end_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Ends the sub-block being filled, short of 255 bytes. Nothing is
+    // written when it is empty, since a size of 0 would end the run.
+    pub fn end_block(&mut self) -> Result<(), W::Error> {
+        if self.length == 0 {
+            return Ok(());
+        }
+        self.block[0] = self.length as u8;
+        let length = core::mem::take(&mut self.length);
+        self.inner.write_all(&self.block[..=length])
+    }
+
+    // Writes what is left and the terminator, and hands the writer back
+    pub fn finish(mut self) -> Result<W, W::Error> {
+        self.end_block()?;
+        self.inner.write_all(&[0])?;
+        Ok(self.inner)
+    }
+}
+
+// A flush leaves the sub-block being filled open, only end_block and finish
+// write it out
+#[cfg(feature = "std")]
+impl<W: Write> Write for SubBlockWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
+        self.write_data(buffer)?;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,169 @@
+// Random valid GIFs built in memory, for tests and as fixtures. Every GIF is
+// one the writer serializes faithfully, so writing it and parsing it back
+// gives the same model: color tables match the flags in the packed fields,
+// pixels fit the LZW code size, sub-blocks are 1 to 255 bytes and
+// identifiers are ASCII.
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::gif::{
+    write_gif_bytes, ApplicationExtension, ColorTable, CommentExtension, GIFHeader,
+    GraphicsControlExtension, ImageDescriptor, LogicalScreenDescriptor, PlainTextExtension, GIF,
+};
+
+// Upper bounds of what random_gif makes
+#[derive(Debug, Clone)]
+pub struct Shape {
+    pub max_side: u16, // Frame width and height
+    pub max_frames: usize,
+    pub max_extensions: usize, // Of each kind: comment, application and plain text
+    pub max_sub_blocks: usize, // In each extension
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Shape {
+            max_side: 32,
+            max_frames: 4,
+            max_extensions: 3,
+            max_sub_blocks: 3,
+        }
+    }
+}
+
+pub fn random_gif<R: Rng + ?Sized>(rng: &mut R, shape: &Shape) -> GIF {
+    let global_color_table = random_color_table(rng);
+    let mut packed_field = rng.gen::<u8>() & 0b0111_1000;
+    if let Some((size_bits, _)) = global_color_table {
+        packed_field |= 0b1000_0000 | size_bits;
+    }
+    let logical_screen_descriptor = LogicalScreenDescriptor {
+        width: rng.gen_range(0..=shape.max_side),
+        height: rng.gen_range(0..=shape.max_side),
+        packed_field,
+        background_color_index: rng.gen(),
+        pixel_aspect_ratio: rng.gen(),
+    };
+
+    let comment_extensions = (0..rng.gen_range(0..=shape.max_extensions))
+        .map(|_| CommentExtension {
+            comments: random_sub_blocks(rng, shape),
+        })
+        .collect();
+    let application_extensions = (0..rng.gen_range(0..=shape.max_extensions))
+        .map(|_| ApplicationExtension {
+            identifier: random_ascii(rng, 8),
+            authentication_code: random_ascii(rng, 3),
+            data: random_sub_blocks(rng, shape).concat(),
+        })
+        .collect();
+    let plain_text_extensions = (0..rng.gen_range(0..=shape.max_extensions))
+        .map(|_| PlainTextExtension {
+            block_size: 12,
+            text_grid_left_position: rng.gen(),
+            text_grid_top_position: rng.gen(),
+            text_grid_width: rng.gen(),
+            text_grid_height: rng.gen(),
+            character_cell_width: rng.gen(),
+            character_cell_height: rng.gen(),
+            text_foreground_color_index: rng.gen(),
+            text_background_color_index: rng.gen(),
+            plain_text_data: random_sub_blocks(rng, shape).concat(),
+        })
+        .collect();
+    let image_descriptors = (0..rng.gen_range(0..=shape.max_frames))
+        .map(|_| random_frame(rng, shape))
+        .collect();
+
+    GIF {
+        header: GIFHeader {
+            signature: *b"GIF",
+            version: *b"89a",
+        },
+        logical_screen_descriptor,
+        global_color_table: global_color_table.map(|(_, table)| table),
+        comment_extensions,
+        application_extensions,
+        plain_text_extensions,
+        image_descriptors,
+        trailing_data: Vec::new(),
+    }
+}
+
+// The bytes of a random GIF. The same seed always gives the same GIF.
+pub fn gif_fixture(seed: u64, shape: &Shape) -> Vec<u8> {
+    let gif = random_gif(&mut ChaCha8Rng::seed_from_u64(seed), shape);
+    write_gif_bytes(&gif, None)
+}
+
+// A table of 2 to 256 colors, with the size bits of packed fields announcing it
+fn random_color_table<R: Rng + ?Sized>(rng: &mut R) -> Option<(u8, ColorTable)> {
+    if rng.gen_bool(0.2) {
+        return None;
+    }
+    let size_bits = rng.gen_range(0..=7);
+    let colors = (0..2 << size_bits).map(|_| rng.gen()).collect();
+    Some((size_bits, ColorTable { colors }))
+}
+
+fn random_frame<R: Rng + ?Sized>(rng: &mut R, shape: &Shape) -> ImageDescriptor {
+    let width = rng.gen_range(0..=shape.max_side);
+    let height = rng.gen_range(0..=shape.max_side);
+    let local_color_table = random_color_table(rng).filter(|_| rng.gen_bool(0.3));
+    // Interlace and sort flags are kept, the reserved bits are not
+    let mut packed_field = rng.gen::<u8>() & 0b0110_0000;
+    if let Some((size_bits, _)) = local_color_table {
+        packed_field |= 0b1000_0000 | size_bits;
+    }
+
+    // Runs of one color next to noise, so the LZW dictionary both fills up
+    // with long strings and starts over
+    let lzw_minimum_code_size = rng.gen_range(2..=8);
+    let colors = 1u16 << lzw_minimum_code_size;
+    let pixel_count = width as usize * height as usize;
+    let mut image_data = Vec::with_capacity(pixel_count);
+    while image_data.len() < pixel_count {
+        let run = rng.gen_range(1..=pixel_count - image_data.len());
+        if rng.gen_bool(0.5) {
+            let color = rng.gen_range(0..colors) as u8;
+            image_data.extend((0..run).map(|_| color));
+        } else {
+            image_data.extend((0..run).map(|_| rng.gen_range(0..colors) as u8));
+        }
+    }
+
+    let graphics_control_extension = rng.gen_bool(0.5).then(|| GraphicsControlExtension {
+        packed_field: rng.gen(),
+        delay_time: rng.gen(),
+        transparent_color_index: rng.gen(),
+    });
+
+    ImageDescriptor {
+        left: rng.gen(),
+        top: rng.gen(),
+        width,
+        height,
+        packed_field,
+        graphics_control_extension,
+        local_color_table: local_color_table.map(|(_, table)| table),
+        lzw_minimum_code_size,
+        image_data,
+        compressed_range: None,
+    }
+}
+
+fn random_sub_blocks<R: Rng + ?Sized>(rng: &mut R, shape: &Shape) -> Vec<Vec<u8>> {
+    (0..rng.gen_range(0..=shape.max_sub_blocks))
+        .map(|_| {
+            let length = rng.gen_range(1..=255);
+            (0..length).map(|_| rng.gen()).collect()
+        })
+        .collect()
+}
+
+fn random_ascii<R: Rng + ?Sized>(rng: &mut R, length: usize) -> String {
+    (0..length)
+        .map(|_| char::from(rng.gen_range(0x20..=0x7E)))
+        .collect()
+}
@@ -0,0 +1,735 @@
+// Properties of the parser, writer and LZW codec, checked with proptest on
+// random GIFs from the testkit. A failing GIF is shrunk towards fewer and
+// smaller frames and extensions before it is reported.
+use gifsauce_core::testkit::{gif_fixture, random_gif, Shape};
+use gifsauce_core::{
+    application_patches, apply_patches, comment_patches, lzw_compress, parse_events,
+    parse_events_bytes, parse_gif, parse_gif_bytes, parse_gif_bytes_with_progress,
+    parse_gif_bytes_with_warnings, parse_truncated_gif_bytes, read_lzw_data, write_gif_bytes,
+    write_gif_bytes_with_progress, ApplicationExtension, BlockLabel, ColorTable, CommentExtension,
+    DisposalMethod, Extension, FrameStart, GIFHeader, GifParser, GifVersion, GifVisitor, GifWriter,
+    GraphicsControlExtension, IccProfile, ImageDescriptor, KnownApplication, LoopExtension,
+    ParseWarning, ParseWarningKind, Stage, SubBlockReader, SubBlockWriter, SubBlocks, TrailingKind,
+    TrailingSegment, XmpPacket, GIF,
+};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::{select, Index};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::io::{Cursor, Read, Write};
+use std::sync::Mutex;
+
+const CASES: u32 = 256;
+
+// Random GIFs no bigger than `most` allows. The bounds shrink along with the
+// seed, so a failure comes down to the smallest shape that still fails.
+fn gifs_within(most: Shape) -> impl Strategy<Value = GIF> {
+    (
+        any::<u64>(),
+        0..=most.max_side,
+        0..=most.max_frames,
+        0..=most.max_extensions,
+        0..=most.max_sub_blocks,
+    )
+        .prop_map(
+            |(seed, max_side, max_frames, max_extensions, max_sub_blocks)| {
+                let shape = Shape {
+                    max_side,
+                    max_frames,
+                    max_extensions,
+                    max_sub_blocks,
+                };
+                random_gif(&mut ChaCha8Rng::seed_from_u64(seed), &shape)
+            },
+        )
+}
+
+fn gifs() -> impl Strategy<Value = GIF> {
+    gifs_within(Shape::default())
+}
+
+// The bytes of random GIFs, written as the testkit writes its fixtures
+fn gif_bytes() -> impl Strategy<Value = Vec<u8>> {
+    gifs().prop_map(|gif| write_gif_bytes(&gif, None))
+}
+
+// Where to cut input into the pieces it arrives in
+fn cuts() -> impl Strategy<Value = Vec<Index>> {
+    vec(any::<Index>(), 0..8)
+}
+
+fn pieces<'a>(data: &'a [u8], cuts: &[Index]) -> Vec<&'a [u8]> {
+    let mut ends: Vec<usize> = cuts.iter().map(|cut| cut.index(data.len() + 1)).collect();
+    ends.extend([0, data.len()]);
+    ends.sort();
+    ends.dedup();
+    ends.windows(2)
+        .map(|pair| &data[pair[0]..pair[1]])
+        .collect()
+}
+
+// The parser records where frames were read from; a built GIF has no source
+fn without_ranges(mut gif: GIF) -> GIF {
+    for frame in &mut gif.image_descriptors {
+        frame.compressed_range = None;
+    }
+    gif
+}
+
+fn sub_blocks(data: &[u8]) -> Vec<u8> {
+    let mut blocks = Vec::new();
+    for chunk in data.chunks(255) {
+        blocks.push(chunk.len() as u8);
+        blocks.extend_from_slice(chunk);
+    }
+    blocks.push(0);
+    blocks
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn parse_write_parse_keeps_the_structure(gif in gifs()) {
+        let bytes = write_gif_bytes(&gif, None);
+        let parsed = parse_gif_bytes(&bytes).unwrap();
+        prop_assert_eq!(without_ranges(parsed), gif);
+    }
+
+    #[test]
+    fn copied_frames_match_recompressed_ones(bytes in gif_bytes()) {
+        let gif = parse_gif_bytes(&bytes).unwrap();
+        prop_assert_eq!(&write_gif_bytes(&gif, Some(&bytes)), &bytes);
+        prop_assert_eq!(&write_gif_bytes(&gif, None), &bytes);
+    }
+
+    #[test]
+    fn duplicated_frames_write_like_their_originals(bytes in gif_bytes()) {
+        let mut gif = parse_gif_bytes(&bytes).unwrap();
+        let copies = gif.image_descriptors.clone();
+        gif.image_descriptors.extend(copies);
+        let copied = write_gif_bytes(&gif, Some(&bytes));
+        prop_assert_eq!(&write_gif_bytes(&gif, None), &copied);
+        let parsed = parse_gif_bytes(&copied).unwrap();
+        prop_assert_eq!(without_ranges(parsed), without_ranges(gif));
+    }
+
+    #[test]
+    fn pushing_in_pieces_matches_parsing_at_once(bytes in gif_bytes(), cuts in cuts()) {
+        let mut parser = GifParser::new();
+        for piece in pieces(&bytes, &cuts) {
+            if parser.is_done() {
+                break;
+            }
+            parser.push(piece).unwrap();
+        }
+        prop_assert_eq!(parser.finish().unwrap(), parse_gif_bytes(&bytes).unwrap());
+        prop_assert_eq!(
+            parse_gif(bytes.as_slice()).unwrap(),
+            parse_gif_bytes(&bytes).unwrap()
+        );
+    }
+
+    // Big enough to take several steps to parse
+    #[test]
+    fn progress_reaches_the_total_and_changes_nothing(
+        gif in gifs_within(Shape {
+            max_side: 255,
+            max_frames: 16,
+            ..Shape::default()
+        })
+    ) {
+        let bytes = write_gif_bytes(&gif, None);
+        let reports = Mutex::new(Vec::new());
+        let record = |stage: Stage, done: usize, total: usize| {
+            reports.lock().unwrap().push((stage, done, total));
+        };
+
+        let gif = parse_gif_bytes_with_progress(&bytes, &record).unwrap();
+        prop_assert_eq!(&gif, &parse_gif_bytes(&bytes).unwrap());
+        let parsed = std::mem::take(&mut *reports.lock().unwrap());
+        prop_assert!(parsed.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        prop_assert_eq!(
+            parsed.last(),
+            Some(&(Stage::Parsing, bytes.len(), bytes.len()))
+        );
+
+        let written = write_gif_bytes_with_progress(&gif, None, &record);
+        prop_assert_eq!(&written, &bytes);
+        let frames = gif.image_descriptors.len();
+        let reports = reports.into_inner().unwrap();
+        prop_assert!(reports
+            .iter()
+            .all(|&(stage, _, total)| (stage, total) == (Stage::Encoding, frames)));
+        let mut encoded: Vec<usize> = reports.into_iter().map(|(_, done, _)| done).collect();
+        encoded.sort();
+        prop_assert_eq!(encoded, (0..=frames).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn truncated_gifs_fail_without_panicking(bytes in gif_bytes()) {
+        for end in 0..bytes.len() {
+            let _ = parse_gif_bytes(&bytes[..end]);
+        }
+    }
+}
+
+proptest! {
+    // Every cut of every GIF is parsed, so fewer of them
+    #![proptest_config(ProptestConfig::with_cases(CASES / 16))]
+
+    #[test]
+    fn truncated_gifs_keep_their_complete_blocks(bytes in gif_bytes()) {
+        let full = parse_gif_bytes(&bytes).unwrap();
+        for end in 0..=bytes.len() {
+            let (gif, cut) = match parse_truncated_gif_bytes(&bytes[..end]) {
+                Ok(outcome) => {
+                    let cut = outcome
+                        .warnings
+                        .iter()
+                        .find_map(|warning| match warning.kind {
+                            ParseWarningKind::CutShort(length) => Some(length),
+                            _ => None,
+                        });
+                    (outcome.gif, cut.unwrap_or(0))
+                }
+                Err(_) => {
+                    // Only a cut through the header leaves nothing to keep
+                    prop_assert!(end < 13 + 3 * 256, "end {}", end);
+                    continue;
+                }
+            };
+            prop_assert_eq!(
+                &gif,
+                &parse_gif_bytes(&bytes[..end - cut]).unwrap(),
+                "end {}",
+                end
+            );
+            prop_assert!(gif.image_descriptors.len() <= full.image_descriptors.len());
+        }
+        prop_assert_eq!(parse_truncated_gif_bytes(&bytes).unwrap().gif, full);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES / 4))]
+
+    #[test]
+    fn skipping_corrupt_frames_keeps_the_others(bytes in gif_bytes(), frame in any::<Index>()) {
+        let mut bytes = bytes;
+        let mut full = parse_gif_bytes(&bytes).unwrap();
+        prop_assume!(!full.image_descriptors.is_empty());
+        let frame = frame.index(full.image_descriptors.len());
+        // Codes of all ones are past any the table holds at the start
+        let start = full.image_descriptors[frame]
+            .compressed_range
+            .clone()
+            .unwrap()
+            .start;
+        prop_assume!(bytes[start] >= 2);
+        bytes[start + 1..start + 3].fill(0xFF);
+        prop_assume!(parse_gif_bytes(&bytes).is_err());
+
+        let mut parser = GifParser::skipping_corrupt();
+        parser.push(&bytes).unwrap();
+        let skipped = parser.skipped().to_vec();
+        let gif = parser.finish().unwrap();
+        prop_assert_eq!(skipped.len(), 1);
+        prop_assert_eq!(skipped[0].frame, Some(frame));
+        full.image_descriptors.remove(frame);
+        prop_assert_eq!(gif, full);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn warnings_point_at_the_oddities(bytes in gif_bytes()) {
+        // Random pixels may overrun their table, the rest of a fixture is
+        // as it should be
+        let structural = |warnings: Vec<ParseWarning>| {
+            warnings
+                .into_iter()
+                .filter(|warning| !matches!(warning.kind, ParseWarningKind::IndexOverrun { .. }))
+                .collect::<Vec<_>>()
+        };
+        let mut bytes = bytes;
+        let outcome = parse_gif_bytes_with_warnings(&bytes).unwrap();
+        prop_assert_eq!(structural(outcome.warnings), []);
+
+        let end = bytes.len();
+        bytes.extend_from_slice(b"garbage");
+        let outcome = parse_gif_bytes_with_warnings(&bytes).unwrap();
+        let trailing = ParseWarning {
+            position: end,
+            kind: ParseWarningKind::TrailingData(7),
+        };
+        prop_assert_eq!(structural(outcome.warnings), [trailing]);
+
+        let outcome = parse_gif_bytes_with_warnings(&bytes[..end - 1]).unwrap();
+        let missing = ParseWarning {
+            position: end - 1,
+            kind: ParseWarningKind::MissingTrailer,
+        };
+        prop_assert_eq!(structural(outcome.warnings), [missing]);
+
+        // Every pixel past the end of a two color table
+        let mut gif = outcome.gif;
+        gif.global_color_table = Some(ColorTable {
+            colors: vec![[0, 0, 0]; 2],
+        });
+        gif.logical_screen_descriptor.packed_field |= 0b1000_0000;
+        gif.logical_screen_descriptor.packed_field &= 0b1111_1000;
+        for frame in &mut gif.image_descriptors {
+            frame.packed_field &= 0b0111_1111;
+            frame.local_color_table = None;
+            frame.image_data.fill(3);
+        }
+        let outcome = parse_gif_bytes_with_warnings(&write_gif_bytes(&gif, None)).unwrap();
+        let overruns = outcome
+            .warnings
+            .iter()
+            .filter(|warning| matches!(warning.kind, ParseWarningKind::IndexOverrun { .. }))
+            .count();
+        let drawn = gif
+            .image_descriptors
+            .iter()
+            .filter(|frame| !frame.image_data.is_empty())
+            .count();
+        prop_assert_eq!(overruns, drawn);
+    }
+
+    #[test]
+    fn gif87a_is_kept_until_it_holds_extensions(
+        gif in gifs_within(Shape {
+            max_extensions: 0,
+            ..Shape::default()
+        })
+    ) {
+        let mut gif = gif;
+        gif.header.version = *b"87a";
+        for frame in &mut gif.image_descriptors {
+            frame.graphics_control_extension = None;
+        }
+        let bytes = write_gif_bytes(&gif, None);
+        let parsed = parse_gif_bytes(&bytes).unwrap();
+        prop_assert_eq!(parsed.header.gif_version(), Some(GifVersion::Gif87a));
+
+        gif.comment_extensions.push(CommentExtension {
+            comments: vec![b"upgrade".to_vec()],
+        });
+        let bytes = write_gif_bytes(&gif, None);
+        prop_assert_eq!(&bytes[..6], b"GIF89a");
+    }
+
+    #[test]
+    fn data_after_the_trailer_is_kept(
+        gif in gifs(),
+        trailing_data in vec(any::<u8>(), 1..600),
+        cuts in cuts()
+    ) {
+        let mut gif = gif;
+        gif.trailing_data = trailing_data;
+        let bytes = write_gif_bytes(&gif, None);
+        prop_assert!(bytes.ends_with(&gif.trailing_data));
+
+        // However the input arrives, every byte past the trailer is kept
+        let mut parser = GifParser::new();
+        for piece in pieces(&bytes, &cuts) {
+            parser.push(piece).unwrap();
+        }
+        prop_assert_eq!(without_ranges(parser.finish().unwrap()), gif);
+        prop_assert_eq!(
+            parse_gif(bytes.as_slice()).unwrap(),
+            parse_gif_bytes(&bytes).unwrap()
+        );
+    }
+
+    // Padding of bytes no signature starts with
+    #[test]
+    fn appended_files_are_told_apart(
+        gif in gifs(),
+        second in gifs(),
+        before in vec(0x80..=0xFFu8, 0..100),
+        between in vec(0x80..=0xFFu8, 0..100)
+    ) {
+        let mut gif = gif;
+        let appended = write_gif_bytes(&second, None);
+        gif.trailing_data = [&before[..], &appended, &between, b"PK\x03\x04archive"].concat();
+
+        let parsed = parse_gif_bytes(&write_gif_bytes(&gif, None)).unwrap();
+        let mut expected = Vec::new();
+        let mut offset = 0;
+        for (length, kind) in [
+            (before.len(), TrailingKind::Data),
+            (
+                appended.len(),
+                TrailingKind::Gif {
+                    frames: second.image_descriptors.len(),
+                },
+            ),
+            (between.len(), TrailingKind::Data),
+            (11, TrailingKind::Zip),
+        ] {
+            if length > 0 {
+                expected.push(TrailingSegment {
+                    offset,
+                    length,
+                    kind,
+                });
+            }
+            offset += length;
+        }
+        prop_assert_eq!(parsed.trailing_segments(), expected);
+    }
+}
+
+// Collects what parse_events reports
+#[derive(Debug, Default, PartialEq)]
+struct Recorder {
+    comments: Vec<Vec<Vec<u8>>>,
+    frames: Vec<FrameStart>,
+    data: Vec<Vec<u8>>, // Sub-blocks of each frame, framed again
+    ends: Vec<usize>,
+    trailer: Option<usize>,
+}
+
+impl GifVisitor for Recorder {
+    fn on_extension(&mut self, _position: usize, label: u8, sub_blocks: SubBlocks) {
+        if label == u8::from(BlockLabel::Comment) {
+            self.comments
+                .push(sub_blocks.map(|block| block.to_vec()).collect());
+        }
+    }
+
+    fn on_frame_start(&mut self, frame: &FrameStart) {
+        self.frames.push(frame.clone());
+        self.data.push(Vec::new());
+    }
+
+    fn on_image_data_chunk(&mut self, _position: usize, chunk: &[u8]) {
+        let data = self.data.last_mut().unwrap();
+        data.push(chunk.len() as u8);
+        data.extend_from_slice(chunk);
+    }
+
+    fn on_frame_end(&mut self, position: usize) {
+        self.data.last_mut().unwrap().push(0);
+        self.ends.push(position);
+    }
+
+    fn on_trailer(&mut self, position: usize) {
+        self.trailer = Some(position);
+    }
+}
+
+const DISPOSAL_METHODS: [DisposalMethod; 4] = [
+    DisposalMethod::None,
+    DisposalMethod::DoNotDispose,
+    DisposalMethod::RestoreBackground,
+    DisposalMethod::RestorePrevious,
+];
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn events_follow_the_parsed_model(bytes in gif_bytes()) {
+        let gif = parse_gif_bytes(&bytes).unwrap();
+        let mut events = Recorder::default();
+        parse_events_bytes(&bytes, &mut events).unwrap();
+
+        let comments: Vec<Vec<Vec<u8>>> = gif
+            .comment_extensions
+            .iter()
+            .map(|comment| comment.comments.clone())
+            .collect();
+        prop_assert_eq!(&events.comments, &comments);
+        prop_assert_eq!(events.frames.len(), gif.image_descriptors.len());
+        for (i, frame) in gif.image_descriptors.iter().enumerate() {
+            let start = &events.frames[i];
+            prop_assert_eq!(
+                (start.width, start.height, &start.local_color_table),
+                (frame.width, frame.height, &frame.local_color_table)
+            );
+            let pixels = frame.width as usize * frame.height as usize;
+            let read = read_lzw_data(&events.data[i], start.lzw_minimum_code_size, pixels);
+            prop_assert_eq!(read.as_ref(), Ok(&frame.image_data));
+            prop_assert_eq!(
+                Some(events.ends[i]),
+                frame.compressed_range.as_ref().map(|range| range.end)
+            );
+        }
+        prop_assert_eq!(events.trailer, Some(bytes.len() - 1));
+
+        let mut read = Recorder::default();
+        parse_events(bytes.as_slice(), &mut read).unwrap();
+        prop_assert_eq!(read, events);
+    }
+
+    #[test]
+    fn streamed_gifs_match_written_ones(gif in gifs()) {
+        let header = GIFHeader {
+            signature: gif.header.signature,
+            version: gif.output_version(),
+        };
+
+        // In the order write_gif_bytes has the blocks
+        let mut writer = GifWriter::new(Vec::new());
+        writer
+            .write_header(
+                &header,
+                &gif.logical_screen_descriptor,
+                gif.global_color_table.as_ref(),
+            )
+            .unwrap();
+        for comment in &gif.comment_extensions {
+            writer.write_extension(Extension::Comment(comment)).unwrap();
+        }
+        for application in &gif.application_extensions {
+            writer
+                .write_extension(Extension::Application(application))
+                .unwrap();
+        }
+        for (index, frame) in gif.image_descriptors.iter().enumerate() {
+            if let Some(plain_text) = gif.plain_text_extensions.get(index) {
+                writer
+                    .write_extension(Extension::PlainText(plain_text))
+                    .unwrap();
+            }
+            writer.write_frame(frame).unwrap();
+        }
+        for plain_text in gif
+            .plain_text_extensions
+            .iter()
+            .skip(gif.image_descriptors.len())
+        {
+            writer
+                .write_extension(Extension::PlainText(plain_text))
+                .unwrap();
+        }
+        let streamed = writer.finish().unwrap();
+        prop_assert_eq!(streamed, write_gif_bytes(&gif, None));
+    }
+
+    #[test]
+    fn disposal_methods_survive_a_round_trip(
+        gif in gifs(),
+        methods in vec(select(DISPOSAL_METHODS.to_vec()), Shape::default().max_frames)
+    ) {
+        let mut gif = gif;
+        let mut chosen = Vec::new();
+        for (frame, &method) in gif.image_descriptors.iter_mut().zip(&methods) {
+            let gce = frame
+                .graphics_control_extension
+                .get_or_insert(GraphicsControlExtension {
+                    packed_field: 0,
+                    delay_time: 0,
+                    transparent_color_index: 0,
+                });
+            let others = gce.packed_field & !0b1_1100;
+            gce.set_disposal_method(method);
+            prop_assert_eq!(gce.packed_field & !0b1_1100, others);
+            chosen.push(method);
+        }
+        let parsed = parse_gif_bytes(&write_gif_bytes(&gif, None)).unwrap();
+        let read: Vec<DisposalMethod> = parsed
+            .image_descriptors
+            .iter()
+            .map(|frame| frame.disposal_method())
+            .collect();
+        prop_assert_eq!(read, chosen);
+    }
+
+    #[test]
+    fn sub_block_adapters_round_trip(data in vec(any::<u8>(), 0..2000), cuts in cuts()) {
+        // Written in pieces of any size, framed as if all at once
+        let mut writer = SubBlockWriter::new(Vec::new());
+        for piece in pieces(&data, &cuts) {
+            writer.write_all(piece).unwrap();
+        }
+        let mut framed = writer.finish().unwrap();
+        prop_assert_eq!(&framed, &sub_blocks(&data));
+
+        // Read back up to the terminator, leaving what follows it
+        framed.extend_from_slice(b"after");
+        let mut reader = SubBlockReader::new(framed.as_slice());
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        prop_assert_eq!(read, data);
+        prop_assert!(reader.is_done());
+        prop_assert_eq!(reader.into_inner(), b"after");
+    }
+
+    #[test]
+    fn patched_files_parse_as_edited(
+        gif in gifs(),
+        comments in 0..3usize,
+        index in any::<Index>(),
+        kept in any::<Index>()
+    ) {
+        let mut gif = gif;
+        let bytes = write_gif_bytes(&gif, None);
+
+        // A comment goes in after every other block
+        let comment = CommentExtension {
+            comments: vec![b"patched".to_vec(); comments],
+        };
+        let patches = comment_patches(&bytes, &comment).unwrap().unwrap();
+        let mut file = Cursor::new(bytes.clone());
+        apply_patches(&mut file, &patches).unwrap();
+        let mut expected = parse_gif_bytes(&bytes).unwrap();
+        expected.comment_extensions.push(comment);
+        let parsed = parse_gif_bytes(file.get_ref()).unwrap();
+        prop_assert_eq!(parsed, expected);
+
+        // An application extension no longer than the one it replaces keeps
+        // the file's size, what's left over filled by a comment of spaces
+        if gif.application_extensions.is_empty() {
+            return Ok(());
+        }
+        let index = index.index(gif.application_extensions.len());
+        let old = &gif.application_extensions[index];
+        let application = ApplicationExtension {
+            data: old.data[..kept.index(old.data.len() + 1)].to_vec(),
+            ..old.clone()
+        };
+        let patches = match application_patches(&bytes, index, &application).unwrap() {
+            Some(patches) => patches,
+            None => return Ok(()), // A gap no filler closes
+        };
+        let mut file = Cursor::new(bytes.clone());
+        apply_patches(&mut file, &patches).unwrap();
+        prop_assert_eq!(file.get_ref().len(), bytes.len());
+        let parsed = parse_gif_bytes(file.get_ref()).unwrap();
+        gif.application_extensions[index] = application;
+        prop_assert_eq!(&parsed.application_extensions, &gif.application_extensions);
+        let fillers: Vec<_> = parsed
+            .comment_extensions
+            .iter()
+            .filter(|comment| !gif.comment_extensions.contains(comment))
+            .collect();
+        prop_assert!(fillers.len() <= 1);
+        prop_assert!(fillers
+            .iter()
+            .all(|filler| filler.comments.concat().iter().all(|&byte| byte == b' ')));
+        prop_assert_eq!(
+            without_ranges(parsed).image_descriptors,
+            gif.image_descriptors
+        );
+    }
+
+    // Text of any length and characters, but for NUL
+    #[test]
+    fn known_applications_round_trip(
+        gif in gifs(),
+        xml in vec(prop::char::range('\u{1}', '\u{2FFF}'), 0..2000)
+            .prop_map(String::from_iter),
+        profile in vec(any::<u8>(), 128..1000),
+        repeats in any::<u16>()
+    ) {
+        let mut gif = gif;
+        let mut profile = profile;
+        profile[36..40].copy_from_slice(b"acsp");
+        let known = vec![
+            KnownApplication::Loop(LoopExtension { repeats }),
+            KnownApplication::Xmp(XmpPacket { xml: xml.clone() }),
+            KnownApplication::IccProfile(IccProfile { profile }),
+        ];
+        gif.application_extensions
+            .extend(known.iter().map(ApplicationExtension::from));
+
+        let bytes = write_gif_bytes(&gif, None);
+        // Readers look for the packet as it is
+        prop_assert!(bytes
+            .windows(xml.len().max(1))
+            .any(|window| xml.is_empty() || window == xml.as_bytes()));
+        let parsed = parse_gif_bytes(&bytes).unwrap();
+        prop_assert_eq!(&parsed.application_extensions, &gif.application_extensions);
+        let found: Vec<_> = parsed
+            .application_extensions
+            .iter()
+            .filter_map(|application| application.known())
+            .collect();
+        prop_assert!(found.ends_with(&known));
+    }
+
+    // Long inputs run the dictionary full and make the encoder clear it
+    #[test]
+    fn lzw_round_trips(
+        (minimum_code_size, pixels) in (2..=8u8).prop_flat_map(|size| {
+            (Just(size), vec((0..1u16 << size).prop_map(|color| color as u8), 0..20_000))
+        })
+    ) {
+        let compressed = sub_blocks(&lzw_compress(&pixels, minimum_code_size));
+        let read = read_lzw_data(&compressed, minimum_code_size, pixels.len());
+        prop_assert_eq!(read.as_ref(), Ok(&pixels));
+    }
+
+    #[test]
+    fn serialized_gifs_write_the_same_bytes(bytes in gif_bytes()) {
+        let gif = parse_gif_bytes(&bytes).unwrap();
+        // TOML stands in for the binary formats a cache would use
+        let cached = toml::Value::try_from(&gif).unwrap();
+        let restored: GIF = cached.try_into().unwrap();
+        prop_assert_eq!(&restored, &gif);
+        prop_assert_eq!(write_gif_bytes(&restored, Some(&bytes)), bytes);
+    }
+}
+
+#[test]
+fn gif87a_streams_refuse_extensions() {
+    let mut gif = random_gif(&mut ChaCha8Rng::seed_from_u64(3), &Shape::default());
+    gif.header.version = *b"87a";
+    let comment = CommentExtension {
+        comments: vec![b"hi".to_vec()],
+    };
+    let mut writer = GifWriter::new(Vec::new());
+    // Nothing goes before the header
+    assert!(writer
+        .write_extension(Extension::Comment(&comment))
+        .is_err());
+    writer
+        .write_header(&gif.header, &gif.logical_screen_descriptor, None)
+        .unwrap();
+    assert!(writer
+        .write_extension(Extension::Comment(&comment))
+        .is_err());
+}
+
+#[test]
+fn fixtures_are_reproducible() {
+    let shape = Shape::default();
+    assert_eq!(gif_fixture(7, &shape), gif_fixture(7, &shape));
+    assert_ne!(gif_fixture(7, &shape), gif_fixture(8, &shape));
+}
+
+#[test]
+fn short_interlaced_frames_keep_the_rows_they_have() {
+    let frame = |height: u16, image_data: Vec<u8>| ImageDescriptor {
+        left: 0,
+        top: 0,
+        width: 2,
+        height,
+        packed_field: 0b0100_0000,
+        graphics_control_extension: None,
+        local_color_table: None,
+        lzw_minimum_code_size: 2,
+        image_data,
+        compressed_range: None,
+    };
+    // Two of four rows: the first lands on row 0, the second on row 2, which
+    // is past the pixels there are
+    assert_eq!(
+        frame(4, vec![1, 1, 2, 2]).pixels_in_row_order(),
+        [1, 1, 0, 0]
+    );
+    for height in 0..20u16 {
+        for length in 0..=2 * height as usize {
+            let pixels = frame(height, vec![7; length]).pixels_in_row_order();
+            assert_eq!(pixels.len(), length);
+        }
+    }
+}
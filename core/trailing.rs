@@ -0,0 +1,106 @@
+use alloc::vec::Vec;
+
+use crate::events::{parse_events_bytes, FrameStart, GifVisitor};
+use crate::gif::GIF;
+
+// Decoders stop at the first trailer, so whatever comes after it is never
+// seen by them: padding, a second GIF written onto the end of the first, or
+// an archive that makes the file a polyglot. The trailing data is split
+// where such a file starts, everything between counting as plain data.
+
+const GIF_SIGNATURES: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+
+// A ZIP local file header, where an appended archive starts
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+
+const TRAILER: u8 = 0x3B;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrailingKind {
+    // A whole GIF up to its own trailer, with the frames it holds
+    Gif { frames: usize },
+    // A ZIP archive, taken to run to the end
+    Zip,
+    Data,
+}
+
+// A part of the trailing data, its offset counted from the end of the trailer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrailingSegment {
+    pub offset: usize,
+    pub length: usize,
+    pub kind: TrailingKind,
+}
+
+// Counts frames up to the trailer, which must be a real one
+#[derive(Default)]
+struct GifEnd {
+    frames: usize,
+    trailer: Option<usize>,
+}
+
+impl GifVisitor for GifEnd {
+    fn on_frame_start(&mut self, _frame: &FrameStart) {
+        self.frames += 1;
+    }
+
+    fn on_trailer(&mut self, position: usize) {
+        self.trailer = Some(position);
+    }
+}
+
+// The frames and length of the GIF `data` starts with. None when it is
+// damaged or cut off before its trailer.
+fn gif_length(data: &[u8]) -> Option<(usize, usize)> {
+    let mut end = GifEnd::default();
+    parse_events_bytes(data, &mut end).ok()?;
+    let trailer = end.trailer?;
+    (data.get(trailer) == Some(&TRAILER)).then_some((end.frames, trailer + 1))
+}
+
+// The file appended at the start of `data`, with its length
+fn appended_file(data: &[u8]) -> Option<(usize, TrailingKind)> {
+    if data.starts_with(ZIP_SIGNATURE) {
+        return Some((data.len(), TrailingKind::Zip));
+    }
+    if !GIF_SIGNATURES
+        .iter()
+        .any(|signature| data.starts_with(signature))
+    {
+        return None;
+    }
+    let (frames, length) = gif_length(data)?;
+    Some((length, TrailingKind::Gif { frames }))
+}
+
+// Split data found after a trailer into the files appended there
+pub fn trailing_segments(data: &[u8]) -> Vec<TrailingSegment> {
+    let mut segments = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let rest = &data[position..];
+        // Plain data runs up to the next file that can be read
+        let (length, kind) = appended_file(rest).unwrap_or_else(|| {
+            let next = (1..rest.len())
+                .find(|&start| appended_file(&rest[start..]).is_some())
+                .unwrap_or(rest.len());
+            (next, TrailingKind::Data)
+        });
+        segments.push(TrailingSegment {
+            offset: position,
+            length,
+            kind,
+        });
+        position += length;
+    }
+    segments
+}
+
+impl GIF {
+    // The trailing data split into appended GIFs, archives and plain data
+    pub fn trailing_segments(&self) -> Vec<TrailingSegment> {
+        trailing_segments(&self.trailing_data)
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Write};
+
+use crate::gif::{
+    compressed_sub_blocks, write_application_extension, write_comment_extension,
+    write_image_descriptor, write_plain_text_extension, write_screen, ApplicationExtension,
+    BlockLabel, ColorTable, CommentExtension, GIFHeader, GifVersion, ImageDescriptor,
+    LogicalScreenDescriptor, PlainTextExtension,
+};
+
+// Writes a GIF block by block as it is produced, so frames can be made,
+// written and dropped one at a time instead of all being held in a GIF for
+// write_gif. Blocks go out in the order they are written in: the header
+// first, then extensions and frames, then finish for the trailer.
+
+// An extension for GifWriter::write_extension. Graphic control extensions
+// go out with the frame they belong to.
+#[derive(Debug, Clone, Copy)]
+pub enum Extension<'a> {
+    Comment(&'a CommentExtension),
+    Application(&'a ApplicationExtension),
+    PlainText(&'a PlainTextExtension),
+}
+
+pub struct GifWriter<W: Write> {
+    writer: W,
+    started: bool,    // Whether the header is out
+    extensions: bool, // Whether the header allows extensions, which 87a doesn't
+    buffer: Vec<u8>,  // The block being written
+}
+
+fn out_of_order(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(writer: W) -> GifWriter<W> {
+        GifWriter {
+            writer,
+            started: false,
+            extensions: true,
+            buffer: Vec::new(),
+        }
+    }
+
+    // The header is written as it is. A stream can't be rewritten once it
+    // turns out to need 89a, so a 87a header rules extensions out.
+    pub fn write_header(
+        &mut self,
+        header: &GIFHeader,
+        logical_screen_descriptor: &LogicalScreenDescriptor,
+        global_color_table: Option<&ColorTable>,
+    ) -> Result<(), io::Error> {
+        if self.started {
+            return Err(out_of_order("The GIF header was already written."));
+        }
+        self.extensions = header.gif_version() != Some(GifVersion::Gif87a);
+        write_screen(
+            &mut self.buffer,
+            header,
+            logical_screen_descriptor,
+            global_color_table,
+        );
+        self.started = true;
+        self.flush_block()
+    }
+
+    pub fn write_extension(&mut self, extension: Extension) -> Result<(), io::Error> {
+        self.check_blocks()?;
+        if !self.extensions {
+            return Err(out_of_order(
+                "A GIF87a header allows no extensions, write it as GIF89a.",
+            ));
+        }
+        match extension {
+            Extension::Comment(comment) => write_comment_extension(&mut self.buffer, comment),
+            Extension::Application(application) => {
+                write_application_extension(&mut self.buffer, application)
+            }
+            Extension::PlainText(plain_text) => {
+                write_plain_text_extension(&mut self.buffer, plain_text)
+            }
+        }
+        self.flush_block()
+    }
+
+    // A frame with its graphic control extension, its pixels compressed
+    // afresh
+    pub fn write_frame(&mut self, frame: &ImageDescriptor) -> Result<(), io::Error> {
+        self.check_blocks()?;
+        if frame.graphics_control_extension.is_some() && !self.extensions {
+            return Err(out_of_order(
+                "A GIF87a header allows no graphic control extensions, write it as GIF89a.",
+            ));
+        }
+        write_image_descriptor(&mut self.buffer, frame);
+        self.buffer.extend_from_slice(&compressed_sub_blocks(frame));
+        self.flush_block()
+    }
+
+    // Writes the trailer and hands the writer back, flushed
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        self.check_blocks()?;
+        self.buffer.push(BlockLabel::Trailer.into());
+        self.flush_block()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn check_blocks(&self) -> Result<(), io::Error> {
+        if !self.started {
+            return Err(out_of_order("The GIF header has to be written first."));
+        }
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<(), io::Error> {
+        let result = self.writer.write_all(&self.buffer);
+        self.buffer.clear();
+        result
+    }
+}
@@ -0,0 +1,131 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Error};
+use std::sync::OnceLock;
+
+#[cfg(feature = "crypto")]
+use crate::container::ContainerCipher;
+
+// Encrypts and decrypts payloads. A container may hold one or two payloads,
+// each under its own passphrase; which of them a passphrase opens must not be
+// told apart from the container itself.
+pub trait PayloadCipher: Send + Sync {
+    // Seal (passphrase, data) payloads into one container
+    fn seal(&self, payloads: &[(&str, &[u8])]) -> Result<Vec<u8>, Error>;
+
+    // The payload of the container this passphrase opens. PermissionDenied
+    // when it opens none of them.
+    fn open(&self, container: &[u8], passphrase: &str) -> Result<Vec<u8>, Error>;
+
+    // Bytes seal makes of payloads of these lengths, None when the cipher
+    // can't tell without sealing
+    fn sealed_length(&self, _lengths: &[usize]) -> Option<usize> {
+        None
+    }
+
+    // A key stretched from the passphrase over `salt`, as slow to derive as
+    // the ones the cipher seals with. Stealth payloads are found with it.
+    fn stretch(&self, _passphrase: &str, _salt: &[u8]) -> Result<[u8; 32], Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The payload cipher can't stretch passphrases.",
+        ))
+    }
+
+    // seal, drawing whatever it would draw at random from `seed` instead, so
+    // the same seed and payloads always give the same container. The seed
+    // comes from the payloads' data alone, so whatever is drawn from it and
+    // stored in clear, such as a salt, has to be keyed with what the cipher
+    // derives from the passphrases.
+    fn seal_seeded(&self, _payloads: &[(&str, &[u8])], _seed: [u8; 32]) -> Result<Vec<u8>, Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The payload cipher can't seal deterministically.",
+        ))
+    }
+}
+
+static CIPHER: OnceLock<Box<dyn PayloadCipher>> = OnceLock::new();
+
+// Replace the built-in cipher, e.g. with one backed by an HSM. It has to be
+// installed before the first payload is sealed or opened.
+pub fn set_payload_cipher(cipher: Box<dyn PayloadCipher>) -> Result<(), Error> {
+    CIPHER.set(cipher).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "A payload cipher is already in use.",
+        )
+    })
+}
+
+#[cfg(feature = "crypto")]
+fn default_cipher() -> Box<dyn PayloadCipher> {
+    Box::new(ContainerCipher)
+}
+
+// Without the crypto feature there is nothing to encrypt with until a cipher
+// is installed
+#[cfg(not(feature = "crypto"))]
+fn default_cipher() -> Box<dyn PayloadCipher> {
+    struct MissingCipher;
+
+    impl PayloadCipher for MissingCipher {
+        fn seal(&self, _payloads: &[(&str, &[u8])]) -> Result<Vec<u8>, Error> {
+            Err(missing_cipher())
+        }
+
+        fn open(&self, _container: &[u8], _passphrase: &str) -> Result<Vec<u8>, Error> {
+            Err(missing_cipher())
+        }
+    }
+
+    fn missing_cipher() -> Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Encryption needs GifSauce built with the crypto feature.",
+        )
+    }
+
+    Box::new(MissingCipher)
+}
+
+fn payload_cipher() -> &'static dyn PayloadCipher {
+    CIPHER.get_or_init(default_cipher).as_ref()
+}
+
+pub fn seal_payloads(payloads: &[(&str, &[u8])]) -> Result<Vec<u8>, Error> {
+    payload_cipher().seal(payloads)
+}
+
+// Seal with a seed from the payloads' data and an optional nonce, for
+// reproducible output. Equal payloads under equal passphrases give equal
+// containers, which is the point, and a nonce tells apart runs that
+// shouldn't. The passphrases stay out of the seed: a fast hash of them would
+// let a guessed payload check them, so the cipher keys the seed itself.
+pub fn seal_payloads_deterministic(
+    payloads: &[(&str, &[u8])],
+    nonce: Option<&str>,
+) -> Result<Vec<u8>, Error> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"gifsauce deterministic");
+    for part in nonce
+        .iter()
+        .map(|nonce| nonce.as_bytes())
+        .chain(payloads.iter().map(|&(_, data)| data))
+    {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    payload_cipher().seal_seeded(payloads, hasher.finalize().into())
+}
+
+pub fn sealed_length(lengths: &[usize]) -> Option<usize> {
+    payload_cipher().sealed_length(lengths)
+}
+
+pub fn stretch_passphrase(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], Error> {
+    payload_cipher().stretch(passphrase, salt)
+}
+
+pub fn open_payload(container: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    payload_cipher().open(container, passphrase)
+}
@@ -0,0 +1,228 @@
+// C interface of the library, declared in gifsauce.h. Every function returns
+// one of the GIFSAUCE_* codes below; on failure gifsauce_last_error() describes
+// what went wrong. Buffers handed out are owned by the caller and released
+// with gifsauce_free / gifsauce_free_string.
+//
+// The safety contract of each function is spelled out in gifsauce.h.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io::{self, Error};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::{embed_bytes, extract_bytes, inspect_json, Options};
+
+pub const GIFSAUCE_OK: c_int = 0;
+pub const GIFSAUCE_INVALID_ARGUMENT: c_int = 1; // Null pointer, bad UTF-8, unknown channel
+pub const GIFSAUCE_INVALID_DATA: c_int = 2; // Not a GIF, or a damaged payload
+pub const GIFSAUCE_PASSPHRASE: c_int = 3; // Passphrase missing or wrong
+pub const GIFSAUCE_NOT_FOUND: c_int = 4; // The GIF holds no payload
+pub const GIFSAUCE_FAILED: c_int = 5; // Anything else, e.g. the payload doesn't fit
+pub const GIFSAUCE_PANIC: c_int = 6; // A bug in the library
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn error_code(error: &Error) -> c_int {
+    match error.kind() {
+        io::ErrorKind::InvalidInput => GIFSAUCE_INVALID_ARGUMENT,
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => GIFSAUCE_INVALID_DATA,
+        io::ErrorKind::PermissionDenied => GIFSAUCE_PASSPHRASE,
+        io::ErrorKind::NotFound => GIFSAUCE_NOT_FOUND,
+        _ => GIFSAUCE_FAILED,
+    }
+}
+
+// Run the body of an exported function: errors become codes and the last
+// error message, and panics never unwind into the caller.
+fn call(body: impl FnOnce() -> Result<(), Error>) -> c_int {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => GIFSAUCE_OK,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            error_code(&e)
+        }
+        Err(_) => {
+            set_last_error("Internal error.".to_string());
+            GIFSAUCE_PANIC
+        }
+    }
+}
+
+fn null_argument(name: &str) -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} must not be null.", name),
+    )
+}
+
+unsafe fn input_buffer<'a>(data: *const u8, length: usize, name: &str) -> Result<&'a [u8], Error> {
+    if length == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(null_argument(name));
+    }
+    Ok(slice::from_raw_parts(data, length))
+}
+
+// A null string is an option left at its default
+unsafe fn input_string(text: *const c_char, name: &str) -> Result<Option<String>, Error> {
+    if text.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map(|text| Some(text.to_string()))
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not valid UTF-8.", name),
+            )
+        })
+}
+
+// struct gifsauce_options. Null fields, or a null pointer to the whole
+// struct, keep the defaults.
+#[repr(C)]
+pub struct GifsauceOptions {
+    pub channels: *const c_char,
+    pub passphrase: *const c_char,
+    pub name: *const c_char,
+}
+
+unsafe fn read_options(options: *const GifsauceOptions) -> Result<Options, Error> {
+    let mut read = Options::default();
+    let options = match options.as_ref() {
+        Some(options) => options,
+        None => return Ok(read),
+    };
+    if let Some(channels) = input_string(options.channels, "channels")? {
+        read.channels = channels;
+    }
+    read.passphrase = input_string(options.passphrase, "passphrase")?;
+    if let Some(name) = input_string(options.name, "name")? {
+        read.name = name;
+    }
+    Ok(read)
+}
+
+unsafe fn output_buffer(
+    data: Vec<u8>,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+) -> Result<(), Error> {
+    if output.is_null() || output_length.is_null() {
+        return Err(null_argument("output"));
+    }
+    *output_length = data.len();
+    *output = Box::into_raw(data.into_boxed_slice()) as *mut u8;
+    Ok(())
+}
+
+unsafe fn output_string(text: String, output: *mut *mut c_char) -> Result<(), Error> {
+    if output.is_null() {
+        return Err(null_argument("output"));
+    }
+    *output = CString::new(text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .into_raw();
+    Ok(())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gifsauce_embed(
+    gif: *const u8,
+    gif_length: usize,
+    payload: *const u8,
+    payload_length: usize,
+    options: *const GifsauceOptions,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+) -> c_int {
+    call(|| {
+        let gif = input_buffer(gif, gif_length, "gif")?;
+        let payload = input_buffer(payload, payload_length, "payload")?;
+        let options = read_options(options)?;
+        output_buffer(embed_bytes(gif, payload, &options)?, output, output_length)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gifsauce_extract(
+    gif: *const u8,
+    gif_length: usize,
+    options: *const GifsauceOptions,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+    name: *mut *mut c_char,
+) -> c_int {
+    call(|| {
+        // Nothing is handed out until all of it can be, so a failed call
+        // leaves the caller nothing to free
+        if output.is_null() || output_length.is_null() {
+            return Err(null_argument("output"));
+        }
+        let gif = input_buffer(gif, gif_length, "gif")?;
+        let options = read_options(options)?;
+        let extracted = extract_bytes(gif, &options)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No payload found."))?;
+        let extracted_name = CString::new(extracted.name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        output_buffer(extracted.data, output, output_length)?;
+        if !name.is_null() {
+            *name = extracted_name.into_raw();
+        }
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gifsauce_inspect_json(
+    gif: *const u8,
+    gif_length: usize,
+    options: *const GifsauceOptions,
+    output: *mut *mut c_char,
+) -> c_int {
+    call(|| {
+        let gif = input_buffer(gif, gif_length, "gif")?;
+        let options = read_options(options)?;
+        output_string(inspect_json(gif, options.passphrase.as_deref())?, output)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gifsauce_free(data: *mut u8, length: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, length)));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gifsauce_free_string(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+// Message of the last failed call on this thread, or null. Valid until the
+// next call into the library.
+#[no_mangle]
+pub extern "C" fn gifsauce_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
@@ -0,0 +1,413 @@
+use std::io::{self, Error};
+use std::ops::Range;
+
+use crate::gif::{
+    quantize_dithered, ApplicationExtension, DisposalMethod, Dither, GIFHeader,
+    GraphicsControlExtension, ImageDescriptor, LogicalScreenDescriptor, GIF,
+};
+
+// Frames own their graphic control extension and local color table, so both
+// travel with them. Plain text extensions stay where they are.
+
+// Frame ranges such as "3..10,20,30..", ends excluded. A range without an end
+// runs to the last frame and one without a start begins at the first.
+pub fn parse_frame_ranges(list: &str) -> Option<Vec<Range<usize>>> {
+    list.split(',')
+        .map(|range| match range.trim().split_once("..") {
+            Some((start, end)) => {
+                let start = match start {
+                    "" => 0,
+                    start => start.parse().ok()?,
+                };
+                let end = match end {
+                    "" => usize::MAX,
+                    end => end.parse().ok()?,
+                };
+                Some(start..end)
+            }
+            None => {
+                let index: usize = range.trim().parse().ok()?;
+                Some(index..index + 1)
+            }
+        })
+        .collect()
+}
+
+// Refuse a selection that none of a GIF's frames are in
+pub fn check_frame_selection(selection: &[Range<usize>], frame_count: usize) -> Result<(), Error> {
+    if selection
+        .iter()
+        .any(|range| range.start < range.end.min(frame_count))
+    {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "The frames selected are not among the {} frame(s) of the GIF.",
+            frame_count
+        ),
+    ))
+}
+
+// Take the frames outside the selected ranges out of the GIF, so the payload
+// channels only see the selected ones. Returns them with their indices.
+pub fn set_aside_frames(
+    gif: &mut GIF,
+    selection: &[Range<usize>],
+) -> Result<Vec<(usize, ImageDescriptor)>, Error> {
+    check_frame_selection(selection, gif.image_descriptors.len())?;
+    let frames = std::mem::take(&mut gif.image_descriptors);
+    let mut set_aside = Vec::new();
+    for (index, frame) in frames.into_iter().enumerate() {
+        if selection.iter().any(|range| range.contains(&index)) {
+            gif.image_descriptors.push(frame);
+        } else {
+            set_aside.push((index, frame));
+        }
+    }
+    Ok(set_aside)
+}
+
+// Put frames back where set_aside_frames took them from. Frames added in the
+// meantime end up after all of them.
+pub fn restore_frames(gif: &mut GIF, set_aside: Vec<(usize, ImageDescriptor)>) {
+    for (index, frame) in set_aside {
+        let index = index.min(gif.image_descriptors.len());
+        gif.image_descriptors.insert(index, frame);
+    }
+}
+
+fn check_frame(gif: &GIF, index: usize) -> Result<(), Error> {
+    if index >= gif.image_descriptors.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No frame #{}, the file has {} frame(s).",
+                index,
+                gif.image_descriptors.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Remove the frames at the given indices, which refer to the frames before
+// any are removed. Returns how many were removed.
+pub fn delete_frames(gif: &mut GIF, indices: &[usize]) -> Result<usize, Error> {
+    for &index in indices {
+        check_frame(gif, index)?;
+    }
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    indices.dedup();
+    for &index in indices.iter().rev() {
+        gif.image_descriptors.remove(index);
+    }
+    Ok(indices.len())
+}
+
+// Keep only the frames at the given indices, in the order they are given
+pub fn extract_frames(gif: &mut GIF, indices: &[usize]) -> Result<(), Error> {
+    for &index in indices {
+        check_frame(gif, index)?;
+    }
+    let frames: Vec<ImageDescriptor> = indices
+        .iter()
+        .map(|&index| gif.image_descriptors[index].clone())
+        .collect();
+    gif.image_descriptors = frames;
+    Ok(())
+}
+
+// Move a frame so it ends up at index `to`, shifting the frames in between
+pub fn move_frame(gif: &mut GIF, from: usize, to: usize) -> Result<(), Error> {
+    check_frame(gif, from)?;
+    check_frame(gif, to)?;
+    let frame = gif.image_descriptors.remove(from);
+    gif.image_descriptors.insert(to, frame);
+    Ok(())
+}
+
+// Frames can be put in any order as they are when each one covers the whole
+// screen without transparency. Otherwise what a frame shows depends on the
+// frames before it.
+fn frames_stand_alone(gif: &GIF) -> bool {
+    let screen = &gif.logical_screen_descriptor;
+    gif.image_descriptors.iter().all(|frame| {
+        frame.left == 0
+            && frame.top == 0
+            && frame.width == screen.width
+            && frame.height == screen.height
+            && frame.image_data.len() >= frame.width as usize * frame.height as usize
+            && frame.transparent_color_index().is_none()
+    })
+}
+
+// Each frame drawn over the ones before it, as the whole screen. Pixels no
+// frame covers are None.
+pub fn composite_frames(gif: &GIF) -> Result<Vec<Vec<Option<[u8; 3]>>>, Error> {
+    let screen_width = gif.logical_screen_descriptor.width as usize;
+    let screen_height = gif.logical_screen_descriptor.height as usize;
+    let mut canvas = vec![None; screen_width * screen_height];
+    let mut composited = Vec::with_capacity(gif.image_descriptors.len());
+
+    for (index, frame) in gif.image_descriptors.iter().enumerate() {
+        let colors = &frame
+            .local_color_table
+            .as_ref()
+            .or(gif.global_color_table.as_ref())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Frame {} has no color table.", index),
+                )
+            })?
+            .colors;
+        let saved =
+            (frame.disposal_method() == DisposalMethod::RestorePrevious).then(|| canvas.clone());
+
+        // The part of the frame that is on screen
+        let left = frame.left as usize;
+        let top = frame.top as usize;
+        let width = frame.width as usize;
+        let visible_width = (left + width).min(screen_width).saturating_sub(left);
+        let visible_rows = (top + frame.height as usize)
+            .min(screen_height)
+            .saturating_sub(top);
+
+        let transparent_index = frame.transparent_color_index();
+        let pixels = frame.pixels_in_row_order();
+        for row in 0..visible_rows {
+            for column in 0..visible_width {
+                let pixel = match pixels.get(row * width + column) {
+                    Some(&pixel) if Some(pixel) != transparent_index => pixel,
+                    _ => continue,
+                };
+                // Indices past the end of the table are drawn black
+                let color = colors.get(pixel as usize).copied().unwrap_or([0; 3]);
+                canvas[(top + row) * screen_width + left + column] = Some(color);
+            }
+        }
+        composited.push(canvas.clone());
+
+        match (frame.disposal_method(), saved) {
+            (DisposalMethod::RestoreBackground, _) => {
+                for row in 0..visible_rows {
+                    let start = (top + row) * screen_width + left;
+                    canvas[start..start + visible_width].fill(None);
+                }
+            }
+            (DisposalMethod::RestorePrevious, Some(saved)) => canvas = saved,
+            _ => {}
+        }
+    }
+    Ok(composited)
+}
+
+// A whole screen frame showing a composited canvas. Its area is cleared once
+// it has been shown, so uncovered pixels stay see-through whatever comes next.
+// Canvases of more than 256 colors are quantized, dithered if asked.
+pub fn canvas_frame(
+    screen: &LogicalScreenDescriptor,
+    canvas: &[Option<[u8; 3]>],
+    delay_time: u16,
+    dither: Dither,
+) -> ImageDescriptor {
+    let pixels: Vec<[u8; 4]> = canvas
+        .iter()
+        .map(|color| match color {
+            Some([red, green, blue]) => [*red, *green, *blue, 0xFF],
+            None => [0; 4],
+        })
+        .collect();
+    let quantized = quantize_dithered(&pixels, screen.width as usize, 256, dither);
+
+    ImageDescriptor {
+        left: 0,
+        top: 0,
+        width: screen.width,
+        height: screen.height,
+        packed_field: 0b1000_0000 | quantized.size_bits,
+        graphics_control_extension: Some(GraphicsControlExtension {
+            packed_field: DisposalMethod::RestoreBackground.packed_bits()
+                | u8::from(quantized.transparent_index.is_some()),
+            delay_time,
+            transparent_color_index: quantized.transparent_index.unwrap_or(0),
+        }),
+        lzw_minimum_code_size: quantized.lzw_minimum_code_size(),
+        local_color_table: Some(quantized.color_table),
+        image_data: quantized.indices,
+        compressed_range: None,
+    }
+}
+
+// The frames as they look on screen, each standing alone so they can be
+// put in any order. Frames already standing alone are kept as they are.
+pub fn standalone_frames(gif: &GIF, dither: Dither) -> Result<Vec<ImageDescriptor>, Error> {
+    if frames_stand_alone(gif) {
+        return Ok(gif.image_descriptors.clone());
+    }
+    Ok(composite_frames(gif)?
+        .iter()
+        .zip(&gif.image_descriptors)
+        .map(|(canvas, source)| {
+            let delay_time = source
+                .graphics_control_extension
+                .as_ref()
+                .map_or(0, |gce| gce.delay_time);
+            canvas_frame(&gif.logical_screen_descriptor, canvas, delay_time, dither)
+        })
+        .collect())
+}
+
+// Play the animation backwards
+pub fn reverse_frames(gif: &mut GIF, dither: Dither) -> Result<(), Error> {
+    let mut frames = standalone_frames(gif, dither)?;
+    frames.reverse();
+    gif.image_descriptors = frames;
+    Ok(())
+}
+
+// Play the animation forwards then backwards, without showing the first and
+// last frames twice in a row when it loops
+pub fn pingpong_frames(gif: &mut GIF, dither: Dither) -> Result<(), Error> {
+    let mut frames = standalone_frames(gif, dither)?;
+    if frames.len() > 2 {
+        let returning: Vec<ImageDescriptor> =
+            frames[1..frames.len() - 1].iter().rev().cloned().collect();
+        frames.extend(returning);
+    }
+    gif.image_descriptors = frames;
+    Ok(())
+}
+
+// Browsers play delays of 0 and 1 hundredths of a second at whatever speed
+// they choose. Those become `floor`. With `fps`, each frame's end is first
+// moved onto the nearest tick of that rate, every frame lasting at least one
+// tick, so the animation keeps its length. Frames without a graphic control
+// extension gain one. Returns how many frames changed their delay.
+pub fn normalize_delays(gif: &mut GIF, floor: u16, fps: Option<u16>) -> usize {
+    let delays: Vec<u16> = gif
+        .image_descriptors
+        .iter()
+        .map(|frame| {
+            frame
+                .graphics_control_extension
+                .as_ref()
+                .map_or(0, |gce| gce.delay_time)
+        })
+        .collect();
+
+    let mut normalized = delays.clone();
+    if let Some(fps) = fps.filter(|&fps| fps > 0) {
+        let fps = fps as u64;
+        let (mut end, mut tick, mut shown) = (0u64, 0u64, 0u64);
+        for delay in &mut normalized {
+            end += *delay as u64;
+            // Hundredths to ticks and back, rounded to the nearest
+            tick = ((end * fps + 50) / 100).max(tick + 1);
+            let until = (tick * 100 + fps / 2) / fps;
+            *delay = (until - shown).min(u16::MAX as u64) as u16;
+            shown = until;
+        }
+    }
+    for delay in &mut normalized {
+        if *delay < 2 {
+            *delay = floor;
+        }
+    }
+
+    let mut changed = 0;
+    for ((frame, &before), &after) in gif
+        .image_descriptors
+        .iter_mut()
+        .zip(&delays)
+        .zip(&normalized)
+    {
+        if before == after {
+            continue;
+        }
+        frame
+            .graphics_control_extension
+            .get_or_insert(GraphicsControlExtension {
+                packed_field: 0,
+                delay_time: 0,
+                transparent_color_index: 0,
+            })
+            .delay_time = after;
+        changed += 1;
+    }
+    changed
+}
+
+pub fn is_loop_extension(application: &ApplicationExtension) -> bool {
+    matches!(
+        (
+            application.identifier.as_str(),
+            application.authentication_code.as_str()
+        ),
+        ("NETSCAPE", "2.0") | ("ANIMEXTS", "1.0")
+    )
+}
+
+// One GIF per range of frame indices. Each part keeps the header, screen,
+// global palette and loop block of the original, and loops forever when the
+// original has no loop block. Comments, plain text and other application
+// extensions are left out.
+pub fn split_frames(
+    gif: &GIF,
+    segments: &[Range<usize>],
+    dither: Dither,
+) -> Result<Vec<GIF>, Error> {
+    for segment in segments {
+        if segment.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Frames {}..{} are an empty range.",
+                    segment.start, segment.end
+                ),
+            ));
+        }
+        check_frame(gif, segment.end - 1)?;
+    }
+    // A part starting mid-animation can't rely on the frames before it
+    let frames = if segments.iter().all(|segment| segment.start == 0) {
+        gif.image_descriptors.clone()
+    } else {
+        standalone_frames(gif, dither)?
+    };
+
+    let mut loop_extension: Vec<ApplicationExtension> = gif
+        .application_extensions
+        .iter()
+        .filter(|application| is_loop_extension(application))
+        .take(1)
+        .cloned()
+        .collect();
+    if loop_extension.is_empty() {
+        loop_extension.push(ApplicationExtension {
+            identifier: "NETSCAPE".to_string(),
+            authentication_code: "2.0".to_string(),
+            data: vec![1, 0, 0], // Sub-block 1, loop count 0 for forever
+        });
+    }
+
+    Ok(segments
+        .iter()
+        .map(|segment| GIF {
+            header: GIFHeader {
+                signature: *b"GIF",
+                version: *b"89a",
+            },
+            logical_screen_descriptor: gif.logical_screen_descriptor.clone(),
+            global_color_table: gif.global_color_table.clone(),
+            comment_extensions: Vec::new(),
+            application_extensions: loop_extension.clone(),
+            plain_text_extensions: Vec::new(),
+            image_descriptors: frames[segment.clone()].to_vec(),
+            trailing_data: Vec::new(),
+        })
+        .collect())
+}
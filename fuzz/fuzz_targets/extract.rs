@@ -0,0 +1,10 @@
+// Look for a payload in arbitrary bytes the way extract and inspect do
+#![no_main]
+
+use gifsauce::{extract_bytes, inspect_json, Options};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = extract_bytes(data, &Options::default());
+    let _ = inspect_json(data, None);
+});
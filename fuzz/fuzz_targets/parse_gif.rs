@@ -0,0 +1,27 @@
+// Parse arbitrary bytes, at once and pushed in pieces, and write back
+// whatever parses.
+#![no_main]
+
+use gifsauce_core::{parse_gif_bytes, write_gif_bytes, GifParser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let parsed = parse_gif_bytes(data);
+
+    // Feeding the parser one byte at a time must agree with one push
+    let mut parser = GifParser::new();
+    let mut pushed = Ok(());
+    for byte in data.chunks(1) {
+        pushed = parser.push(byte);
+        if pushed.is_err() || parser.is_done() {
+            break;
+        }
+    }
+    let streamed = pushed.and_then(|_| parser.finish());
+    assert_eq!(parsed.is_ok(), streamed.is_ok());
+
+    if let Ok(gif) = parsed {
+        write_gif_bytes(&gif, Some(data));
+        write_gif_bytes(&gif, None);
+    }
+});
@@ -0,0 +1,28 @@
+// Decompress arbitrary sub-blocks, then check that compressing pixels and
+// decompressing them again gives the same pixels.
+#![no_main]
+
+use gifsauce_core::{lzw_compress, read_lzw_data};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u8, u16, &[u8])| {
+    let (minimum_code_size, limit, data) = input;
+    if let Ok(pixels) = read_lzw_data(data, minimum_code_size, limit as usize) {
+        assert!(pixels.len() <= limit as usize);
+    }
+
+    let minimum_code_size = 2 + minimum_code_size % 7;
+    let mask = ((1u16 << minimum_code_size) - 1) as u8;
+    let pixels: Vec<u8> = data.iter().map(|pixel| pixel & mask).collect();
+    let compressed = lzw_compress(&pixels, minimum_code_size);
+    let mut blocks = Vec::new();
+    for chunk in compressed.chunks(255) {
+        blocks.push(chunk.len() as u8);
+        blocks.extend_from_slice(chunk);
+    }
+    blocks.push(0);
+    assert_eq!(
+        read_lzw_data(&blocks, minimum_code_size, pixels.len()),
+        Ok(pixels)
+    );
+});
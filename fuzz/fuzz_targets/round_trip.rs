@@ -0,0 +1,21 @@
+// Write an arbitrary GIF model, parse it back and write it again. The bytes
+// and the pixels of every frame must survive.
+#![no_main]
+
+use gifsauce_core::{parse_gif_bytes, write_gif_bytes};
+use gifsauce_fuzz::ArbitraryGif;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|gif: ArbitraryGif| {
+    let gif = gif.0;
+    let bytes = write_gif_bytes(&gif, None);
+    let parsed = parse_gif_bytes(&bytes).expect("a written GIF parses");
+
+    assert_eq!(write_gif_bytes(&parsed, None), bytes);
+    // Frames copied from the source match recompressed ones
+    assert_eq!(write_gif_bytes(&parsed, Some(&bytes)), bytes);
+    assert_eq!(parsed.image_descriptors.len(), gif.image_descriptors.len());
+    for (frame, parsed) in gif.image_descriptors.iter().zip(&parsed.image_descriptors) {
+        assert_eq!(frame.image_data, parsed.image_data);
+    }
+});
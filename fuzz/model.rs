@@ -0,0 +1,136 @@
+// Arbitrary GIF models for round trip fuzzing. The model types belong to
+// gifsauce-core, so they are wrapped here. Every model is one the writer can
+// serialize faithfully: the header is GIF87a or GIF89a, color tables match
+// the flags in the packed fields, pixels fit the LZW code size and
+// identifiers are eight ASCII bytes.
+use arbitrary::{Arbitrary, Result, Unstructured};
+use gifsauce_core::{
+    ApplicationExtension, ColorTable, CommentExtension, GIFHeader, GraphicsControlExtension,
+    ImageDescriptor, LogicalScreenDescriptor, PlainTextExtension, GIF,
+};
+
+// Frames are kept small so each run stays fast
+const MAX_FRAMES: usize = 4;
+const MAX_FRAME_SIDE: u16 = 32;
+
+#[derive(Debug)]
+pub struct ArbitraryGif(pub GIF);
+
+impl<'a> Arbitrary<'a> for ArbitraryGif {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        // Anything else is not a GIF the parser reads back
+        let header = GIFHeader {
+            signature: *b"GIF",
+            version: *u.choose(&[*b"87a", *b"89a"])?,
+        };
+        let packed_field = u.arbitrary()?;
+        let logical_screen_descriptor = LogicalScreenDescriptor {
+            width: u.arbitrary()?,
+            height: u.arbitrary()?,
+            packed_field,
+            background_color_index: u.arbitrary()?,
+            pixel_aspect_ratio: u.arbitrary()?,
+        };
+        let global_color_table = color_table(u, packed_field)?;
+
+        let comment_extensions = (0..u.int_in_range(0..=3)?)
+            .map(|_| {
+                Ok(CommentExtension {
+                    comments: u.arbitrary()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let application_extensions = (0..u.int_in_range(0..=3)?)
+            .map(|_| {
+                Ok(ApplicationExtension {
+                    identifier: ascii(u, 8)?,
+                    authentication_code: ascii(u, 3)?,
+                    data: u.arbitrary()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let plain_text_extensions = (0..u.int_in_range(0..=MAX_FRAMES + 1)?)
+            .map(|_| plain_text(u))
+            .collect::<Result<_>>()?;
+        let image_descriptors = (0..u.int_in_range(0..=MAX_FRAMES)?)
+            .map(|_| frame(u))
+            .collect::<Result<_>>()?;
+
+        Ok(ArbitraryGif(GIF {
+            header,
+            logical_screen_descriptor,
+            global_color_table,
+            comment_extensions,
+            application_extensions,
+            plain_text_extensions,
+            image_descriptors,
+            trailing_data: u.arbitrary()?,
+        }))
+    }
+}
+
+// The color table a packed field announces, if any
+fn color_table(u: &mut Unstructured, packed_field: u8) -> Result<Option<ColorTable>> {
+    if packed_field & 0b1000_0000 == 0 {
+        return Ok(None);
+    }
+    let size = 2usize << (packed_field & 0b111);
+    let colors = (0..size).map(|_| u.arbitrary()).collect::<Result<_>>()?;
+    Ok(Some(ColorTable { colors }))
+}
+
+fn ascii(u: &mut Unstructured, length: usize) -> Result<String> {
+    (0..length)
+        .map(|_| Ok(char::from(u.int_in_range(0x20..=0x7E)?)))
+        .collect()
+}
+
+fn plain_text(u: &mut Unstructured) -> Result<PlainTextExtension> {
+    Ok(PlainTextExtension {
+        block_size: 12,
+        text_grid_left_position: u.arbitrary()?,
+        text_grid_top_position: u.arbitrary()?,
+        text_grid_width: u.arbitrary()?,
+        text_grid_height: u.arbitrary()?,
+        character_cell_width: u.arbitrary()?,
+        character_cell_height: u.arbitrary()?,
+        text_foreground_color_index: u.arbitrary()?,
+        text_background_color_index: u.arbitrary()?,
+        plain_text_data: u.arbitrary()?,
+    })
+}
+
+fn frame(u: &mut Unstructured) -> Result<ImageDescriptor> {
+    let left = u.arbitrary()?;
+    let top = u.arbitrary()?;
+    let width = u.int_in_range(0..=MAX_FRAME_SIDE)?;
+    let height = u.int_in_range(0..=MAX_FRAME_SIDE)?;
+    let packed_field = u.arbitrary()?;
+    let graphics_control_extension = match u.arbitrary()? {
+        true => Some(GraphicsControlExtension {
+            packed_field: u.arbitrary()?,
+            delay_time: u.arbitrary()?,
+            transparent_color_index: u.arbitrary()?,
+        }),
+        false => None,
+    };
+    let local_color_table = color_table(u, packed_field)?;
+    let lzw_minimum_code_size = u.int_in_range(2..=8)?;
+    let mask = ((1u16 << lzw_minimum_code_size) - 1) as u8;
+    let image_data = (0..width as usize * height as usize)
+        .map(|_| Ok(u.arbitrary::<u8>()? & mask))
+        .collect::<Result<_>>()?;
+
+    Ok(ImageDescriptor {
+        left,
+        top,
+        width,
+        height,
+        packed_field,
+        graphics_control_extension,
+        local_color_table,
+        lzw_minimum_code_size,
+        image_data,
+        compressed_range: None,
+    })
+}
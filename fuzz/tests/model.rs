@@ -0,0 +1,71 @@
+// The models the round_trip target is fed, drawn from pseudo-random bytes
+// instead of libFuzzer's, so they can be checked without a nightly toolchain:
+//
+//   cargo test --manifest-path fuzz/Cargo.toml
+use arbitrary::{Arbitrary, Unstructured};
+use gifsauce::{extract_bytes, inspect_json, Options};
+use gifsauce_core::{parse_gif_bytes, write_gif_bytes};
+use gifsauce_fuzz::ArbitraryGif;
+
+const CASES: u64 = 500;
+
+// xorshift64*, plenty for test input
+fn random_bytes(seed: u64, length: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    (0..length)
+        .map(|_| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn arbitrary_models_round_trip() {
+    for seed in 0..CASES {
+        let bytes = random_bytes(seed, 4096);
+        let gif = match ArbitraryGif::arbitrary(&mut Unstructured::new(&bytes)) {
+            Ok(gif) => gif.0,
+            Err(_) => continue,
+        };
+        let written = write_gif_bytes(&gif, None);
+        let parsed = parse_gif_bytes(&written).unwrap_or_else(|e| panic!("seed {seed}: {e}"));
+        assert_eq!(write_gif_bytes(&parsed, None), written, "seed {seed}");
+        assert_eq!(
+            write_gif_bytes(&parsed, Some(&written)),
+            written,
+            "seed {seed}"
+        );
+        for (frame, parsed) in gif.image_descriptors.iter().zip(&parsed.image_descriptors) {
+            assert_eq!(frame.image_data, parsed.image_data, "seed {seed}");
+            // Rows come out in order, interlaced or not
+            assert!(parsed.pixels_in_row_order().len() <= parsed.image_data.len());
+        }
+    }
+}
+
+// What the extract target does, on models cut short or with bytes flipped
+#[test]
+fn damaged_models_are_searched_without_panicking() {
+    for seed in 0..CASES {
+        let bytes = random_bytes(seed, 4096);
+        let gif = match ArbitraryGif::arbitrary(&mut Unstructured::new(&bytes)) {
+            Ok(gif) => gif.0,
+            Err(_) => continue,
+        };
+        let mut written = write_gif_bytes(&gif, None);
+        let noise = random_bytes(!seed, 8);
+        written.truncate(written.len() - noise[0] as usize % written.len().max(1) / 2);
+        for (index, &byte) in noise[1..].iter().enumerate() {
+            let position = (index * 7919 + byte as usize * 31) % written.len().max(1);
+            if let Some(target) = written.get_mut(position) {
+                *target ^= byte;
+            }
+        }
+        let _ = parse_gif_bytes(&written);
+        let _ = extract_bytes(&written, &Options::default());
+        let _ = inspect_json(&written, None);
+    }
+}
@@ -0,0 +1,175 @@
+use std::f32::consts::TAU;
+use std::io::{self, Error};
+
+use crate::frames::canvas_frame;
+use crate::gif::{ApplicationExtension, Dither, GIFHeader, LogicalScreenDescriptor, GIF};
+
+// Carriers made from nothing. Every style is a function of the pixel, a phase
+// going once around the circle over the animation and the seed, so the
+// animation loops without a jump and the same seed makes the same GIF.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Noise,    // Drifting clouds of fractal value noise
+    Gradient, // Bands of color sliding across the screen
+    Plasma,   // Sums of sine waves, the demo effect
+}
+
+pub fn parse_style(name: &str) -> Option<Style> {
+    match name {
+        "noise" => Some(Style::Noise),
+        "gradient" => Some(Style::Gradient),
+        "plasma" => Some(Style::Plasma),
+        _ => None,
+    }
+}
+
+// A number in [0, 1) for a lattice point, from splitmix64
+fn hash(seed: u64, x: i64, y: i64) -> f32 {
+    let mut z = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// Lattice values blended smoothly between the points
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (cell_x, cell_y) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (fx, fy) = (smooth(x - cell_x), smooth(y - cell_y));
+    let (cx, cy) = (cell_x as i64, cell_y as i64);
+    let top = hash(seed, cx, cy) * (1.0 - fx) + hash(seed, cx + 1, cy) * fx;
+    let bottom = hash(seed, cx, cy + 1) * (1.0 - fx) + hash(seed, cx + 1, cy + 1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+// Octaves of value noise, each half the size and weight of the one before
+fn fractal_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (mut total, mut weight, mut scale) = (0.0, 0.5, 1.0);
+    for octave in 0..4 {
+        total += weight * value_noise(seed.wrapping_add(octave), x * scale, y * scale);
+        weight /= 2.0;
+        scale *= 2.0;
+    }
+    total / 0.9375
+}
+
+// Hue, saturation and value in [0, 1] as RGB
+fn hsv(hue: f32, saturation: f32, value: f32) -> [u8; 3] {
+    let hue = hue.rem_euclid(1.0) * 6.0;
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let base = value - chroma;
+    [r, g, b].map(|channel| ((channel + base) * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+// The color of pixel (x, y) at `phase`, in radians
+fn pixel(
+    style: Style,
+    seed: u64,
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    phase: f32,
+) -> [u8; 3] {
+    // Coordinates in [0, 1) across the screen's longer side
+    let side = width.max(height) as f32;
+    let (u, v) = (x as f32 / side, y as f32 / side);
+    let hue = hash(seed, -1, -1);
+    match style {
+        Style::Noise => {
+            // Travel around a circle through the noise, four cells across
+            let (dx, dy) = (phase.cos() * 0.75, phase.sin() * 0.75);
+            // Octaves average out towards the middle, so spread them again
+            let value = fractal_noise(seed, u * 4.0 + dx, v * 4.0 + dy);
+            let value = ((value - 0.5) * 1.8 + 0.5).clamp(0.0, 1.0);
+            let detail = fractal_noise(seed ^ 0xA5A5, u * 4.0 - dy, v * 4.0 + dx);
+            hsv(hue + value * 0.3, 0.35 + detail * 0.4, 0.25 + value * 0.75)
+        }
+        Style::Gradient => {
+            let angle = hash(seed, -2, -2) * TAU;
+            let along = u * angle.cos() + v * angle.sin();
+            let across = v * angle.cos() - u * angle.sin();
+            hsv(
+                hue + along * 0.5 + phase / TAU,
+                0.55 + 0.2 * (across * TAU + phase).sin(),
+                0.85 + 0.1 * (along * TAU * 2.0).cos(),
+            )
+        }
+        Style::Plasma => {
+            let [a, b, c] = [-3, -4, -5].map(|key| 2.0 + hash(seed, key, key) * 4.0);
+            let (cx, cy) = (0.5 + 0.3 * phase.cos(), 0.5 + 0.3 * phase.sin());
+            let distance = ((u - cx).powi(2) + (v - cy).powi(2)).sqrt();
+            let sum = (u * a * TAU + phase).sin()
+                + (v * b * TAU - phase).sin()
+                + ((u + v) * c * TAU / 2.0 + phase).sin()
+                + (distance * (a + b) * TAU).sin();
+            hsv(hue + sum / 8.0, 0.7, 0.6 + 0.4 * (sum * 0.8).cos().abs())
+        }
+    }
+}
+
+// An animation of `frames` frames `delay` hundredths of a second apart,
+// looping forever
+pub fn generate_carrier(
+    width: usize,
+    height: usize,
+    frames: usize,
+    style: Style,
+    seed: u64,
+    delay: u16,
+    dither: Dither,
+) -> Result<GIF, Error> {
+    if width == 0 || height == 0 || width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A GIF is 1 to 65535 pixels across and down.",
+        ));
+    }
+    let screen = LogicalScreenDescriptor {
+        width: width as u16,
+        height: height as u16,
+        packed_field: 0,
+        background_color_index: 0,
+        pixel_aspect_ratio: 0,
+    };
+    let image_descriptors = (0..frames.max(1))
+        .map(|frame| {
+            let phase = frame as f32 / frames.max(1) as f32 * TAU;
+            let canvas: Vec<Option<[u8; 3]>> = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| Some(pixel(style, seed, width, height, x, y, phase)))
+                .collect();
+            canvas_frame(&screen, &canvas, delay, dither)
+        })
+        .collect();
+    Ok(GIF {
+        header: GIFHeader {
+            signature: *b"GIF",
+            version: *b"89a",
+        },
+        logical_screen_descriptor: screen,
+        global_color_table: None,
+        comment_extensions: Vec::new(),
+        application_extensions: vec![ApplicationExtension {
+            identifier: "NETSCAPE".to_string(),
+            authentication_code: "2.0".to_string(),
+            data: vec![1, 0, 0],
+        }],
+        plain_text_extensions: Vec::new(),
+        image_descriptors,
+        trailing_data: Vec::new(),
+    })
+}
@@ -0,0 +1,78 @@
+/*
+ * C interface of the GifSauce library (libgifsauce.so / gifsauce.dll).
+ *
+ * Every function returns one of the GIFSAUCE_* codes. When it isn't
+ * GIFSAUCE_OK, gifsauce_last_error() returns a message describing the
+ * failure, valid until the next call into the library on the same thread.
+ *
+ * Input buffers are only read during the call. Buffers and strings handed
+ * back belong to the caller, who releases them with gifsauce_free and
+ * gifsauce_free_string. All strings are NUL terminated UTF-8.
+ */
+#ifndef GIFSAUCE_H
+#define GIFSAUCE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define GIFSAUCE_OK 0
+#define GIFSAUCE_INVALID_ARGUMENT 1 /* Null pointer, bad UTF-8, unknown channel */
+#define GIFSAUCE_INVALID_DATA 2     /* Not a GIF, or a damaged payload */
+#define GIFSAUCE_PASSPHRASE 3       /* Passphrase missing or wrong */
+#define GIFSAUCE_NOT_FOUND 4        /* The GIF holds no payload */
+#define GIFSAUCE_FAILED 5           /* Anything else, e.g. the payload doesn't fit */
+#define GIFSAUCE_PANIC 6            /* A bug in the library */
+
+/*
+ * Null fields keep their default, and a null pointer to the whole struct
+ * keeps all of them.
+ */
+struct gifsauce_options {
+    const char *channels;   /* Comma separated, "plaintext" by default */
+    const char *passphrase; /* Encrypts on embed, decrypts on extract */
+    const char *name;       /* File name stored with an embedded payload */
+};
+
+/* Hide payload in a GIF. The new GIF is returned in output. */
+int gifsauce_embed(const uint8_t *gif, size_t gif_length,
+                   const uint8_t *payload, size_t payload_length,
+                   const struct gifsauce_options *options,
+                   uint8_t **output, size_t *output_length);
+
+/*
+ * Recover the payload of a GIF. name, when not null, receives the file name
+ * stored with it. GIFSAUCE_NOT_FOUND when there is none.
+ */
+int gifsauce_extract(const uint8_t *gif, size_t gif_length,
+                     const struct gifsauce_options *options,
+                     uint8_t **output, size_t *output_length,
+                     char **name);
+
+/*
+ * Describe a GIF and any payload it holds as a JSON object:
+ *
+ *   {"width":32,"height":32,"frames":5,"comments":1,
+ *    "application_extensions":0,"plain_text_extensions":0,
+ *    "payload":{"channels":["comment"],"version":2,"length":236,
+ *               "encrypted":false,"compressed":false}}
+ *
+ * "payload" is null when none is found.
+ */
+int gifsauce_inspect_json(const uint8_t *gif, size_t gif_length,
+                          const struct gifsauce_options *options,
+                          char **output);
+
+void gifsauce_free(uint8_t *data, size_t length);
+void gifsauce_free_string(char *text);
+
+const char *gifsauce_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GIFSAUCE_H */
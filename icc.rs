@@ -0,0 +1,32 @@
+use std::fs;
+use std::io::{self, Error};
+
+use crate::gif::{ApplicationExtension, IccProfile, KnownApplication, GIF};
+
+// ICC color profiles in ICCRGBG1012 application extensions. Profiles are
+// checked only as far as their header goes.
+
+// A profile from a .icc or .icm file, which states its own size
+pub fn read_icc_file(path: &str) -> Result<Vec<u8>, Error> {
+    let profile = fs::read(path)?;
+    let stated = profile
+        .get(..4)
+        .map(|size| u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize);
+    let checked = ApplicationExtension::from(&KnownApplication::IccProfile(IccProfile {
+        profile: profile.clone(),
+    }));
+    if checked.known().is_none() || stated != Some(profile.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} isn't an ICC profile.", path),
+        ));
+    }
+    Ok(profile)
+}
+
+// Put the profile in the first ICC block, or a new one, dropping any others
+pub fn set_icc_profile(gif: &mut GIF, profile: Vec<u8>) {
+    gif.set_application(ApplicationExtension::from(&KnownApplication::IccProfile(
+        IccProfile { profile },
+    )));
+}
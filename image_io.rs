@@ -0,0 +1,61 @@
+use std::io::{self, Error};
+
+use crate::gif::GIF;
+
+// One frame on its own, as an indexed PNG with the frame's palette. The
+// transparent index of the graphic control extension stays transparent.
+pub fn frame_png(gif: &GIF, index: usize) -> Result<Vec<u8>, Error> {
+    let frame = gif.image_descriptors.get(index).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The GIF has no frame {}.", index),
+        )
+    })?;
+    let palette = frame
+        .local_color_table
+        .as_ref()
+        .or(gif.global_color_table.as_ref())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Frame {} has no color table.", index),
+            )
+        })?;
+
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    if frame.image_data.len() < width * height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame {} is missing pixels.", index),
+        ));
+    }
+    let pixels = frame.pixels_in_row_order();
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.colors.concat());
+    if let Some(transparent_index) = frame.transparent_color_index() {
+        let mut alpha = vec![0xFF; transparent_index as usize + 1];
+        alpha[transparent_index as usize] = 0;
+        encoder.set_trns(alpha);
+    }
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&pixels).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)?;
+    Ok(output)
+}
+
+// An RGBA image, rows of `width` pixels, as a PNG
+pub fn rgba_png(width: usize, height: usize, rgba: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(rgba).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)?;
+    Ok(output)
+}
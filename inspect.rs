@@ -0,0 +1,386 @@
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::io::Error;
+use std::ops::Range;
+
+use crate::frames::{composite_frames, is_loop_extension};
+use crate::gif::{
+    trailing_segments, BlockLabel, DisposalMethod, KnownApplication, ParseWarning, TrailingKind,
+    GIF,
+};
+use crate::hex;
+
+// Blocks are found by walking the bytes, not by parsing them, so a damaged
+// file still shows as far as its blocks can be told apart. A block that runs
+// past the end of the file ends the map.
+
+// Bytes of each block shown in the dump
+const DUMP_BYTES: usize = 16;
+
+pub struct Block {
+    pub offset: usize,
+    pub length: usize,
+    pub kind: String,
+}
+
+// Where the sub-blocks starting at `start` end, after their terminator.
+// None when the file ends first.
+fn sub_blocks_end(bytes: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        match *bytes.get(position)? {
+            0 => return Some(position + 1),
+            size => position += 1 + size as usize,
+        }
+    }
+}
+
+fn color_table_length(packed_field: u8) -> usize {
+    match packed_field & 0b1000_0000 {
+        0 => 0,
+        _ => 3 << ((packed_field & 0b111) + 1),
+    }
+}
+
+// Add the block from `position` to `end`, cut at the end of the file, and
+// move past it. False once nothing follows it.
+fn add_block(
+    blocks: &mut Vec<Block>,
+    bytes: &[u8],
+    position: &mut usize,
+    end: Option<usize>,
+    kind: &str,
+) -> bool {
+    let truncated = end.is_none_or(|end| end > bytes.len());
+    let end = end.unwrap_or(bytes.len()).min(bytes.len());
+    blocks.push(Block {
+        offset: *position,
+        length: end - *position,
+        kind: match truncated {
+            true => format!("{} (truncated)", kind),
+            false => kind.to_string(),
+        },
+    });
+    *position = end;
+    !truncated && end < bytes.len()
+}
+
+// Every block in file order. The header and logical screen descriptor are
+// counted as blocks, and so is anything after the trailer.
+pub fn block_map(bytes: &[u8]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut position = 0;
+    let mut add = |position: &mut usize, end: Option<usize>, kind: &str| {
+        add_block(&mut blocks, bytes, position, end, kind)
+    };
+
+    if !add(&mut position, Some(6), "Header")
+        || !add(&mut position, Some(13), "Logical Screen Descriptor")
+    {
+        return blocks;
+    }
+    let global = color_table_length(bytes[10]);
+    if global > 0 && !add(&mut position, Some(13 + global), "Global Color Table") {
+        return blocks;
+    }
+
+    loop {
+        let start = position;
+        let more = match BlockLabel::introducer(bytes[start]) {
+            Some(BlockLabel::Extension) => {
+                let label = bytes.get(start + 1).copied();
+                let kind = match label.and_then(BlockLabel::extension) {
+                    Some(BlockLabel::GraphicControl) => "Graphic Control Extension".to_string(),
+                    Some(BlockLabel::Comment) => "Comment Extension".to_string(),
+                    Some(BlockLabel::PlainText) => "Plain Text Extension".to_string(),
+                    Some(_) => match bytes.get(start + 3..start + 14) {
+                        Some(name) => format!(
+                            "Application Extension {}",
+                            String::from_utf8_lossy(name).escape_debug()
+                        ),
+                        None => "Application Extension".to_string(),
+                    },
+                    None => match label {
+                        Some(label) => format!("Extension 0x{:02X}", label),
+                        None => "Extension".to_string(),
+                    },
+                };
+                add(&mut position, sub_blocks_end(bytes, start + 2), &kind)
+            }
+            Some(BlockLabel::Image) => {
+                if !add(&mut position, Some(start + 10), "Image Descriptor") {
+                    break;
+                }
+                let local = start + 10 + color_table_length(bytes[start + 9]);
+                if local > position && !add(&mut position, Some(local), "Local Color Table") {
+                    break;
+                }
+                // The LZW minimum code size, then the sub-blocks
+                let data = position;
+                add(&mut position, sub_blocks_end(bytes, data + 1), "Image Data")
+            }
+            Some(_) => {
+                if add(&mut position, Some(start + 1), "Trailer") {
+                    for segment in trailing_segments(&bytes[position..]) {
+                        let end = position + segment.length;
+                        add(&mut position, Some(end), &trailing_kind(segment.kind));
+                    }
+                }
+                false
+            }
+            None => add(&mut position, Some(bytes.len()), "Unknown data"),
+        };
+        if !more {
+            break;
+        }
+    }
+    blocks
+}
+
+// The blocks of the selected frames, with their graphic control extensions,
+// and every block that isn't part of a frame
+pub fn frame_blocks(blocks: Vec<Block>, selection: &[Range<usize>]) -> Vec<Block> {
+    let mut frame = 0;
+    let mut kept = Vec::new();
+    for block in blocks {
+        let selected = selection.iter().any(|range| range.contains(&frame));
+        match block.kind.as_str() {
+            "Graphic Control Extension" | "Image Descriptor" | "Local Color Table" => {
+                if selected {
+                    kept.push(block);
+                }
+            }
+            "Image Data" => {
+                if selected {
+                    kept.push(block);
+                }
+                frame += 1;
+            }
+            _ => kept.push(block),
+        }
+    }
+    kept
+}
+
+fn trailing_kind(kind: TrailingKind) -> String {
+    match kind {
+        TrailingKind::Gif { frames } => format!("Appended GIF, {} frame(s)", frames),
+        TrailingKind::Zip => "Appended ZIP archive".to_string(),
+        TrailingKind::Data => "Data after the trailer".to_string(),
+    }
+}
+
+// The map as a table, each block with its first bytes in hex and as text
+pub fn format_block_map(bytes: &[u8], blocks: &[Block]) -> String {
+    let mut output = format!(
+        "{:>10} {:>10}  {:<40} first bytes\n",
+        "offset", "length", "block"
+    );
+    for block in blocks {
+        let shown = &bytes[block.offset..block.offset + block.length.min(DUMP_BYTES)];
+        let hex: Vec<String> = shown.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text: String = shown
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7E => byte as char,
+                _ => '.',
+            })
+            .collect();
+        let _ = writeln!(
+            output,
+            "{:>10} {:>10}  {:<40} {:<47}{} |{}|",
+            block.offset,
+            block.length,
+            block.kind,
+            hex.join(" "),
+            if block.length > DUMP_BYTES { "+" } else { " " },
+            text
+        );
+    }
+    output
+}
+
+// A few lines on what the file holds
+// `frame_count` is how many frames the file has, more than the GIF holds when
+// only some were selected
+pub fn format_summary(
+    gif: &GIF,
+    size: usize,
+    frame_count: usize,
+    warnings: &[ParseWarning],
+) -> String {
+    let screen = &gif.logical_screen_descriptor;
+    let mut output = String::new();
+    let _ = writeln!(
+        output,
+        "GIF{}, {} bytes",
+        String::from_utf8_lossy(&gif.header.version),
+        size
+    );
+    let _ = writeln!(output, "Screen: {}x{}", screen.width, screen.height);
+    let _ = writeln!(
+        output,
+        "Global color table: {}",
+        gif.global_color_table
+            .as_ref()
+            .map_or("none".to_string(), |table| format!(
+                "{} colors",
+                table.colors.len()
+            ))
+    );
+    match gif.image_descriptors.len() {
+        selected if selected == frame_count => {
+            let _ = writeln!(output, "Frames: {}", frame_count);
+        }
+        selected => {
+            let _ = writeln!(output, "Frames: {} of {} selected", selected, frame_count);
+        }
+    }
+    let _ = writeln!(
+        output,
+        "Extensions: {} comment, {} application, {} plain text",
+        gif.comment_extensions.len(),
+        gif.application_extensions.len(),
+        gif.plain_text_extensions.len()
+    );
+    for application in &gif.application_extensions {
+        match application.known() {
+            Some(KnownApplication::Xmp(packet)) => {
+                let _ = writeln!(output, "XMP metadata: {} bytes", packet.xml.len());
+            }
+            Some(KnownApplication::IccProfile(profile)) => {
+                let _ = writeln!(
+                    output,
+                    "ICC profile: {} bytes, {} color space",
+                    profile.profile.len(),
+                    String::from_utf8_lossy(profile.color_space()).trim_end()
+                );
+            }
+            _ => {}
+        }
+    }
+    if !gif.image_descriptors.is_empty() {
+        output.push_str(&format_animation(gif));
+    }
+    if !gif.trailing_data.is_empty() {
+        let _ = writeln!(
+            output,
+            "Data after the trailer: {} bytes",
+            gif.trailing_data.len()
+        );
+        // Offsets count from the start of the file
+        let trailer_end = size.saturating_sub(gif.trailing_data.len());
+        for segment in gif.trailing_segments() {
+            let _ = writeln!(
+                output,
+                "  {}: {} bytes at byte {}",
+                trailing_kind(segment.kind),
+                segment.length,
+                trailer_end + segment.offset
+            );
+        }
+    }
+    for warning in warnings {
+        let _ = writeln!(
+            output,
+            "Warning at byte {}: {}",
+            warning.position, warning.kind
+        );
+    }
+    output
+}
+
+// Timing, looping and disposal of the frames. Delays are in hundredths of a
+// second, a frame without a graphic control extension counting as 0.
+fn format_animation(gif: &GIF) -> String {
+    let frames = &gif.image_descriptors;
+    let delays: Vec<u32> = frames
+        .iter()
+        .map(|frame| {
+            frame
+                .graphics_control_extension
+                .as_ref()
+                .map_or(0, |gce| gce.delay_time as u32)
+        })
+        .collect();
+    let total: u32 = delays.iter().sum();
+    let seconds = |hundredths: u32| format!("{}.{:02}s", hundredths / 100, hundredths % 100);
+
+    let mut output = String::new();
+    let _ = writeln!(
+        output,
+        "Duration: {} (delay {} average, {} min, {} max)",
+        seconds(total),
+        seconds(total / delays.len() as u32),
+        seconds(delays.iter().copied().min().unwrap_or(0)),
+        seconds(delays.iter().copied().max().unwrap_or(0))
+    );
+
+    // The loop count is the number of repeats after the first time
+    let loop_block = gif
+        .application_extensions
+        .iter()
+        .find(|application| is_loop_extension(application));
+    let loops = match loop_block.map(|application| application.known()) {
+        Some(Some(KnownApplication::Loop(looping))) => match looping.repeats {
+            0 => "forever".to_string(),
+            count => format!("{} repeat(s)", count),
+        },
+        Some(_) => "forever, the loop block has no count".to_string(),
+        None => "once, no loop block".to_string(),
+    };
+    let _ = writeln!(output, "Loop: {}", loops);
+
+    let mut disposals = [0; 4];
+    for frame in frames {
+        let disposal = frame
+            .graphics_control_extension
+            .as_ref()
+            .map_or(DisposalMethod::None, |gce| gce.disposal_method());
+        disposals[(disposal.packed_bits() >> 2) as usize] += 1;
+    }
+    let _ = writeln!(
+        output,
+        "Disposal: {} unspecified, {} keep, {} restore background, {} restore previous",
+        disposals[0], disposals[1], disposals[2], disposals[3]
+    );
+    let _ = writeln!(
+        output,
+        "Local color tables: {} of {} frames",
+        frames
+            .iter()
+            .filter(|frame| frame.local_color_table.is_some())
+            .count(),
+        frames.len()
+    );
+    output
+}
+
+// A SHA-256 of each frame as it shows on screen: the screen size, then every
+// pixel as RGBA, with uncovered and transparent pixels all zero. Equal hashes
+// mean the frames look the same, whatever their palettes or compression.
+pub fn frame_hashes(gif: &GIF) -> Result<Vec<[u8; 32]>, Error> {
+    let screen = &gif.logical_screen_descriptor;
+    Ok(composite_frames(gif)?
+        .iter()
+        .map(|canvas| {
+            let mut hasher = Sha256::new();
+            hasher.update(screen.width.to_le_bytes());
+            hasher.update(screen.height.to_le_bytes());
+            for pixel in canvas {
+                hasher.update(match pixel {
+                    Some([red, green, blue]) => [*red, *green, *blue, 0xFF],
+                    None => [0; 4],
+                });
+            }
+            hasher.finalize().into()
+        })
+        .collect())
+}
+
+pub fn format_frame_hashes(hashes: &[(usize, [u8; 32])]) -> String {
+    let mut output = format!("{:>6}  sha256\n", "frame");
+    for (index, hash) in hashes {
+        let _ = writeln!(output, "{:>6}  {}", index, hex(hash));
+    }
+    output
+}
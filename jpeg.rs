@@ -0,0 +1,709 @@
+use std::io::{self, Error};
+
+// Baseline and progressive JPEG decoding into RGBA, enough to turn photos
+// into carriers. Chroma is upsampled by repeating samples, and 12 bit,
+// arithmetic coded, lossless and CMYK images are refused.
+
+fn invalid(message: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn unsupported(message: &str) -> Error {
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+// Most pixels of a decoded image, as many as a 32 megapixel photo has. The
+// WebP and PNG decoders keep to it too.
+pub(crate) const MAX_PIXELS: usize = 1 << 25;
+
+// Most coefficients kept for the blocks of an image, enough for three full
+// size components with their padding
+const MAX_COEFFICIENTS: usize = 4 * MAX_PIXELS;
+
+// Where each coefficient in zigzag order sits in the 8x8 block
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// A Huffman table as the number of codes of each length and the symbols in
+// code order
+#[derive(Clone, Default)]
+struct HuffmanTable {
+    counts: [u16; 17],
+    symbols: Vec<u8>,
+}
+
+// Reads entropy coded data bit by bit, most significant first, dropping the
+// zero byte stuffed after every 0xFF. A marker reads as zero bits.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> u32 {
+        if self.count == 0 {
+            self.bits = match (
+                self.data.get(self.position),
+                self.data.get(self.position + 1),
+            ) {
+                (Some(0xFF), Some(0x00)) => {
+                    self.position += 2;
+                    0xFF
+                }
+                (Some(0xFF), _) | (None, _) => 0,
+                (Some(&byte), _) => {
+                    self.position += 1;
+                    byte as u32
+                }
+            };
+            self.count = 8;
+        }
+        self.count -= 1;
+        (self.bits >> self.count) & 1
+    }
+
+    fn receive(&mut self, count: u32) -> u32 {
+        (0..count).fold(0, |value, _| (value << 1) | self.bit())
+    }
+
+    // A value of `size` bits, negative when its top bit is clear. Sizes
+    // over 16 only come from corrupt data and are cut down.
+    fn receive_extend(&mut self, size: u32) -> i32 {
+        let size = size.min(16);
+        if size == 0 {
+            return 0;
+        }
+        let value = self.receive(size) as i32;
+        if value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        }
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..17 {
+            code |= self.bit() as i32;
+            let count = table.counts[length] as i32;
+            if code - first < count {
+                return table
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or_else(|| invalid("Invalid Huffman code in a JPEG image."));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("Invalid Huffman code in a JPEG image."))
+    }
+
+    // Skip to the restart marker and past it
+    fn restart(&mut self) -> Result<(), Error> {
+        self.count = 0;
+        while self.data.get(self.position) == Some(&0xFF)
+            && self.data.get(self.position + 1) == Some(&0xFF)
+        {
+            self.position += 1;
+        }
+        match self.data.get(self.position..self.position + 2) {
+            Some(&[0xFF, 0xD0..=0xD7]) => {
+                self.position += 2;
+                Ok(())
+            }
+            _ => Err(invalid("A JPEG restart marker is missing.")),
+        }
+    }
+}
+
+struct Component {
+    id: u8,
+    horizontal: usize,
+    vertical: usize,
+    quantization: usize,
+    // Blocks across the stored area, which is a whole number of MCUs
+    blocks_across: usize,
+    blocks_down: usize,
+    // Coefficients of every block, in zigzag order
+    coefficients: Vec<[i32; 64]>,
+    predictor: i32,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    progressive: bool,
+    components: Vec<Component>,
+    max_horizontal: usize,
+    max_vertical: usize,
+}
+
+impl Frame {
+    fn mcus_across(&self) -> usize {
+        self.width.div_ceil(8 * self.max_horizontal)
+    }
+
+    fn mcus_down(&self) -> usize {
+        self.height.div_ceil(8 * self.max_vertical)
+    }
+}
+
+fn read_frame(data: &[u8], progressive: bool) -> Result<Frame, Error> {
+    if data.len() < 6 || data[0] != 8 {
+        return Err(unsupported("Only 8 bit JPEG images can be read."));
+    }
+    let height = u16::from_be_bytes([data[1], data[2]]) as usize;
+    let width = u16::from_be_bytes([data[3], data[4]]) as usize;
+    let count = data[5] as usize;
+    if width == 0 || height == 0 {
+        return Err(unsupported(
+            "JPEG images sized by a DNL marker can't be read.",
+        ));
+    }
+    if width * height > MAX_PIXELS {
+        return Err(invalid("The JPEG image is too big."));
+    }
+    if count != 1 && count != 3 {
+        return Err(unsupported(
+            "Only grayscale and color JPEG images can be read.",
+        ));
+    }
+    let mut components = Vec::new();
+    for fields in data[6..].chunks_exact(3).take(count) {
+        let (horizontal, vertical) = ((fields[1] >> 4) as usize, (fields[1] & 0xF) as usize);
+        if !(1..=4).contains(&horizontal) || !(1..=4).contains(&vertical) {
+            return Err(invalid("Invalid JPEG frame header."));
+        }
+        components.push(Component {
+            id: fields[0],
+            horizontal,
+            vertical,
+            quantization: (fields[2] & 3) as usize,
+            blocks_across: 0,
+            blocks_down: 0,
+            coefficients: Vec::new(),
+            predictor: 0,
+        });
+    }
+    if components.len() != count {
+        return Err(invalid("Invalid JPEG frame header."));
+    }
+    let mut frame = Frame {
+        width,
+        height,
+        progressive,
+        max_horizontal: components.iter().map(|c| c.horizontal).max().unwrap_or(1),
+        max_vertical: components.iter().map(|c| c.vertical).max().unwrap_or(1),
+        components,
+    };
+    let (across, down) = (frame.mcus_across(), frame.mcus_down());
+    let blocks: usize = frame
+        .components
+        .iter()
+        .map(|component| across * component.horizontal * down * component.vertical)
+        .sum();
+    if blocks * 64 > MAX_COEFFICIENTS {
+        return Err(invalid("The JPEG image is too big."));
+    }
+    for component in &mut frame.components {
+        component.blocks_across = across * component.horizontal;
+        component.blocks_down = down * component.vertical;
+        component.coefficients = vec![[0; 64]; component.blocks_across * component.blocks_down];
+    }
+    Ok(frame)
+}
+
+// What a scan holds: its components with their tables, and for progressive
+// images which coefficients and bits
+struct Scan {
+    components: Vec<(usize, usize, usize)>, // Component, DC table, AC table
+    start: usize,
+    end: usize,
+    high: u32,
+    low: u32,
+    progressive: bool,
+}
+
+fn decode_block(
+    reader: &mut BitReader,
+    block: &mut [i32; 64],
+    predictor: &mut i32,
+    dc: &HuffmanTable,
+    ac: &HuffmanTable,
+    scan: &Scan,
+    end_of_bands: &mut u32,
+) -> Result<(), Error> {
+    if !scan.progressive {
+        let size = reader.decode(dc)? as u32;
+        *predictor = predictor.wrapping_add(reader.receive_extend(size));
+        block[0] = *predictor;
+        let mut k = 1;
+        while k < 64 {
+            let symbol = reader.decode(ac)?;
+            let (run, size) = ((symbol >> 4) as usize, (symbol & 0xF) as u32);
+            if size == 0 {
+                if run != 15 {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += run;
+            if k > 63 {
+                return Err(invalid("Invalid JPEG block."));
+            }
+            block[k] = reader.receive_extend(size);
+            k += 1;
+        }
+        return Ok(());
+    }
+
+    if scan.start == 0 {
+        // DC coefficients, first their top bits, then one more bit a scan
+        if scan.high == 0 {
+            let size = reader.decode(dc)? as u32;
+            *predictor = predictor.wrapping_add(reader.receive_extend(size));
+            block[0] = predictor.wrapping_mul(1 << scan.low);
+        } else if reader.bit() == 1 {
+            block[0] |= 1 << scan.low;
+        }
+        return Ok(());
+    }
+
+    if scan.high == 0 {
+        // AC coefficients of one band, first pass
+        if *end_of_bands > 0 {
+            *end_of_bands -= 1;
+            return Ok(());
+        }
+        let mut k = scan.start;
+        while k <= scan.end {
+            let symbol = reader.decode(ac)?;
+            let (run, size) = ((symbol >> 4) as u32, (symbol & 0xF) as u32);
+            if size == 0 {
+                if run < 15 {
+                    *end_of_bands = (1 << run) - 1 + reader.receive(run);
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += run as usize;
+            if k > 63 {
+                return Err(invalid("Invalid JPEG block."));
+            }
+            block[k] = reader.receive_extend(size) * (1 << scan.low);
+            k += 1;
+        }
+        return Ok(());
+    }
+
+    // AC refinement: one more bit for coefficients already set, and new
+    // coefficients of magnitude one placed among the zero ones
+    let bit = 1 << scan.low;
+    let refine = |reader: &mut BitReader, coefficient: &mut i32| {
+        if reader.bit() == 1 && *coefficient & bit == 0 {
+            *coefficient += if *coefficient >= 0 { bit } else { -bit };
+        }
+    };
+    let mut k = scan.start;
+    if *end_of_bands > 0 {
+        *end_of_bands -= 1;
+        while k <= scan.end {
+            if block[k] != 0 {
+                refine(reader, &mut block[k]);
+            }
+            k += 1;
+        }
+        return Ok(());
+    }
+    while k <= scan.end {
+        let symbol = reader.decode(ac)?;
+        let (mut run, size) = ((symbol >> 4) as u32, (symbol & 0xF) as u32);
+        let mut value = 0;
+        if size == 0 {
+            if run < 15 {
+                *end_of_bands = (1 << run) - 1 + reader.receive(run);
+                // Refine what is left of this block
+                run = 64;
+            }
+        } else {
+            value = if reader.bit() == 1 { bit } else { -bit };
+        }
+        while k <= scan.end {
+            let coefficient = &mut block[k];
+            k += 1;
+            if *coefficient != 0 {
+                refine(reader, coefficient);
+            } else if run == 0 {
+                *coefficient = value;
+                break;
+            } else {
+                run -= 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Decode one scan's entropy coded data and return where it ends
+fn decode_scan(
+    data: &[u8],
+    frame: &mut Frame,
+    scan: &Scan,
+    tables: &[[HuffmanTable; 4]; 2],
+    restart_interval: usize,
+) -> Result<usize, Error> {
+    let mut reader = BitReader {
+        data,
+        position: 0,
+        bits: 0,
+        count: 0,
+    };
+    for &(index, _, _) in &scan.components {
+        frame.components[index].predictor = 0;
+    }
+    let mut end_of_bands = 0;
+
+    // A scan of one component goes block by block over the blocks the image
+    // covers, others MCU by MCU
+    let units: Vec<Vec<(usize, usize)>> = if let [(index, _, _)] = scan.components[..] {
+        let component = &frame.components[index];
+        let across = (frame.width * component.horizontal).div_ceil(frame.max_horizontal * 8);
+        let down = (frame.height * component.vertical).div_ceil(frame.max_vertical * 8);
+        (0..down)
+            .flat_map(|y| (0..across).map(move |x| vec![(index, y * component.blocks_across + x)]))
+            .collect()
+    } else {
+        let (across, down) = (frame.mcus_across(), frame.mcus_down());
+        let frame = &*frame;
+        (0..down)
+            .flat_map(|mcu_y| {
+                (0..across).map(move |mcu_x| {
+                    let mut blocks = Vec::new();
+                    for &(index, _, _) in &scan.components {
+                        let component = &frame.components[index];
+                        for v in 0..component.vertical {
+                            for h in 0..component.horizontal {
+                                let row = mcu_y * component.vertical + v;
+                                let column = mcu_x * component.horizontal + h;
+                                blocks.push((index, row * component.blocks_across + column));
+                            }
+                        }
+                    }
+                    blocks
+                })
+            })
+            .collect()
+    };
+
+    for (number, unit) in units.iter().enumerate() {
+        if restart_interval > 0 && number > 0 && number % restart_interval == 0 {
+            reader.restart()?;
+            for &(index, _, _) in &scan.components {
+                frame.components[index].predictor = 0;
+            }
+            end_of_bands = 0;
+        }
+        for &(index, block) in unit {
+            let &(_, dc, ac) = scan
+                .components
+                .iter()
+                .find(|(component, _, _)| *component == index)
+                .unwrap();
+            let component = &mut frame.components[index];
+            decode_block(
+                &mut reader,
+                &mut component.coefficients[block],
+                &mut component.predictor,
+                &tables[0][dc],
+                &tables[1][ac],
+                scan,
+                &mut end_of_bands,
+            )?;
+        }
+    }
+    Ok(reader.position)
+}
+
+type Cosines = [[f32; 8]; 8];
+
+// The basis functions of the DCT, scaled, at each of the eight positions
+fn cosines() -> Cosines {
+    let mut cosines = [[0f32; 8]; 8];
+    for (u, row) in cosines.iter_mut().enumerate() {
+        let scale = if u == 0 {
+            std::f32::consts::FRAC_1_SQRT_2
+        } else {
+            1.0
+        };
+        for (x, value) in row.iter_mut().enumerate() {
+            *value =
+                scale * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos() / 2.0;
+        }
+    }
+    cosines
+}
+
+// The inverse DCT of a dequantized block in natural order, as samples
+fn inverse_dct(block: &[f32; 64], cosines: &Cosines) -> [u8; 64] {
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            rows[y * 8 + x] = (0..8).map(|u| cosines[u][x] * block[y * 8 + u]).sum();
+        }
+    }
+    let mut samples = [0u8; 64];
+    for x in 0..8 {
+        for y in 0..8 {
+            let value: f32 = (0..8).map(|v| cosines[v][y] * rows[v * 8 + x]).sum();
+            samples[y * 8 + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    samples
+}
+
+// Samples of a component across its stored area
+fn component_samples(component: &Component, table: &[u16; 64]) -> Vec<u8> {
+    let width = component.blocks_across * 8;
+    let mut samples = vec![0; width * component.blocks_down * 8];
+    let cosines = cosines();
+    for (index, coefficients) in component.coefficients.iter().enumerate() {
+        let mut block = [0f32; 64];
+        for k in 0..64 {
+            block[ZIGZAG[k]] = coefficients[k].wrapping_mul(table[k] as i32) as f32;
+        }
+        let decoded = inverse_dct(&block, &cosines);
+        let (left, top) = (
+            (index % component.blocks_across) * 8,
+            (index / component.blocks_across) * 8,
+        );
+        for y in 0..8 {
+            samples[(top + y) * width + left..][..8].copy_from_slice(&decoded[y * 8..y * 8 + 8]);
+        }
+    }
+    samples
+}
+
+fn segment(bytes: &[u8], position: usize) -> Result<&[u8], Error> {
+    let length = match bytes.get(position..position + 2) {
+        Some(&[high, low]) => u16::from_be_bytes([high, low]) as usize,
+        _ => return Err(invalid("A JPEG image ends early.")),
+    };
+    bytes
+        .get(position + 2..position + length.max(2))
+        .ok_or_else(|| invalid("A JPEG image ends early."))
+}
+
+// The ICC profile of a JPEG image, split over APP2 segments that each give
+// their place in the sequence. None when there is none or a piece is missing.
+pub fn jpeg_icc_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut pieces = Vec::new();
+    let mut position = 2;
+    // Metadata segments all come before the first scan
+    while let Some(&[0xFF, marker]) = bytes.get(position..position + 2) {
+        position += 2;
+        if marker == 0xFF {
+            position -= 1;
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let data = segment(bytes, position).ok()?;
+        position += 2 + data.len();
+        if marker == 0xE2 && data.len() >= 14 && data.starts_with(b"ICC_PROFILE\0") {
+            pieces.push((data[12], data[13], &data[14..]));
+        }
+    }
+    pieces.sort_by_key(|&(sequence, _, _)| sequence);
+    let count = pieces.first()?.1 as usize;
+    let complete = pieces.len() == count
+        && pieces
+            .iter()
+            .enumerate()
+            .all(|(index, &(sequence, _, _))| sequence as usize == index + 1);
+    complete.then(|| {
+        pieces
+            .iter()
+            .flat_map(|&(_, _, piece)| piece.to_vec())
+            .collect()
+    })
+}
+
+// A JPEG image as its width, height and RGBA pixels
+pub fn decode_jpeg(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>), Error> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Err(invalid("Not a JPEG image."));
+    }
+    let mut quantization = [[0u16; 64]; 4];
+    let mut tables: [[HuffmanTable; 4]; 2] = Default::default();
+    let mut frame: Option<Frame> = None;
+    let mut restart_interval = 0;
+    let mut adobe_transform = None;
+
+    let mut position = 2;
+    loop {
+        // Fill bytes may come before a marker
+        while bytes.get(position) == Some(&0xFF) && bytes.get(position + 1) == Some(&0xFF) {
+            position += 1;
+        }
+        let marker = match bytes.get(position..position + 2) {
+            Some(&[0xFF, marker]) => marker,
+            _ => return Err(invalid("Invalid JPEG marker.")),
+        };
+        position += 2;
+        if marker == 0xD9 {
+            break;
+        }
+        let data = segment(bytes, position)?;
+        position += 2 + data.len();
+        match marker {
+            0xDB => {
+                let mut rest = data;
+                while let Some(&info) = rest.first() {
+                    let wide = info >> 4 == 1;
+                    let size = if wide { 128 } else { 64 };
+                    let values = rest
+                        .get(1..1 + size)
+                        .ok_or_else(|| invalid("Invalid JPEG quantization table."))?;
+                    let table = &mut quantization[(info & 3) as usize];
+                    for (k, value) in table.iter_mut().enumerate() {
+                        *value = match wide {
+                            true => u16::from_be_bytes([values[2 * k], values[2 * k + 1]]),
+                            false => values[k] as u16,
+                        };
+                    }
+                    rest = &rest[1 + size..];
+                }
+            }
+            0xC4 => {
+                let mut rest = data;
+                while let Some(&info) = rest.first() {
+                    let counts = rest
+                        .get(1..17)
+                        .ok_or_else(|| invalid("Invalid JPEG Huffman table."))?;
+                    let total: usize = counts.iter().map(|&count| count as usize).sum();
+                    let symbols = rest
+                        .get(17..17 + total)
+                        .ok_or_else(|| invalid("Invalid JPEG Huffman table."))?;
+                    let mut table = HuffmanTable {
+                        counts: [0; 17],
+                        symbols: symbols.to_vec(),
+                    };
+                    for (length, &count) in counts.iter().enumerate() {
+                        table.counts[length + 1] = count as u16;
+                    }
+                    tables[((info >> 4) & 1) as usize][(info & 3) as usize] = table;
+                    rest = &rest[17 + total..];
+                }
+            }
+            0xC0..=0xC2 => frame = Some(read_frame(data, marker == 0xC2)?),
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                return Err(unsupported(
+                    "Only baseline and progressive JPEG images can be read.",
+                ))
+            }
+            0xDD if data.len() >= 2 => {
+                restart_interval = u16::from_be_bytes([data[0], data[1]]) as usize
+            }
+            0xEE if data.starts_with(b"Adobe") && data.len() >= 12 => {
+                adobe_transform = Some(data[11])
+            }
+            0xDA => {
+                let frame = frame
+                    .as_mut()
+                    .ok_or_else(|| invalid("A JPEG scan comes before its frame header."))?;
+                let count = *data.first().unwrap_or(&0) as usize;
+                if data.len() < 4 + 2 * count {
+                    return Err(invalid("Invalid JPEG scan header."));
+                }
+                let mut components = Vec::new();
+                for fields in data[1..1 + 2 * count].chunks(2) {
+                    let index = frame
+                        .components
+                        .iter()
+                        .position(|component| component.id == fields[0])
+                        .ok_or_else(|| invalid("A JPEG scan names an unknown component."))?;
+                    components.push((
+                        index,
+                        (fields[1] >> 4 & 3) as usize,
+                        (fields[1] & 3) as usize,
+                    ));
+                }
+                let parameters = &data[1 + 2 * count..];
+                let scan = Scan {
+                    components,
+                    start: (parameters[0] as usize).min(63),
+                    end: (parameters[1] as usize).min(63),
+                    high: (parameters[2] >> 4) as u32,
+                    low: (parameters[2] & 0xF) as u32,
+                    progressive: frame.progressive,
+                };
+                if scan.start > scan.end || (scan.start > 0 && scan.components.len() != 1) {
+                    return Err(invalid("Invalid JPEG scan header."));
+                }
+                position +=
+                    decode_scan(&bytes[position..], frame, &scan, &tables, restart_interval)?;
+                // Past any bytes left before the next marker
+                while position < bytes.len()
+                    && !(bytes[position] == 0xFF
+                        && bytes
+                            .get(position + 1)
+                            .is_some_and(|&next| next != 0 && !(0xD0..=0xD7).contains(&next)))
+                {
+                    position += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let frame = frame.ok_or_else(|| invalid("A JPEG image has no frame."))?;
+    let planes: Vec<Vec<u8>> = frame
+        .components
+        .iter()
+        .map(|component| component_samples(component, &quantization[component.quantization]))
+        .collect();
+    // Color images are YCbCr unless an Adobe marker or the component ids say
+    // they are RGB
+    let rgb = adobe_transform == Some(0)
+        || (adobe_transform.is_none() && frame.components.iter().map(|c| c.id).eq(*b"RGB"));
+
+    let mut rgba = Vec::with_capacity(frame.width * frame.height * 4);
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let sample = |index: usize| {
+                let component = &frame.components[index];
+                let row = y * component.vertical / frame.max_vertical;
+                let column = x * component.horizontal / frame.max_horizontal;
+                planes[index][row * component.blocks_across * 8 + column] as f32
+            };
+            let pixel = match frame.components.len() {
+                1 => {
+                    let gray = sample(0) as u8;
+                    [gray, gray, gray]
+                }
+                _ if rgb => [sample(0) as u8, sample(1) as u8, sample(2) as u8],
+                _ => {
+                    let (luma, blue, red) = (sample(0), sample(1) - 128.0, sample(2) - 128.0);
+                    [
+                        luma + 1.402 * red,
+                        luma - 0.344_136 * blue - 0.714_136 * red,
+                        luma + 1.772 * blue,
+                    ]
+                    .map(|value| value.round().clamp(0.0, 255.0) as u8)
+                }
+            };
+            rgba.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 0xFF]);
+        }
+    }
+    Ok((frame.width, frame.height, rgba))
+}
@@ -0,0 +1,119 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Error};
+use std::process::Command;
+
+use crate::hex;
+
+// Passphrases kept in the OS credential store under the service "gifsauce",
+// one per key id, so they never show up on a command line. The store is
+// reached through the tools each OS ships: security on macOS, secret-tool
+// (libsecret) elsewhere on Unix, and the DPAPI-backed Windows password vault
+// through PowerShell.
+
+const SERVICE: &str = "gifsauce";
+
+#[cfg(target_os = "macos")]
+fn lookup_command(key_id: &str) -> (Command, String) {
+    let mut command = Command::new("security");
+    command.args(["find-generic-password", "-s", SERVICE, "-a", key_id, "-w"]);
+    let hint = format!(
+        "security add-generic-password -s {} -a {} -w",
+        SERVICE, key_id
+    );
+    (command, hint)
+}
+
+#[cfg(windows)]
+fn lookup_command(key_id: &str) -> (Command, String) {
+    // The id goes through the environment, where PowerShell can't mistake it
+    // for code
+    let mut command = Command::new("powershell");
+    command.env("GIFSAUCE_KEY_ID", key_id).args([
+        "-NoProfile",
+        "-NonInteractive",
+        "-Command",
+        "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,ContentType=WindowsRuntime]; \
+         $credential = (New-Object Windows.Security.Credentials.PasswordVault).Retrieve('gifsauce', $env:GIFSAUCE_KEY_ID); \
+         $credential.RetrievePassword(); \
+         [Console]::Out.Write($credential.Password)",
+    ]);
+    let hint = format!(
+        "(New-Object Windows.Security.Credentials.PasswordVault).Add((New-Object Windows.Security.Credentials.PasswordCredential('{}', '{}', '<passphrase>')))",
+        SERVICE, key_id
+    );
+    (command, hint)
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn lookup_command(key_id: &str) -> (Command, String) {
+    let mut command = Command::new("secret-tool");
+    command.args(["lookup", "service", SERVICE, "key-id", key_id]);
+    let hint = format!(
+        "secret-tool store --label=GifSauce service {} key-id {}",
+        SERVICE, key_id
+    );
+    (command, hint)
+}
+
+// The passphrase stored under `key_id`
+pub fn keychain_passphrase(key_id: &str) -> Result<String, Error> {
+    let (mut command, hint) = lookup_command(key_id);
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Looking up --key-id needs {}, which isn't installed.",
+                program
+            ),
+        ),
+        _ => e,
+    })?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No passphrase for {} in the keychain. Store one with: {}",
+                key_id, hint
+            ),
+        ));
+    }
+
+    let mut passphrase = String::from_utf8(output.stdout).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The passphrase for {} isn't UTF-8.", key_id),
+        )
+    })?;
+    // The tools end it with a newline, which isn't part of it
+    if passphrase.ends_with('\n') {
+        passphrase.pop();
+        if passphrase.ends_with('\r') {
+            passphrase.pop();
+        }
+    }
+    Ok(passphrase)
+}
+
+// The secret of a --keyfile, with the passphrase given alongside it if any.
+// Whatever the file holds is hashed, so any file of random bytes will do,
+// and the passphrase rides along so a payload can need both.
+pub fn keyfile_passphrase(path: &str, passphrase: Option<&str>) -> Result<String, Error> {
+    let key = fs::read(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Can't read the keyfile {}: {}", path, e)))?;
+    if key.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The keyfile {} is empty.", path),
+        ));
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(b"gifsauce keyfile");
+    hasher.update(&key);
+    let digest = hex(&hasher.finalize());
+    Ok(match passphrase {
+        Some(passphrase) => format!("keyfile:{}:{}", digest, passphrase),
+        None => format!("keyfile:{}", digest),
+    })
+}
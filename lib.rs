@@ -0,0 +1,475 @@
+// Library build of the embed and extract core. Everything works on buffers in
+// memory, so it can be called from other languages through the C functions of
+// ffi.rs (declared in gifsauce.h), and it compiles to wasm32-unknown-unknown
+// to run in a browser:
+//
+//   wasm-pack build --target web --features wasm
+//
+// The GIF model comes from the gifsauce-core crate in core/. Embedding and
+// extracting come with the stego feature, and the built-in payload cipher
+// with crypto, both on by default. async adds fronts for tokio, image-io
+// renders frames as PNG and network fetches GIFs over HTTP(S). The command
+// line tool in GifSauce.rs is built on this library, with the cli feature.
+
+#[cfg(feature = "age")]
+extern crate age;
+#[cfg(feature = "crypto")]
+extern crate argon2;
+#[cfg(feature = "crypto")]
+extern crate chacha20poly1305;
+#[cfg(feature = "fontdue")]
+extern crate fontdue;
+#[cfg(feature = "wasm")]
+extern crate getrandom;
+extern crate gifsauce_core as gif;
+#[cfg(feature = "cli")]
+extern crate indicatif;
+#[cfg(all(feature = "cli", unix))]
+extern crate libc;
+#[cfg_attr(any(feature = "network", feature = "stego"), macro_use)]
+extern crate log;
+#[cfg(feature = "compression")]
+extern crate miniz_oxide;
+#[cfg(feature = "image-io")]
+extern crate png;
+#[cfg(feature = "stego")]
+extern crate rand;
+#[cfg(feature = "stego")]
+extern crate rand_chacha;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "stego")]
+extern crate sha2;
+#[cfg(feature = "server")]
+extern crate tiny_http;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "cli")]
+extern crate toml;
+#[cfg(feature = "network")]
+extern crate ureq;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+// The modules are public for the command line tool, which is built on them,
+// but they aren't part of the library's API and may change at any time.
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod analyze;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod archive;
+#[cfg(feature = "async")]
+mod async_io;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod audit;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod caption;
+#[cfg(feature = "stego")]
+#[doc(hidden)]
+pub mod channels;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod chunk_map;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod comments;
+#[cfg(feature = "compression")]
+mod compression;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod config;
+#[cfg(feature = "crypto")]
+mod container;
+#[cfg(all(feature = "cli", feature = "image-io"))]
+#[doc(hidden)]
+pub mod convert;
+#[cfg(feature = "stego")]
+#[doc(hidden)]
+pub mod crypto;
+#[cfg(feature = "stego")]
+mod ffi;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod frames;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod generate;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod icc;
+#[cfg(feature = "image-io")]
+#[doc(hidden)]
+pub mod image_io;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod inspect;
+#[cfg(all(feature = "cli", feature = "image-io"))]
+#[doc(hidden)]
+pub mod jpeg;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod keychain;
+#[cfg(feature = "stego")]
+#[doc(hidden)]
+pub mod metadata;
+#[cfg(feature = "network")]
+mod network;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod optimize;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod output;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod palette;
+#[cfg(feature = "stego")]
+#[doc(hidden)]
+pub mod payload;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod plain_text;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod polyglot;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod preview;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod progress;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod prompt;
+#[cfg(feature = "age")]
+mod recipients;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod report;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod sanitize;
+#[cfg(feature = "server")]
+#[doc(hidden)]
+pub mod server;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod sheet;
+#[cfg(feature = "stego")]
+#[doc(hidden)]
+pub mod shuffle;
+#[cfg(all(feature = "cli", feature = "image-io"))]
+#[doc(hidden)]
+pub mod transcode;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod transform;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod watermark;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod xmp;
+
+#[cfg(feature = "async")]
+pub use async_io::{parse_gif_async, reassemble_gif_async};
+#[cfg(feature = "stego")]
+use channels::parse_channel_list;
+#[cfg(feature = "stego")]
+pub use channels::{
+    register_channel, Channel, ChannelKind, ChannelOptions, FrameExpansion, FIRST_CUSTOM_CHANNEL_ID,
+};
+#[cfg(feature = "crypto")]
+pub use container::ContainerCipher;
+#[cfg(feature = "stego")]
+use crypto::{seal_payloads, sealed_length};
+#[cfg(feature = "stego")]
+pub use crypto::{set_payload_cipher, PayloadCipher};
+pub use gif::{
+    application_patches, apply_patches, comment_patches, map_to_palette, parse_events,
+    parse_events_bytes, parse_gif, parse_gif_bytes, parse_gif_bytes_with_progress,
+    parse_gif_bytes_with_warnings, parse_truncated_gif_bytes, quantize, quantize_dithered,
+    trailing_segments, write_gif, write_gif_bytes, write_gif_bytes_with_progress,
+    ApplicationExtension, BlockLabel, BlockSink, BlockSource, ColorTable, CommentExtension,
+    DisposalMethod, Dither, Extension, GIFHeader, GifParser, GifVisitor, GifWriter,
+    GraphicsControlExtension, IccProfile, ImageDescriptor, KnownApplication,
+    LogicalScreenDescriptor, LoopExtension, ParseError, ParseOutcome, ParseWarning,
+    ParseWarningKind, Patch, PlainTextExtension, Quantized, SkippedBlock, Stage, SubBlockReader,
+    SubBlockWriter, SubBlocks, TrailingKind, TrailingSegment, XmpPacket, GIF,
+};
+#[cfg(feature = "image-io")]
+pub use image_io::frame_png;
+#[cfg(feature = "stego")]
+use metadata::{guess_mime_type, PayloadMetadata};
+#[cfg(feature = "network")]
+pub use network::open_url;
+#[cfg(feature = "stego")]
+pub use payload::DEFAULT_PADDING;
+#[cfg(feature = "stego")]
+use payload::{
+    add_checksums, deflate_body, embed_payload, find_payload, pad_body, padded_length,
+    padding_room, read_payload, whitened_key, write_payload_body, Payload, FLAG_CHECKSUMS,
+    FLAG_COMPRESSED, FLAG_ENCRYPTED, FLAG_PADDED, PAYLOAD_HEADER_SIZE,
+};
+#[cfg(feature = "stego")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "stego")]
+use shuffle::derive_layout_key;
+#[cfg(feature = "stego")]
+pub use shuffle::ChunkKey;
+#[cfg(feature = "stego")]
+use std::io::Error;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// Settings of embed and extract. From JavaScript:
+//
+//   const options = new Options();
+//   options.channels = "comment,lsb";
+//   options.passphrase = "secret";
+#[cfg(feature = "stego")]
+#[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
+#[derive(Clone)]
+pub struct Options {
+    pub channels: String, // Comma separated, as with --channels
+    pub passphrase: Option<String>,
+    pub name: String,           // File name stored with the payload
+    pub compress: bool,         // Deflate the payload, as with --compress
+    pub padding: Option<usize>, // Pad to a multiple of this many bytes, as with --padding
+    pub checksums: bool,        // Checksum blocks of a plain payload, off with --no-checksums
+}
+
+#[cfg(feature = "stego")]
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            channels: "plaintext".to_string(),
+            passphrase: None,
+            name: String::new(),
+            compress: false,
+            padding: Some(DEFAULT_PADDING),
+            checksums: true,
+        }
+    }
+}
+
+#[cfg(feature = "stego")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Options {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new() -> Options {
+        Options::default()
+    }
+}
+
+// A payload found by extract
+#[cfg(feature = "stego")]
+#[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
+pub struct Extracted {
+    pub name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+    pub expires: i64, // Seconds since the Unix epoch, not to be handed out after. 0 for never.
+}
+
+// embed and extract for Rust callers, keeping the error kinds
+#[cfg(feature = "stego")]
+pub fn embed_bytes(bytes: &[u8], payload: &[u8], options: &Options) -> Result<Vec<u8>, Error> {
+    embed_bytes_with_progress(bytes, payload, options, &|_, _, _| {})
+}
+
+// embed_bytes, reporting how parsing the carrier and writing its frames go
+// as parse_gif_bytes_with_progress and write_gif_bytes_with_progress do
+#[cfg(feature = "stego")]
+pub fn embed_bytes_with_progress(
+    bytes: &[u8],
+    payload: &[u8],
+    options: &Options,
+    progress: &(dyn Fn(Stage, usize, usize) + Sync),
+) -> Result<Vec<u8>, Error> {
+    let channels = parse_channel_list(&options.channels)?;
+    let mut gif = parse_gif_bytes_with_progress(bytes, progress)?;
+
+    let body = write_payload_body(&Payload {
+        metadata: PayloadMetadata {
+            name: options.name.clone(),
+            mime_type: guess_mime_type(&options.name, payload),
+            size: payload.len() as u64,
+            modified: 0,
+            expires: 0,
+        },
+        data: payload.to_vec(),
+    });
+    let (body, flags) = match options.compress {
+        true => (deflate_body(&body)?, FLAG_COMPRESSED),
+        false => (body, 0),
+    };
+    let layout = ChannelOptions::default();
+
+    // Sealed or checksummed, then padded as the command line tool pads:
+    // inside any encryption, as far as the channels have room
+    let (data, flags, key, digest) = match options.passphrase {
+        Some(ref passphrase) => {
+            let container = seal_payloads(&[(passphrase.as_str(), &body)])?;
+            let key = derive_layout_key(&container);
+            (container, flags | FLAG_ENCRYPTED, Some(key), None)
+        }
+        None if options.checksums && flags == 0 => (
+            add_checksums(&body),
+            FLAG_CHECKSUMS,
+            None,
+            Some(Sha256::digest(payload).into()),
+        ),
+        None => (
+            body.clone(),
+            flags,
+            None,
+            Some(Sha256::digest(payload).into()),
+        ),
+    };
+    let header_size = PAYLOAD_HEADER_SIZE + digest.map_or(0, |digest: [u8; 32]| digest.len());
+    let room = padding_room(
+        &gif,
+        &channels,
+        key.as_ref(),
+        &layout,
+        header_size,
+        data.len(),
+    );
+    let padded = match (options.padding, options.passphrase.as_deref()) {
+        (None, _) => None,
+        (Some(bucket), None) => padded_length(data.len(), bucket, room, |length| length)
+            .map(|length| (pad_body(&data, length), key.clone())),
+        (Some(bucket), Some(passphrase)) => {
+            let stored_length = |length| sealed_length(&[length]).unwrap_or(length);
+            match padded_length(body.len(), bucket, room, stored_length) {
+                Some(length) => {
+                    let sealed = seal_payloads(&[(passphrase, &pad_body(&body, length))])?;
+                    let key = derive_layout_key(&sealed);
+                    room.is_none_or(|room| sealed.len() <= room)
+                        .then_some((sealed, Some(key)))
+                }
+                None => None,
+            }
+        }
+    };
+    let (data, flags, key) = match padded {
+        Some((padded, key)) => (padded, flags | FLAG_PADDED, key),
+        None => (data, flags, key),
+    };
+    let key = match (options.passphrase.as_deref(), key) {
+        (Some(passphrase), Some(key)) => Some(whitened_key(key, passphrase, false, &channels)?),
+        (_, key) => key,
+    };
+    embed_payload(
+        &mut gif,
+        &data,
+        flags,
+        digest.as_ref(),
+        &channels,
+        key.as_ref(),
+        &layout,
+    )?;
+
+    Ok(write_gif_bytes_with_progress(&gif, Some(bytes), progress))
+}
+
+#[cfg(feature = "stego")]
+pub fn extract_bytes(bytes: &[u8], options: &Options) -> Result<Option<Extracted>, Error> {
+    extract_bytes_with_progress(bytes, options, &|_, _, _| {})
+}
+
+// extract_bytes, reporting how parsing the carrier goes, for progress bars
+// on big carriers
+#[cfg(feature = "stego")]
+pub fn extract_bytes_with_progress(
+    bytes: &[u8],
+    options: &Options,
+    progress: &(dyn Fn(Stage, usize, usize) + Sync),
+) -> Result<Option<Extracted>, Error> {
+    let gif = parse_gif_bytes_with_progress(bytes, progress)?;
+    let payload = read_payload(&gif, options.passphrase.as_deref())?;
+    Ok(payload.map(|payload| Extracted {
+        name: payload.metadata.name,
+        mime_type: payload.metadata.mime_type,
+        data: payload.data,
+        expires: payload.metadata.expires,
+    }))
+}
+
+// Quote a string for JSON
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Lowercase hex of a byte string, as digests are shown
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Describe a GIF and the payload it holds, if any, as a JSON object
+#[cfg(feature = "stego")]
+pub fn inspect_json(bytes: &[u8], passphrase: Option<&str>) -> Result<String, Error> {
+    let gif = parse_gif_bytes(bytes)?;
+    let payload = match find_payload(&gif, passphrase) {
+        Some(location) => format!(
+            "{{\"channels\":[{}],\"version\":{},\"length\":{},\"encrypted\":{},\"compressed\":{},\"sha256\":{}}}",
+            location
+                .channels
+                .iter()
+                .map(|channel| json_string(&channel.to_string()))
+                .collect::<Vec<_>>()
+                .join(","),
+            location.header.version,
+            location.header.length,
+            location.header.is_encrypted() || location.header.is_for_recipients(),
+            location.header.is_compressed(),
+            location
+                .header
+                .digest
+                .map_or("null".to_string(), |digest| format!("\"{}\"", hex(&digest)))
+        ),
+        None => "null".to_string(),
+    };
+    Ok(format!(
+        "{{\"width\":{},\"height\":{},\"frames\":{},\"comments\":{},\"application_extensions\":{},\"plain_text_extensions\":{},\"payload\":{}}}",
+        gif.logical_screen_descriptor.width,
+        gif.logical_screen_descriptor.height,
+        gif.image_descriptors.len(),
+        gif.comment_extensions.len(),
+        gif.application_extensions.len(),
+        gif.plain_text_extensions.len(),
+        payload
+    ))
+}
+
+// Hide payload in the GIF held by bytes and return the new GIF
+#[cfg(feature = "stego")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn embed(bytes: &[u8], payload: &[u8], options: &Options) -> Result<Vec<u8>, String> {
+    embed_bytes(bytes, payload, options).map_err(|e| e.to_string())
+}
+
+// The payload hidden in the GIF held by bytes, if there is one
+#[cfg(feature = "stego")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn extract(bytes: &[u8], options: &Options) -> Result<Option<Extracted>, String> {
+    extract_bytes(bytes, options).map_err(|e| e.to_string())
+}
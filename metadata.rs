@@ -0,0 +1,236 @@
+use std::fs;
+use std::io::{self, Error};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Since payload version 2 the body starts with a record describing the
+// embedded file:
+//
+//   name length (2) | name | mime length (1) | mime | size (8) | mtime (8)
+//
+// Payloads read from stdin have an empty name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PayloadMetadata {
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub modified: i64, // Seconds since the Unix epoch, 0 when unknown
+    pub expires: i64,  // When extract stops handing it out, 0 for never. Not in the record.
+}
+
+// MIME types by file extension, checked before sniffing the content
+const MIME_TYPES: [(&str, &str); 20] = [
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+];
+
+// Leading bytes of common formats, for files without a telling extension
+const MAGIC_NUMBERS: [(&[u8], &str); 7] = [
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF8", "image/gif"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+];
+
+pub fn guess_mime_type(name: &str, data: &[u8]) -> String {
+    let extension = Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    if let Some(extension) = extension {
+        if let Some(&(_, mime_type)) = MIME_TYPES.iter().find(|entry| entry.0 == extension) {
+            return mime_type.to_string();
+        }
+    }
+
+    if let Some(&(_, mime_type)) = MAGIC_NUMBERS.iter().find(|entry| data.starts_with(entry.0)) {
+        return mime_type.to_string();
+    }
+
+    if std::str::from_utf8(data).is_ok() {
+        "text/plain".to_string()
+    } else {
+        "application/octet-stream".to_string()
+    }
+}
+
+// Describe a file about to be embedded
+pub fn file_metadata(path: &Path, data: &[u8]) -> PayloadMetadata {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+
+    PayloadMetadata {
+        mime_type: guess_mime_type(&name, data),
+        name,
+        size: data.len() as u64,
+        modified,
+        expires: 0,
+    }
+}
+
+pub fn write_metadata(metadata: &PayloadMetadata) -> Vec<u8> {
+    let name = &metadata.name.as_bytes()[..metadata.name.len().min(u16::MAX as usize)];
+    let mime_type = &metadata.mime_type.as_bytes()[..metadata.mime_type.len().min(255)];
+
+    let mut data = Vec::with_capacity(2 + name.len() + 1 + mime_type.len() + 16);
+    data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    data.extend_from_slice(name);
+    data.push(mime_type.len() as u8);
+    data.extend_from_slice(mime_type);
+    data.extend_from_slice(&metadata.size.to_le_bytes());
+    data.extend_from_slice(&metadata.modified.to_le_bytes());
+    data
+}
+
+// Parse the record at the start of a body, returning it and the file data.
+pub fn read_metadata(data: &[u8]) -> Result<(PayloadMetadata, &[u8]), Error> {
+    let truncated = || io::Error::new(io::ErrorKind::InvalidData, "Payload metadata is truncated.");
+
+    let mut position = 0;
+    let mut take = |count: usize| -> Result<&[u8], Error> {
+        let field = data.get(position..position + count).ok_or_else(truncated)?;
+        position += count;
+        Ok(field)
+    };
+
+    let mut name_length = [0; 2];
+    name_length.copy_from_slice(take(2)?);
+    let name =
+        String::from_utf8_lossy(take(u16::from_le_bytes(name_length) as usize)?).into_owned();
+
+    let mime_length = take(1)?[0] as usize;
+    let mime_type = String::from_utf8_lossy(take(mime_length)?).into_owned();
+
+    let mut size = [0; 8];
+    size.copy_from_slice(take(8)?);
+    let mut modified = [0; 8];
+    modified.copy_from_slice(take(8)?);
+
+    let metadata = PayloadMetadata {
+        name,
+        mime_type,
+        size: u64::from_le_bytes(size),
+        modified: i64::from_le_bytes(modified),
+        expires: 0,
+    };
+    Ok((metadata, &data[position..]))
+}
+
+// Days from 1970-01-01 to a date of the Gregorian calendar, and back
+fn days_from_date(year: i64, month: i64, day: i64) -> i64 {
+    // Years start in March, so the leap day comes last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn date_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// An expiry in seconds since the Unix epoch, from a date such as 2025-12-31,
+// which runs to the end of that day in UTC, or a time such as
+// 2025-12-31T18:00:00Z
+pub fn parse_expiry(text: &str) -> Option<i64> {
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z')?)),
+        None => (text, None),
+    };
+    let fields = |text: &str, separator: char| -> Option<Vec<i64>> {
+        text.split(separator)
+            .map(
+                |field| match field.bytes().all(|byte| byte.is_ascii_digit()) {
+                    true => field.parse().ok(),
+                    false => None,
+                },
+            )
+            .collect()
+    };
+    let [year @ 1970..=9999, month @ 1..=12, day @ 1..=31] = fields(date, '-')?[..] else {
+        return None;
+    };
+    let days = days_from_date(year, month, day);
+    // Days past the end of the month would roll over into the next
+    if date_from_days(days) != (year, month, day) {
+        return None;
+    }
+    let seconds = match time {
+        Some(time) => match fields(time, ':')?[..] {
+            [hours @ 0..=23, minutes @ 0..=59, seconds @ 0..=59] => {
+                hours * 3600 + minutes * 60 + seconds
+            }
+            _ => return None,
+        },
+        None => 86_400,
+    };
+    Some(days * 86_400 + seconds)
+}
+
+// Seconds since the Unix epoch
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+// Seconds since the Unix epoch as 2025-12-31 18:00:00 UTC
+pub fn format_time(seconds: i64) -> String {
+    let (year, month, day) = date_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
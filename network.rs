@@ -0,0 +1,28 @@
+use std::io::{self, Error, Read};
+
+// Start fetching a carrier over HTTP(S). The body is handed out as a reader so
+// the parser consumes it as it arrives, along with the length the server
+// announced, if any.
+pub fn open_url(url: &str) -> Result<(Box<dyn Read + Send>, Option<u64>), Error> {
+    info!("Fetching {}", url);
+    let response = ureq::get(url).call().map_err(|e| match e {
+        ureq::Error::Status(code, response) => io::Error::other(format!(
+            "{} answered {} {}",
+            url,
+            code,
+            response.status_text()
+        )),
+        e => io::Error::other(e),
+    })?;
+
+    let content_type = response.content_type().to_string();
+    if content_type != "image/gif" {
+        debug!("{} is served as {}", url, content_type);
+    }
+    let length = response
+        .header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok());
+    debug!("Content length: {:?}", length);
+
+    Ok((response.into_reader(), length))
+}
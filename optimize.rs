@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Error;
+
+use crate::frames::composite_frames;
+use crate::gif::{
+    lzw_lossy, quantize, ColorTable, DisposalMethod, GraphicsControlExtension, ImageDescriptor, GIF,
+};
+
+// Frames are rebuilt from what the animation shows: each one only covers the
+// area that changed since the frame before, with the pixels in it that didn't
+// change left transparent. Frames with nothing new shrink to one transparent
+// pixel, so frame indices and timing stay as they were.
+
+type Canvas = Vec<Option<[u8; 3]>>;
+
+// A rectangle of the screen
+#[derive(Clone, Copy)]
+struct Area {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+}
+
+// The smallest area holding every pixel where the canvases differ
+fn changed_area(
+    before: &[Option<[u8; 3]>],
+    after: &[Option<[u8; 3]>],
+    width: usize,
+) -> Option<Area> {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (position, (old, new)) in before.iter().zip(after).enumerate() {
+        if old == new {
+            continue;
+        }
+        let (x, y) = (position % width, position / width);
+        bounds = Some(match bounds {
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }
+            None => (x, y, x, y),
+        });
+    }
+    bounds.map(|(left, top, right, bottom)| Area {
+        left,
+        top,
+        width: right - left + 1,
+        height: bottom - top + 1,
+    })
+}
+
+// What is left on screen once a frame covering `area` is disposed of by
+// restoring the background
+fn cleared(canvas: &[Option<[u8; 3]>], area: Area, width: usize) -> Canvas {
+    let mut canvas = canvas.to_vec();
+    for row in area.top..area.top + area.height {
+        canvas[row * width + area.left..row * width + area.left + area.width].fill(None);
+    }
+    canvas
+}
+
+// Whether drawing over `before` can give `after`. Frames can't make a pixel
+// see-through again, only disposal can.
+fn can_draw_over(before: &[Option<[u8; 3]>], after: &[Option<[u8; 3]>]) -> bool {
+    before
+        .iter()
+        .zip(after)
+        .all(|(old, new)| new.is_some() || old.is_none())
+}
+
+// A frame covering `area` that turns `before` into `after`. It uses the global
+// color table when that has every color it needs and an index to spare for
+// transparency, a quantized local one otherwise.
+fn difference_frame(
+    gif: &GIF,
+    before: &[Option<[u8; 3]>],
+    after: &[Option<[u8; 3]>],
+    area: Area,
+    source: &ImageDescriptor,
+) -> ImageDescriptor {
+    let screen_width = gif.logical_screen_descriptor.width as usize;
+    let pixels: Vec<[u8; 4]> = (area.top..area.top + area.height)
+        .flat_map(|row| {
+            (area.left..area.left + area.width).map(move |column| row * screen_width + column)
+        })
+        .map(|position| match after[position] {
+            Some([red, green, blue]) if before[position] != after[position] => {
+                [red, green, blue, 0xFF]
+            }
+            _ => [0; 4],
+        })
+        .collect();
+
+    let (packed_field, lzw_minimum_code_size, local_color_table, transparent_index, image_data) =
+        match global_indices(gif, &pixels) {
+            Some((transparent_index, image_data)) => {
+                let size_bits = gif.logical_screen_descriptor.packed_field & 0b111;
+                (
+                    0,
+                    (size_bits + 1).max(2),
+                    None,
+                    transparent_index,
+                    image_data,
+                )
+            }
+            None => {
+                let quantized = quantize(&pixels, 256);
+                (
+                    0b1000_0000 | quantized.size_bits,
+                    quantized.lzw_minimum_code_size(),
+                    Some(quantized.color_table),
+                    quantized.transparent_index,
+                    quantized.indices,
+                )
+            }
+        };
+
+    ImageDescriptor {
+        left: area.left as u16,
+        top: area.top as u16,
+        width: area.width as u16,
+        height: area.height as u16,
+        packed_field,
+        graphics_control_extension: Some(GraphicsControlExtension {
+            packed_field: DisposalMethod::DoNotDispose.packed_bits()
+                | u8::from(transparent_index.is_some()),
+            delay_time: source
+                .graphics_control_extension
+                .as_ref()
+                .map_or(0, |gce| gce.delay_time),
+            transparent_color_index: transparent_index.unwrap_or(0),
+        }),
+        local_color_table,
+        lzw_minimum_code_size,
+        image_data,
+        compressed_range: None,
+    }
+}
+
+// The pixels as indices into the global color table, with the index left
+// free for transparent pixels, if the table can hold them
+fn global_indices(gif: &GIF, pixels: &[[u8; 4]]) -> Option<(Option<u8>, Vec<u8>)> {
+    let table: &ColorTable = gif.global_color_table.as_ref()?;
+    let mut lookup: HashMap<[u8; 3], u8> = HashMap::new();
+    for (index, color) in table.colors.iter().enumerate().rev() {
+        lookup.insert(*color, index as u8);
+    }
+
+    let mut used = vec![false; table.colors.len()];
+    let mut transparent = false;
+    for pixel in pixels {
+        if pixel[3] == 0 {
+            transparent = true;
+            continue;
+        }
+        let index = *lookup.get(&[pixel[0], pixel[1], pixel[2]])?;
+        used[index as usize] = true;
+    }
+    let transparent_index = match transparent {
+        true => Some(used.iter().position(|used| !used)? as u8),
+        false => None,
+    };
+
+    let image_data = pixels
+        .iter()
+        .map(|pixel| match pixel[3] {
+            0 => transparent_index.unwrap_or(0),
+            _ => lookup[&[pixel[0], pixel[1], pixel[2]]],
+        })
+        .collect();
+    Some((transparent_index, image_data))
+}
+
+// Rebuild the frames to draw only what changes from one to the next
+pub fn optimize_frames(gif: &mut GIF) -> Result<(), Error> {
+    let screen_width = gif.logical_screen_descriptor.width as usize;
+    let screen_height = gif.logical_screen_descriptor.height as usize;
+    if screen_width == 0 || screen_height == 0 {
+        return Ok(()); // Nothing is ever shown
+    }
+    let canvases = composite_frames(gif)?;
+    let empty: Canvas = vec![None; screen_width * screen_height];
+    let whole_screen = Area {
+        left: 0,
+        top: 0,
+        width: screen_width,
+        height: screen_height,
+    };
+
+    let mut frames: Vec<ImageDescriptor> = Vec::with_capacity(canvases.len());
+    // What is on screen before the next frame is drawn
+    let mut before = empty.clone();
+    for (index, after) in canvases.iter().enumerate() {
+        // The screen starts empty, so the first frame can always be drawn
+        if let Some(previous) = frames.last_mut().filter(|_| !can_draw_over(&before, after)) {
+            // Clear the last frame's area once it has been shown, or failing
+            // that redraw it over the whole screen and clear all of it
+            let area = Area {
+                left: previous.left as usize,
+                top: previous.top as usize,
+                width: previous.width as usize,
+                height: previous.height as usize,
+            };
+            before = cleared(&canvases[index - 1], area, screen_width);
+            if !can_draw_over(&before, after) {
+                *previous = difference_frame(
+                    gif,
+                    &empty,
+                    &canvases[index - 1],
+                    whole_screen,
+                    &gif.image_descriptors[index - 1],
+                );
+                before = empty.clone();
+            }
+            if let Some(ref mut gce) = previous.graphics_control_extension {
+                gce.set_disposal_method(DisposalMethod::RestoreBackground);
+            }
+        }
+
+        let area = changed_area(&before, after, screen_width).unwrap_or(Area {
+            left: 0,
+            top: 0,
+            width: 1,
+            height: 1,
+        });
+        let source = &gif.image_descriptors[index];
+        frames.push(difference_frame(gif, &before, after, area, source));
+        before = after.clone();
+    }
+
+    gif.image_descriptors = frames;
+    Ok(())
+}
+
+// Let every frame's pixels drift by up to `tolerance` in color where that
+// makes them compress better. Frames without a color table are left alone.
+pub fn lossy_frames(gif: &mut GIF, tolerance: u32) {
+    for frame in &mut gif.image_descriptors {
+        let colors = match frame
+            .local_color_table
+            .as_ref()
+            .or(gif.global_color_table.as_ref())
+        {
+            Some(table) => &table.colors,
+            None => continue,
+        };
+        frame.image_data = lzw_lossy(
+            &frame.image_data,
+            frame.lzw_minimum_code_size,
+            colors,
+            frame.transparent_color_index(),
+            tolerance,
+        );
+        frame.compressed_range = None;
+    }
+}
+
+// Whether a frame shows the same colors with `table` in place of its local
+// table. Only the indices its opaque pixels use have to match.
+fn fits_table(frame: &ImageDescriptor, local: &ColorTable, table: &ColorTable) -> bool {
+    let mut used = [false; 256];
+    for &pixel in &frame.image_data {
+        used[pixel as usize] = true;
+    }
+    if let Some(transparent_index) = frame.transparent_color_index() {
+        used[transparent_index as usize] = false;
+    }
+    used.iter()
+        .enumerate()
+        .filter(|(_, used)| **used)
+        .all(|(index, _)| local.colors.get(index) == table.colors.get(index))
+}
+
+// Drop the local color tables the global one can stand in for. When no frame
+// draws with the global table, the local table most frames carry becomes the
+// global one first. Returns how many local tables were dropped.
+pub fn share_color_tables(gif: &mut GIF) -> usize {
+    let global_in_use = gif.global_color_table.is_some()
+        && gif
+            .image_descriptors
+            .iter()
+            .any(|frame| frame.local_color_table.is_none());
+    if !global_in_use {
+        let mut counts: BTreeMap<&[[u8; 3]], usize> = BTreeMap::new();
+        for table in gif
+            .image_descriptors
+            .iter()
+            .filter_map(|frame| frame.local_color_table.as_ref())
+        {
+            *counts.entry(&table.colors).or_insert(0) += 1;
+        }
+        // A table only one frame carries saves nothing when moved
+        let minimum = if gif.global_color_table.is_some() {
+            1
+        } else {
+            2
+        };
+        let common = counts
+            .into_iter()
+            .filter(|&(_, count)| count >= minimum)
+            .max_by_key(|&(_, count)| count)
+            .and_then(|(colors, _)| {
+                gif.image_descriptors.iter().find(|frame| {
+                    frame
+                        .local_color_table
+                        .as_ref()
+                        .is_some_and(|table| table.colors == colors)
+                })
+            })
+            .map(|frame| (frame.local_color_table.clone(), frame.packed_field & 0b111));
+        if let Some((table, size_bits)) = common {
+            let screen = &mut gif.logical_screen_descriptor;
+            screen.packed_field = (screen.packed_field & 0b0111_0000) | 0b1000_0000 | size_bits;
+            gif.global_color_table = table;
+        }
+    }
+
+    let global = match gif.global_color_table {
+        Some(ref global) => global,
+        None => return 0,
+    };
+    let mut dropped = 0;
+    for frame in &mut gif.image_descriptors {
+        if let Some(ref local) = frame.local_color_table {
+            if fits_table(frame, local, global) {
+                frame.local_color_table = None;
+                // Clear the table, sort and size bits
+                frame.packed_field &= !0b1010_0111;
+                dropped += 1;
+            }
+        }
+    }
+    dropped
+}
@@ -0,0 +1,132 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// Backups keep the whole file name and add this
+const BACKUP_EXTENSION: &str = "bak";
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn write_temporary(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+// Set by --force: outputs may replace existing files, the input included
+pub static FORCE: AtomicBool = AtomicBool::new(false);
+
+// Set by --strict: GIF87a files are not upgraded to GIF89a when writing them
+// needs extension blocks, and the write fails instead
+pub static STRICT: AtomicBool = AtomicBool::new(false);
+
+// Files read as input, so that writing over one of them can be told apart
+static INPUTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+pub fn note_input(path: &str) {
+    if let Ok(path) = fs::canonicalize(path) {
+        INPUTS.lock().unwrap().push(path);
+    }
+}
+
+// Refuse to replace an existing file unless --force was given. A file kept as
+// a backup is not lost, so --backup allows it too.
+pub fn check_overwrite(path: &Path, backup: bool) -> Result<(), Error> {
+    if backup || FORCE.load(Ordering::Relaxed) || !path.exists() {
+        return Ok(());
+    }
+    let input = fs::canonicalize(path)
+        .map(|path| INPUTS.lock().unwrap().contains(&path))
+        .unwrap_or(false);
+    let message = if input {
+        format!(
+            "{} is also the input, use --force to write over it.",
+            path.display()
+        )
+    } else {
+        format!(
+            "{} already exists, use --force to replace it.",
+            path.display()
+        )
+    };
+    Err(Error::new(ErrorKind::AlreadyExists, message))
+}
+
+// Write a new file, or replace one as check_overwrite allows
+pub fn write_atomic(path: &str, data: &[u8], backup: bool) -> Result<(), Error> {
+    check_overwrite(Path::new(path), backup)?;
+    replace_atomic(path, data, backup)
+}
+
+// Write a file so that readers only ever see the old or the new content: the
+// data goes to a temporary file next to the destination, which is then renamed
+// over it. This also makes writing over the input safe, even while it is
+// mapped. With `backup` an existing file is kept as `<name>.bak`.
+pub fn replace_atomic(path: &str, data: &[u8], backup: bool) -> Result<(), Error> {
+    let path = Path::new(path);
+    let temporary = sibling_path(path, &format!(".{}.tmp", process::id()));
+
+    if let Err(e) = write_temporary(&temporary, data) {
+        let _ = fs::remove_file(&temporary);
+        return Err(e);
+    }
+
+    if backup && path.exists() {
+        let backup_path = sibling_path(path, &format!(".{}", BACKUP_EXTENSION));
+        let _ = fs::remove_file(&backup_path);
+        // A hard link leaves the destination in place until the rename
+        if fs::hard_link(path, &backup_path).is_err() {
+            if let Err(e) = fs::copy(path, &backup_path) {
+                let _ = fs::remove_file(&temporary);
+                return Err(e);
+            }
+        }
+    }
+
+    if let Err(e) = fs::rename(&temporary, path) {
+        let _ = fs::remove_file(&temporary);
+        return Err(e);
+    }
+
+    // Make the rename itself durable where directories can be synced
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        if let Ok(directory) = File::open(parent) {
+            let _ = directory.sync_all();
+        }
+    }
+    Ok(())
+}
+
+// Standard base64 with padding
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
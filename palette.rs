@@ -0,0 +1,235 @@
+use std::io::{self, Error};
+
+use crate::gif::{closest_color, ColorTable, GIF};
+
+// Palettes are read and written as GIMP palettes, Adobe color tables or a
+// JSON object of hex colors. An imported palette replaces one color table and
+// the pixels drawn with it are moved to the nearest new color.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteFormat {
+    Gpl,
+    Act,
+    Json,
+}
+
+pub fn parse_palette_format(name: &str) -> Option<PaletteFormat> {
+    match name.to_ascii_lowercase().as_str() {
+        "gpl" => Some(PaletteFormat::Gpl),
+        "act" => Some(PaletteFormat::Act),
+        "json" => Some(PaletteFormat::Json),
+        _ => None,
+    }
+}
+
+// The format a file name's extension stands for
+pub fn palette_format_of(path: &str) -> Option<PaletteFormat> {
+    parse_palette_format(path.rsplit_once('.')?.1)
+}
+
+fn invalid_palette(message: String) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn write_palette(colors: &[[u8; 3]], format: PaletteFormat) -> Vec<u8> {
+    match format {
+        PaletteFormat::Gpl => {
+            let mut text = String::from("GIMP Palette\nName: GifSauce\nColumns: 16\n#\n");
+            for (index, [red, green, blue]) in colors.iter().enumerate() {
+                text.push_str(&format!(
+                    "{:3} {:3} {:3}\tIndex {}\n",
+                    red, green, blue, index
+                ));
+            }
+            text.into_bytes()
+        }
+        // 256 colors padded with black, then the number of colors and no
+        // transparent entry, both big endian
+        PaletteFormat::Act => {
+            let mut bytes: Vec<u8> = colors.iter().take(256).flatten().copied().collect();
+            bytes.resize(768, 0);
+            bytes.extend_from_slice(&(colors.len().min(256) as u16).to_be_bytes());
+            bytes.extend_from_slice(&0xFFFFu16.to_be_bytes());
+            bytes
+        }
+        PaletteFormat::Json => {
+            let colors: Vec<String> = colors
+                .iter()
+                .map(|[red, green, blue]| format!("\"#{:02x}{:02x}{:02x}\"", red, green, blue))
+                .collect();
+            format!("{{\"colors\":[{}]}}\n", colors.join(",")).into_bytes()
+        }
+    }
+}
+
+// Read 1 to 256 colors
+pub fn read_palette(bytes: &[u8], format: PaletteFormat) -> Result<Vec<[u8; 3]>, Error> {
+    let colors = match format {
+        PaletteFormat::Gpl => read_gpl(&String::from_utf8_lossy(bytes))?,
+        PaletteFormat::Act => {
+            if bytes.len() != 768 && bytes.len() != 772 {
+                return Err(invalid_palette(format!(
+                    "A color table file holds 768 or 772 bytes, not {}.",
+                    bytes.len()
+                )));
+            }
+            let count = match bytes.get(768..770) {
+                Some(&[high, low]) => match u16::from_be_bytes([high, low]) {
+                    count @ 1..=256 => count as usize,
+                    _ => 256,
+                },
+                _ => 256,
+            };
+            bytes[..count * 3]
+                .chunks(3)
+                .map(|color| [color[0], color[1], color[2]])
+                .collect()
+        }
+        PaletteFormat::Json => read_json_colors(&String::from_utf8_lossy(bytes))?,
+    };
+    if colors.is_empty() || colors.len() > 256 {
+        return Err(invalid_palette(format!(
+            "A palette holds 1 to 256 colors, not {}.",
+            colors.len()
+        )));
+    }
+    Ok(colors)
+}
+
+// Lines of "red green blue name" after a header, with # comments
+fn read_gpl(text: &str) -> Result<Vec<[u8; 3]>, Error> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err(invalid_palette("Not a GIMP palette.".to_string()));
+    }
+    let mut colors = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        let components: Option<Vec<u8>> = line
+            .split_whitespace()
+            .take(3)
+            .map(|component| component.parse().ok())
+            .collect();
+        match components.as_deref() {
+            Some(&[red, green, blue]) => colors.push([red, green, blue]),
+            _ => return Err(invalid_palette(format!("Invalid palette line: {}", line))),
+        }
+    }
+    Ok(colors)
+}
+
+// Every "#rrggbb" string of the text in order, so {"colors": [...]} and a
+// bare array both work
+fn read_json_colors(text: &str) -> Result<Vec<[u8; 3]>, Error> {
+    let mut colors = Vec::new();
+    for (position, _) in text.match_indices("\"#") {
+        let hex = text[position + 2..].split('"').next().unwrap_or("");
+        let value = match hex.len() {
+            6 => u32::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+        match value {
+            Some(value) => colors.push([(value >> 16) as u8, (value >> 8) as u8, value as u8]),
+            None => return Err(invalid_palette(format!("Invalid color: #{}", hex))),
+        }
+    }
+    Ok(colors)
+}
+
+// The global color table, or the local one of a frame
+pub fn color_table(gif: &GIF, frame: Option<usize>) -> Result<&ColorTable, Error> {
+    let table = match frame {
+        Some(index) => gif
+            .image_descriptors
+            .get(index)
+            .and_then(|frame| frame.local_color_table.as_ref()),
+        None => gif.global_color_table.as_ref(),
+    };
+    table.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            match frame {
+                Some(index) => format!("Frame {} has no local color table.", index),
+                None => "The file has no global color table.".to_string(),
+            },
+        )
+    })
+}
+
+// Replace the global color table, or the local one of a frame, moving every
+// pixel drawn with it to the nearest new color. A palette that leaves the
+// table a spare entry gets transparent pixels there, otherwise they keep
+// their index as far as the table reaches and no other pixel is moved onto it.
+pub fn import_palette(
+    gif: &mut GIF,
+    frame: Option<usize>,
+    colors: &[[u8; 3]],
+) -> Result<(), Error> {
+    let old = color_table(gif, frame)?.clone();
+    let size_bits = (colors.len().max(2).next_power_of_two().trailing_zeros() - 1) as u8;
+    let mut table = colors.to_vec();
+    table.resize(2 << size_bits, [0; 3]);
+    let spare = (colors.len() < table.len()).then_some(colors.len() as u8);
+
+    for (index, image_descriptor) in gif.image_descriptors.iter_mut().enumerate() {
+        let uses_table = match frame {
+            Some(frame) => index == frame,
+            None => image_descriptor.local_color_table.is_none(),
+        };
+        if !uses_table {
+            continue;
+        }
+
+        let transparent_index = image_descriptor.transparent_color_index();
+        let last = (table.len() - 1) as u8;
+        let new_transparent_index = transparent_index.map(|index| spare.unwrap_or(index.min(last)));
+        let mut mapping = [0u8; 256];
+        for (index, mapped) in mapping.iter_mut().enumerate() {
+            // Indices past the end of the table are drawn black
+            let color = old.colors.get(index).copied().unwrap_or([0; 3]);
+            *mapped = match transparent_index {
+                Some(transparent_index) if index == transparent_index as usize => {
+                    new_transparent_index.unwrap_or(last)
+                }
+                _ => closest_color(colors, color, new_transparent_index),
+            };
+        }
+        for pixel in &mut image_descriptor.image_data {
+            *pixel = mapping[*pixel as usize];
+        }
+        if let (Some(gce), Some(new_transparent_index)) = (
+            image_descriptor.graphics_control_extension.as_mut(),
+            new_transparent_index,
+        ) {
+            gce.transparent_color_index = new_transparent_index;
+        }
+        image_descriptor.lzw_minimum_code_size = (size_bits + 1).max(2);
+        image_descriptor.compressed_range = None;
+        if frame.is_some() {
+            image_descriptor.packed_field = (image_descriptor.packed_field & !0b111) | size_bits;
+            image_descriptor.local_color_table = Some(ColorTable {
+                colors: table.clone(),
+            });
+        }
+    }
+
+    if frame.is_none() {
+        let screen = &mut gif.logical_screen_descriptor;
+        let background = old
+            .colors
+            .get(screen.background_color_index as usize)
+            .copied()
+            .unwrap_or([0; 3]);
+        screen.background_color_index = closest_color(colors, background, None);
+        screen.packed_field = (screen.packed_field & !0b111) | size_bits;
+        gif.global_color_table = Some(ColorTable { colors: table });
+    }
+    Ok(())
+}
@@ -0,0 +1,1746 @@
+use std::io::{self, Error};
+use std::iter;
+use std::ops::Range;
+
+use crate::channels::{
+    all_channels, channel_from_id, channel_id, chunks_needed, embed_channel, lookup_channel,
+    ChannelKind, ChannelOptions, APPLICATION_IDENTIFIER, SINGLE_EXTENSION_SIZE,
+};
+#[cfg(feature = "compression")]
+pub use crate::compression::{deflate_body, inflate_body};
+use crate::crypto::{open_payload, seal_payloads, sealed_length};
+use crate::gif::{ApplicationExtension, GIF};
+use crate::metadata::{read_metadata, write_metadata, PayloadMetadata};
+// Only the command line tool encrypts to public keys
+#[cfg(feature = "age")]
+pub use crate::recipients::{decrypt_with_identities, encrypt_to_recipients};
+use crate::shuffle::{
+    chunk_count, chunk_placement, derive_chunk_key, derive_layout_key, first_chunk_position,
+    header_mask, legacy_chunk_count, padding_rng, unshuffle_chunks, unshuffle_damaged_chunks,
+    ChunkKey, StealthKey, LAYOUT_SEED_SIZE,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+// Every embedded payload starts with this header so it can be found again
+// without knowing how it was written:
+//
+//   magic (4) | version (1) | flags (1) | length (4, little endian)
+//   [| digest (32)]
+//
+// The digest, there with FLAG_DIGEST, is the SHA-256 of the embedded file.
+// Only payloads that aren't encrypted have one, as it would let anyone
+// holding the carrier confirm a guess at what an encrypted one holds.
+//
+// Payloads of every version written so far are still read:
+//
+//   0  no header at all: the bare file in plain text extensions, the last
+//      one padded with zeros. Only read by extract --legacy, through
+//      read_legacy_payload.
+//   1  the header, then the bare file
+//   2  the header, then a metadata record and the file
+//   3  as 2, the header may end with a digest
+//   4  as 3, the body starting with an expiry time
+//   5  as 4, an encrypted body padded inside its encryption
+pub const PAYLOAD_MAGIC: [u8; 4] = *b"GSau";
+pub const PAYLOAD_VERSION: u8 = 5;
+pub const PAYLOAD_HEADER_SIZE: usize = 10; // Without the digest
+
+pub const FLAG_ENCRYPTED: u8 = 0b0000_0001;
+pub const FLAG_COMPRESSED: u8 = 0b0000_0010; // The body is deflated, before any encryption
+pub const FLAG_RECIPIENTS: u8 = 0b0000_0100; // The body is encrypted to age public keys
+pub const FLAG_PADDED: u8 = 0b0000_1000; // The body is padded, inside any encryption
+pub const FLAG_CHECKSUMS: u8 = 0b0001_0000; // The body has block checksums, before any padding
+pub const FLAG_DIGEST: u8 = 0b0010_0000; // The header ends with the digest of the file
+
+// A padded body hides how long the payload really is:
+//
+//   length (4, little endian) | body | padding
+//
+// The whole runs to a multiple of a bucket size. An encrypted body is padded
+// before it is encrypted, so its length is sealed with it; before version 5
+// the padding went around the encrypted body, length and all.
+const PADDING_LENGTH_SIZE: usize = 4;
+const PADDED_INSIDE_VERSION: u8 = 5;
+
+// Bucket size bodies are padded to unless asked otherwise
+pub const DEFAULT_PADDING: usize = 256;
+
+// A body stored as it is may carry a checksum for every block of it, so
+// extract --partial can tell which parts of a damaged one are still good:
+//
+//   count (4, little endian) | count * checksum (4) | body
+//
+// A checksum is the start of the SHA-256 of its block.
+const CHECKSUM_BLOCK_SIZE: usize = 256;
+const CHECKSUM_SIZE: usize = 4;
+
+// When a payload is spread over several channels, the first channel starts
+// with a manifest listing where the pieces went:
+//
+//   magic (4) | version (1) | count (1) | count * (channel (1) | length (4))
+pub const MANIFEST_MAGIC: [u8; 4] = *b"GSmf";
+const MANIFEST_ENTRY_SIZE: usize = 5;
+
+// Besides the payload, embed writes an application extension of its own
+// describing it, so extract can go straight to its channels and detect
+// needn't search them all:
+//
+//   version (1) | count (1) | count * (channel (1) | length (4) | chunks (4))
+//   | digest (32)
+//
+// A stream's length is the bytes written to its channel, the manifest above
+// included, and chunks the blocks they went into. The digest is the SHA-256
+// of the payload blob, header and all, as it was embedded.
+const MANIFEST_EXTENSION_AUTHENTICATION_CODE: &str = "MAN";
+const MANIFEST_EXTENSION_ENTRY_SIZE: usize = 9;
+pub const DIGEST_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadHeader {
+    pub version: u8,
+    pub flags: u8,
+    pub length: u32,
+    pub digest: Option<[u8; DIGEST_SIZE]>,
+}
+
+impl PayloadHeader {
+    // Bytes of the header, the body starting right after them
+    pub fn size(&self) -> usize {
+        match self.digest {
+            Some(_) => PAYLOAD_HEADER_SIZE + DIGEST_SIZE,
+            None => PAYLOAD_HEADER_SIZE,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    pub fn is_for_recipients(&self) -> bool {
+        self.flags & FLAG_RECIPIENTS != 0
+    }
+
+    pub fn is_padded(&self) -> bool {
+        self.flags & FLAG_PADDED != 0
+    }
+
+    pub fn has_checksums(&self) -> bool {
+        self.flags & FLAG_CHECKSUMS != 0
+    }
+
+    // Whether the padding is to be stripped before any decryption, rather
+    // than after it
+    pub fn is_padded_outside(&self) -> bool {
+        self.is_padded()
+            && (self.version < PADDED_INSIDE_VERSION
+                || !(self.is_encrypted() || self.is_for_recipients()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub version: u8,
+    pub pieces: Vec<(ChannelKind, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestExtension {
+    pub version: u8,
+    pub streams: Vec<ManifestStream>,
+    pub digest: [u8; DIGEST_SIZE],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ManifestStream {
+    pub channel: ChannelKind,
+    pub length: usize,
+    pub chunks: usize,
+}
+
+// An extracted file and what was recorded about it
+#[derive(Debug, Clone)]
+pub struct Payload {
+    pub metadata: PayloadMetadata,
+    pub data: Vec<u8>,
+}
+
+// What is left of a damaged payload
+pub struct PartialPayload {
+    pub payload: Payload,
+    pub lost: Damage,       // Ranges of the payload's data that were lost
+    pub lost_chunks: usize, // Chunks of chunked channels that were missing
+}
+
+// Where a payload was found and what its header says
+#[derive(Debug, Clone)]
+pub struct PayloadLocation {
+    pub channels: Vec<ChannelKind>,
+    pub header: PayloadHeader,
+    pub manifest: Option<ManifestExtension>, // When its digest matches the payload
+}
+
+// The header with FLAG_DIGEST set when a digest is given, then the payload
+pub fn write_payload_header(
+    payload: &[u8],
+    flags: u8,
+    digest: Option<&[u8; DIGEST_SIZE]>,
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(PAYLOAD_HEADER_SIZE + DIGEST_SIZE + payload.len());
+    data.extend_from_slice(&PAYLOAD_MAGIC);
+    data.push(PAYLOAD_VERSION);
+    data.push(match digest {
+        Some(_) => flags | FLAG_DIGEST,
+        None => flags & !FLAG_DIGEST,
+    });
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    if let Some(digest) = digest {
+        data.extend_from_slice(digest);
+    }
+    data.extend_from_slice(payload);
+    data
+}
+
+pub fn read_payload_header(data: &[u8]) -> Option<PayloadHeader> {
+    if data.len() < PAYLOAD_HEADER_SIZE || data[0..4] != PAYLOAD_MAGIC {
+        return None;
+    }
+
+    let mut length = [0; 4];
+    length.copy_from_slice(&data[6..10]);
+
+    // Older versions had no use for the flag
+    let flags = data[5];
+    let digest = match flags & FLAG_DIGEST != 0 && data[4] >= 3 {
+        true => Some(
+            data.get(PAYLOAD_HEADER_SIZE..PAYLOAD_HEADER_SIZE + DIGEST_SIZE)?
+                .try_into()
+                .unwrap(),
+        ),
+        false => None,
+    };
+
+    Some(PayloadHeader {
+        version: data[4],
+        flags,
+        length: u32::from_le_bytes(length),
+        digest,
+    })
+}
+
+fn manifest_size(count: usize) -> usize {
+    MANIFEST_MAGIC.len() + 2 + count * MANIFEST_ENTRY_SIZE
+}
+
+fn write_manifest(manifest: &Manifest) -> Vec<u8> {
+    let mut data = Vec::with_capacity(manifest_size(manifest.pieces.len()));
+    data.extend_from_slice(&MANIFEST_MAGIC);
+    data.push(manifest.version);
+    data.push(manifest.pieces.len() as u8);
+    for &(channel, length) in &manifest.pieces {
+        data.push(channel_id(channel));
+        data.extend_from_slice(&(length as u32).to_le_bytes());
+    }
+    data
+}
+
+pub fn read_manifest(data: &[u8]) -> Option<Manifest> {
+    if data.len() < manifest_size(0) || data[0..4] != MANIFEST_MAGIC {
+        return None;
+    }
+
+    let version = data[4];
+    let count = data[5] as usize;
+    if data.len() < manifest_size(count) {
+        return None;
+    }
+
+    let mut pieces = Vec::with_capacity(count);
+    for entry in data[manifest_size(0)..manifest_size(count)].chunks(MANIFEST_ENTRY_SIZE) {
+        let channel = channel_from_id(entry[0])?;
+        let mut length = [0; 4];
+        length.copy_from_slice(&entry[1..5]);
+        pieces.push((channel, u32::from_le_bytes(length) as usize));
+    }
+
+    Some(Manifest { version, pieces })
+}
+
+fn is_manifest_extension(application: &ApplicationExtension) -> bool {
+    application.identifier == APPLICATION_IDENTIFIER
+        && application.authentication_code == MANIFEST_EXTENSION_AUTHENTICATION_CODE
+}
+
+fn write_manifest_extension(gif: &mut GIF, manifest: &ManifestExtension) {
+    let mut data = Vec::with_capacity(
+        2 + manifest.streams.len() * MANIFEST_EXTENSION_ENTRY_SIZE + DIGEST_SIZE,
+    );
+    data.push(manifest.version);
+    data.push(manifest.streams.len() as u8);
+    for stream in &manifest.streams {
+        data.push(channel_id(stream.channel));
+        data.extend_from_slice(&(stream.length as u32).to_le_bytes());
+        data.extend_from_slice(&(stream.chunks as u32).to_le_bytes());
+    }
+    data.extend_from_slice(&manifest.digest);
+    clear_manifest_extension(gif);
+    gif.application_extensions.push(ApplicationExtension {
+        identifier: APPLICATION_IDENTIFIER.to_string(),
+        authentication_code: MANIFEST_EXTENSION_AUTHENTICATION_CODE.to_string(),
+        data,
+    });
+}
+
+fn clear_manifest_extension(gif: &mut GIF) {
+    gif.application_extensions
+        .retain(|application| !is_manifest_extension(application));
+}
+
+pub fn read_manifest_extension(gif: &GIF) -> Option<ManifestExtension> {
+    let data = &gif
+        .application_extensions
+        .iter()
+        .find(|application| is_manifest_extension(application))?
+        .data;
+    let (&version, data) = data.split_first()?;
+    let (&count, data) = data.split_first()?;
+    let entries_size = count as usize * MANIFEST_EXTENSION_ENTRY_SIZE;
+    if data.len() != entries_size + DIGEST_SIZE {
+        return None;
+    }
+
+    let mut streams = Vec::with_capacity(count as usize);
+    for entry in data[..entries_size].chunks(MANIFEST_EXTENSION_ENTRY_SIZE) {
+        streams.push(ManifestStream {
+            channel: channel_from_id(entry[0])?,
+            length: u32::from_le_bytes(entry[1..5].try_into().unwrap()) as usize,
+            chunks: u32::from_le_bytes(entry[5..9].try_into().unwrap()) as usize,
+        });
+    }
+    Some(ManifestExtension {
+        version,
+        streams,
+        digest: data[entries_size..].try_into().unwrap(),
+    })
+}
+
+// The room of each channel for its piece of a blob spread over several. The
+// manifest eats into the first channel's room.
+fn piece_capacities(gif: &GIF, channels: &[ChannelKind]) -> Vec<Option<usize>> {
+    channels
+        .iter()
+        .enumerate()
+        .map(|(index, channel)| {
+            lookup_channel(*channel).capacity(gif).map(|capacity| {
+                if index == 0 {
+                    capacity.saturating_sub(manifest_size(channels.len()))
+                } else {
+                    capacity
+                }
+            })
+        })
+        .collect()
+}
+
+// Share `total` bytes out over the channels as evenly as their capacities
+// allow. Channels without a fixed capacity soak up whatever is left.
+fn plan_pieces(capacities: &[Option<usize>], total: usize) -> Result<Vec<usize>, Error> {
+    let mut lengths = vec![0; capacities.len()];
+    let mut remaining = total;
+    let mut open: Vec<usize> = (0..capacities.len()).collect();
+
+    while remaining > 0 && !open.is_empty() {
+        let share = remaining.div_ceil(open.len());
+        let mut still_open = Vec::new();
+        for &index in &open {
+            let room = match capacities[index] {
+                Some(capacity) => capacity - lengths[index],
+                None => usize::MAX,
+            };
+            let take = share.min(room).min(remaining);
+            lengths[index] += take;
+            remaining -= take;
+            if room > take {
+                still_open.push(index);
+            }
+        }
+        open = still_open;
+    }
+
+    if remaining > 0 {
+        return Err(io::Error::other(
+            "Payload does not fit in the selected channels.",
+        ));
+    }
+    Ok(lengths)
+}
+
+// Embed a payload into one channel, or spread it over several behind a
+// manifest when more than one channel is given. With a key, chunked channels
+// get randomly sized chunks in a keyed order. Returns how many blocks the
+// payload went into.
+pub fn embed_payload(
+    gif: &mut GIF,
+    payload: &[u8],
+    flags: u8,
+    digest: Option<&[u8; DIGEST_SIZE]>,
+    channels: &[ChannelKind],
+    key: Option<&ChunkKey>,
+    options: &ChannelOptions,
+) -> Result<usize, Error> {
+    let mut blob = write_payload_header(payload, flags, digest);
+    if options.stealth {
+        let key = check_stealth(channels, key)?;
+        mask_header(&mut blob, key);
+    }
+    let streams = embed_streams(gif, &blob, channels, key, options)?;
+    let chunks = streams.iter().map(|stream| stream.chunks).sum();
+
+    // A GIF87a carrier whose payload needed no extensions stays one
+    if !options.manifest || options.stealth || gif.output_version() == *b"87a" {
+        clear_manifest_extension(gif);
+        return Ok(chunks);
+    }
+    write_manifest_extension(
+        gif,
+        &ManifestExtension {
+            version: PAYLOAD_VERSION,
+            streams,
+            digest: Sha256::digest(&blob).into(),
+        },
+    );
+    Ok(chunks)
+}
+
+// A stealth payload is found by its key alone, so it needs one, and it can't
+// go where GifSauce's name or a manifest would give it away
+fn check_stealth<'a>(
+    channels: &[ChannelKind],
+    key: Option<&'a ChunkKey>,
+) -> Result<&'a ChunkKey, Error> {
+    let refuse = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if channels.len() > 1 {
+        return Err(refuse(
+            "A stealth payload goes into one channel, a manifest would mark it.",
+        ));
+    }
+    if channels.contains(&ChannelKind::Application) {
+        return Err(refuse(
+            "A stealth payload can't use the appext channel, its extension is named after GifSauce.",
+        ));
+    }
+    key.filter(|key| header_mask(key).is_some())
+        .ok_or_else(|| refuse("A stealth payload needs a passphrase to be found with."))
+}
+
+fn mask_header(blob: &mut [u8], key: &ChunkKey) {
+    if let Some(mask) = header_mask(key) {
+        for (byte, mask) in blob[..PAYLOAD_HEADER_SIZE].iter_mut().zip(mask) {
+            *byte ^= mask;
+        }
+    }
+}
+
+// Channel data that starts with a stealth header, with the header unmasked.
+// Anything else is left as it is.
+fn unmask_header(mut data: Vec<u8>, key: Option<&ChunkKey>) -> Vec<u8> {
+    if let Some(key) = key {
+        if starts_masked(&data, key) {
+            mask_header(&mut data, key);
+        }
+    }
+    data
+}
+
+fn starts_masked(data: &[u8], key: &ChunkKey) -> bool {
+    header_mask(key).is_some_and(|mask| {
+        data.len() >= PAYLOAD_HEADER_SIZE
+            && data
+                .iter()
+                .zip(mask)
+                .zip(PAYLOAD_MAGIC)
+                .all(|((byte, mask), magic)| byte ^ mask == magic)
+    })
+}
+
+// The streams the blob was written as, in the order of `channels`
+fn embed_streams(
+    gif: &mut GIF,
+    blob: &[u8],
+    channels: &[ChannelKind],
+    key: Option<&ChunkKey>,
+    options: &ChannelOptions,
+) -> Result<Vec<ManifestStream>, Error> {
+    // Both pick pixel indices bit by bit, so one would overwrite the other
+    if channels.contains(&ChannelKind::Lsb) && channels.contains(&ChannelKind::Background) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The lsb and background channels can't be used together.",
+        ));
+    }
+
+    if channels.len() == 1 {
+        let chunks = embed_channel(gif, channels[0], blob, key, options)?;
+        return Ok(vec![ManifestStream {
+            channel: channels[0],
+            length: blob.len(),
+            chunks,
+        }]);
+    }
+    if channels.is_empty() || channels.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid number of channels.",
+        ));
+    }
+
+    let lengths = plan_pieces(&piece_capacities(gif, channels), blob.len())?;
+    debug!(
+        "Spreading {} bytes as {:?}",
+        blob.len(),
+        channels.iter().zip(lengths.iter()).collect::<Vec<_>>()
+    );
+
+    let manifest = Manifest {
+        version: PAYLOAD_VERSION,
+        pieces: channels
+            .iter()
+            .cloned()
+            .zip(lengths.iter().cloned())
+            .collect(),
+    };
+
+    // Channels that may restructure the frame list go before the lsb channel
+    let mut order: Vec<usize> = (0..channels.len()).collect();
+    order.sort_by_key(|&index| channels[index] == ChannelKind::Lsb);
+
+    let mut streams: Vec<ManifestStream> = channels
+        .iter()
+        .map(|&channel| ManifestStream {
+            channel,
+            length: 0,
+            chunks: 0,
+        })
+        .collect();
+    for index in order {
+        let start: usize = lengths[..index].iter().sum();
+        let piece = &blob[start..start + lengths[index]];
+        if index == 0 {
+            let mut data = write_manifest(&manifest);
+            data.extend_from_slice(piece);
+            streams[index].chunks = embed_channel(gif, channels[index], &data, key, options)?;
+            streams[index].length = data.len();
+        } else if !piece.is_empty() {
+            streams[index].chunks = embed_channel(gif, channels[index], piece, key, options)?;
+            streams[index].length = piece.len();
+        }
+    }
+
+    Ok(streams)
+}
+
+// The largest body the channels can hold behind a header of `header_size`
+// bytes, None when they grow with it
+pub fn payload_room(gif: &GIF, channels: &[ChannelKind], header_size: usize) -> Option<usize> {
+    let mut room = 0;
+    for channel in channels {
+        room += lookup_channel(*channel).capacity(gif)?;
+    }
+    let manifest = match channels.len() {
+        1 => 0,
+        count => manifest_size(count),
+    };
+    Some(room.saturating_sub(header_size + manifest))
+}
+
+// Whether the channels take a body of `length` bytes behind a header of
+// `header_size` bytes as the carrier is, without going over a fixed capacity
+// or adding frames for plain text chunks
+pub fn fits_carrier(
+    gif: &GIF,
+    channels: &[ChannelKind],
+    key: Option<&ChunkKey>,
+    options: &ChannelOptions,
+    header_size: usize,
+    length: usize,
+) -> bool {
+    let blob = header_size + length;
+    let streams = match channels {
+        [channel] => match lookup_channel(*channel).capacity(gif) {
+            Some(capacity) if blob > capacity => return false,
+            _ => vec![blob],
+        },
+        _ => match plan_pieces(&piece_capacities(gif, channels), blob) {
+            Ok(mut lengths) => {
+                lengths[0] += manifest_size(channels.len());
+                lengths
+            }
+            Err(_) => return false,
+        },
+    };
+    let frames = gif.image_descriptors.len();
+    channels.iter().zip(streams).all(|(channel, stream)| {
+        *channel != ChannelKind::PlainText
+            || stream == 0
+            || frames == 0
+            || chunks_needed(*channel, stream, key, options) <= frames
+    })
+}
+
+// The room padding may fill. A body small enough for one extension of a
+// chunked channel is only padded as far as that extension holds, so the
+// padding doesn't spill it over into several.
+pub fn padding_room(
+    gif: &GIF,
+    channels: &[ChannelKind],
+    key: Option<&ChunkKey>,
+    options: &ChannelOptions,
+    header_size: usize,
+    body_length: usize,
+) -> Option<usize> {
+    let room = payload_room(gif, channels, header_size);
+    let single = SINGLE_EXTENSION_SIZE - header_size;
+    let whole = match channels {
+        [channel] => {
+            lookup_channel(*channel).chunk_size().is_some()
+                && (key.is_some() || options.chunk_size.is_none())
+        }
+        _ => false,
+    };
+    if !whole || PADDING_LENGTH_SIZE + body_length > single {
+        return room;
+    }
+    Some(room.map_or(single, |room| room.min(single)))
+}
+
+// The length to pad a body of `length` bytes to, `sealed_length` being what
+// encryption makes of a padded body that long: what takes the stored body to
+// a multiple of `bucket` bytes, or as far as `room` lets it, without
+// spilling over the cipher's own rounding into more. None when not even the
+// length fits.
+pub fn padded_length(
+    length: usize,
+    bucket: usize,
+    room: Option<usize>,
+    sealed_length: impl Fn(usize) -> usize,
+) -> Option<usize> {
+    let shortest = PADDING_LENGTH_SIZE + length;
+    let stored = sealed_length(shortest);
+    let mut target = stored.div_ceil(bucket.max(1)) * bucket.max(1);
+    if let Some(room) = room {
+        if stored > room {
+            return None;
+        }
+        target = target.min(room);
+    }
+
+    // Sealing never makes a body shorter, so the longest that still seals
+    // within the target is at most that much longer
+    let (mut low, mut high) = (shortest, shortest + (target - stored));
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        match sealed_length(middle) <= target {
+            true => low = middle,
+            false => high = middle - 1,
+        }
+    }
+    Some(low)
+}
+
+// Pad a body to the `padded_length` bytes padded_length gave for it
+pub fn pad_body(body: &[u8], padded_length: usize) -> Vec<u8> {
+    let length = PADDING_LENGTH_SIZE + body.len();
+    let mut padded = Vec::with_capacity(padded_length);
+    padded.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    padded.extend_from_slice(body);
+    let mut padding = vec![0; padded_length - length];
+    padding_rng(body).fill_bytes(&mut padding);
+    padded.extend_from_slice(&padding);
+    padded
+}
+
+pub fn unpad_body(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let damaged = || io::Error::new(io::ErrorKind::InvalidData, "Payload padding is damaged.");
+    let length = data
+        .get(..PADDING_LENGTH_SIZE)
+        .ok_or_else(damaged)?
+        .try_into()
+        .map(u32::from_le_bytes)
+        .map_err(|_| damaged())? as usize;
+    data.get(PADDING_LENGTH_SIZE..PADDING_LENGTH_SIZE + length)
+        .map(|body| body.to_vec())
+        .ok_or_else(damaged)
+}
+
+fn block_checksum(block: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let digest = Sha256::digest(block);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+pub fn add_checksums(body: &[u8]) -> Vec<u8> {
+    let count = body.len().div_ceil(CHECKSUM_BLOCK_SIZE);
+    let mut data = Vec::with_capacity(4 + count * CHECKSUM_SIZE + body.len());
+    data.extend_from_slice(&(count as u32).to_le_bytes());
+    for block in body.chunks(CHECKSUM_BLOCK_SIZE) {
+        data.extend_from_slice(&block_checksum(block));
+    }
+    data.extend_from_slice(body);
+    data
+}
+
+// The body without its checksums, and the ranges of it whose checksum fails
+// or was itself among the `damage` already known. Those are filled with
+// `placeholder`.
+fn check_body(
+    data: &[u8],
+    damage: &[Range<usize>],
+    placeholder: u8,
+) -> Result<(Vec<u8>, Damage), Error> {
+    let damaged = || io::Error::new(io::ErrorKind::InvalidData, "Payload checksums are damaged.");
+    if !clip_damage(damage, 0..4, 0).is_empty() {
+        return Err(damaged());
+    }
+    let count = data
+        .get(..4)
+        .ok_or_else(damaged)?
+        .try_into()
+        .map(u32::from_le_bytes)
+        .map_err(|_| damaged())? as usize;
+    let start = count
+        .checked_mul(CHECKSUM_SIZE)
+        .and_then(|size| size.checked_add(4))
+        .filter(|&start| start <= data.len())
+        .ok_or_else(damaged)?;
+    let body = &data[start..];
+    if body.len().div_ceil(CHECKSUM_BLOCK_SIZE) != count {
+        return Err(damaged());
+    }
+
+    let mut failed = clip_damage(damage, start..data.len(), 0);
+    for (index, block) in body.chunks(CHECKSUM_BLOCK_SIZE).enumerate() {
+        let entry = 4 + index * CHECKSUM_SIZE..4 + (index + 1) * CHECKSUM_SIZE;
+        if data[entry.clone()] != block_checksum(block) || !clip_damage(damage, entry, 0).is_empty()
+        {
+            let offset = index * CHECKSUM_BLOCK_SIZE;
+            failed.push(offset..offset + block.len());
+        }
+    }
+    let failed = merge_damage(failed);
+    let mut body = body.to_vec();
+    for range in &failed {
+        body[range.clone()].fill(placeholder);
+    }
+    Ok((body, failed))
+}
+
+// Bytes a channel stream should hold, judging by the header it starts with
+fn expected_stream_length(data: &[u8], channel: ChannelKind) -> Option<usize> {
+    if let Some(manifest) = read_manifest(data) {
+        let piece = manifest.pieces.iter().find(|piece| piece.0 == channel)?;
+        return Some(manifest_size(manifest.pieces.len()) + piece.1);
+    }
+    read_payload_header(data).map(|header| header.size() + header.length as usize)
+}
+
+// Lengths come from the file, so one too large for the chunks is refused
+// before chunk_count walks through billions of chunks
+fn fits_in_chunks(chunks: &[Vec<u8>], length: usize) -> bool {
+    length <= chunks.iter().map(Vec::len).sum()
+}
+
+// Find a shuffled stream when its length isn't known: try every chunk count
+// whose first chunk starts with a magic, and keep the one whose header agrees
+// with that count.
+fn probe_keyed_stream(gif: &GIF, channel: ChannelKind, key: &ChunkKey) -> Option<Vec<u8>> {
+    let chunks = lookup_channel(channel).chunks(gif);
+    for count in 1..=chunks.len() {
+        let first = &chunks[first_chunk_position(count, key, channel)];
+        if !first.starts_with(&PAYLOAD_MAGIC)
+            && !first.starts_with(&MANIFEST_MAGIC)
+            && !starts_masked(first, key)
+        {
+            continue;
+        }
+        let data = unmask_header(unshuffle_chunks(&chunks, count, key, channel)?, Some(key));
+        if let Some(length) = expected_stream_length(&data, channel) {
+            if fits_in_chunks(&chunks, length)
+                && (chunk_count(length, key, channel) == count
+                    || legacy_chunk_count(length, key, channel) == count)
+            {
+                return Some(data);
+            }
+        }
+    }
+    None
+}
+
+// Channel data with any keyed shuffling undone and any stealth header
+// unmasked. `length` is the number of bytes the stream holds when a manifest
+// already told us.
+fn read_channel(
+    gif: &GIF,
+    channel: ChannelKind,
+    key: Option<&ChunkKey>,
+    length: Option<usize>,
+) -> Option<Vec<u8>> {
+    read_raw_channel(gif, channel, key, length).map(|data| unmask_header(data, key))
+}
+
+// read_channel leaving a stealth header masked, unless it had to be probed
+// for
+fn read_raw_channel(
+    gif: &GIF,
+    channel: ChannelKind,
+    key: Option<&ChunkKey>,
+    length: Option<usize>,
+) -> Option<Vec<u8>> {
+    match key {
+        Some(key) if lookup_channel(channel).chunk_size().is_some() => match length {
+            Some(length) => {
+                let chunks = lookup_channel(channel).chunks(gif);
+                if !fits_in_chunks(&chunks, length) {
+                    return None;
+                }
+                unshuffle_chunks(&chunks, chunk_count(length, key, channel), key, channel)
+            }
+            None => probe_keyed_stream(gif, channel, key),
+        },
+        _ => lookup_channel(channel).extract_keyed(gif, key),
+    }
+}
+
+// Merge the pieces a manifest points to back into one header + body blob.
+fn merge_pieces(
+    gif: &GIF,
+    holder: ChannelKind,
+    holder_data: &[u8],
+    manifest: &Manifest,
+    key: Option<&ChunkKey>,
+) -> Option<Vec<u8>> {
+    let mut blob = Vec::new();
+    for &(channel, length) in &manifest.pieces {
+        if channel == holder {
+            let start = manifest_size(manifest.pieces.len());
+            blob.extend_from_slice(holder_data.get(start..start.checked_add(length)?)?);
+        } else if length > 0 {
+            let data = read_channel(gif, channel, key, Some(length))?;
+            blob.extend_from_slice(data.get(0..length)?);
+        }
+    }
+    Some(blob)
+}
+
+// A channel holding part of a payload and how many stream bytes it holds
+type Stream = (ChannelKind, usize);
+
+// Byte ranges of a stream or payload that were lost, in order
+pub type Damage = Vec<Range<usize>>;
+
+// Sorted, with ranges that touch or overlap made one
+fn merge_damage(mut damage: Damage) -> Damage {
+    damage.sort_by_key(|range| range.start);
+    let mut merged: Damage = Vec::new();
+    for range in damage {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// The part of `damage` inside `window`, moved to start at `offset`
+fn clip_damage(damage: &[Range<usize>], window: Range<usize>, offset: usize) -> Damage {
+    damage
+        .iter()
+        .filter_map(|range| {
+            let start = range.start.max(window.start);
+            let end = range.end.min(window.end);
+            (start < end).then(|| start - window.start + offset..end - window.start + offset)
+        })
+        .collect()
+}
+
+// The length of a keyed stream that may be missing chunks, from its first
+// chunk: one starting with a magic, found where the layout for the length it
+// gives puts the first chunk. Up to half the stream may be missing.
+fn damaged_stream_length(
+    chunks: &[Vec<u8>],
+    key: &ChunkKey,
+    channel: ChannelKind,
+) -> Option<usize> {
+    let present: usize = chunks.iter().map(Vec::len).sum();
+    chunks.iter().enumerate().find_map(|(position, chunk)| {
+        if !chunk.starts_with(&PAYLOAD_MAGIC) && !chunk.starts_with(&MANIFEST_MAGIC) {
+            return None;
+        }
+        let length = expected_stream_length(chunk, channel)?;
+        let count = chunk_count(length, key, channel);
+        (within_damage(length, present) && first_chunk_position(count, key, channel) == position)
+            .then_some(length)
+    })
+}
+
+// Lengths of a damaged stream come from the file, so one claiming more than
+// twice the bytes there are is refused before buffers are sized for it. Up to
+// half a stream may be missing.
+fn within_damage(length: usize, present: usize) -> bool {
+    length <= present.saturating_mul(2)
+}
+
+// read_channel for a stream that may be damaged: bytes missing from it are
+// `placeholder`, and returned as its damage along with how many chunks they
+// were. None when not even the start of a stream is there, or when it can't
+// be as long as it claims.
+fn read_damaged_channel(
+    gif: &GIF,
+    channel: ChannelKind,
+    key: Option<&ChunkKey>,
+    length: Option<usize>,
+    placeholder: u8,
+) -> Option<(Vec<u8>, Damage, usize)> {
+    let chunk_size = lookup_channel(channel).chunk_size();
+    match key {
+        Some(key) if chunk_size.is_some() => {
+            let chunks = lookup_channel(channel).chunks(gif);
+            let length = match length {
+                Some(length) => length,
+                None => damaged_stream_length(&chunks, key, channel)?,
+            };
+            if !within_damage(length, chunks.iter().map(Vec::len).sum()) {
+                return None;
+            }
+            let (data, lost) = unshuffle_damaged_chunks(&chunks, length, key, channel, placeholder);
+            let lost_chunks = lost.len();
+            Some((data, lost, lost_chunks))
+        }
+        _ => {
+            let mut data = lookup_channel(channel)
+                .extract_keyed(gif, key)
+                .unwrap_or_default();
+            let length = match length {
+                Some(length) => length,
+                None => expected_stream_length(&data, channel)?,
+            };
+            if !within_damage(length, data.len()) {
+                return None;
+            }
+            let mut damage = Vec::new();
+            let mut lost_chunks = 0;
+            if data.len() < length {
+                // Every chunk but the last is as long as the first
+                if let Some(default_size) = chunk_size {
+                    let size = lookup_channel(channel)
+                        .chunks(gif)
+                        .first()
+                        .map_or(default_size, Vec::len);
+                    lost_chunks = (length - data.len()).div_ceil(size.max(1));
+                }
+                damage.push(data.len()..length);
+                data.resize(length, placeholder);
+            }
+            data.truncate(length);
+            Some((data, damage, lost_chunks))
+        }
+    }
+}
+
+// locate_payload for a payload that may be damaged: the blob, with pieces
+// missing from a manifest's channels and chunks missing from a channel
+// filled in with `placeholder`, and how many chunks were missing
+fn locate_damaged_payload(
+    gif: &GIF,
+    key: Option<&ChunkKey>,
+    placeholder: u8,
+) -> Option<(Vec<u8>, Damage, usize)> {
+    for channel in all_channels().iter() {
+        let (data, damage, lost_chunks) =
+            match read_damaged_channel(gif, *channel, key, None, placeholder) {
+                Some(stream) => stream,
+                None => continue,
+            };
+        if let Some(manifest) = read_manifest(&data) {
+            let mut pieces = Vec::new();
+            let mut present = 0;
+            for &(piece_channel, length) in &manifest.pieces {
+                let piece = if piece_channel == *channel {
+                    let start = manifest_size(manifest.pieces.len());
+                    let window = start..start.saturating_add(length);
+                    (
+                        data.get(window.clone()).unwrap_or_default().to_vec(),
+                        clip_damage(&damage, window, 0),
+                        lost_chunks,
+                    )
+                } else {
+                    read_damaged_channel(gif, piece_channel, key, Some(length), placeholder)
+                        .unwrap_or_default()
+                };
+                let missing: usize = clip_damage(&piece.1, 0..piece.0.len(), 0)
+                    .iter()
+                    .map(|range| range.len())
+                    .sum();
+                present += piece.0.len() - missing;
+                pieces.push((piece, length));
+            }
+            let total = manifest.pieces.iter().map(|piece| piece.1).sum();
+            if !within_damage(total, present) {
+                continue;
+            }
+
+            let mut blob = Vec::new();
+            let mut blob_damage = Vec::new();
+            let mut blob_lost_chunks = 0;
+            for ((mut piece, mut piece_damage, piece_lost_chunks), length) in pieces {
+                blob_lost_chunks += piece_lost_chunks;
+                if piece.len() < length {
+                    piece_damage.push(piece.len()..length);
+                    piece.resize(length, placeholder);
+                }
+                blob_damage.extend(clip_damage(&piece_damage, 0..length, blob.len()));
+                blob.extend_from_slice(&piece[..length]);
+            }
+            return Some((blob, blob_damage, blob_lost_chunks));
+        }
+        if read_payload_header(&data).is_some() {
+            return Some((data, damage, lost_chunks));
+        }
+    }
+    None
+}
+
+// The payload blob and its streams as the manifest extension describes
+// them, when the blob found there has the digest it records
+fn locate_described_payload(gif: &GIF, key: Option<&ChunkKey>) -> Option<(Vec<Stream>, Vec<u8>)> {
+    let manifest = read_manifest_extension(gif)?;
+    let first = manifest.streams.first()?;
+    let data = read_channel(gif, first.channel, key, Some(first.length))?;
+    let data = data.get(..first.length)?;
+    let blob = match manifest.streams.len() {
+        1 => data.to_vec(),
+        _ => merge_pieces(gif, first.channel, data, &read_manifest(data)?, key)?,
+    };
+    if Sha256::digest(&blob)[..] != manifest.digest {
+        return None;
+    }
+    debug!(
+        "Found the payload the manifest extension describes: {:?}",
+        manifest.streams
+    );
+    let streams = manifest
+        .streams
+        .iter()
+        .map(|stream| (stream.channel, stream.length))
+        .collect();
+    Some((streams, blob))
+}
+
+// A piece of a stored payload: `range` of the header and body blob, held in
+// the channel's chunk `block`, counted as the channel lists its chunks.
+// Channels that aren't chunked hold their whole piece, with no block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedChunk {
+    pub channel: ChannelKind,
+    pub range: Range<usize>,
+    pub block: Option<usize>,
+}
+
+// Where each piece of the payload the key finds lies, in blob order. None
+// when there is no payload.
+pub fn map_payload(gif: &GIF, key: Option<&ChunkKey>) -> Option<Vec<MappedChunk>> {
+    let (streams, _) = locate_payload(gif, key)?;
+    let mut mapped = Vec::new();
+    let mut blob_start = 0;
+    for (index, &(channel, length)) in streams.iter().enumerate() {
+        // The first of several streams starts with the manifest, which isn't
+        // part of the blob
+        let skip = match (index, streams.len()) {
+            (0, count) if count > 1 => manifest_size(count),
+            _ => 0,
+        };
+        let pieces: Vec<(Range<usize>, Option<usize>)> =
+            match (lookup_channel(channel).chunk_size(), key) {
+                (None, _) => vec![(0..length, None)],
+                (Some(_), Some(key)) => chunk_placement(length, key, channel)
+                    .into_iter()
+                    .map(|(range, position)| (range, Some(position)))
+                    .collect(),
+                (Some(_), None) => {
+                    let mut start = 0;
+                    lookup_channel(channel)
+                        .chunks(gif)
+                        .iter()
+                        .enumerate()
+                        .map_while(|(block, chunk)| {
+                            let end = (start + chunk.len()).min(length);
+                            let range = start..end;
+                            start = end;
+                            (!range.is_empty()).then_some((range, Some(block)))
+                        })
+                        .collect()
+                }
+            };
+        for (range, block) in pieces {
+            if range.end <= skip {
+                continue;
+            }
+            mapped.push(MappedChunk {
+                channel,
+                range: blob_start + range.start.max(skip) - skip..blob_start + range.end - skip,
+                block,
+            });
+        }
+        blob_start += length - skip;
+    }
+    mapped.sort_by_key(|chunk| chunk.range.start);
+    Some(mapped)
+}
+
+// Find the payload blob (header + body) and the streams it lives in.
+fn locate_payload(gif: &GIF, key: Option<&ChunkKey>) -> Option<(Vec<Stream>, Vec<u8>)> {
+    if let Some(found) = locate_described_payload(gif, key) {
+        return Some(found);
+    }
+    for channel in all_channels().iter() {
+        let data = match read_channel(gif, *channel, key, None) {
+            Some(data) => data,
+            None => continue,
+        };
+        if let Some(manifest) = read_manifest(&data) {
+            debug!(
+                "Found a manifest in the {} channel: {:?}",
+                channel, manifest.pieces
+            );
+            let blob = merge_pieces(gif, *channel, &data, &manifest, key)?;
+            let streams = manifest
+                .pieces
+                .iter()
+                .map(|&(piece_channel, length)| {
+                    if piece_channel == *channel {
+                        (piece_channel, manifest_size(manifest.pieces.len()) + length)
+                    } else {
+                        (piece_channel, length)
+                    }
+                })
+                .collect();
+            return Some((streams, blob));
+        }
+        if let Some(header) = read_payload_header(&data) {
+            debug!(
+                "Found a payload header in the {} channel: {:?}",
+                channel, header
+            );
+            let length = header.size() + header.length as usize;
+            return Some((vec![(*channel, length)], data));
+        }
+    }
+    None
+}
+
+// Look through all channels for a payload header, without returning the body.
+pub fn detect_payload(gif: &GIF, key: Option<&ChunkKey>) -> Option<PayloadLocation> {
+    let (streams, blob) = locate_payload(gif, key)?;
+    let header = read_payload_header(&blob)?;
+    let channels = streams.iter().map(|stream| stream.0).collect();
+    let manifest = read_manifest_extension(gif)
+        .filter(|manifest| Sha256::digest(&blob)[..] == manifest.digest);
+    Some(PayloadLocation {
+        channels,
+        header,
+        manifest,
+    })
+}
+
+// Return the payload body, trimmed to the length recorded in its header.
+pub fn extract_payload(gif: &GIF, key: Option<&ChunkKey>) -> Option<(PayloadHeader, Vec<u8>)> {
+    let (_, blob) = locate_payload(gif, key)?;
+    let header = read_payload_header(&blob)?;
+    let end = header.size() + header.length as usize;
+    if blob.len() < end {
+        return None;
+    }
+    Some((header, blob[header.size()..end].to_vec()))
+}
+
+// extract_payload for a payload that may be damaged, with the ranges of the
+// body that were lost and how many chunks. None when the header itself is.
+pub fn extract_damaged_payload(
+    gif: &GIF,
+    key: Option<&ChunkKey>,
+    placeholder: u8,
+) -> Option<(PayloadHeader, Vec<u8>, Damage, usize)> {
+    let (blob, damage, lost_chunks) = locate_damaged_payload(gif, key, placeholder)?;
+    let header = read_payload_header(&blob)?;
+    let start = header.size();
+    if !clip_damage(&damage, 0..start, 0).is_empty() {
+        return None;
+    }
+    let end = start + header.length as usize;
+    let mut body = blob.get(start..)?.to_vec();
+    let mut damage = clip_damage(&damage, start..end, 0);
+    let missing: usize = clip_damage(&damage, 0..body.len(), 0)
+        .iter()
+        .map(|range| range.len())
+        .sum();
+    if !within_damage(end - start, body.len() - missing) {
+        return None;
+    }
+    if blob.len() < end {
+        damage.push(body.len()..end - start);
+    }
+    body.resize(end - start, placeholder);
+
+    Some((header, body, merge_damage(damage), lost_chunks))
+}
+
+// The layout key of the encrypted payload whose stream starts with `data`,
+// drawn from its container. None when `data` starts no such stream.
+fn stream_layout_key(data: &[u8]) -> Option<ChunkKey> {
+    let data = match read_manifest(data) {
+        Some(manifest) => data.get(manifest_size(manifest.pieces.len())..)?,
+        None => data,
+    };
+    let header = read_payload_header(data)?;
+    if !header.is_encrypted() {
+        return None;
+    }
+    let start = match header.is_padded_outside() {
+        true => header.size() + PADDING_LENGTH_SIZE,
+        false => header.size(),
+    };
+    data.get(start..start + LAYOUT_SEED_SIZE)
+        .map(derive_layout_key)
+}
+
+// Keys to look for a payload with, in order: the layouts of the encrypted
+// payloads whose streams start in some chunk, those the passphrase finds,
+// then the plain layout. A
+// chunk only starts a stream where the layout its key gives puts the first
+// chunk, so any other chunk that happens to read as a header costs no search.
+fn search_keys<'a>(
+    gif: &'a GIF,
+    passphrase: Option<&'a str>,
+) -> impl Iterator<Item = Option<ChunkKey>> + 'a {
+    let mut starts = Vec::new();
+    let mut layout_keys = Vec::new();
+    for kind in all_channels() {
+        let channel = lookup_channel(kind);
+        match channel.chunk_size() {
+            Some(_) => {
+                let chunks = channel.chunks(gif);
+                layout_keys.extend(chunks.iter().enumerate().filter_map(|(position, chunk)| {
+                    let key = stream_layout_key(chunk)?;
+                    let length = expected_stream_length(chunk, kind)?;
+                    (fits_in_chunks(&chunks, length)
+                        && [
+                            chunk_count(length, &key, kind),
+                            legacy_chunk_count(length, &key, kind),
+                        ]
+                        .iter()
+                        .any(|&count| first_chunk_position(count, &key, kind) == position))
+                    .then_some(Some(key))
+                }));
+                starts.extend(chunks);
+            }
+            None => {
+                let data = channel.extract(gif);
+                layout_keys.extend(data.as_deref().and_then(stream_layout_key).map(Some));
+                starts.extend(data);
+            }
+        }
+    }
+    // Stretching the passphrase is slow, so it waits until the layouts have
+    // turned up nothing
+    let found = layout_keys.clone();
+    let passphrase_keys = iter::once_with(move || match passphrase {
+        Some(passphrase) => passphrase_keys(gif, &starts, &found, passphrase),
+        None => Vec::new(),
+    })
+    .flatten();
+    layout_keys
+        .into_iter()
+        .chain(passphrase_keys)
+        .chain(iter::once(None))
+}
+
+// Keys only the passphrase finds: those of the stealth payloads it unmasks
+// some chunk's header for, a stealth stream being its masked header and then
+// its container, and those of the payloads in channels it whitens, which
+// read as noise without it. Payloads with a manifest may have their header
+// in one channel and whitened pieces in another, so the layouts found
+// already are tried whitened as well.
+fn passphrase_keys(
+    gif: &GIF,
+    starts: &[Vec<u8>],
+    found: &[Option<ChunkKey>],
+    passphrase: &str,
+) -> Vec<Option<ChunkKey>> {
+    let stealth = match StealthKey::new(passphrase) {
+        Ok(stealth) => stealth,
+        Err(_) => return Vec::new(),
+    };
+    let whitening = stealth.whitened(&derive_chunk_key(""));
+    let whitened: Vec<Vec<u8>> = all_channels()
+        .into_iter()
+        .map(lookup_channel)
+        .filter(|channel| channel.chunk_size().is_none())
+        .filter_map(|channel| {
+            let data = channel.extract_keyed(gif, Some(&whitening))?;
+            (Some(&data) != channel.extract(gif).as_ref()).then_some(data)
+        })
+        .collect();
+    let stealth_key = |data: &[u8]| {
+        let key = stealth.chunk_key(data.get(PAYLOAD_HEADER_SIZE..)?);
+        starts_masked(data, &key).then_some(key)
+    };
+
+    let mut keys: Vec<ChunkKey> = starts.iter().filter_map(|data| stealth_key(data)).collect();
+    keys.extend(
+        whitened
+            .iter()
+            .filter_map(|data| stream_layout_key(data).or_else(|| stealth_key(data)))
+            .map(|key| stealth.whitened(&key)),
+    );
+    keys.extend(found.iter().flatten().map(|key| stealth.whitened(key)));
+    keys.into_iter().map(Some).collect()
+}
+
+// The key to embed a passphrase payload with: `key`, whitened with the
+// passphrase when one of the channels mixes in a stream, so only the
+// passphrase tells its bits from noise. The whitening is the one
+// passphrase's, so such a payload can't share its container with a decoy.
+pub fn whitened_key(
+    key: ChunkKey,
+    passphrase: &str,
+    decoy: bool,
+    channels: &[ChannelKind],
+) -> Result<ChunkKey, Error> {
+    if !channels.contains(&ChannelKind::Delay) {
+        return Ok(key);
+    }
+    if decoy {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The delay channel is whitened with one passphrase, so it can't carry a decoy.",
+        ));
+    }
+    Ok(StealthKey::new(passphrase)?.whitened(&key))
+}
+
+pub fn find_payload(gif: &GIF, passphrase: Option<&str>) -> Option<PayloadLocation> {
+    search_keys(gif, passphrase).find_map(|key| detect_payload(gif, key.as_ref()))
+}
+
+// Body of a version 4 payload: the expiry time, the metadata record, then
+// the file. The expiry is in seconds since the Unix epoch, 0 for never:
+//
+//   expires (8, little endian) | metadata record | file
+//
+// Only an encrypted body is authenticated, so only its expiry can be relied on.
+const EXPIRES_SIZE: usize = 8;
+
+pub fn write_payload_body(payload: &Payload) -> Vec<u8> {
+    let mut body = payload.metadata.expires.to_le_bytes().to_vec();
+    body.extend_from_slice(&write_metadata(&payload.metadata));
+    body.extend_from_slice(&payload.data);
+    body
+}
+
+// The metadata of a body of version 2 or later, and where its file starts
+fn read_body_metadata(version: u8, body: &[u8]) -> Result<(PayloadMetadata, usize), Error> {
+    let (expires, record) = match version {
+        0..=3 => (0, body),
+        _ => match body.split_first_chunk::<EXPIRES_SIZE>() {
+            Some((expires, record)) => (i64::from_le_bytes(*expires), record),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Payload metadata is truncated.",
+                ))
+            }
+        },
+    };
+    let (mut metadata, data) = read_metadata(record)?;
+    metadata.expires = expires;
+    Ok((metadata, body.len() - data.len()))
+}
+
+// Strip the payload found with this passphrase (or without one) from every
+// channel it was written to. Returns whether there was one.
+pub fn remove_payload(gif: &mut GIF, passphrase: Option<&str>) -> Result<bool, Error> {
+    let found = search_keys(gif, passphrase).find_map(|key| locate_payload(gif, key.as_ref()));
+
+    let streams = match found {
+        Some((streams, _)) => streams,
+        None => return Ok(false),
+    };
+    clear_streams(gif, &streams)?;
+    Ok(true)
+}
+
+fn clear_streams(gif: &mut GIF, streams: &[Stream]) -> Result<(), Error> {
+    for &(channel, length) in streams {
+        lookup_channel(channel).clear(gif, length)?;
+    }
+    clear_manifest_extension(gif);
+    Ok(())
+}
+
+// Seal the payload `old` opens under `new` instead, in the channels it was
+// found in: stealth if it was, padded if it was, described by a
+// manifest extension if it was. Nothing else in the carrier changes. A decoy
+// sharing its container can't be told apart from the container's slack, so
+// it is only kept when `decoy` opens it.
+pub fn rekey_payload(
+    gif: &mut GIF,
+    old: &str,
+    new: &str,
+    decoy: Option<&str>,
+) -> Result<(), Error> {
+    let (key, (streams, blob)) = search_keys(gif, Some(old))
+        .find_map(|key| locate_payload(gif, key.as_ref()).map(|found| (key, found)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No payload found."))?;
+    let header = read_payload_header(&blob).ok_or_else(damaged_payload)?;
+    check_version(&header)?;
+    if header.is_for_recipients() || !header.is_encrypted() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Payload isn't encrypted with a passphrase, embed it again with --passphrase.",
+        ));
+    }
+    let body = blob
+        .get(header.size()..header.size() + header.length as usize)
+        .ok_or_else(damaged_payload)?;
+    let padded_outside = header.is_padded_outside();
+    let container = match padded_outside {
+        true => unpad_body(body)?,
+        false => body.to_vec(),
+    };
+    let opened = open_payload(&container, old)?;
+    let decoy_body = match decoy {
+        Some(decoy) => Some(open_payload(&container, decoy).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "The payload's container holds no decoy for that passphrase.",
+            )
+        })?),
+        None => None,
+    };
+
+    // Padding from before it went inside the encryption moves there: the
+    // decoy takes only its format, the payload enough to seal to the length
+    // the body was padded to
+    let (opened, decoy_body) = match padded_outside {
+        true => {
+            let decoy_body =
+                decoy_body.map(|decoy| pad_body(&decoy, PADDING_LENGTH_SIZE + decoy.len()));
+            let stored_length = |length| {
+                let mut lengths = vec![length];
+                lengths.extend(decoy_body.as_ref().map(Vec::len));
+                sealed_length(&lengths).unwrap_or(length)
+            };
+            let length = padded_length(opened.len(), body.len(), None, stored_length)
+                .expect("padding with no room to keep to has a length");
+            (pad_body(&opened, length), decoy_body)
+        }
+        false => (opened, decoy_body),
+    };
+    let mut payloads = vec![(new, opened.as_slice())];
+    if let (Some(decoy), Some(decoy_body)) = (decoy, &decoy_body) {
+        payloads.push((decoy, decoy_body.as_slice()));
+    }
+    let sealed = seal_payloads(&payloads)?;
+
+    let stealth = key.as_ref().is_some_and(|key| {
+        read_raw_channel(gif, streams[0].0, Some(key), Some(streams[0].1))
+            .is_some_and(|data| starts_masked(&data, key))
+    });
+    if stealth && decoy.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A stealth payload has no decoy to keep.",
+        ));
+    }
+    let channels: Vec<ChannelKind> = streams.iter().map(|stream| stream.0).collect();
+    let new_key = match stealth {
+        true => StealthKey::new(new)?.chunk_key(&sealed),
+        false => derive_layout_key(&sealed),
+    };
+    let new_key = whitened_key(new_key, new, decoy.is_some(), &channels)?;
+    let options = ChannelOptions {
+        manifest: read_manifest_extension(gif).is_some(),
+        stealth,
+        ..ChannelOptions::default()
+    };
+    clear_streams(gif, &streams)?;
+    embed_payload(
+        gif,
+        &sealed,
+        header.flags,
+        header.digest.as_ref(),
+        &channels,
+        Some(&new_key),
+        &options,
+    )?;
+    Ok(())
+}
+
+fn damaged_payload() -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Payload is damaged.")
+}
+
+#[cfg(not(feature = "compression"))]
+fn missing_compression() -> Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Compressed payloads need GifSauce built with the compression feature.",
+    )
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn deflate_body(_body: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(missing_compression())
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn inflate_body(_data: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(missing_compression())
+}
+
+#[cfg(not(feature = "age"))]
+fn missing_age() -> Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Public key encryption needs GifSauce built with the age feature.",
+    )
+}
+
+#[cfg(not(feature = "age"))]
+pub fn encrypt_to_recipients(_body: &[u8], _recipients: &[String]) -> Result<Vec<u8>, Error> {
+    Err(missing_age())
+}
+
+#[cfg(not(feature = "age"))]
+pub fn decrypt_with_identities(_data: &[u8], _identities: &str) -> Result<Vec<u8>, Error> {
+    Err(missing_age())
+}
+
+// Extract and, when needed, decrypt and inflate the payload. Ok(None) means
+// there is none.
+pub fn read_payload(gif: &GIF, passphrase: Option<&str>) -> Result<Option<Payload>, Error> {
+    read_payload_with_identities(gif, passphrase, None)
+}
+
+// read_payload for payloads that may be encrypted to age public keys,
+// `identities` being the text of an identity file
+pub fn read_payload_with_identities(
+    gif: &GIF,
+    passphrase: Option<&str>,
+    identities: Option<&str>,
+) -> Result<Option<Payload>, Error> {
+    let found = search_keys(gif, passphrase).find_map(|key| extract_payload(gif, key.as_ref()));
+    if let Some((ref header, _)) = found {
+        check_version(header)?;
+    }
+    let found = match found {
+        Some((header, body)) if header.is_padded_outside() => Some((header, unpad_body(&body)?)),
+        found => found,
+    };
+    let found = match found {
+        Some((header, body)) if header.has_checksums() => match check_body(&body, &[], 0)? {
+            (body, failed) if failed.is_empty() => Some((header, body)),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Payload is damaged, extract --partial recovers what is left of it.",
+                ))
+            }
+        },
+        found => found,
+    };
+
+    let (header, body) = match found {
+        Some((header, body)) if header.is_encrypted() => match passphrase {
+            Some(passphrase) => (header, open_payload(&body, passphrase)?),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Payload is encrypted, use --passphrase.",
+                ))
+            }
+        },
+        Some((header, body)) if header.is_for_recipients() => match identities {
+            Some(identities) => (header, decrypt_with_identities(&body, identities)?),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Payload is encrypted to public keys, use --identity.",
+                ))
+            }
+        },
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let body = match header.is_padded() && !header.is_padded_outside() {
+        true => unpad_body(&body)?,
+        false => body,
+    };
+    let body = if header.is_compressed() {
+        inflate_body(&body)?
+    } else {
+        body
+    };
+
+    decode_body(header.version, body).map(Some)
+}
+
+// Payloads written by a newer GifSauce may mean something else by their
+// flags, so they are refused rather than misread
+fn check_version(header: &PayloadHeader) -> Result<(), Error> {
+    if header.version > PAYLOAD_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Payload format version {} is newer than this GifSauce reads (up to {}), upgrade to extract it.",
+                header.version, PAYLOAD_VERSION
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// The file in a body of `version`, decrypted, inflated and unpadded
+fn decode_body(version: u8, body: Vec<u8>) -> Result<Payload, Error> {
+    match version {
+        // The bare file
+        0..=1 => Ok(bare_payload(body)),
+        _ => {
+            let (metadata, start) = read_body_metadata(version, &body)?;
+            Ok(Payload {
+                metadata,
+                data: body[start..].to_vec(),
+            })
+        }
+    }
+}
+
+fn bare_payload(data: Vec<u8>) -> Payload {
+    Payload {
+        metadata: PayloadMetadata {
+            size: data.len() as u64,
+            ..Default::default()
+        },
+        data,
+    }
+}
+
+// A payload from before the payload header existed: the plain text
+// extensions run together, less the zeros the last one was padded with.
+// Nothing marks such a payload, so any plain text in the carrier is taken
+// for one.
+pub fn read_legacy_payload(gif: &GIF) -> Option<Payload> {
+    let mut data = lookup_channel(ChannelKind::PlainText).extract(gif)?;
+    let length = data.iter().rposition(|&byte| byte != 0)? + 1;
+    data.truncate(length);
+    Some(bare_payload(data))
+}
+
+// read_payload_with_identities for a payload that may be damaged: what is
+// left of it, the lost bytes being `placeholder`. Only payloads stored as
+// they are can be read in part. Encryption and compression spread damage
+// over everything after it.
+pub fn read_partial_payload(
+    gif: &GIF,
+    passphrase: Option<&str>,
+    identities: Option<&str>,
+    placeholder: u8,
+) -> Result<Option<PartialPayload>, Error> {
+    let intact = read_payload_with_identities(gif, passphrase, identities);
+    if let Ok(Some(payload)) = intact {
+        return Ok(Some(PartialPayload {
+            payload,
+            lost: Vec::new(),
+            lost_chunks: 0,
+        }));
+    }
+    let found = search_keys(gif, passphrase)
+        .find_map(|key| extract_damaged_payload(gif, key.as_ref(), placeholder));
+    let (header, body, damage, lost_chunks) = match found {
+        Some(found) if !found.2.is_empty() || found.0.has_checksums() => found,
+        _ => return intact.map(|_| None),
+    };
+    check_version(&header)?;
+
+    let missing = match lost_chunks {
+        0 => String::new(),
+        count => format!(", {} chunk(s) of it missing,", count),
+    };
+    let unrecoverable = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The payload is damaged{} and {}, none of it can be recovered.",
+                missing, what
+            ),
+        )
+    };
+    if header.is_encrypted() || header.is_for_recipients() {
+        return Err(unrecoverable("encrypted"));
+    }
+    if header.is_compressed() {
+        return Err(unrecoverable("compressed"));
+    }
+    let (body, damage) = if header.is_padded() {
+        let length = match body.get(..PADDING_LENGTH_SIZE) {
+            Some(length) if clip_damage(&damage, 0..PADDING_LENGTH_SIZE, 0).is_empty() => {
+                u32::from_le_bytes(length.try_into().unwrap()) as usize
+            }
+            _ => return Err(unrecoverable("its length is lost")),
+        };
+        let window = PADDING_LENGTH_SIZE..PADDING_LENGTH_SIZE + length;
+        match body.get(window.clone()) {
+            Some(unpadded) => (unpadded.to_vec(), clip_damage(&damage, window, 0)),
+            None => return Err(unrecoverable("its length is lost")),
+        }
+    } else {
+        (body, damage)
+    };
+    let (body, damage) = if header.has_checksums() {
+        check_body(&body, &damage, placeholder)
+            .map_err(|_| unrecoverable("its checksums are lost"))?
+    } else {
+        (body, damage)
+    };
+
+    // Version 1 bodies are the bare file, and so is a body whose metadata
+    // record was lost
+    let metadata = match header.version {
+        0..=1 => None,
+        version => read_body_metadata(version, &body)
+            .ok()
+            .filter(|&(_, start)| clip_damage(&damage, 0..start, 0).is_empty()),
+    };
+    let (payload, lost) = match metadata {
+        Some((metadata, start)) => (
+            Payload {
+                metadata,
+                data: body[start..].to_vec(),
+            },
+            clip_damage(&damage, start..body.len(), 0),
+        ),
+        None => {
+            if header.version >= 2 {
+                warn!("The payload's name and type were lost, keeping it as it is");
+            }
+            (bare_payload(body), damage)
+        }
+    };
+    Ok(Some(PartialPayload {
+        payload,
+        lost,
+        lost_chunks,
+    }))
+}
@@ -0,0 +1,266 @@
+use std::io::{self, Error};
+
+use crate::gif::{ImageDescriptor, PlainTextExtension, GIF};
+
+// Plain text extensions are drawn as frames of their own, in the place they
+// had among the frames. Characters are stretched from a 5x7 bitmap font to
+// the character cell, with a column and a row of spacing. Characters the font
+// lacks are drawn as spaces.
+
+const FONT_WIDTH: usize = 5;
+const FONT_HEIGHT: usize = 7;
+
+// Rows of each printable ASCII character from the space on, leftmost pixel in
+// the highest bit
+#[rustfmt::skip]
+const FONT: [[u8; FONT_HEIGHT]; 95] = [
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000], // space
+    [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100], // !
+    [0b01010, 0b01010, 0b01010, 0b00000, 0b00000, 0b00000, 0b00000], // "
+    [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010], // #
+    [0b00100, 0b01111, 0b10100, 0b01110, 0b00101, 0b11110, 0b00100], // $
+    [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011], // %
+    [0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101], // &
+    [0b00100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000], // '
+    [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010], // (
+    [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000], // )
+    [0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000], // *
+    [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000], // +
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000], // ,
+    [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000], // -
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100], // .
+    [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000], // /
+    [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110], // 0
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // 1
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111], // 2
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110], // 3
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010], // 4
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110], // 5
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110], // 6
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000], // 7
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110], // 8
+    [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100], // 9
+    [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000], // :
+    [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b00100, 0b01000], // ;
+    [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010], // <
+    [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000], // =
+    [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000], // >
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100], // ?
+    [0b01110, 0b10001, 0b00001, 0b01101, 0b10101, 0b10101, 0b01110], // @
+    [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001], // A
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110], // B
+    [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110], // C
+    [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100], // D
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111], // E
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000], // F
+    [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111], // G
+    [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001], // H
+    [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // I
+    [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100], // J
+    [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001], // K
+    [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111], // L
+    [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001], // M
+    [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001], // N
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110], // O
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000], // P
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101], // Q
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001], // R
+    [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110], // S
+    [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100], // T
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110], // U
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100], // V
+    [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010], // W
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001], // X
+    [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100], // Y
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111], // Z
+    [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110], // [
+    [0b00000, 0b10000, 0b01000, 0b00100, 0b00010, 0b00001, 0b00000], // \
+    [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110], // ]
+    [0b00100, 0b01010, 0b10001, 0b00000, 0b00000, 0b00000, 0b00000], // ^
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111], // _
+    [0b01000, 0b00100, 0b00010, 0b00000, 0b00000, 0b00000, 0b00000], // `
+    [0b00000, 0b00000, 0b01110, 0b00001, 0b01111, 0b10001, 0b01111], // a
+    [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b11110], // b
+    [0b00000, 0b00000, 0b01110, 0b10000, 0b10000, 0b10001, 0b01110], // c
+    [0b00001, 0b00001, 0b01101, 0b10011, 0b10001, 0b10001, 0b01111], // d
+    [0b00000, 0b00000, 0b01110, 0b10001, 0b11111, 0b10000, 0b01110], // e
+    [0b00110, 0b01001, 0b01000, 0b11100, 0b01000, 0b01000, 0b01000], // f
+    [0b00000, 0b01111, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110], // g
+    [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001], // h
+    [0b00100, 0b00000, 0b01100, 0b00100, 0b00100, 0b00100, 0b01110], // i
+    [0b00010, 0b00000, 0b00110, 0b00010, 0b00010, 0b10010, 0b01100], // j
+    [0b10000, 0b10000, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010], // k
+    [0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // l
+    [0b00000, 0b00000, 0b11010, 0b10101, 0b10101, 0b10001, 0b10001], // m
+    [0b00000, 0b00000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001], // n
+    [0b00000, 0b00000, 0b01110, 0b10001, 0b10001, 0b10001, 0b01110], // o
+    [0b00000, 0b00000, 0b11110, 0b10001, 0b11110, 0b10000, 0b10000], // p
+    [0b00000, 0b00000, 0b01101, 0b10011, 0b01111, 0b00001, 0b00001], // q
+    [0b00000, 0b00000, 0b10110, 0b11001, 0b10000, 0b10000, 0b10000], // r
+    [0b00000, 0b00000, 0b01110, 0b10000, 0b01110, 0b00001, 0b11110], // s
+    [0b01000, 0b01000, 0b11100, 0b01000, 0b01000, 0b01001, 0b00110], // t
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b10011, 0b01101], // u
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100], // v
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b10101, 0b10101, 0b01010], // w
+    [0b00000, 0b00000, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001], // x
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110], // y
+    [0b00000, 0b00000, 0b11111, 0b00010, 0b00100, 0b01000, 0b11111], // z
+    [0b00010, 0b00100, 0b00100, 0b01000, 0b00100, 0b00100, 0b00010], // {
+    [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100], // |
+    [0b01000, 0b00100, 0b00100, 0b00010, 0b00100, 0b00100, 0b01000], // }
+    [0b00000, 0b00000, 0b01000, 0b10101, 0b00010, 0b00000, 0b00000], // ~
+];
+
+// Whether the pixel at (x, y) of a cell shows a character
+pub fn glyph_pixel(
+    character: u8,
+    x: usize,
+    y: usize,
+    cell_width: usize,
+    cell_height: usize,
+) -> bool {
+    let glyph = match character {
+        0x20..=0x7E => &FONT[(character - 0x20) as usize],
+        _ => return false,
+    };
+    let column = x * (FONT_WIDTH + 1) / cell_width;
+    let row = y * (FONT_HEIGHT + 1) / cell_height;
+    column < FONT_WIDTH && row < FONT_HEIGHT && glyph[row] & (0b10000 >> column) != 0
+}
+
+// Most pixels drawn for the text grids of one GIF, as many as a 4096x4096
+// screen has
+const MAX_DRAWN_PIXELS: usize = 1 << 24;
+
+// The text grid with its characters, as a frame drawn with the global color
+// table. Cells run left to right, then top to bottom, and what the text
+// doesn't fill shows the background color. The grid is cut off at the edges
+// of the logical screen it sits on.
+fn text_frame(
+    plain_text: &PlainTextExtension,
+    (screen_width, screen_height): (usize, usize),
+    most_pixels: usize,
+    minimum_code_size: u8,
+) -> Result<Option<ImageDescriptor>, Error> {
+    let left = plain_text.text_grid_left_position as usize;
+    let top = plain_text.text_grid_top_position as usize;
+    let grid_width = plain_text.text_grid_width as usize;
+    let grid_height = plain_text.text_grid_height as usize;
+    let cell_width = plain_text.character_cell_width as usize;
+    let cell_height = plain_text.character_cell_height as usize;
+    if grid_width == 0 || grid_height == 0 {
+        return Ok(None);
+    }
+    if left >= screen_width || top >= screen_height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "A {}x{} text grid at ({}, {}) lies outside the {}x{} logical screen.",
+                grid_width, grid_height, left, top, screen_width, screen_height
+            ),
+        ));
+    }
+    let width = grid_width.min(screen_width - left);
+    let height = grid_height.min(screen_height - top);
+    if width * height > most_pixels {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Drawing a {}x{} text grid goes past the {} pixels drawn for a GIF.",
+                width, height, MAX_DRAWN_PIXELS
+            ),
+        ));
+    }
+
+    // Cells cut off by the edge of the grid or the screen stay empty
+    let columns = grid_width.checked_div(cell_width).unwrap_or(0);
+    let rows = grid_height.checked_div(cell_height).unwrap_or(0);
+    let mut image_data = vec![plain_text.text_background_color_index; width * height];
+    for (cell, &character) in plain_text
+        .plain_text_data
+        .iter()
+        .enumerate()
+        .take(columns * rows)
+    {
+        let (column, row) = (cell % columns, cell / columns);
+        for y in (0..cell_height).take_while(|y| row * cell_height + y < height) {
+            for x in (0..cell_width).take_while(|x| column * cell_width + x < width) {
+                if glyph_pixel(character, x, y, cell_width, cell_height) {
+                    let position = (row * cell_height + y) * width + column * cell_width + x;
+                    image_data[position] = plain_text.text_foreground_color_index;
+                }
+            }
+        }
+    }
+
+    Ok(Some(ImageDescriptor {
+        left: plain_text.text_grid_left_position,
+        top: plain_text.text_grid_top_position,
+        width: width as u16,
+        height: height as u16,
+        packed_field: 0,
+        graphics_control_extension: None,
+        local_color_table: None,
+        lzw_minimum_code_size: minimum_code_size,
+        image_data,
+        compressed_range: None,
+    }))
+}
+
+// Replace the plain text extensions with frames showing their text. Returns
+// how many were drawn; empty text grids are dropped. Grids off the logical
+// screen, or more than there is room to draw, are refused.
+pub fn render_plain_text(gif: &mut GIF) -> Result<usize, Error> {
+    if gif.plain_text_extensions.is_empty() {
+        return Ok(0);
+    }
+    let colors = match gif.global_color_table {
+        Some(ref table) => table.colors.len(),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Plain text is drawn with the global color table, which the file lacks.",
+            ))
+        }
+    };
+
+    let screen = (
+        gif.logical_screen_descriptor.width as usize,
+        gif.logical_screen_descriptor.height as usize,
+    );
+    // Draw every grid before touching the GIF, so a refused one leaves it as
+    // it was
+    let mut text_frames = Vec::with_capacity(gif.plain_text_extensions.len());
+    let mut drawn = 0;
+    for plain_text in &gif.plain_text_extensions {
+        // Enough bits for the table and both colors, whichever is larger
+        let largest = colors
+            .saturating_sub(1)
+            .max(plain_text.text_foreground_color_index as usize)
+            .max(plain_text.text_background_color_index as usize);
+        let minimum_code_size = (usize::BITS - largest.leading_zeros()).max(2) as u8;
+        let frame = text_frame(
+            plain_text,
+            screen,
+            MAX_DRAWN_PIXELS - drawn,
+            minimum_code_size,
+        )?;
+        drawn += frame.as_ref().map_or(0, |frame| frame.image_data.len());
+        text_frames.push(frame);
+    }
+
+    gif.plain_text_extensions.clear();
+    let frames = std::mem::take(&mut gif.image_descriptors);
+    let mut frames = frames.into_iter();
+    let mut rendered = 0;
+    for frame in text_frames {
+        if let Some(frame) = frame {
+            gif.image_descriptors.push(frame);
+            rendered += 1;
+        }
+        // Each plain text comes before the frame with its index
+        gif.image_descriptors.extend(frames.next());
+    }
+    gif.image_descriptors.extend(frames);
+    Ok(rendered)
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Error};
+
+// A GIF/ZIP polyglot is a GIF with a ZIP archive after its trailer. GIF
+// decoders stop at the trailer and ZIP readers start from the end of the
+// file, where the central directory is, so each sees only its own part. The
+// offsets in the archive count from the start of the file, so they are moved
+// past the GIF.
+
+const LOCAL_FILE_HEADER: &[u8] = b"PK\x03\x04";
+const CENTRAL_DIRECTORY_ENTRY: &[u8] = b"PK\x01\x02";
+const END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+
+// Fixed part of the central directory entries and of the end record
+const ENTRY_SIZE: usize = 46;
+const END_SIZE: usize = 22;
+
+// A ZIP archive found at the end of some bytes
+pub struct ZipArchive {
+    pub entries: usize,
+    pub zip64: bool,           // Offsets live in ZIP64 records, which aren't walked
+    pub directory: usize,      // Where the central directory starts
+    pub end: usize,            // Where the end of central directory record starts
+    pub offsets: Vec<usize>,   // Offset field of each entry in the directory
+    pub directory_offset: u32, // Offset the end record gives the directory
+}
+
+fn read_u16(bytes: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(position..position + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(position..position + 4)?.try_into().ok()?,
+    ))
+}
+
+// The last end of central directory record whose comment runs to the end
+fn find_end(bytes: &[u8]) -> Option<usize> {
+    let lowest = bytes.len().saturating_sub(END_SIZE + u16::MAX as usize);
+    (lowest..=bytes.len().checked_sub(END_SIZE)?)
+        .rev()
+        .find(|&position| {
+            bytes[position..].starts_with(END_OF_CENTRAL_DIRECTORY)
+                && read_u16(bytes, position + 20)
+                    .is_some_and(|comment| position + END_SIZE + comment as usize == bytes.len())
+        })
+}
+
+// The ZIP archive `bytes` end with, its central directory walked. None when
+// there is none or its directory is damaged.
+pub fn find_zip(bytes: &[u8]) -> Option<ZipArchive> {
+    let end = find_end(bytes)?;
+    let entries = read_u16(bytes, end + 10)?;
+    let size = read_u32(bytes, end + 12)?;
+    let directory_offset = read_u32(bytes, end + 16)?;
+    let mut archive = ZipArchive {
+        entries: entries as usize,
+        zip64: false,
+        directory: end.checked_sub(size as usize)?,
+        end,
+        offsets: Vec::with_capacity(entries as usize),
+        directory_offset,
+    };
+    if entries == u16::MAX || size == u32::MAX || directory_offset == u32::MAX {
+        archive.zip64 = true;
+        return Some(archive);
+    }
+
+    let mut position = archive.directory;
+    for _ in 0..entries {
+        if !bytes[position..end].starts_with(CENTRAL_DIRECTORY_ENTRY) {
+            return None;
+        }
+        let name = read_u16(bytes, position + 28)? as usize;
+        let extra = read_u16(bytes, position + 30)? as usize;
+        let comment = read_u16(bytes, position + 32)? as usize;
+        if read_u32(bytes, position + 42)? == u32::MAX {
+            archive.zip64 = true;
+        }
+        archive.offsets.push(position + 42);
+        position += ENTRY_SIZE + name + extra + comment;
+        if position > end {
+            return None;
+        }
+    }
+    Some(archive)
+}
+
+// Append `zip` to the bytes of a GIF, its offsets moved to count from the
+// start of the GIF. Archives that already sit after other data, as
+// self-extracting ones do, keep it in front of their entries.
+pub fn append_zip(mut gif: Vec<u8>, zip: &[u8]) -> Result<Vec<u8>, Error> {
+    let archive = find_zip(zip)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not a ZIP archive."))?;
+    if archive.zip64 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "ZIP64 archives can't be made into polyglots.",
+        ));
+    }
+    // Offsets counted from the start of the archive's data rather than of
+    // the file are off by whatever comes before it
+    let prefix = (archive.directory as u64)
+        .checked_sub(archive.directory_offset as u64)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The ZIP archive is damaged."))?;
+    let shift = gif.len() as u64 + prefix;
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The polyglot would be over 4 GiB, which ZIP needs ZIP64 for.",
+        )
+    };
+
+    let start = gif.len();
+    gif.extend_from_slice(zip);
+    let mut move_offset = |field: usize| -> Result<(), Error> {
+        let offset = read_u32(zip, field).unwrap_or(0) as u64 + shift;
+        if offset >= u32::MAX as u64 {
+            return Err(too_large());
+        }
+        gif[start + field..start + field + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+        Ok(())
+    };
+    for &field in &archive.offsets {
+        move_offset(field)?;
+    }
+    move_offset(archive.end + 16)?;
+
+    // Each entry should now point at a local file header
+    for &field in &archive.offsets {
+        let header = read_u32(&gif, start + field).unwrap_or(0) as usize;
+        if !gif[header.min(gif.len())..].starts_with(LOCAL_FILE_HEADER) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The ZIP archive is damaged.",
+            ));
+        }
+    }
+    Ok(gif)
+}
@@ -0,0 +1,269 @@
+use std::env;
+use std::io::{Error, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::frames::composite_frames;
+use crate::gif::{quantize, GIF};
+use crate::output::base64;
+
+// Frames are composited as a browser shows them, then drawn over each other
+// in place. Uncovered pixels are left to the terminal background. Lines for
+// the picture are made before the first frame so the terminal doesn't scroll
+// while playing; for images their number assumes cells at least 12 pixels
+// high.
+
+// The smallest cell height the lines for an image are counted with
+const CELL_HEIGHT: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Blocks, // Half blocks in 24 bit color, two pixels a cell
+}
+
+pub fn parse_protocol(name: &str) -> Option<Protocol> {
+    match name {
+        "kitty" => Some(Protocol::Kitty),
+        "sixel" => Some(Protocol::Sixel),
+        "blocks" => Some(Protocol::Blocks),
+        _ => None,
+    }
+}
+
+// Guess what the terminal can draw from its environment, as asking it would
+// mean reading its replies from a raw mode terminal
+pub fn detect_protocol() -> Protocol {
+    let term = env::var("TERM").unwrap_or_default();
+    let program = env::var("TERM_PROGRAM").unwrap_or_default();
+    if env::var_os("KITTY_WINDOW_ID").is_some()
+        || term == "xterm-kitty"
+        || term == "xterm-ghostty"
+        || program == "WezTerm"
+    {
+        Protocol::Kitty
+    } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") {
+        Protocol::Sixel
+    } else {
+        Protocol::Blocks
+    }
+}
+
+type Canvas = Vec<Option<[u8; 3]>>;
+
+// Scale a canvas down to at most `columns` pixels across, nearest neighbour
+fn fit_width(canvas: &[Option<[u8; 3]>], width: usize, columns: usize) -> (Canvas, usize) {
+    if width <= columns || columns == 0 {
+        return (canvas.to_vec(), width);
+    }
+    let height = canvas.len() / width;
+    let scaled_height = (height * columns / width).max(1);
+    let scaled = (0..scaled_height)
+        .flat_map(|y| {
+            (0..columns).map(move |x| (y * height / scaled_height) * width + x * width / columns)
+        })
+        .map(|position| canvas[position])
+        .collect();
+    (scaled, columns)
+}
+
+fn blocks_frame(canvas: &[Option<[u8; 3]>], width: usize) -> Vec<u8> {
+    let mut output = String::new();
+    let rows: Vec<&[Option<[u8; 3]>]> = canvas.chunks(width.max(1)).collect();
+    for pair in rows.chunks(2) {
+        for x in 0..width {
+            let top = pair[0][x];
+            let bottom = pair.get(1).and_then(|row| row[x]);
+            match (top, bottom) {
+                (Some([r, g, b]), Some([br, bg, bb])) => output.push_str(&format!(
+                    "\x1b[38;2;{};{};{};48;2;{};{};{}m\u{2580}",
+                    r, g, b, br, bg, bb
+                )),
+                (Some([r, g, b]), None) => {
+                    output.push_str(&format!("\x1b[49;38;2;{};{};{}m\u{2580}", r, g, b))
+                }
+                (None, Some([r, g, b])) => {
+                    output.push_str(&format!("\x1b[49;38;2;{};{};{}m\u{2584}", r, g, b))
+                }
+                (None, None) => output.push_str("\x1b[0m "),
+            }
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    output.into_bytes()
+}
+
+// The canvas as RGBA, sent in chunks of base64 as the protocol asks
+fn kitty_frame(canvas: &[Option<[u8; 3]>], width: usize) -> Vec<u8> {
+    let rgba: Vec<u8> = canvas
+        .iter()
+        .flat_map(|pixel| match pixel {
+            Some([r, g, b]) => [*r, *g, *b, 0xFF],
+            None => [0; 4],
+        })
+        .collect();
+    let encoded = base64(&rgba);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    let mut output = Vec::with_capacity(encoded.len() + chunks.len() * 16);
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        // The first chunk describes the image. Reusing its id replaces the
+        // frame shown before, and the cursor stays where it is.
+        if index == 0 {
+            output.extend_from_slice(
+                format!(
+                    "\x1b_Ga=T,i=1,q=2,C=1,f=32,s={},v={},m={};",
+                    width,
+                    canvas.len() / width.max(1),
+                    more
+                )
+                .as_bytes(),
+            );
+        } else {
+            output.extend_from_slice(format!("\x1b_Gm={};", more).as_bytes());
+        }
+        output.extend_from_slice(chunk);
+        output.extend_from_slice(b"\x1b\\");
+    }
+    output
+}
+
+// Bands of six rows, each color drawn across the band in turn, with runs
+// of the same sixel shortened. Zero bits show the background.
+fn sixel_frame(canvas: &[Option<[u8; 3]>], width: usize) -> Vec<u8> {
+    let pixels: Vec<[u8; 4]> = canvas
+        .iter()
+        .map(|pixel| match pixel {
+            Some([r, g, b]) => [*r, *g, *b, 0xFF],
+            None => [0; 4],
+        })
+        .collect();
+    let quantized = quantize(&pixels, 256);
+    let height = canvas.len() / width.max(1);
+
+    let mut output = format!("\x1bP0;0;0q\"1;1;{};{}", width, height);
+    for (index, [r, g, b]) in quantized.color_table.colors.iter().enumerate() {
+        let percent = |component: &u8| *component as u32 * 100 / 255;
+        output.push_str(&format!(
+            "#{};2;{};{};{}",
+            index,
+            percent(r),
+            percent(g),
+            percent(b)
+        ));
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let mut colors: Vec<u8> = rows
+            .clone()
+            .flat_map(|y| {
+                quantized.indices[y * width..(y + 1) * width]
+                    .iter()
+                    .copied()
+            })
+            .filter(|&index| Some(index) != quantized.transparent_index)
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+
+        for color in colors {
+            output.push_str(&format!("#{}", color));
+            let sixels: Vec<u8> = (0..width)
+                .map(|x| {
+                    let bits = rows
+                        .clone()
+                        .enumerate()
+                        .filter(|&(_, y)| quantized.indices[y * width + x] == color)
+                        .fold(0, |bits, (bit, _)| bits | 1 << bit);
+                    63 + bits
+                })
+                .collect();
+            for run in sixels.chunk_by(|a, b| a == b) {
+                match run.len() {
+                    1..=3 => run.iter().for_each(|&sixel| output.push(sixel as char)),
+                    length => output.push_str(&format!("!{}{}", length, run[0] as char)),
+                }
+            }
+            output.push('$'); // Back to the start of the band
+        }
+        output.push('-'); // Next band
+    }
+    output.push_str("\x1b\\");
+    output.into_bytes()
+}
+
+// Play the animation once, or until interrupted with `repeat`. Blocks are
+// fitted to `columns` cells across.
+pub fn preview_gif<W: Write>(
+    gif: &GIF,
+    protocol: Protocol,
+    columns: usize,
+    repeat: bool,
+    mut output: W,
+) -> Result<(), Error> {
+    let width = gif.logical_screen_descriptor.width as usize;
+    let canvases = composite_frames(gif)?;
+    if width == 0 || canvases.is_empty() {
+        return Ok(());
+    }
+
+    let mut lines = 0;
+    let frames: Vec<Vec<u8>> = canvases
+        .iter()
+        .map(|canvas| {
+            let (canvas, width) = match protocol {
+                Protocol::Blocks => fit_width(canvas, width, columns),
+                _ => (canvas.clone(), width),
+            };
+            let height = canvas.len() / width;
+            match protocol {
+                Protocol::Kitty => {
+                    lines = height.div_ceil(CELL_HEIGHT);
+                    kitty_frame(&canvas, width)
+                }
+                Protocol::Sixel => {
+                    lines = height.div_ceil(CELL_HEIGHT);
+                    sixel_frame(&canvas, width)
+                }
+                Protocol::Blocks => {
+                    lines = height.div_ceil(2);
+                    blocks_frame(&canvas, width)
+                }
+            }
+        })
+        .collect();
+    // Browsers play delays under 20ms at 100ms, and so does this
+    let delays: Vec<Duration> = gif
+        .image_descriptors
+        .iter()
+        .map(|frame| {
+            let delay = frame
+                .graphics_control_extension
+                .as_ref()
+                .map_or(0, |gce| gce.delay_time);
+            Duration::from_millis(if delay < 2 { 100 } else { delay as u64 * 10 })
+        })
+        .collect();
+
+    // Make the lines, go back up and remember where they start
+    output.write_all(&b"\n".repeat(lines))?;
+    output.write_all(format!("\x1b[{}A\x1b7", lines).as_bytes())?;
+    loop {
+        for (drawn, delay) in frames.iter().zip(&delays) {
+            output.write_all(b"\x1b8")?;
+            output.write_all(drawn)?;
+            output.flush()?;
+            if frames.len() > 1 {
+                thread::sleep(*delay);
+            }
+        }
+        if !repeat || frames.len() == 1 {
+            break;
+        }
+    }
+    // Leave the cursor under the picture
+    output.write_all(format!("\x1b8\x1b[{}B\n", lines).as_bytes())?;
+    output.flush()
+}
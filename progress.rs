@@ -0,0 +1,107 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::gif::Stage;
+
+// Progress bars on stderr for what takes a while on big carriers: parsing,
+// encoding frames and batch runs. indicatif leaves them out when stderr isn't
+// a terminal, and --quiet and --no-progress turn them off.
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+// The bar last drawn, which log lines are written above
+static ACTIVE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+pub fn disable_progress() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+// stderr for the logger: the bar is taken off while a line is written and
+// drawn again below it
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *ACTIVE.lock().unwrap() {
+            Some(ref bar) => bar.suspend(|| io::stderr().write_all(buf))?,
+            None => io::stderr().write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+fn new_bar(total: usize, template: &str) -> ProgressBar {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(total as u64);
+    if let Ok(style) = ProgressStyle::with_template(template) {
+        bar.set_style(style.progress_chars("=> "));
+    }
+    *ACTIVE.lock().unwrap() = Some(bar.clone());
+    bar
+}
+
+// One bar at a time for the stages of a parse or write, each cleared once
+// its stage is done. Files of a batch run have none, the run has a bar of its
+// own.
+pub struct StageProgress {
+    shown: bool,
+    bar: Mutex<Option<(Stage, ProgressBar)>>,
+}
+
+impl StageProgress {
+    pub fn new(shown: bool) -> StageProgress {
+        StageProgress {
+            shown,
+            bar: Mutex::new(None),
+        }
+    }
+
+    // Takes the reports of the _with_progress functions
+    pub fn update(&self, stage: Stage, done: usize, total: usize) {
+        if !self.shown {
+            return;
+        }
+        let mut current = self.bar.lock().unwrap();
+        if current.as_ref().map(|(current, _)| *current) != Some(stage) {
+            if let Some((_, bar)) = current.take() {
+                bar.finish_and_clear();
+            }
+            let bar = match stage {
+                Stage::Parsing => new_bar(total, "parsing  [{bar:40}] {bytes}/{total_bytes}"),
+                Stage::Encoding => new_bar(total, "encoding [{bar:40}] {pos}/{len} frames"),
+            };
+            *current = Some((stage, bar));
+        }
+        let bar = match *current {
+            Some((_, ref bar)) if !bar.is_finished() => bar,
+            _ => return,
+        };
+        // Frames encoded in parallel may report out of order
+        bar.set_position(bar.position().max(done as u64));
+        if done >= total {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+impl Drop for StageProgress {
+    fn drop(&mut self) {
+        if let Some((_, ref bar)) = *self.bar.lock().unwrap() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+// Bar of a batch run, ticked as each file is done
+pub fn batch_progress(files: usize) -> ProgressBar {
+    new_bar(files, "{msg:7} [{bar:40}] {pos}/{len} files")
+}
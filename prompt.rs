@@ -0,0 +1,146 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Error, Write};
+
+// Passphrases typed at the terminal with echo off, so they show up neither
+// on screen nor in process listings and shell history. The terminal itself
+// is opened, not stdin and stdout, which may be carrying a GIF.
+
+#[cfg(unix)]
+fn open_terminal() -> Result<(File, File), Error> {
+    let terminal = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    Ok((terminal.try_clone()?, terminal))
+}
+
+#[cfg(windows)]
+fn open_terminal() -> Result<(File, File), Error> {
+    let input = OpenOptions::new().read(true).write(true).open("CONIN$")?;
+    let output = OpenOptions::new().write(true).open("CONOUT$")?;
+    Ok((input, output))
+}
+
+// Turns echo off until dropped
+#[cfg(unix)]
+struct EchoOff {
+    fd: libc::c_int,
+    saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl EchoOff {
+    fn new(terminal: &File) -> Result<EchoOff, Error> {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = terminal.as_raw_fd();
+        // Filled in by tcgetattr
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut quiet = saved;
+        quiet.c_lflag &= !libc::ECHO;
+        quiet.c_lflag |= libc::ECHONL;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &quiet) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(EchoOff { fd, saved })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+#[cfg(windows)]
+struct EchoOff {
+    handle: std::os::windows::io::RawHandle,
+    saved: u32,
+}
+
+#[cfg(windows)]
+extern "system" {
+    fn GetConsoleMode(handle: std::os::windows::io::RawHandle, mode: *mut u32) -> i32;
+    fn SetConsoleMode(handle: std::os::windows::io::RawHandle, mode: u32) -> i32;
+}
+
+#[cfg(windows)]
+impl EchoOff {
+    fn new(terminal: &File) -> Result<EchoOff, Error> {
+        use std::os::windows::io::AsRawHandle;
+
+        const ENABLE_ECHO_INPUT: u32 = 0x0004;
+        let handle = terminal.as_raw_handle();
+        let mut saved = 0;
+        if unsafe { GetConsoleMode(handle, &mut saved) } == 0
+            || unsafe { SetConsoleMode(handle, saved & !ENABLE_ECHO_INPUT) } == 0
+        {
+            return Err(Error::last_os_error());
+        }
+        Ok(EchoOff { handle, saved })
+    }
+}
+
+#[cfg(windows)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        unsafe {
+            SetConsoleMode(self.handle, self.saved);
+        }
+    }
+}
+
+fn read_hidden(
+    input: &mut BufReader<File>,
+    output: &mut File,
+    prompt: &str,
+) -> Result<String, Error> {
+    output.write_all(prompt.as_bytes())?;
+    output.flush()?;
+    let mut line = String::new();
+    {
+        let _echo_off = EchoOff::new(input.get_ref())?;
+        input.read_line(&mut line)?;
+    }
+    // Windows doesn't echo the newline on its own
+    #[cfg(windows)]
+    output.write_all(b"\r\n")?;
+
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(line)
+}
+
+// Ask for a passphrase, twice when `confirm` is set so a typo doesn't lock
+// the payload away for good
+pub fn prompt_passphrase(confirm: bool) -> Result<String, Error> {
+    let (input, mut output) = open_terminal().map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("No terminal to ask for the passphrase on ({}).", e),
+        )
+    })?;
+    let mut input = BufReader::new(input);
+
+    let passphrase = read_hidden(&mut input, &mut output, "Passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The passphrase is empty.",
+        ));
+    }
+    if confirm && read_hidden(&mut input, &mut output, "Repeat passphrase: ")? != passphrase {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The passphrases don't match.",
+        ));
+    }
+    Ok(passphrase)
+}
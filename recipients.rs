@@ -0,0 +1,65 @@
+use std::io::{self, BufReader, Error, Read, Write};
+
+use age::x25519;
+use age::{Decryptor, Encryptor, IdentityFile};
+
+// Payloads encrypted to age X25519 public keys instead of a passphrase. The
+// body becomes a binary age file, which the age tool can also decrypt, and
+// any one of the recipients' identities opens it.
+
+fn parse_recipient(recipient: &str) -> Result<x25519::Recipient, Error> {
+    recipient.trim().parse().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not an age public key: {}", recipient, e),
+        )
+    })
+}
+
+pub fn encrypt_to_recipients(body: &[u8], recipients: &[String]) -> Result<Vec<u8>, Error> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| parse_recipient(recipient))
+        .collect::<Result<Vec<_>, _>>()?;
+    let encryptor = Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    let mut output = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut output)?;
+    writer.write_all(body)?;
+    writer.finish()?;
+    Ok(output)
+}
+
+// `identities` is the text of an identity file, as age-keygen writes it:
+// one AGE-SECRET-KEY-1... line per key, with # comments
+pub fn decrypt_with_identities(data: &[u8], identities: &str) -> Result<Vec<u8>, Error> {
+    let identities = IdentityFile::from_buffer(BufReader::new(identities.as_bytes()))?
+        .into_identities()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    if identities.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The identity file holds no keys.",
+        ));
+    }
+
+    let decryptor = Decryptor::new_buffered(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))
+        .map_err(|e| match e {
+            age::DecryptError::NoMatchingKeys => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "None of the identities can decrypt the payload.",
+            ),
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        })?;
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body)?;
+    Ok(body)
+}
@@ -0,0 +1,129 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Error, Write};
+use std::mem;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use crate::channels::ChannelKind;
+use crate::output::write_atomic;
+use crate::{hex, json_string};
+
+// What an embed or extract did, for --report json. Written as one JSON
+// object, whether the operation succeeded or not, so wrappers can check the
+// outcome without scraping the log.
+
+// Warnings logged while a report is being put together. None when no report
+// was asked for.
+static WARNINGS: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+pub fn record_warnings() {
+    *WARNINGS.lock().unwrap() = Some(Vec::new());
+}
+
+pub fn record_warning(message: String) {
+    if let Some(ref mut warnings) = *WARNINGS.lock().unwrap() {
+        warnings.push(message);
+    }
+}
+
+// The warnings since the last call, recording on
+fn take_warnings() -> Vec<String> {
+    WARNINGS
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(mem::take)
+        .unwrap_or_default()
+}
+
+// Where the report goes: stderr, or a file from --report-file
+#[derive(Clone)]
+pub enum ReportTarget {
+    Stderr,
+    File(String),
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub carrier_sha256: Option<[u8; 32]>,
+    pub payload_sha256: Option<[u8; 32]>,
+    pub payload_size: Option<usize>,
+    pub channels: Vec<ChannelKind>,
+    pub channel_choice: Option<String>, // Why --channels auto picked the channel
+    pub chunks: Option<usize>,          // Blocks the payload was written to, embed only
+    pub frames_added: Option<usize>,
+    pub output_size: Option<usize>,
+    pub lost: Vec<Range<usize>>, // Payload bytes extract --partial couldn't recover
+    pub lost_chunks: Option<usize>, // How many chunks those were
+    pub unchanged: bool,         // embed --if-changed found the payload already there
+    pub compression_ratio: Option<f64>, // Compressed size over the original, embed only
+}
+
+pub fn sha256(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
+
+fn hash_json(hash: Option<[u8; 32]>) -> String {
+    match hash {
+        Some(hash) => format!("\"{}\"", hex(&hash)),
+        None => "null".to_string(),
+    }
+}
+
+fn number_json(number: Option<usize>) -> String {
+    number.map_or("null".to_string(), |number| number.to_string())
+}
+
+// The report of `operation` as JSON, with the error it failed with if any
+pub fn format_report(operation: &str, result: Result<&Report, String>) -> String {
+    let warnings: Vec<String> = take_warnings()
+        .iter()
+        .map(|warning| json_string(warning))
+        .collect();
+    let failed = Report::default();
+    let (report, error) = match result {
+        Ok(report) => (report, "null".to_string()),
+        Err(error) => (&failed, json_string(&error)),
+    };
+    let channels: Vec<String> = report
+        .channels
+        .iter()
+        .map(|channel| json_string(&channel.to_string()))
+        .collect();
+    let lost: Vec<String> = report
+        .lost
+        .iter()
+        .map(|range| format!("[{},{}]", range.start, range.end))
+        .collect();
+    format!(
+        "{{\"operation\":{},\"ok\":{},\"error\":{},\"carrier_sha256\":{},\"payload_sha256\":{},\"payload_size\":{},\"channels\":[{}],\"channel_choice\":{},\"chunks\":{},\"frames_added\":{},\"output_size\":{},\"lost\":[{}],\"lost_chunks\":{},\"unchanged\":{},\"compression_ratio\":{},\"warnings\":[{}]}}",
+        json_string(operation),
+        error == "null",
+        error,
+        hash_json(report.carrier_sha256),
+        hash_json(report.payload_sha256),
+        number_json(report.payload_size),
+        channels.join(","),
+        report
+            .channel_choice
+            .as_deref()
+            .map_or("null".to_string(), json_string),
+        number_json(report.chunks),
+        number_json(report.frames_added),
+        number_json(report.output_size),
+        lost.join(","),
+        number_json(report.lost_chunks),
+        report.unchanged,
+        report
+            .compression_ratio
+            .map_or("null".to_string(), |ratio| format!("{:.4}", ratio)),
+        warnings.join(",")
+    )
+}
+
+pub fn write_report(target: &ReportTarget, report: &str) -> Result<(), Error> {
+    match target {
+        ReportTarget::Stderr => writeln!(io::stderr(), "{}", report),
+        ReportTarget::File(path) => write_atomic(path, format!("{}\n", report).as_bytes(), false),
+    }
+}
@@ -0,0 +1,261 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::gif::{closest_color, ColorTable, ImageDescriptor, GIF};
+
+// Application extensions that only carry playback information and are safe
+// to keep. Everything else is treated as a possible hiding place.
+const KNOWN_APPLICATION_EXTENSIONS: [&str; 2] = ["NETSCAPE2.0", "ANIMEXTS1.0"];
+
+#[derive(Debug, Default)]
+pub struct SanitizeReport {
+    pub plain_text_removed: usize,
+    pub comments_removed: usize,
+    pub application_removed: usize,
+    pub trailing_removed: usize, // Bytes after the trailer
+    pub pixels_scrubbed: bool,
+    pub colors_merged: usize, // Dropped from a full color table by scrubbing
+}
+
+// Whether a name matches a pattern where * stands for any run of characters,
+// none included, and ? for any one
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where the last * was and where in the name it started matching
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last * take one more character
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Drop the application extensions whose identifier and authentication code,
+// such as NETSCAPE2.0, match a pattern of `drop` and none of `keep`. Returns
+// how many were dropped.
+pub fn filter_applications(gif: &mut GIF, keep: &[String], drop: &[String]) -> usize {
+    let before = gif.application_extensions.len();
+    gif.application_extensions.retain(|application| {
+        let name = format!(
+            "{}{}",
+            application.identifier, application.authentication_code
+        );
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| wildcard_match(pattern, &name))
+        };
+        matches(keep) || !matches(drop)
+    });
+    before - gif.application_extensions.len()
+}
+
+pub fn sanitize_gif(gif: &mut GIF, scrub_pixels: bool) -> SanitizeReport {
+    let mut report = SanitizeReport {
+        plain_text_removed: gif.plain_text_extensions.len(),
+        comments_removed: gif.comment_extensions.len(),
+        trailing_removed: gif.trailing_data.len(),
+        ..Default::default()
+    };
+
+    gif.plain_text_extensions.clear();
+    gif.comment_extensions.clear();
+    gif.trailing_data.clear();
+
+    let before = gif.application_extensions.len();
+    gif.application_extensions.retain(|application| {
+        let name = format!(
+            "{}{}",
+            application.identifier, application.authentication_code
+        );
+        KNOWN_APPLICATION_EXTENSIONS.contains(&name.as_str())
+    });
+    report.application_removed = before - gif.application_extensions.len();
+
+    if scrub_pixels {
+        report.colors_merged = scrub_pixel_data(gif);
+        report.pixels_scrubbed = true;
+    }
+
+    report
+}
+
+// Clear the low bit of every palette entry and rebuild each color table so
+// the colors its frames draw sit at even indices, with a copy at the odd
+// index after each. Pixels are moved to the even entry of their color, so
+// the low bit of every index is 0 and neither the palette, the choice between
+// duplicate entries nor the lsb channel can carry hidden bits. Frames without
+// any color table just have the low bit cleared. Returns how many colors were
+// merged into their closest neighbour because a table had no room for them.
+// The frames are recompressed when the GIF is reassembled.
+fn scrub_pixel_data(gif: &mut GIF) -> usize {
+    let mut merged = 0;
+
+    if let Some(ref mut global_color_table) = gif.global_color_table {
+        scrub_color_table(global_color_table);
+        let old = global_color_table.clone();
+        let mut frames: Vec<&mut ImageDescriptor> = gif
+            .image_descriptors
+            .iter_mut()
+            .filter(|image_descriptor| image_descriptor.local_color_table.is_none())
+            .collect();
+        let screen = &mut gif.logical_screen_descriptor;
+        merged += even_color_table(global_color_table, &mut screen.packed_field, &mut frames);
+        let background = old
+            .colors
+            .get(screen.background_color_index as usize)
+            .copied()
+            .unwrap_or([0; 3]);
+        screen.background_color_index = closest_color(&global_color_table.colors, background, None);
+    }
+
+    for image_descriptor in &mut gif.image_descriptors {
+        match image_descriptor.local_color_table.take() {
+            Some(mut local_color_table) => {
+                scrub_color_table(&mut local_color_table);
+                let mut packed_field = image_descriptor.packed_field;
+                merged += even_color_table(
+                    &mut local_color_table,
+                    &mut packed_field,
+                    &mut [&mut *image_descriptor],
+                );
+                image_descriptor.packed_field = packed_field;
+                image_descriptor.local_color_table = Some(local_color_table);
+            }
+            None if gif.global_color_table.is_none() => {
+                image_descriptor.compressed_range = None;
+                for pixel in &mut image_descriptor.image_data {
+                    *pixel &= 0xFE;
+                }
+            }
+            None => {}
+        }
+    }
+
+    merged
+}
+
+fn scrub_color_table(color_table: &mut ColorTable) {
+    for color in &mut color_table.colors {
+        for component in color.iter_mut() {
+            *component &= 0xFE;
+        }
+    }
+}
+
+// What a pixel draws: a color, or nothing where its frame is see-through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Ink {
+    Transparent,
+    Color([u8; 3]),
+}
+
+// Rebuild a color table, and the size bits of the packed field announcing
+// it, for even_indices. The table grows up to 256 entries to give every
+// color two of them; past that the most used colors are kept and the rest
+// drawn with the closest kept one. Returns how many colors were dropped.
+fn even_color_table(
+    table: &mut ColorTable,
+    packed_field: &mut u8,
+    frames: &mut [&mut ImageDescriptor],
+) -> usize {
+    let ink = |image_descriptor: &ImageDescriptor, pixel: u8| match image_descriptor
+        .transparent_color_index()
+    {
+        Some(index) if index == pixel => Ink::Transparent,
+        // Indices past the end of the table are drawn black
+        _ => Ink::Color(table.colors.get(pixel as usize).copied().unwrap_or([0; 3])),
+    };
+
+    let mut counts: HashMap<Ink, usize> = HashMap::new();
+    for image_descriptor in frames.iter() {
+        if image_descriptor.transparent_color_index().is_some() {
+            counts.entry(Ink::Transparent).or_insert(0);
+        }
+        for pixel in &image_descriptor.image_data {
+            *counts.entry(ink(image_descriptor, *pixel)).or_insert(0) += 1;
+        }
+    }
+    // See-through pixels first, then the most used colors
+    let mut inks: Vec<(Ink, usize)> = counts.into_iter().collect();
+    inks.sort_by_key(|&(ink, count)| (ink != Ink::Transparent, Reverse(count), ink));
+
+    let length = (2 * inks.len())
+        .next_power_of_two()
+        .clamp(table.colors.len().max(2), 256);
+    let kept: Vec<Ink> = inks.iter().take(length / 2).map(|&(ink, _)| ink).collect();
+    let transparent_color = frames
+        .iter()
+        .find_map(|image_descriptor| image_descriptor.transparent_color_index())
+        .and_then(|index| table.colors.get(index as usize).copied())
+        .unwrap_or([0; 3]);
+    let kept_colors: Vec<[u8; 3]> = kept
+        .iter()
+        .map(|ink| match ink {
+            Ink::Transparent => transparent_color,
+            Ink::Color(color) => *color,
+        })
+        .collect();
+    let transparent_slot = kept.iter().position(|ink| *ink == Ink::Transparent);
+
+    let mut colors = vec![[0; 3]; length];
+    for (slot, color) in kept_colors.iter().enumerate() {
+        colors[2 * slot] = *color;
+        colors[2 * slot + 1] = *color;
+    }
+    let size_bits = (length.trailing_zeros() - 1) as u8;
+
+    for image_descriptor in frames.iter_mut() {
+        let mut mapping = [0u8; 256];
+        for (pixel, mapped) in mapping.iter_mut().enumerate() {
+            let ink = ink(image_descriptor, pixel as u8);
+            let slot = match (kept.iter().position(|kept| *kept == ink), ink) {
+                (Some(slot), _) => slot,
+                (None, Ink::Color(color)) => {
+                    closest_color(&kept_colors, color, transparent_slot.map(|slot| slot as u8))
+                        as usize
+                }
+                (None, Ink::Transparent) => 0, // Always kept
+            };
+            *mapped = (2 * slot) as u8;
+        }
+        for pixel in &mut image_descriptor.image_data {
+            *pixel = mapping[*pixel as usize];
+        }
+        let see_through = image_descriptor.transparent_color_index().is_some();
+        if let (Some(gce), Some(slot)) = (
+            image_descriptor.graphics_control_extension.as_mut(),
+            transparent_slot.filter(|_| see_through),
+        ) {
+            gce.transparent_color_index = (2 * slot) as u8;
+        }
+        image_descriptor.lzw_minimum_code_size = image_descriptor
+            .lzw_minimum_code_size
+            .max(size_bits + 1)
+            .max(2);
+        image_descriptor.compressed_range = None;
+    }
+
+    *packed_field = (*packed_field & !0b111) | size_bits;
+    table.colors = colors;
+    inks.len() - kept.len()
+}
@@ -0,0 +1,234 @@
+use std::io::{self, Error, Read};
+
+use crate::{embed_bytes, extract_bytes, inspect_json, json_string, Options};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::metadata::{format_time, unix_time};
+use crate::output::base64;
+
+// Uploads larger than this are refused before they are read
+const MAX_BODY_SIZE: u64 = 64 * 1024 * 1024;
+
+// One part of a multipart/form-data body
+struct Part {
+    name: String,
+    filename: Option<String>,
+    data: Vec<u8>,
+}
+
+// Serve embed, extract and inspect over HTTP until the process is stopped.
+// Every endpoint takes a POST of multipart/form-data:
+//
+//   /embed    gif, payload, [channels], [passphrase], [name] -> image/gif
+//   /extract  gif, [passphrase]                              -> JSON
+//   /inspect  gif, [passphrase]                              -> JSON
+//
+// Errors are answered with {"error": "..."}.
+pub fn serve(listen: &str) -> Result<(), Error> {
+    let server = Server::http(listen).map_err(io::Error::other)?;
+    info!("Listening on http://{}", listen);
+    for request in server.incoming_requests() {
+        rayon::spawn(move || handle(request));
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    let response = match respond(&mut request) {
+        Ok(response) => response,
+        Err((status, message)) => json_response(
+            status,
+            format!("{{\"error\":{}}}", json_string(&message)).into_bytes(),
+        ),
+    };
+    info!("{} {} {}", method, url, response.status_code().0);
+    if let Err(e) = request.respond(response) {
+        debug!("Failed to answer {}: {}", url, e);
+    }
+}
+
+fn respond(request: &mut Request) -> Result<Response<io::Cursor<Vec<u8>>>, (u16, String)> {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    if !matches!(path.as_str(), "/embed" | "/extract" | "/inspect") {
+        return Err((404, format!("No endpoint at {}.", path)));
+    }
+    if *request.method() != Method::Post {
+        return Err((405, format!("{} only accepts POST.", path)));
+    }
+
+    let parts = read_form(request)?;
+    let field = |name: &str| {
+        parts
+            .iter()
+            .find(|part| part.name == name)
+            .map(|part| String::from_utf8_lossy(&part.data).into_owned())
+    };
+    let gif = parts
+        .iter()
+        .find(|part| part.name == "gif")
+        .ok_or((400, "Missing the gif part.".to_string()))?;
+    let mut options = Options {
+        passphrase: field("passphrase").filter(|passphrase| !passphrase.is_empty()),
+        ..Options::default()
+    };
+
+    match path.as_str() {
+        "/embed" => {
+            let payload = parts
+                .iter()
+                .find(|part| part.name == "payload")
+                .ok_or((400, "Missing the payload part.".to_string()))?;
+            if let Some(channels) = field("channels") {
+                options.channels = channels;
+            }
+            options.name = field("name")
+                .or_else(|| payload.filename.clone())
+                .unwrap_or_default();
+            let output = embed_bytes(&gif.data, &payload.data, &options).map_err(error_status)?;
+            Ok(Response::from_data(output)
+                .with_header(content_type("image/gif"))
+                .with_status_code(200))
+        }
+        "/extract" => {
+            let extracted = extract_bytes(&gif.data, &options)
+                .map_err(error_status)?
+                .ok_or((404, "No payload found.".to_string()))?;
+            if extracted.expires != 0 && unix_time() >= extracted.expires {
+                return Err((
+                    403,
+                    format!("The payload expired at {}.", format_time(extracted.expires)),
+                ));
+            }
+            Ok(json_response(
+                200,
+                format!(
+                    "{{\"name\":{},\"mime_type\":{},\"size\":{},\"data\":\"{}\"}}",
+                    json_string(&extracted.name),
+                    json_string(&extracted.mime_type),
+                    extracted.data.len(),
+                    base64(&extracted.data)
+                )
+                .into_bytes(),
+            ))
+        }
+        _ => {
+            let json =
+                inspect_json(&gif.data, options.passphrase.as_deref()).map_err(error_status)?;
+            Ok(json_response(200, json.into_bytes()))
+        }
+    }
+}
+
+fn error_status(error: Error) -> (u16, String) {
+    let status = match error.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+            400
+        }
+        io::ErrorKind::PermissionDenied => 403,
+        io::ErrorKind::NotFound => 404,
+        _ => 422,
+    };
+    (status, error.to_string())
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).unwrap()
+}
+
+fn json_response(status: u16, body: Vec<u8>) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_data(body)
+        .with_header(content_type("application/json"))
+        .with_status_code(status)
+}
+
+// Read and split a multipart/form-data body
+fn read_form(request: &mut Request) -> Result<Vec<Part>, (u16, String)> {
+    let invalid = |message: &str| (400, message.to_string());
+    let too_large = || {
+        (
+            413,
+            format!("Uploads are limited to {} bytes.", MAX_BODY_SIZE),
+        )
+    };
+
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str().to_string())
+        .unwrap_or_default();
+    if !content_type.starts_with("multipart/form-data") {
+        return Err(invalid("Expected a multipart/form-data body."));
+    }
+    let boundary = content_type
+        .split(';')
+        .filter_map(|parameter| parameter.trim().strip_prefix("boundary="))
+        .next()
+        .map(|boundary| boundary.trim_matches('"').to_string())
+        .ok_or_else(|| invalid("The multipart body has no boundary."))?;
+
+    if request.body_length().unwrap_or(0) as u64 > MAX_BODY_SIZE {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_SIZE + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| (400, e.to_string()))?;
+    if body.len() as u64 > MAX_BODY_SIZE {
+        return Err(too_large());
+    }
+
+    parse_multipart(&body, boundary.as_bytes()).ok_or_else(|| invalid("Malformed multipart body."))
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+fn parse_multipart(body: &[u8], boundary: &[u8]) -> Option<Vec<Part>> {
+    let delimiter = [b"--", boundary].concat();
+    let mut parts = Vec::new();
+    let mut position = find(body, &delimiter, 0)? + delimiter.len();
+
+    // Each part follows a delimiter line, and the last delimiter ends with --
+    while !body[position..].starts_with(b"--") {
+        let headers_start = find(body, b"\r\n", position)? + 2;
+        let headers_end = find(body, b"\r\n\r\n", headers_start)?;
+        let data_start = headers_end + 4;
+        let next = find(body, &[b"\r\n", delimiter.as_slice()].concat(), data_start)?;
+
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        let disposition = headers.lines().find(|line| {
+            line.to_ascii_lowercase()
+                .starts_with("content-disposition:")
+        })?;
+        parts.push(Part {
+            name: disposition_parameter(disposition, "name")?,
+            filename: disposition_parameter(disposition, "filename"),
+            data: body[data_start..next].to_vec(),
+        });
+        position = next + 2 + delimiter.len();
+    }
+    Some(parts)
+}
+
+// name="..." or filename="..." of a Content-Disposition header
+fn disposition_parameter(disposition: &str, key: &str) -> Option<String> {
+    disposition.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.trim().split_once('=')?;
+        (name == key).then(|| value.trim_matches('"').to_string())
+    })
+}
@@ -0,0 +1,49 @@
+use std::io::{self, Error};
+
+use crate::frames::composite_frames;
+use crate::gif::GIF;
+
+// Frames are laid out left to right, then top to bottom, as they look on
+// screen. Pixels no frame covers and the gaps between frames are transparent.
+
+// Pixels between neighbouring frames
+const GAP: usize = 2;
+
+// Every `every`th frame from the first in a grid `columns` frames across, as
+// RGBA pixels. Returns the width and height of the grid with them.
+pub fn contact_sheet(
+    gif: &GIF,
+    every: usize,
+    columns: usize,
+) -> Result<(usize, usize, Vec<u8>), Error> {
+    let width = gif.logical_screen_descriptor.width as usize;
+    let height = gif.logical_screen_descriptor.height as usize;
+    let canvases: Vec<_> = composite_frames(gif)?
+        .into_iter()
+        .step_by(every.max(1))
+        .collect();
+    if canvases.is_empty() || width == 0 || height == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The GIF has no frames to show.",
+        ));
+    }
+
+    let columns = columns.clamp(1, canvases.len());
+    let rows = canvases.len().div_ceil(columns);
+    let sheet_width = columns * (width + GAP) - GAP;
+    let sheet_height = rows * (height + GAP) - GAP;
+    let mut rgba = vec![0; sheet_width * sheet_height * 4];
+    for (index, canvas) in canvases.iter().enumerate() {
+        let left = (index % columns) * (width + GAP);
+        let top = (index / columns) * (height + GAP);
+        for (position, pixel) in canvas.iter().enumerate() {
+            if let Some([red, green, blue]) = pixel {
+                let (x, y) = (left + position % width, top + position / width);
+                let offset = (y * sheet_width + x) * 4;
+                rgba[offset..offset + 4].copy_from_slice(&[*red, *green, *blue, 0xFF]);
+            }
+        }
+    }
+    Ok((sheet_width, sheet_height, rgba))
+}
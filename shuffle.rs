@@ -0,0 +1,293 @@
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use std::io::Error;
+use std::ops::Range;
+
+use crate::channels::{channel_id, ChannelKind, SINGLE_EXTENSION_SIZE};
+use crate::crypto::stretch_passphrase;
+
+// Chunk sizes are drawn from this range so extension boundaries don't line
+// up on a fixed stride. The upper bound keeps every chunk in one sub-block.
+const MIN_CHUNK_SIZE: usize = 64;
+const MAX_CHUNK_SIZE: usize = 255;
+
+// Secret that decides how payload chunks are sized and ordered. A stealth
+// payload's also masks its header, and a passphrase payload's may whiten the
+// channels that mix their data with a stream.
+#[derive(Clone)]
+pub struct ChunkKey {
+    seed: [u8; 32],
+    header_mask: Option<[u8; 32]>,
+    whitening: Option<[u8; 32]>,
+}
+
+pub fn derive_chunk_key(passphrase: &str) -> ChunkKey {
+    let mut hasher = Sha256::new();
+    hasher.update(b"gifsauce chunk shuffle");
+    hasher.update(passphrase.as_bytes());
+    ChunkKey {
+        seed: hasher.finalize().into(),
+        header_mask: None,
+        whitening: None,
+    }
+}
+
+// Bytes at the start of a sealed container its layout key is drawn from: the
+// salt, with the built-in cipher
+pub const LAYOUT_SEED_SIZE: usize = 16;
+
+// The keyed layout of an encrypted payload, drawn from its container rather
+// than a passphrase. Each passphrase a container opens for finds the same
+// one, so a container holding a decoy is laid out as one that doesn't.
+pub fn derive_layout_key(container: &[u8]) -> ChunkKey {
+    let mut hasher = Sha256::new();
+    hasher.update(b"gifsauce container layout");
+    hasher.update(container.get(..LAYOUT_SEED_SIZE).unwrap_or(container));
+    ChunkKey {
+        seed: hasher.finalize().into(),
+        header_mask: None,
+        whitening: None,
+    }
+}
+
+// Argon2 wants a salt, and the passphrase key of a stealth payload has to be
+// found without one from the carrier
+const STEALTH_SALT: &[u8] = b"gifsauce stealth";
+
+// A stealth payload's passphrase, stretched by the payload cipher. The same
+// passphrase gives the same key everywhere, so it only ever keys a payload
+// together with that payload's container.
+#[derive(Clone)]
+pub struct StealthKey {
+    seed: [u8; 32],
+}
+
+impl StealthKey {
+    pub fn new(passphrase: &str) -> Result<StealthKey, Error> {
+        Ok(StealthKey {
+            seed: stretch_passphrase(passphrase, STEALTH_SALT)?,
+        })
+    }
+
+    // The layout and header mask of the stealth payload sealed as
+    // `container`. Its salt is drawn anew for every embed, so no two
+    // carriers share them, and checking a passphrase against one takes the
+    // stretching first.
+    pub fn chunk_key(&self, container: &[u8]) -> ChunkKey {
+        let nonce = container.get(..LAYOUT_SEED_SIZE).unwrap_or(container);
+        let draw = |label: &[u8]| -> [u8; 32] {
+            let mut hasher = Sha256::new();
+            hasher.update(label);
+            hasher.update(self.seed);
+            hasher.update(nonce);
+            hasher.finalize().into()
+        };
+        ChunkKey {
+            seed: draw(b"gifsauce stealth layout"),
+            header_mask: Some(draw(b"gifsauce stealth header")),
+            whitening: None,
+        }
+    }
+
+    // `key` with the whitening of this passphrase. A layout key comes from
+    // the container, which is read from the start of the stream, so channels
+    // that mix in a stream are read with this before any layout is known.
+    pub fn whitened(&self, key: &ChunkKey) -> ChunkKey {
+        let mut hasher = Sha256::new();
+        hasher.update(b"gifsauce whitening");
+        hasher.update(self.seed);
+        ChunkKey {
+            whitening: Some(hasher.finalize().into()),
+            ..key.clone()
+        }
+    }
+}
+
+// Each channel gets its own stream so spreading doesn't correlate them.
+fn channel_rng(key: &ChunkKey, channel: ChannelKind) -> ChaCha20Rng {
+    seeded_channel_rng(&key.seed, channel)
+}
+
+fn seeded_channel_rng(seed: &[u8; 32], channel: ChannelKind) -> ChaCha20Rng {
+    let mut hasher = Sha256::new();
+    hasher.update(seed);
+    hasher.update([channel_id(channel)]);
+    ChaCha20Rng::from_seed(hasher.finalize().into())
+}
+
+// Bits for channels that mix their data with a stream rather than shuffle
+// it: the passphrase's whitening, or without one the empty passphrase's key,
+// which anyone can undo. `carrier` is what the channel leaves as it was, so
+// one passphrase whitens different carriers differently.
+pub fn whitening_rng(key: Option<&ChunkKey>, channel: ChannelKind, carrier: &[u8]) -> ChaCha20Rng {
+    match key.and_then(|key| key.whitening.as_ref()) {
+        Some(seed) => {
+            let mut hasher = Sha256::new();
+            hasher.update(seed);
+            hasher.update(carrier);
+            seeded_channel_rng(&hasher.finalize().into(), channel)
+        }
+        None => channel_rng(&derive_chunk_key(""), channel),
+    }
+}
+
+// Bytes to pad a payload with. They follow from the payload, so they come out
+// the same every time; an encrypted payload is padded before it is sealed, so
+// they are sealed with it.
+pub fn padding_rng(payload: &[u8]) -> ChaCha20Rng {
+    let mut hasher = Sha256::new();
+    hasher.update(b"gifsauce padding");
+    hasher.update(payload);
+    ChaCha20Rng::from_seed(hasher.finalize().into())
+}
+
+// XORed over the payload header of a stealth payload, so without the key not
+// even its magic shows. Longer than any header. None for any other key.
+pub fn header_mask(key: &ChunkKey) -> Option<[u8; 32]> {
+    key.header_mask
+}
+
+// The RNG is always consumed as: one draw per chunk size, then the shuffle.
+// That lets extraction replay it knowing only the number of chunks.
+fn chunk_layout(rng: &mut ChaCha20Rng, count: usize) -> Vec<usize> {
+    for _ in 0..count {
+        rng.gen_range(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE);
+    }
+    let mut order: Vec<usize> = (0..count).collect();
+    order.shuffle(rng);
+    order
+}
+
+// Cut data into randomly sized chunks and return them in the order they are
+// to be written to the file. A small stream stays one chunk.
+pub fn shuffle_chunks(data: &[u8], key: &ChunkKey, channel: ChannelKind) -> Vec<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    for size in chunk_sizes(data.len(), key, channel) {
+        chunks.push(data[start..start + size].to_vec());
+        start += size;
+    }
+
+    let mut rng = channel_rng(key, channel);
+    let order = chunk_layout(&mut rng, chunks.len());
+    let mut placed = vec![Vec::new(); chunks.len()];
+    for (chunk, position) in chunks.into_iter().zip(order) {
+        placed[position] = chunk;
+    }
+    placed
+}
+
+// Sizes of the chunks shuffle_chunks cuts `length` bytes into, in data order
+fn chunk_sizes(length: usize, key: &ChunkKey, channel: ChannelKind) -> Vec<usize> {
+    match length {
+        0 => return Vec::new(),
+        1..=SINGLE_EXTENSION_SIZE => return vec![length],
+        _ => {}
+    }
+    let mut rng = channel_rng(key, channel);
+    let mut sizes = Vec::new();
+    let mut covered = 0;
+    while covered < length {
+        let size = rng.gen_range(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE);
+        sizes.push(size.min(length - covered));
+        covered += size;
+    }
+    sizes
+}
+
+// How many chunks shuffle_chunks produces for `length` bytes
+pub fn chunk_count(length: usize, key: &ChunkKey, channel: ChannelKind) -> usize {
+    match length {
+        0 => 0,
+        1..=SINGLE_EXTENSION_SIZE => 1,
+        _ => legacy_chunk_count(length, key, channel),
+    }
+}
+
+// How many chunks files from before small streams were kept whole have for
+// `length` bytes: they cut every stream the same way
+pub fn legacy_chunk_count(length: usize, key: &ChunkKey, channel: ChannelKind) -> usize {
+    let mut rng = channel_rng(key, channel);
+    let mut count = 0;
+    let mut covered = 0;
+    while covered < length {
+        covered += rng.gen_range(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE);
+        count += 1;
+    }
+    count
+}
+
+// Where shuffle_chunks puts the chunks of `length` bytes: in data order, the
+// range of the data each holds and its position among the chunks in the file
+pub fn chunk_placement(
+    length: usize,
+    key: &ChunkKey,
+    channel: ChannelKind,
+) -> Vec<(Range<usize>, usize)> {
+    let sizes = chunk_sizes(length, key, channel);
+    let mut rng = channel_rng(key, channel);
+    let order = chunk_layout(&mut rng, sizes.len());
+    let mut start = 0;
+    sizes
+        .into_iter()
+        .zip(order)
+        .map(|(size, position)| {
+            start += size;
+            (start - size..start, position)
+        })
+        .collect()
+}
+
+// Which of `count` chunks in the file holds the start of the data
+pub fn first_chunk_position(count: usize, key: &ChunkKey, channel: ChannelKind) -> usize {
+    let mut rng = channel_rng(key, channel);
+    chunk_layout(&mut rng, count)[0]
+}
+
+// Undo shuffle_chunks for the first `count` chunks found in the file.
+pub fn unshuffle_chunks(
+    chunks: &[Vec<u8>],
+    count: usize,
+    key: &ChunkKey,
+    channel: ChannelKind,
+) -> Option<Vec<u8>> {
+    if count > chunks.len() {
+        return None;
+    }
+    let mut rng = channel_rng(key, channel);
+    let order = chunk_layout(&mut rng, count);
+    let mut data = Vec::new();
+    for position in order {
+        data.extend_from_slice(&chunks[position]);
+    }
+    Some(data)
+}
+
+// unshuffle_chunks for a stream of `length` bytes some chunks of which are
+// missing or the wrong size. Their bytes are `placeholder` and the ranges
+// they covered are returned with the data.
+pub fn unshuffle_damaged_chunks(
+    chunks: &[Vec<u8>],
+    length: usize,
+    key: &ChunkKey,
+    channel: ChannelKind,
+    placeholder: u8,
+) -> (Vec<u8>, Vec<Range<usize>>) {
+    let sizes = chunk_sizes(length, key, channel);
+    let mut rng = channel_rng(key, channel);
+    let order = chunk_layout(&mut rng, sizes.len());
+    let mut data = Vec::with_capacity(length);
+    let mut lost = Vec::new();
+    for (size, position) in sizes.into_iter().zip(order) {
+        match chunks.get(position) {
+            Some(chunk) if chunk.len() == size => data.extend_from_slice(chunk),
+            _ => {
+                lost.push(data.len()..data.len() + size);
+                data.resize(data.len() + size, placeholder);
+            }
+        }
+    }
+    (data, lost)
+}
@@ -0,0 +1,1662 @@
+// The command line tool, run as a user would run it, on carriers it
+// generates itself. Each test works in a directory of its own.
+#![cfg(feature = "cli")]
+
+use gifsauce::sheet::contact_sheet;
+use gifsauce::{parse_gif_bytes, write_gif_bytes, ColorTable, PlainTextExtension, GIF};
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// A directory of a test's own, removed when the test is done with it
+struct Scratch(PathBuf);
+
+impl Scratch {
+    // Emptied first, in case a run that crashed left it behind
+    fn new(test: &str) -> Scratch {
+        let dir = std::env::temp_dir().join(format!("gifsauce-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// `command` is split at whitespace, so no argument can hold any
+fn gifsauce(dir: &Path, command: &str) -> Output {
+    gifsauce_with_env(dir, command, &[])
+}
+
+// Same, with variables added to the environment
+fn gifsauce_with_env(dir: &Path, command: &str, env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_GifSauce"))
+        .current_dir(dir)
+        .args(command.split_whitespace())
+        .arg("-q")
+        .envs(env.iter().copied())
+        .output()
+        .unwrap()
+}
+
+// What a command that has to succeed printed
+fn run(dir: &Path, command: &str) -> String {
+    let output = gifsauce(dir, command);
+    assert!(
+        output.status.success(),
+        "{command}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// The error of a command that has to fail
+fn fail(dir: &Path, command: &str) -> String {
+    let output = gifsauce(dir, command);
+    assert!(!output.status.success(), "{command} succeeded");
+    String::from_utf8(output.stderr).unwrap()
+}
+
+// carrier.gif, 96x96 and three frames of noise, with files to embed in it
+fn setup(test: &str) -> Scratch {
+    let dir = Scratch::new(test);
+    run(
+        &dir,
+        "carrier generate -o carrier.gif --size 96x96 --frames 3 --seed 1",
+    );
+    fs::write(dir.join("small.txt"), b"hello").unwrap();
+    fs::write(dir.join("decoy.txt"), b"nothing to see").unwrap();
+    let medium: Vec<u8> = (0..300u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(dir.join("medium.bin"), medium).unwrap();
+    let large: Vec<u8> = (0..3000u32).map(|i| (i * 31 % 251) as u8).collect();
+    fs::write(dir.join("large.bin"), large).unwrap();
+    dir
+}
+
+fn extracted(dir: &Path, gif: &str, options: &str) -> Vec<u8> {
+    run(dir, &format!("extract {gif} -o out.bin --force {options}"));
+    fs::read(dir.join("out.bin")).unwrap()
+}
+
+// The length detect reports for the body of the payload in `gif`
+fn body_length(dir: &Path, gif: &str) -> usize {
+    let report = run(dir, &format!("detect {gif}"));
+    let length = report
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("length="))
+        .unwrap_or_else(|| panic!("no length in {report}"));
+    length.parse().unwrap()
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn rekey_moves_payloads_to_the_new_passphrase() {
+    let dir = setup("rekey");
+    run(
+        &dir,
+        "embed carrier.gif lone.gif --payload small.txt --passphrase old",
+    );
+    // Nothing tells a container without a decoy from one with, so rekey
+    // has to be told
+    let error = fail(&dir, "rekey lone.gif new.gif --old-pass old --new-pass new");
+    assert!(error.contains("--drop-decoy"), "{error}");
+    run(
+        &dir,
+        "rekey lone.gif new.gif --old-pass old --new-pass new --drop-decoy",
+    );
+    assert_eq!(extracted(&dir, "new.gif", "--passphrase new"), b"hello");
+    fail(&dir, "extract new.gif -o out.bin --force --passphrase old");
+
+    run(
+        &dir,
+        "embed carrier.gif decoy.gif --payload small.txt --passphrase old \
+         --decoy decoy.txt --decoy-passphrase duress",
+    );
+    run(
+        &dir,
+        "rekey decoy.gif kept.gif --old-pass old --new-pass new --decoy-passphrase duress",
+    );
+    assert_eq!(extracted(&dir, "kept.gif", "--passphrase new"), b"hello");
+    assert_eq!(
+        extracted(&dir, "kept.gif", "--passphrase duress"),
+        b"nothing to see"
+    );
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn stealth_payloads_only_show_to_their_passphrase() {
+    let dir = setup("stealth");
+    let medium = fs::read(dir.join("medium.bin")).unwrap();
+    for channels in ["comment", "plaintext", "lsb"] {
+        run(
+            &dir,
+            &format!(
+                "embed carrier.gif {channels}.gif --payload medium.bin --channels {channels} \
+                 --passphrase secret --stealth"
+            ),
+        );
+        let gif = format!("{channels}.gif");
+        let report = run(&dir, &format!("detect {gif}"));
+        assert!(report.ends_with("no payload\n"), "{report}");
+        assert_eq!(extracted(&dir, &gif, "--passphrase secret"), medium);
+        fail(
+            &dir,
+            &format!("extract {gif} -o out.bin --force --passphrase guess"),
+        );
+    }
+    // The mask is drawn anew for each embed, so carriers of the same
+    // passphrase don't share it
+    run(
+        &dir,
+        "embed carrier.gif again.gif --payload medium.bin --channels lsb \
+         --passphrase secret --stealth",
+    );
+    assert_ne!(
+        fs::read(dir.join("lsb.gif")).unwrap(),
+        fs::read(dir.join("again.gif")).unwrap()
+    );
+
+    // Only a passphrase's keyed layout finds a stealth payload
+    for options in [
+        "",
+        "--passphrase secret --decoy decoy.txt --decoy-passphrase duress",
+    ] {
+        let error = fail(
+            &dir,
+            &format!("embed carrier.gif refused.gif --payload small.txt --stealth {options}"),
+        );
+        assert!(error.contains("--stealth needs"), "{error}");
+    }
+
+    // A cut carrier, or one with a bit flipped in every 32 bytes of the
+    // payload, gives nothing back, not part of it
+    let comment = fs::read(dir.join("comment.gif")).unwrap();
+    let payload = comment
+        .windows(2)
+        .position(|label| label == [0x21, 0xFE])
+        .unwrap();
+    fs::write(dir.join("cut.gif"), &comment[..payload + 100]).unwrap();
+    edited(&dir, "comment.gif", "tampered.gif", |gif| {
+        edit_comment_bytes(gif, |bytes| {
+            for byte in bytes.iter_mut().step_by(32) {
+                *byte ^= 1;
+            }
+        })
+    });
+    for gif in ["cut.gif", "tampered.gif"] {
+        let error = fail(
+            &dir,
+            &format!("extract {gif} -o lost.bin --passphrase secret"),
+        );
+        assert!(!error.contains("panicked"), "{error}");
+    }
+    assert!(!dir.join("lost.bin").exists());
+}
+
+#[test]
+fn padding_hides_the_payload_length() {
+    let dir = setup("padding");
+    let mut padded = Vec::new();
+    for payload in ["small.txt", "medium.bin"] {
+        run(
+            &dir,
+            &format!(
+                "embed carrier.gif padded.gif --payload {payload} --channels comment \
+                 --padding 1024 --force"
+            ),
+        );
+        padded.push(body_length(&dir, "padded.gif"));
+        assert_eq!(
+            extracted(&dir, "padded.gif", ""),
+            fs::read(dir.join(payload)).unwrap()
+        );
+    }
+    assert_eq!(padded[0], padded[1]);
+
+    run(
+        &dir,
+        "embed carrier.gif bare.gif --payload small.txt --channels comment --no-padding",
+    );
+    assert!(body_length(&dir, "bare.gif") < padded[0]);
+
+    for padding in ["many", "-1"] {
+        let error = fail(
+            &dir,
+            &format!("embed carrier.gif bad.gif --payload small.txt --padding {padding}"),
+        );
+        assert!(error.contains("--padding expects"), "{error}");
+    }
+
+    // The length at the start of the padded body, right after the header and
+    // its digest, pointing past the end of it or short of the file
+    run(
+        &dir,
+        "embed carrier.gif stored.gif --payload small.txt --channels comment \
+         --padding 1024 --no-checksums --no-compress",
+    );
+    for (length, message) in [(u32::MAX, "padding is damaged"), (1, "truncated")] {
+        edited(&dir, "stored.gif", "damaged.gif", |gif| {
+            edit_comment_bytes(gif, |bytes| {
+                let start = bytes.windows(4).position(|magic| magic == b"GSau").unwrap() + 42;
+                bytes[start..start + 4].copy_from_slice(&length.to_le_bytes());
+            })
+        });
+        let error = fail(&dir, "extract damaged.gif -o out.bin --force");
+        assert!(error.contains(message), "{error}");
+    }
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn deterministic_embeds_repeat_byte_for_byte() {
+    let dir = setup("deterministic");
+    let embed = |output: &str, options: &str| -> Vec<u8> {
+        run(
+            &dir,
+            &format!(
+                "embed carrier.gif {output} --payload small.txt --passphrase secret \
+                 --deterministic {options}"
+            ),
+        );
+        fs::read(dir.join(output)).unwrap()
+    };
+    let first = embed("first.gif", "");
+    assert_eq!(embed("second.gif", ""), first);
+    assert_ne!(embed("nonce.gif", "--nonce run-2"), first);
+    assert_ne!(
+        embed("other.gif", "--decoy decoy.txt --decoy-passphrase duress"),
+        first
+    );
+    assert_eq!(
+        extracted(&dir, "first.gif", "--passphrase secret"),
+        b"hello"
+    );
+    assert_eq!(
+        extracted(&dir, "nonce.gif", "--passphrase secret"),
+        b"hello"
+    );
+
+    // age draws a key of its own, and a nonce needs a value
+    let error = fail(
+        &dir,
+        "embed carrier.gif age.gif --payload small.txt --deterministic \
+         --recipient age1qyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqs3290gq",
+    );
+    assert!(error.contains("--recipient"), "{error}");
+    let error = fail(
+        &dir,
+        "embed carrier.gif bare.gif --payload small.txt --passphrase secret --nonce",
+    );
+    assert!(error.contains("--nonce"), "{error}");
+    assert!(!dir.join("age.gif").exists() && !dir.join("bare.gif").exists());
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn expired_payloads_need_ignore_expiry() {
+    let dir = setup("expiry");
+    run(
+        &dir,
+        "embed carrier.gif expired.gif --payload small.txt --passphrase secret \
+         --expires 2000-01-01",
+    );
+    let error = fail(&dir, "extract expired.gif -o out.bin --passphrase secret");
+    assert!(error.contains("expired"), "{error}");
+    assert_eq!(
+        extracted(&dir, "expired.gif", "--passphrase secret --ignore-expiry"),
+        b"hello"
+    );
+
+    // Only an encrypted payload can be trusted to keep its expiry
+    fail(
+        &dir,
+        "embed carrier.gif plain.gif --payload small.txt --expires 2000-01-01",
+    );
+
+    for date in [
+        "tomorrow",
+        "2025-02-30",
+        "2025-13-01",
+        "1969-12-31",
+        "+2025-12-31",
+        "2025-12-31T24:00:00Z",
+        "2025-12-31T18:00:00",
+    ] {
+        let error = fail(
+            &dir,
+            &format!(
+                "embed carrier.gif bad.gif --payload small.txt --passphrase secret \
+                 --expires {date}"
+            ),
+        );
+        assert!(error.contains("--expires expects"), "{date}: {error}");
+    }
+
+    // The expiry is sealed with the payload, wherever in the region after
+    // the salt and slot headers it went, so editing it gives nothing back
+    run(
+        &dir,
+        "embed carrier.gif sealed.gif --payload small.txt --passphrase secret \
+         --channels comment --expires 2000-01-01",
+    );
+    edited(&dir, "sealed.gif", "tampered.gif", |gif| {
+        edit_comment_bytes(gif, |bytes| {
+            let body = bytes.windows(4).position(|magic| magic == b"GSau").unwrap() + 10;
+            for byte in &mut bytes[body + 64..] {
+                *byte ^= 0x80;
+            }
+        })
+    });
+    fail(
+        &dir,
+        "extract tampered.gif -o lost.bin --passphrase secret --ignore-expiry",
+    );
+    assert!(!dir.join("lost.bin").exists());
+}
+
+#[test]
+fn audit_trails_record_each_operation() {
+    let dir = setup("audit");
+    run(&dir, "embed carrier.gif plain.gif --payload small.txt");
+    assert!(run(&dir, "inspect plain.gif --history").contains("No audit trail"));
+
+    run(
+        &dir,
+        "--audit embed carrier.gif audited.gif --payload small.txt",
+    );
+    run(&dir, "--audit comment add audited.gif commented.gif hi");
+    let history = run(&dir, "inspect commented.gif --history");
+    // After the number, date, time, zone, tool and its version
+    let operations: Vec<&str> = history
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(6))
+        .collect();
+    assert_eq!(operations, ["embed", "comment"], "{history}");
+}
+
+#[test]
+fn chunk_maps_cover_the_stored_payload() {
+    let dir = setup("map");
+    run(
+        &dir,
+        "embed carrier.gif mapped.gif --payload large.bin --channels comment --map map.json",
+    );
+    let map = fs::read_to_string(dir.join("map.json")).unwrap();
+    // The map counts the header and digest ahead of the body
+    let length = body_length(&dir, "mapped.gif") + 10 + 32;
+    assert!(map.starts_with(&format!("{{\"length\":{length},")), "{map}");
+
+    // The chunks' ranges, put in order, run from 0 to the length
+    let number = |entry: &str, key: &str| -> usize {
+        let start = entry.find(&format!("\"{key}\":")).unwrap() + key.len() + 3;
+        entry[start..]
+            .split(|c: char| !c.is_ascii_digit())
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let mut ranges: Vec<(usize, usize)> = map
+        .split("{\"channel\":")
+        .skip(1)
+        .map(|entry| {
+            assert!(entry.starts_with("\"comment\""), "{entry}");
+            (number(entry, "start"), number(entry, "end"))
+        })
+        .collect();
+    ranges.sort();
+    assert!(ranges.len() > 1);
+    assert_eq!(ranges[0].0, 0);
+    assert!(ranges.windows(2).all(|pair| pair[0].1 == pair[1].0));
+    assert_eq!(ranges.last().unwrap().1, length);
+}
+
+#[test]
+fn fit_strategies_are_taken_in_turn() {
+    let dir = setup("fit");
+    // Three frames hold fewer plain text extensions than the payload takes
+    let error = fail(
+        &dir,
+        "embed carrier.gif grown.gif --payload large.bin --channels plaintext \
+         --fit-strategy fail",
+    );
+    assert!(error.contains("--fit-strategy"), "{error}");
+    run(
+        &dir,
+        "embed carrier.gif grown.gif --payload large.bin --channels plaintext \
+         --fit-strategy duplicate",
+    );
+    let grown = parse_gif_bytes(&fs::read(dir.join("grown.gif")).unwrap()).unwrap();
+    assert!(grown.image_descriptors.len() > 3);
+    assert_eq!(
+        extracted(&dir, "grown.gif", ""),
+        fs::read(dir.join("large.bin")).unwrap()
+    );
+
+    // Far more than the pixels hold, but it deflates to almost nothing
+    if cfg!(feature = "compression") {
+        fs::write(dir.join("zeros.bin"), vec![0; 20_000]).unwrap();
+        fail(
+            &dir,
+            "embed carrier.gif packed.gif --payload zeros.bin --channels lsb \
+             --fit-strategy fail",
+        );
+        run(
+            &dir,
+            "embed carrier.gif packed.gif --payload zeros.bin --channels lsb \
+             --fit-strategy compress,fail",
+        );
+        assert_eq!(extracted(&dir, "packed.gif", ""), vec![0; 20_000]);
+    }
+}
+
+#[test]
+fn watermarks_verify_only_their_id() {
+    let dir = setup("watermark");
+    run(
+        &dir,
+        "watermark embed carrier.gif marked.gif --id alice --key k",
+    );
+    let verify =
+        |gif: &str, id: &str| run(&dir, &format!("watermark verify {gif} --id {id} --key k"));
+    assert!(verify("marked.gif", "alice").contains("watermark alice found"));
+    assert!(verify("marked.gif", "bob").contains("not found"));
+    assert!(verify("carrier.gif", "alice").contains("not found"));
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+// A ZIP archive of one stored file
+fn zip_of(name: &str, data: &[u8]) -> Vec<u8> {
+    // The fields local and central headers share: version needed, flags,
+    // method, time and date, CRC, sizes and the name's length
+    let fields = [
+        &[20, 0, 0, 0, 0, 0, 0, 0, 0, 0][..],
+        &crc32(data).to_le_bytes(),
+        &(data.len() as u32).to_le_bytes(),
+        &(data.len() as u32).to_le_bytes(),
+        &(name.len() as u16).to_le_bytes(),
+    ]
+    .concat();
+    let local = [&b"PK\x03\x04"[..], &fields, &[0, 0], name.as_bytes(), data].concat();
+    // Made by, then after the shared fields: extra and comment lengths, disk,
+    // attributes and the local header's offset
+    let central = [
+        &b"PK\x01\x02\x14\x00"[..],
+        &fields,
+        &[0; 16],
+        name.as_bytes(),
+    ]
+    .concat();
+    let end = [
+        &b"PK\x05\x06\0\0\0\0\x01\0\x01\0"[..],
+        &(central.len() as u32).to_le_bytes(),
+        &(local.len() as u32).to_le_bytes(),
+        &[0, 0],
+    ]
+    .concat();
+    [local, central, end].concat()
+}
+
+#[test]
+fn polyglots_are_both_gif_and_zip() {
+    let dir = setup("polyglot");
+    fs::write(dir.join("archive.zip"), zip_of("hello.txt", b"hello")).unwrap();
+    run(&dir, "polyglot carrier.gif archive.zip -o both.gif");
+    let both = fs::read(dir.join("both.gif")).unwrap();
+    let carrier = parse_gif_bytes(&fs::read(dir.join("carrier.gif")).unwrap()).unwrap();
+    let gif = parse_gif_bytes(&both).unwrap();
+    assert_eq!(gif.image_descriptors.len(), carrier.image_descriptors.len());
+
+    // The archive's offsets now count from the start of the GIF
+    let read_u32 = |at: usize| u32::from_le_bytes(both[at..at + 4].try_into().unwrap()) as usize;
+    let end = both.len() - 22;
+    assert!(both[end..].starts_with(b"PK\x05\x06"));
+    let directory = read_u32(end + 16);
+    assert!(both[directory..].starts_with(b"PK\x01\x02"));
+    let local = read_u32(directory + 42);
+    assert!(both[local..].starts_with(b"PK\x03\x04"));
+    assert_eq!(&both[local + 30..local + 39], b"hello.txt");
+}
+
+// carrier.gif with a plain text grid for each (left, top, width, height)
+fn with_text_grids(dir: &Path, screen: (u16, u16), grids: &[(u16, u16, u16, u16)]) {
+    let mut gif = parse_gif_bytes(&fs::read(dir.join("carrier.gif")).unwrap()).unwrap();
+    gif.logical_screen_descriptor.width = screen.0;
+    gif.logical_screen_descriptor.height = screen.1;
+    // Plain text is drawn with a global table of black and white
+    let packed_field = &mut gif.logical_screen_descriptor.packed_field;
+    *packed_field = *packed_field & 0b0111_1000 | 0b1000_0000;
+    gif.global_color_table = Some(ColorTable {
+        colors: vec![[0; 3], [0xFF; 3]],
+    });
+    gif.plain_text_extensions = grids
+        .iter()
+        .map(|&(left, top, width, height)| PlainTextExtension {
+            block_size: 12,
+            text_grid_left_position: left,
+            text_grid_top_position: top,
+            text_grid_width: width,
+            text_grid_height: height,
+            character_cell_width: 6,
+            character_cell_height: 8,
+            text_foreground_color_index: 1,
+            text_background_color_index: 0,
+            plain_text_data: b"Hi".to_vec(),
+        })
+        .collect();
+    fs::write(dir.join("text.gif"), write_gif_bytes(&gif, None)).unwrap();
+}
+
+#[test]
+fn plain_text_is_drawn_within_the_screen() {
+    let dir = setup("render-text");
+    with_text_grids(&dir, (96, 96), &[(0, 0, 12, 8), (90, 92, 60, 60)]);
+    run(&dir, "render-text text.gif drawn.gif");
+    let drawn = parse_gif_bytes(&fs::read(dir.join("drawn.gif")).unwrap()).unwrap();
+    assert!(drawn.plain_text_extensions.is_empty());
+    // Each grid goes before the frame it came before
+    assert_eq!(drawn.image_descriptors.len(), 5);
+    let first = &drawn.image_descriptors[0];
+    assert_eq!((first.width, first.height), (12, 8));
+    let pixels = first.pixels_in_row_order();
+    // The H has its left stroke in the first column, the i has none there
+    assert_eq!(pixels[12], 1);
+    assert_eq!(pixels[12 + 6], 0);
+    assert!(pixels[6..12].contains(&1));
+    // A grid hanging over the edge is cut off there
+    let second = &drawn.image_descriptors[2];
+    assert_eq!((second.width, second.height), (6, 4));
+}
+
+#[test]
+fn text_grids_off_the_screen_or_too_big_are_refused() {
+    let dir = setup("render-text-refused");
+    with_text_grids(&dir, (96, 96), &[(96, 0, 8, 8)]);
+    assert!(fail(&dir, "render-text text.gif drawn.gif").contains("outside"));
+
+    // A few bytes ask for billions of pixels
+    let most = u16::MAX;
+    with_text_grids(&dir, (most, most), &[(0, 0, most, most); 3]);
+    assert!(fail(&dir, "render-text text.gif drawn.gif").contains("pixels"));
+    assert!(!dir.join("drawn.gif").exists());
+}
+
+// `gif` rewritten as `out` after `edit` has had its way with it
+fn edited(dir: &Path, gif: &str, out: &str, edit: impl FnOnce(&mut GIF)) {
+    let mut parsed = parse_gif_bytes(&fs::read(dir.join(gif)).unwrap()).unwrap();
+    edit(&mut parsed);
+    fs::write(dir.join(out), write_gif_bytes(&parsed, None)).unwrap();
+}
+
+// Edit the bytes the comments hold, run together, keeping their length
+fn edit_comment_bytes(gif: &mut GIF, edit: impl FnOnce(&mut [u8])) {
+    let mut bytes: Vec<u8> = gif
+        .comment_extensions
+        .iter()
+        .flat_map(|comment| comment.comments.concat())
+        .collect();
+    edit(&mut bytes);
+    let mut rest = &bytes[..];
+    for block in gif
+        .comment_extensions
+        .iter_mut()
+        .flat_map(|comment| comment.comments.iter_mut())
+    {
+        let (this, next) = rest.split_at(block.len());
+        block.copy_from_slice(this);
+        rest = next;
+    }
+}
+
+// Embed with --channels auto, giving the channel picked and why once the
+// payload is extracted again with `extract`
+fn auto_channel(
+    dir: &Path,
+    carrier: &str,
+    payload: &str,
+    options: &str,
+    extract: &str,
+) -> (String, String) {
+    let output = gifsauce(
+        dir,
+        &format!(
+            "embed {carrier} auto.gif --payload {payload} --channels auto --force \
+             --report json {options}"
+        ),
+    );
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{report}");
+    // The first string in the field, which is all either holds here
+    let field = |name: &str| {
+        let start = report.find(&format!("\"{name}\":")).unwrap() + name.len() + 3;
+        report[start..].split('"').nth(1).unwrap().to_string()
+    };
+    assert_eq!(
+        extracted(dir, "auto.gif", extract),
+        fs::read(dir.join(payload)).unwrap()
+    );
+    (field("channels"), field("channel_choice"))
+}
+
+#[test]
+fn auto_channels_suit_the_carrier() {
+    let dir = setup("auto");
+    let (channel, why) = auto_channel(&dir, "carrier.gif", "small.txt", "", "");
+    assert_eq!(channel, "lsb");
+    assert!(why.contains("low bits"), "{why}");
+
+    // Too much for the pixels goes to an extension, among comments if the
+    // carrier has some to hide among
+    let huge: Vec<u8> = (0..5000u32).map(|i| (i * 31 % 251) as u8).collect();
+    fs::write(dir.join("huge.bin"), huge).unwrap();
+    let (channel, why) = auto_channel(&dir, "carrier.gif", "huge.bin", "--no-compress", "");
+    assert_eq!(channel, "appext");
+    assert!(why.contains("more than the pixels hold"), "{why}");
+    run(&dir, "comment add carrier.gif commented.gif hi");
+    let (channel, why) = auto_channel(&dir, "commented.gif", "huge.bin", "--no-compress", "");
+    assert_eq!(channel, "comment");
+    assert!(why.contains("1 comment(s)"), "{why}");
+
+    // --stealth can't have the extension named after GifSauce
+    #[cfg(feature = "crypto")]
+    {
+        let (channel, why) = auto_channel(
+            &dir,
+            "carrier.gif",
+            "huge.bin",
+            "--no-compress --passphrase secret --stealth",
+            "--passphrase secret",
+        );
+        assert_eq!(channel, "comment");
+        assert!(why.contains("rules out the appext channel"), "{why}");
+    }
+
+    // A carrier without frames has no pixels to hold even a small payload
+    edited(&dir, "carrier.gif", "empty.gif", |gif| {
+        gif.image_descriptors.clear();
+    });
+    let (channel, why) = auto_channel(&dir, "empty.gif", "small.txt", "", "");
+    assert_eq!(channel, "appext");
+    assert!(why.contains("(0 bytes)"), "{why}");
+
+    let error = fail(
+        &dir,
+        "embed carrier.gif bad.gif --payload small.txt --channels auto,lsb",
+    );
+    assert!(error.contains("Unknown channel: auto"), "{error}");
+}
+
+#[test]
+fn partial_extraction_maps_what_was_lost() {
+    let dir = setup("partial");
+    run(
+        &dir,
+        "embed carrier.gif stego.gif --payload large.bin --channels comment --no-padding",
+    );
+    edited(&dir, "stego.gif", "damaged.gif", |gif| {
+        gif.comment_extensions.remove(4);
+    });
+    // Without --partial a damaged payload is not found
+    fail(&dir, "extract damaged.gif -o out.bin");
+
+    // The report goes to stderr
+    let output = gifsauce(
+        &dir,
+        "extract damaged.gif -o out.bin --partial --placeholder 0 --report json",
+    );
+    assert!(output.status.success());
+    let report = String::from_utf8(output.stderr).unwrap();
+    let lost = report
+        .split("\"lost\":[")
+        .nth(1)
+        .unwrap_or_else(|| panic!("no lost bytes in {report}"));
+    let lost: Vec<usize> = lost[..lost.find("]]").unwrap()]
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|number| !number.is_empty())
+        .map(|number| number.parse().unwrap())
+        .collect();
+    assert_eq!(lost.len(), 2, "{report}");
+    assert!(report.contains("\"lost_chunks\":1"), "{report}");
+
+    let large = fs::read(dir.join("large.bin")).unwrap();
+    let mut recovered = fs::read(dir.join("out.bin")).unwrap();
+    assert_eq!(recovered.len(), large.len());
+    assert!(recovered[lost[0]..lost[1]].iter().all(|&byte| byte == 0));
+    recovered[lost[0]..lost[1]].copy_from_slice(&large[lost[0]..lost[1]]);
+    assert_eq!(recovered, large);
+}
+
+#[test]
+fn partial_extraction_refuses_lengths_past_the_data() {
+    let dir = setup("partial-length");
+    run(
+        &dir,
+        "embed carrier.gif stego.gif --payload small.txt --channels comment",
+    );
+    // A header claiming 4 GiB of body
+    edited(&dir, "stego.gif", "patched.gif", |gif| {
+        let chunk = &mut gif.comment_extensions[0].comments[0];
+        assert!(chunk.starts_with(b"GSau"));
+        chunk[6..10].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+    });
+    fail(&dir, "extract patched.gif -o out.bin --partial");
+    assert!(!dir.join("out.bin").exists());
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn keyed_layouts_refuse_chunk_sizes() {
+    let dir = setup("chunk-size");
+    for options in ["", "--decoy decoy.txt --decoy-passphrase duress"] {
+        let error = fail(
+            &dir,
+            &format!(
+                "embed carrier.gif out.gif --payload small.txt --passphrase secret \
+                 --chunk-size 16 {options}"
+            ),
+        );
+        assert!(error.contains("--chunk-size"), "{error}");
+    }
+    let error = fail(
+        &dir,
+        "embed carrier.gif out.gif --payload small.txt --passphrase secret --stealth \
+         --decoy decoy.txt --decoy-passphrase duress",
+    );
+    assert!(error.contains("--decoy"), "{error}");
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn detect_triages_a_directory() {
+    let dir = setup("detect");
+    fs::create_dir(dir.join("triage")).unwrap();
+    run(
+        &dir,
+        "embed carrier.gif triage/plain.gif --payload small.txt --channels comment",
+    );
+    run(
+        &dir,
+        "embed carrier.gif triage/sealed.gif --payload small.txt --channels plaintext \
+         --passphrase secret",
+    );
+    fs::copy(dir.join("carrier.gif"), dir.join("triage/clean.gif")).unwrap();
+    // A file cut short, and one that isn't a GIF at all
+    let plain = fs::read(dir.join("triage/plain.gif")).unwrap();
+    fs::write(dir.join("triage/cut.gif"), &plain[..plain.len() / 2]).unwrap();
+    fs::write(dir.join("triage/junk.gif"), b"GIF89a\xff\xff").unwrap();
+
+    // Unreadable files fail the run, but not before the others are reported
+    let output = gifsauce(&dir, "detect triage");
+    assert_eq!(output.status.code(), Some(1));
+    let report = String::from_utf8(output.stdout).unwrap();
+    let line = |name: &str| {
+        report
+            .lines()
+            .find(|line| line.contains(name))
+            .unwrap_or_else(|| panic!("no {name} in {report}"))
+            .to_string()
+    };
+    assert!(line("plain.gif").contains("channel=comment version=5 length="));
+    assert!(line("plain.gif").contains("encrypted=no compressed=no"));
+    assert!(line("sealed.gif").contains("channel=plaintext"));
+    assert!(line("sealed.gif").contains("encrypted=yes"));
+    assert!(line("clean.gif").ends_with("no payload"));
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert!(errors.contains("junk.gif"), "{errors}");
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn analyze_flags_carriers_with_a_payload() {
+    let dir = setup("analyze");
+    assert!(run(&dir, "analyze carrier.gif").ends_with("Nothing looks suspicious\n"));
+
+    run(
+        &dir,
+        "embed carrier.gif pixels.gif --payload large.bin --channels lsb",
+    );
+    let report = run(&dir, "analyze pixels.gif");
+    assert!(report.contains("Suspicious:\n"), "{report}");
+    assert!(report.contains("Index pairs are evened out"), "{report}");
+
+    run(
+        &dir,
+        "embed carrier.gif comments.gif --payload large.bin --channels comment \
+         --passphrase secret",
+    );
+    let report = run(&dir, "analyze comments.gif");
+    assert!(report.contains("Suspicious:\n"), "{report}");
+    assert!(report.contains("looks compressed or encrypted"), "{report}");
+}
+
+#[test]
+fn scrubbed_pixels_carry_no_lsb_payload() {
+    let dir = setup("scrub");
+    run(
+        &dir,
+        "embed carrier.gif stego.gif --payload small.txt --channels lsb",
+    );
+    assert!(run(&dir, "detect stego.gif").contains("channel=lsb"));
+    run(&dir, "sanitize stego.gif clean.gif --scrub-pixels");
+
+    let error = fail(&dir, "extract clean.gif -o out.bin --force");
+    assert!(error.contains("No payload found"), "{error}");
+    assert!(run(&dir, "detect clean.gif").ends_with("no payload\n"));
+    for frame in frames_of(&dir, "clean.gif") {
+        assert!(frame.image_data.iter().all(|pixel| pixel & 1 == 0));
+    }
+}
+
+// The frames of `gif`, parsed
+fn frames_of(dir: &Path, gif: &str) -> Vec<gifsauce::ImageDescriptor> {
+    parse_gif_bytes(&fs::read(dir.join(gif)).unwrap())
+        .unwrap()
+        .image_descriptors
+}
+
+#[test]
+fn frame_selections_limit_embed_extract_and_inspect() {
+    let dir = setup("frames");
+    run(
+        &dir,
+        "embed carrier.gif picked.gif --payload small.txt --channels lsb --frames 1",
+    );
+    let (before, after) = (
+        frames_of(&dir, "carrier.gif"),
+        frames_of(&dir, "picked.gif"),
+    );
+    assert_eq!(before[0].image_data, after[0].image_data);
+    assert_ne!(before[1].image_data, after[1].image_data);
+    assert_eq!(before[2].image_data, after[2].image_data);
+    assert_eq!(extracted(&dir, "picked.gif", "--frames 1"), b"hello");
+    fail(&dir, "extract picked.gif -o out.bin --force --frames 2");
+
+    let summary = run(&dir, "inspect picked.gif --frames 1..");
+    assert!(summary.contains("Frames: 2 of 3 selected"), "{summary}");
+    let hashes = run(&dir, "inspect picked.gif --hash-frames --frames 0,2");
+    let indices: Vec<&str> = hashes
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    assert_eq!(indices, ["0", "2"]);
+    let blocks = run(&dir, "inspect picked.gif --blocks --frames 2");
+    assert_eq!(blocks.matches("Image Data").count(), 1);
+    assert!(blocks.contains("Trailer"), "{blocks}");
+
+    // A selection past the last frame is refused, not ignored
+    for command in [
+        "embed carrier.gif late.gif --payload small.txt --frames 7..",
+        "extract picked.gif -o out.bin --force --frames 3",
+        "inspect picked.gif --frames 5..",
+        "inspect picked.gif --blocks --frames 5..",
+        "inspect picked.gif --hash-frames --frames 5..",
+    ] {
+        let error = fail(&dir, command);
+        assert!(
+            error.contains("not among the 3 frame(s)"),
+            "{command}: {error}"
+        );
+    }
+    assert!(!dir.join("late.gif").exists());
+}
+
+#[test]
+fn expansion_adds_only_the_frames_needed() {
+    let dir = setup("expand");
+    let chunks = |gif: &str| {
+        parse_gif_bytes(&fs::read(dir.join(gif)).unwrap())
+            .unwrap()
+            .plain_text_extensions
+            .len()
+    };
+    let mut sizes = Vec::new();
+    for expansion in ["duplicate", "minimal", "tiny-frames"] {
+        let out = format!("{expansion}.gif");
+        run(
+            &dir,
+            &format!(
+                "embed carrier.gif {out} --payload large.bin --channels plaintext \
+                 --no-padding --no-compress --expand {expansion}"
+            ),
+        );
+        // One frame for every chunk, the carrier's three first
+        let frames = frames_of(&dir, &out);
+        assert_eq!(frames.len(), chunks(&out));
+        for added in &frames[3..] {
+            let gce = added.graphics_control_extension.as_ref().unwrap();
+            assert_eq!(gce.delay_time, 0);
+            if expansion != "duplicate" {
+                assert_eq!((added.width, added.height), (1, 1));
+                assert!(added.transparent_color_index().is_some());
+            }
+        }
+        assert_eq!(
+            extracted(&dir, &out, ""),
+            fs::read(dir.join("large.bin")).unwrap()
+        );
+        sizes.push(fs::metadata(dir.join(&out)).unwrap().len());
+    }
+    // 1x1 frames cost a few bytes where copies cost a frame each
+    assert!(sizes[1] * 2 < sizes[0], "{sizes:?}");
+    assert!(sizes[2] * 2 < sizes[0], "{sizes:?}");
+
+    // A carrier without frames has none to copy, and its plain text blocks
+    // stand on their own
+    edited(&dir, "carrier.gif", "empty.gif", |gif| {
+        gif.image_descriptors.clear();
+    });
+    run(
+        &dir,
+        "embed empty.gif out.gif --payload large.bin --channels plaintext --no-compress",
+    );
+    assert!(frames_of(&dir, "out.gif").is_empty());
+    assert_eq!(
+        extracted(&dir, "out.gif", ""),
+        fs::read(dir.join("large.bin")).unwrap()
+    );
+}
+
+// What each frame of `gif` shows: its pixels and the colors they index
+fn pictures_of(dir: &Path, gif: &str) -> Vec<(Vec<u8>, Option<gifsauce::ColorTable>)> {
+    frames_of(dir, gif)
+        .into_iter()
+        .map(|frame| (frame.image_data, frame.local_color_table))
+        .collect()
+}
+
+#[test]
+fn frame_edits_keep_the_frames_they_keep() {
+    let dir = setup("frame-edits");
+    let original = pictures_of(&dir, "carrier.gif");
+    let picked = |indices: &[usize]| -> Vec<_> {
+        indices
+            .iter()
+            .map(|&index| original[index].clone())
+            .collect()
+    };
+
+    run(&dir, "frame delete carrier.gif deleted.gif 1");
+    assert_eq!(pictures_of(&dir, "deleted.gif"), picked(&[0, 2]));
+    run(&dir, "frame extract carrier.gif extracted.gif 2");
+    assert_eq!(pictures_of(&dir, "extracted.gif"), picked(&[2]));
+    run(&dir, "frame move carrier.gif moved.gif 0 2");
+    assert_eq!(pictures_of(&dir, "moved.gif"), picked(&[1, 2, 0]));
+    run(&dir, "frame move moved.gif back.gif 2 0");
+    assert_eq!(pictures_of(&dir, "back.gif"), original);
+    run(&dir, "reverse carrier.gif pingpong.gif --pingpong");
+    assert_eq!(pictures_of(&dir, "pingpong.gif"), picked(&[0, 1, 2, 1]));
+
+    let error = fail(&dir, "frame delete carrier.gif bad.gif 3");
+    assert!(!error.is_empty());
+    assert!(!dir.join("bad.gif").exists());
+}
+
+#[test]
+fn split_parts_join_back_to_the_animation() {
+    let dir = setup("split");
+    let original = pictures_of(&dir, "carrier.gif");
+    run(&dir, "split carrier.gif --every 2 -o part_%d.gif");
+    let parts = [
+        pictures_of(&dir, "part_0.gif"),
+        pictures_of(&dir, "part_1.gif"),
+    ];
+    assert_eq!(parts.concat(), original);
+    assert!(!dir.join("part_2.gif").exists());
+
+    // Ranges running past the end stop at the last frame, but one starting
+    // there selects nothing
+    run(&dir, "split carrier.gif --frames 1.. -o tail_%d.gif");
+    assert_eq!(pictures_of(&dir, "tail_0.gif"), original[1..]);
+    run(&dir, "frame delete carrier.gif two.gif 0");
+    for ranges in ["5..", "2..4"] {
+        let error = fail(
+            &dir,
+            &format!("split two.gif --frames {ranges} -o two_%d.gif"),
+        );
+        assert!(error.contains("start past the last frame"), "{error}");
+        assert!(error.contains("(2 frames)"), "{error}");
+    }
+    assert!(!dir.join("two_0.gif").exists());
+}
+
+#[test]
+fn transforms_resize_and_crop_every_frame() {
+    let dir = setup("transform");
+    let original = frames_of(&dir, "carrier.gif");
+    run(&dir, "transform carrier.gif whole.gif --crop 0,0,96,96");
+    assert_eq!(
+        pictures_of(&dir, "whole.gif"),
+        pictures_of(&dir, "carrier.gif")
+    );
+
+    run(&dir, "transform carrier.gif small.gif --resize 48x48");
+    run(&dir, "transform carrier.gif cropped.gif --crop 8,8,32,16");
+    for (gif, (width, height)) in [("small.gif", (48, 48)), ("cropped.gif", (32, 16))] {
+        let screen = parse_gif_bytes(&fs::read(dir.join(gif)).unwrap())
+            .unwrap()
+            .logical_screen_descriptor;
+        assert_eq!((screen.width, screen.height), (width, height), "{gif}");
+        let frames = frames_of(&dir, gif);
+        assert_eq!(frames.len(), original.len(), "{gif}");
+        for frame in frames {
+            assert_eq!((frame.width, frame.height), (width, height), "{gif}");
+            assert_eq!(frame.image_data.len(), width as usize * height as usize);
+        }
+    }
+
+    let error = fail(&dir, "transform carrier.gif bad.gif --crop 90,90,20,20");
+    assert!(!error.is_empty());
+    assert!(!dir.join("bad.gif").exists());
+}
+
+#[test]
+fn normalized_delays_come_out_uniform() {
+    let dir = setup("delays");
+    let delays = |gif: &str| -> Vec<u16> {
+        frames_of(&dir, gif)
+            .iter()
+            .map(|frame| {
+                frame
+                    .graphics_control_extension
+                    .as_ref()
+                    .unwrap()
+                    .delay_time
+            })
+            .collect()
+    };
+    let with_delays = |out: &str, chosen: [u16; 3]| {
+        edited(&dir, "carrier.gif", out, |gif| {
+            for (frame, delay) in gif.image_descriptors.iter_mut().zip(chosen) {
+                frame
+                    .graphics_control_extension
+                    .as_mut()
+                    .unwrap()
+                    .delay_time = delay;
+            }
+        });
+    };
+
+    // Delays players would speed up take the floor
+    with_delays("fast.gif", [0, 1, 10]);
+    run(
+        &dir,
+        "transform fast.gif floored.gif --normalize-delays --min-delay 10",
+    );
+    assert_eq!(delays("floored.gif"), [10, 10, 10]);
+
+    // Frames ending 3, 7 and 12 hundredths in move onto ticks of 4
+    with_delays("uneven.gif", [3, 4, 5]);
+    run(
+        &dir,
+        "transform uneven.gif ticked.gif --normalize-delays --fps 25",
+    );
+    assert_eq!(delays("ticked.gif"), [4, 4, 4]);
+}
+
+// The "#rrggbb" colors of a JSON palette
+fn json_colors(json: &str) -> Vec<[u8; 3]> {
+    json.split('"')
+        .filter_map(|field| field.strip_prefix('#'))
+        .map(|hex| {
+            let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).unwrap();
+            [channel(0), channel(2), channel(4)]
+        })
+        .collect()
+}
+
+#[test]
+fn palettes_export_and_import_color_tables() {
+    let dir = setup("palette");
+    let original = pictures_of(&dir, "carrier.gif");
+    let json = run(&dir, "palette export carrier.gif --frame 1 --format json");
+    assert_eq!(json_colors(&json), original[1].1.as_ref().unwrap().colors);
+    let gpl = run(&dir, "palette export carrier.gif --frame 0");
+    assert!(gpl.starts_with("GIMP Palette\n"), "{gpl}");
+    let [red, green, blue] = original[0].1.as_ref().unwrap().colors[5];
+    assert!(
+        gpl.contains(&format!("{red:3} {green:3} {blue:3}\tIndex 5\n")),
+        "{gpl}"
+    );
+
+    let colors = [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 0, 255]];
+    let text: String = colors
+        .iter()
+        .map(|[red, green, blue]| format!("{red} {green} {blue}\n"))
+        .collect();
+    fs::write(dir.join("four.gpl"), format!("GIMP Palette\n#\n{text}")).unwrap();
+    run(
+        &dir,
+        "palette import carrier.gif four.gpl out.gif --frame 2",
+    );
+    let imported = pictures_of(&dir, "out.gif");
+    assert_eq!(imported[..2], original[..2]);
+    assert_eq!(imported[2].1.as_ref().unwrap().colors, colors);
+    assert!(imported[2].0.iter().all(|&pixel| pixel < 4));
+    let json = run(&dir, "palette export out.gif --frame 2 -o - --format json");
+    assert_eq!(json_colors(&json), colors);
+}
+
+#[test]
+fn captions_keep_the_frames_and_extension_payloads() {
+    let dir = setup("caption");
+    run(
+        &dir,
+        "embed carrier.gif stego.gif --payload medium.bin --channels comment",
+    );
+    run(
+        &dir,
+        "caption stego.gif captioned.gif --text HELLO --pos top --frames 0..2",
+    );
+    let screen = parse_gif_bytes(&fs::read(dir.join("captioned.gif")).unwrap())
+        .unwrap()
+        .logical_screen_descriptor;
+    assert_eq!((screen.width, screen.height), (96, 96));
+    let before = frames_of(&dir, "stego.gif");
+    let after = frames_of(&dir, "captioned.gif");
+    assert_eq!(after.len(), before.len());
+    for frame in &after {
+        assert_eq!((frame.width, frame.height), (96, 96));
+    }
+    // Only the frames picked carry the caption
+    let shown = |frame: &gifsauce::ImageDescriptor| {
+        let colors = &frame.local_color_table.as_ref().unwrap().colors;
+        frame
+            .image_data
+            .iter()
+            .map(|&pixel| colors[pixel as usize])
+            .collect::<Vec<_>>()
+    };
+    assert_ne!(shown(&after[0]), shown(&before[0]));
+    assert_ne!(shown(&after[1]), shown(&before[1]));
+    assert_eq!(shown(&after[2]), shown(&before[2]));
+    assert_eq!(
+        extracted(&dir, "captioned.gif", ""),
+        fs::read(dir.join("medium.bin")).unwrap()
+    );
+}
+
+#[test]
+fn previews_draw_every_frame_in_place() {
+    let dir = setup("preview");
+    // 96 pixels fitted to 24 columns, two rows of pixels to a line
+    let blocks = run(&dir, "preview carrier.gif --protocol blocks --width 24");
+    assert!(blocks.starts_with(&format!("{}\x1b[12A\x1b7", "\n".repeat(12))));
+    assert!(blocks.ends_with("\x1b8\x1b[12B\n"));
+    assert_eq!(blocks.matches("\x1b8").count(), 3 + 1);
+    assert_eq!(blocks.matches('\u{2580}').count(), 3 * 24 * 12);
+
+    let sixels = run(&dir, "preview carrier.gif --protocol sixel");
+    assert_eq!(sixels.matches("\x1bP0;0;0q\"1;1;96;96").count(), 3);
+    let kitty = run(&dir, "preview carrier.gif --protocol kitty");
+    assert_eq!(
+        kitty
+            .matches("\x1b_Ga=T,i=1,q=2,C=1,f=32,s=96,v=96,")
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn sheets_lay_the_frames_out_in_a_grid() {
+    let dir = setup("sheet");
+    let gif = parse_gif_bytes(&fs::read(dir.join("carrier.gif")).unwrap()).unwrap();
+    let sheet = |every, columns| {
+        let (width, height, rgba) = contact_sheet(&gif, every, columns).unwrap();
+        assert_eq!(rgba.len(), width * height * 4);
+        (width, height, rgba)
+    };
+    // Three 96x96 frames two to a row, 2 pixels apart, the gaps and the
+    // cell no frame fills left transparent
+    let (width, height, rgba) = sheet(1, 2);
+    assert_eq!((width, height), (2 * 96 + 2, 2 * 96 + 2));
+    let alpha = |x: usize, y: usize| rgba[(y * width + x) * 4 + 3];
+    assert_eq!(alpha(95, 0), 0xFF);
+    assert_eq!(alpha(96, 0), 0);
+    assert_eq!(alpha(0, 96), 0);
+    assert_eq!(alpha(0, 98), 0xFF);
+    assert_eq!(alpha(150, 150), 0);
+
+    // Columns past the frame count make one row
+    let (width, height, _) = sheet(1, 8);
+    assert_eq!((width, height), (3 * 96 + 2 * 2, 96));
+    // Frames 0 and 2
+    let (width, height, _) = sheet(2, 1);
+    assert_eq!((width, height), (96, 2 * 96 + 2));
+}
+
+// A server that announces `length` bytes of GIF and sends six
+#[cfg(feature = "network")]
+fn announce(length: u64) -> u16 {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0; 1024]);
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: image/gif\r\nContent-Length: {length}\r\n\r\nGIF89a"
+        );
+        let _ = stream.write_all(head.as_bytes());
+    });
+    port
+}
+
+#[cfg(feature = "network")]
+#[test]
+fn announced_lengths_are_checked_without_overflowing() {
+    let dir = setup("announced");
+    for channels in ["lsb", "lsb,background"] {
+        let port = announce(u64::MAX);
+        let error = fail(
+            &dir,
+            &format!(
+                "embed --input-url http://127.0.0.1:{port}/carrier.gif out.gif \
+                 --payload small.txt --channels {channels} --max-output-size 1000000"
+            ),
+        );
+        assert!(error.contains("over the limit of 1000000 bytes"), "{error}");
+    }
+    assert!(!dir.join("out.gif").exists());
+}
+
+// A serve process, killed when the test is done with it
+#[cfg(feature = "server")]
+struct Served(std::process::Child, u16);
+
+#[cfg(feature = "server")]
+impl Served {
+    fn start(dir: &Path) -> Served {
+        // A port nothing listens on, as far as can be told
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_GifSauce"))
+            .current_dir(dir)
+            .args(["serve", "--listen", &format!("127.0.0.1:{port}"), "-q"])
+            .spawn()
+            .unwrap();
+        let served = Served(child, port);
+        for _ in 0..100 {
+            if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return served;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        panic!("serve never listened on {port}");
+    }
+
+    // The status and body of the answer to `head` and `body`
+    fn request(&self, head: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        use std::io::{Read, Write};
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", self.1)).unwrap();
+        stream
+            .write_all(format!("{head}\r\nConnection: close\r\n\r\n").as_bytes())
+            .unwrap();
+        stream.write_all(body).unwrap();
+        let mut answer = Vec::new();
+        stream.read_to_end(&mut answer).unwrap();
+        let split = answer.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let status = String::from_utf8_lossy(&answer[9..12]).parse().unwrap();
+        (status, answer[split + 4..].to_vec())
+    }
+
+    // A POST of `parts`, each a name, a file name and its data
+    fn post(&self, path: &str, parts: &[(&str, Option<&str>, &[u8])]) -> (u16, Vec<u8>) {
+        let mut body = Vec::new();
+        for (name, filename, data) in parts {
+            body.extend_from_slice(b"--XYZ\r\nContent-Disposition: form-data; ");
+            body.extend_from_slice(format!("name=\"{name}\"").as_bytes());
+            if let Some(filename) = filename {
+                body.extend_from_slice(format!("; filename=\"{filename}\"").as_bytes());
+            }
+            body.extend_from_slice(b"\r\n\r\n");
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--XYZ--\r\n");
+        self.request(
+            &format!(
+                "POST {path} HTTP/1.1\r\nHost: localhost\r\n\
+                 Content-Type: multipart/form-data; boundary=XYZ\r\nContent-Length: {}",
+                body.len()
+            ),
+            &body,
+        )
+    }
+}
+
+#[cfg(feature = "server")]
+impl Drop for Served {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn serve_answers_embed_extract_and_inspect() {
+    let dir = setup("serve");
+    let served = Served::start(&dir);
+    let carrier = fs::read(dir.join("carrier.gif")).unwrap();
+
+    let (status, stego) = served.post(
+        "/embed",
+        &[
+            ("gif", Some("carrier.gif"), &carrier),
+            ("payload", Some("small.txt"), b"hello"),
+            ("channels", None, b"comment"),
+            ("passphrase", None, b"secret"),
+        ],
+    );
+    assert_eq!(status, 200, "{}", String::from_utf8_lossy(&stego));
+    assert!(stego.starts_with(b"GIF89a"));
+
+    let (status, body) = served.post(
+        "/extract",
+        &[("gif", None, &stego), ("passphrase", None, b"secret")],
+    );
+    let json = String::from_utf8(body).unwrap();
+    assert_eq!(status, 200, "{json}");
+    assert!(json.contains("\"name\":\"small.txt\""), "{json}");
+    assert!(json.contains("\"data\":\"aGVsbG8=\""), "{json}");
+
+    let (status, body) = served.post("/inspect", &[("gif", None, &stego)]);
+    let json = String::from_utf8(body).unwrap();
+    assert_eq!(status, 200, "{json}");
+    assert!(json.contains("\"frames\":3"), "{json}");
+    assert!(json.contains("\"encrypted\":true"), "{json}");
+
+    // Without the passphrase the payload is found but not handed out, and a
+    // GIF without one has nothing to hand out
+    let (status, _) = served.post("/extract", &[("gif", None, &stego)]);
+    assert_eq!(status, 403);
+    let (status, _) = served.post("/extract", &[("gif", None, &carrier)]);
+    assert_eq!(status, 404);
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn serve_refuses_malformed_requests() {
+    let dir = setup("serve-malformed");
+    let served = Served::start(&dir);
+    let error = |(status, body): (u16, Vec<u8>)| {
+        let json = String::from_utf8(body).unwrap();
+        assert!(json.starts_with("{\"error\":"), "{json}");
+        status
+    };
+
+    assert_eq!(error(served.post("/nowhere", &[])), 404);
+    assert_eq!(
+        error(served.request("GET /inspect HTTP/1.1\r\nHost: localhost", b"")),
+        405
+    );
+    assert_eq!(error(served.post("/inspect", &[])), 400);
+    assert_eq!(
+        error(served.post("/inspect", &[("gif", None, b"GIF89a\xff")])),
+        400
+    );
+    assert_eq!(
+        error(served.post("/embed", &[("gif", None, b"GIF89a")])),
+        400
+    );
+    // Not multipart, no boundary, and a body cut off before its last part
+    let post = |headers: &str, body: &[u8]| {
+        served.request(
+            &format!(
+                "POST /inspect HTTP/1.1\r\nHost: localhost\r\n{headers}\r\nContent-Length: {}",
+                body.len()
+            ),
+            body,
+        )
+    };
+    assert_eq!(error(post("Content-Type: text/plain", b"gif")), 400);
+    assert_eq!(error(post("Content-Type: multipart/form-data", b"")), 400);
+    assert_eq!(
+        error(post(
+            "Content-Type: multipart/form-data; boundary=XYZ",
+            b"--XYZ\r\nContent-Disposition: form-data; name=\"gif\"\r\n\r\nGIF"
+        )),
+        400
+    );
+    // Refused on its announced length, before any of it is sent
+    assert_eq!(
+        error(served.request(
+            "POST /inspect HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: multipart/form-data; boundary=XYZ\r\nContent-Length: 1000000000",
+            b""
+        )),
+        413
+    );
+}
+
+// A secret-tool that knows the passphrase of key id "work", and one that
+// isn't UTF-8 under "binary"
+#[cfg(all(feature = "crypto", unix, not(target_os = "macos")))]
+fn fake_secret_tool(dir: &Path) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let bin = dir.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let script = bin.join("secret-tool");
+    fs::write(
+        &script,
+        "#!/bin/sh\n\
+         # secret-tool lookup service gifsauce key-id ID\n\
+         case \"$5\" in\n\
+         work) printf 'secret\\n' ;;\n\
+         binary) printf '\\377\\376' ;;\n\
+         *) exit 1 ;;\n\
+         esac\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    bin.to_string_lossy().into_owned()
+}
+
+#[cfg(all(feature = "crypto", unix, not(target_os = "macos")))]
+#[test]
+fn key_ids_look_passphrases_up_in_the_keychain() {
+    let dir = setup("keychain");
+    let path = fake_secret_tool(&dir);
+    let with_keychain = |command: &str| gifsauce_with_env(&dir, command, &[("PATH", &path)]);
+
+    let output = with_keychain(
+        "embed carrier.gif stego.gif --payload small.txt --channels comment --key-id work",
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // The newline the tool ends with isn't part of the passphrase
+    assert_eq!(
+        extracted(&dir, "stego.gif", "--passphrase secret"),
+        b"hello"
+    );
+
+    let error = |output: Output| {
+        assert!(!output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let missing = error(with_keychain("extract stego.gif -o lost.bin --key-id home"));
+    assert!(missing.contains("No passphrase for home"), "{missing}");
+    assert!(missing.contains("secret-tool store"), "{missing}");
+    let binary = error(with_keychain(
+        "extract stego.gif -o lost.bin --key-id binary",
+    ));
+    assert!(binary.contains("isn't UTF-8"), "{binary}");
+    // Without the tool there is no keychain to ask
+    let empty = dir.join("empty");
+    fs::create_dir(&empty).unwrap();
+    let uninstalled = error(gifsauce_with_env(
+        &dir,
+        "extract stego.gif -o lost.bin --key-id work",
+        &[("PATH", &empty.to_string_lossy())],
+    ));
+    assert!(uninstalled.contains("isn't installed"), "{uninstalled}");
+    assert!(!dir.join("lost.bin").exists());
+}
+
+// Run `command` with a terminal of its own, typing each of `lines` once the
+// prompt before it is shown. Returns how it went and what the terminal showed.
+#[cfg(all(feature = "crypto", target_os = "linux"))]
+fn at_terminal(dir: &Path, command: &str, lines: &[&str]) -> (Output, String) {
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    let (mut master, mut slave) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_GifSauce"));
+    child
+        .current_dir(dir)
+        .args(command.split_whitespace())
+        .arg("-q")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // The terminal becomes the controlling one of a session of its own
+    unsafe {
+        child.pre_exec(move || {
+            if libc::setsid() < 0 || libc::ioctl(slave, libc::TIOCSCTTY, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = child.spawn().unwrap();
+    unsafe { libc::close(slave) };
+    let mut terminal = unsafe { fs::File::from_raw_fd(master) };
+
+    let mut screen = Vec::new();
+    for line in lines {
+        // Wait for the prompt, then for echo to be turned off behind it
+        while !screen.ends_with(b": ") {
+            let mut byte = [0];
+            if terminal.read(&mut byte).unwrap_or(0) == 0 {
+                break;
+            }
+            screen.push(byte[0]);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        terminal.write_all(format!("{line}\n").as_bytes()).unwrap();
+        screen.push(b'|');
+    }
+    let output = child.wait_with_output().unwrap();
+    // Once the program is gone, reading fails after what it left
+    let mut rest = [0; 1024];
+    while let Ok(read @ 1..) = terminal.read(&mut rest) {
+        screen.extend_from_slice(&rest[..read]);
+    }
+    (output, String::from_utf8_lossy(&screen).into_owned())
+}
+
+#[cfg(all(feature = "crypto", target_os = "linux"))]
+#[test]
+fn encrypt_asks_for_the_passphrase_at_the_terminal() {
+    let dir = setup("prompt");
+    let embed = "embed carrier.gif stego.gif --payload small.txt --channels comment --encrypt";
+    let (output, screen) = at_terminal(&dir, embed, &["typed secret", "typed secret"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(screen.contains("Passphrase: "), "{screen}");
+    assert!(screen.contains("Repeat passphrase: "), "{screen}");
+    // Nothing typed shows on screen
+    assert!(!screen.contains("typed"), "{screen}");
+
+    let (output, _) = at_terminal(
+        &dir,
+        "extract stego.gif -o out.bin --encrypt",
+        &["typed secret"],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(dir.join("out.bin")).unwrap(), b"hello");
+
+    // A typo in the repeat, and nothing typed at all
+    for (lines, message) in [
+        (&["typed secret", "typo secret"][..], "don't match"),
+        (&[""][..], "empty"),
+    ] {
+        let (output, _) = at_terminal(&dir, &embed.replace("stego", "other"), lines);
+        assert!(!output.status.success());
+        let error = String::from_utf8(output.stderr).unwrap();
+        assert!(error.contains(message), "{error}");
+    }
+    assert!(!dir.join("other.gif").exists());
+}
+
+// Without a terminal there is no one to ask
+#[cfg(all(feature = "crypto", unix))]
+#[test]
+fn encrypt_without_a_terminal_fails() {
+    use std::os::unix::process::CommandExt;
+
+    let dir = setup("no-terminal");
+    let output = unsafe {
+        Command::new(env!("CARGO_BIN_EXE_GifSauce"))
+            .current_dir(&*dir)
+            .args([
+                "embed",
+                "carrier.gif",
+                "stego.gif",
+                "--payload",
+                "small.txt",
+            ])
+            .args(["--encrypt", "-q"])
+            .pre_exec(|| match libc::setsid() {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            })
+            .output()
+            .unwrap()
+    };
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains("No terminal"), "{error}");
+    assert!(!dir.join("stego.gif").exists());
+}
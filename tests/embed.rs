@@ -0,0 +1,453 @@
+// Payloads embedded in random GIFs from the testkit come back out unchanged,
+// through every channel. proptest shrinks a failure to the smallest carrier
+// and payload that still fail.
+#![cfg(feature = "stego")]
+
+use gifsauce::{
+    embed_bytes, extract_bytes, hex, inspect_json, parse_gif_bytes, register_channel,
+    write_gif_bytes, Channel, ChannelKind, CommentExtension, GraphicsControlExtension,
+    ImageDescriptor, Options, DEFAULT_PADDING, FIRST_CUSTOM_CHANNEL_ID, GIF,
+};
+use gifsauce_core::testkit::{gif_fixture, random_gif, Shape};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use sha2::{Digest, Sha256};
+
+const CASES: u32 = 32;
+
+// Carriers no bigger than `most` allows, shrinking towards smaller shapes
+fn carriers_within(most: Shape) -> impl Strategy<Value = Vec<u8>> {
+    (
+        any::<u64>(),
+        0..=most.max_side,
+        0..=most.max_frames,
+        0..=most.max_extensions,
+        0..=most.max_sub_blocks,
+    )
+        .prop_map(
+            |(seed, max_side, max_frames, max_extensions, max_sub_blocks)| {
+                let shape = Shape {
+                    max_side,
+                    max_frames,
+                    max_extensions,
+                    max_sub_blocks,
+                };
+                gif_fixture(seed, &shape)
+            },
+        )
+}
+
+fn payloads() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..64)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    // Frames big enough for the lsb channel to hold a small payload
+    #[test]
+    fn embedded_payloads_extract_unchanged(
+        carrier in carriers_within(Shape {
+            max_side: 64,
+            ..Shape::default()
+        }),
+        payload in payloads()
+    ) {
+        for channels in [
+            "plaintext",
+            "comment",
+            "appext",
+            "lsb",
+            "trailer",
+            "comment,appext",
+        ] {
+            let options = Options {
+                channels: channels.to_string(),
+                name: "payload.txt".to_string(),
+                ..Options::default()
+            };
+            let output = match embed_bytes(&carrier, &payload, &options) {
+                Ok(output) => output,
+                Err(_) if channels == "lsb" => continue, // Too few pixels
+                Err(e) => panic!("{channels}: {e}"),
+            };
+            let extracted = extract_bytes(&output, &options)
+                .unwrap()
+                .unwrap_or_else(|| panic!("{channels}: no payload"));
+            prop_assert_eq!(&extracted.data, &payload, "{}", channels);
+            prop_assert_eq!(&extracted.name, "payload.txt", "{}", channels);
+        }
+    }
+}
+
+proptest! {
+    // Fewer cases, deriving keys for encrypted ones is slow
+    #![proptest_config(ProptestConfig::with_cases(CASES / 4))]
+
+    #[test]
+    fn small_payloads_take_one_extension(
+        carrier in carriers_within(Shape {
+            max_frames: 1,
+            max_extensions: 0,
+            ..Shape::default()
+        }),
+        payload in vec(any::<u8>(), 0..80)
+    ) {
+        let frames = parse_gif_bytes(&carrier).unwrap().image_descriptors.len();
+        for passphrase in [None, Some("secret".to_string())] {
+            for channels in ["plaintext", "comment"] {
+                let options = Options {
+                    channels: channels.to_string(),
+                    passphrase: passphrase.clone(),
+                    ..Options::default()
+                };
+                let output = embed_bytes(&carrier, &payload, &options).unwrap();
+                let gif = parse_gif_bytes(&output).unwrap();
+                let extensions = gif.plain_text_extensions.len() + gif.comment_extensions.len();
+                prop_assert_eq!(extensions, 1, "{}", channels);
+                prop_assert_eq!(gif.image_descriptors.len(), frames, "{}", channels);
+                let extracted = extract_bytes(&output, &options).unwrap().unwrap();
+                prop_assert_eq!(&extracted.data, &payload, "{}", channels);
+            }
+        }
+    }
+
+    #[test]
+    fn plain_payloads_record_their_digest(
+        carrier in carriers_within(Shape::default()),
+        payload in payloads()
+    ) {
+        let digest = hex(&Sha256::digest(&payload));
+        for passphrase in [None, Some("secret".to_string())] {
+            let options = Options {
+                passphrase: passphrase.clone(),
+                ..Options::default()
+            };
+            let output = embed_bytes(&carrier, &payload, &options).unwrap();
+            let json = inspect_json(&output, passphrase.as_deref()).unwrap();
+            // An encrypted payload gives nothing of its file away
+            let expected = match passphrase {
+                None => format!("\"sha256\":\"{digest}\""),
+                Some(_) => "\"sha256\":null".to_string(),
+            };
+            prop_assert!(json.contains(&expected), "{}", json);
+            let extracted = extract_bytes(&output, &options).unwrap().unwrap();
+            prop_assert_eq!(&extracted.data, &payload);
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    // What the extract fuzz target does, on carriers with a payload that
+    // were cut short or had bytes overwritten. Errors are fine, panics not.
+    #[test]
+    fn damaged_carriers_are_searched_without_panicking(
+        carrier in carriers_within(Shape::default()),
+        payload in payloads(),
+        cut in any::<proptest::sample::Index>(),
+        edits in vec((any::<proptest::sample::Index>(), any::<u8>()), 0..8)
+    ) {
+        for channels in ["plaintext", "comment", "appext", "trailer"] {
+            let options = Options {
+                channels: channels.to_string(),
+                ..Options::default()
+            };
+            let mut output = embed_bytes(&carrier, &payload, &options).unwrap();
+            for (position, byte) in &edits {
+                let position = position.index(output.len());
+                output[position] = *byte;
+            }
+            output.truncate(output.len() - cut.index(output.len() / 2 + 1));
+            let _ = extract_bytes(&output, &options);
+            let _ = inspect_json(&output, None);
+        }
+    }
+}
+
+// A channel of the test's own: the stream reversed after the trailer, behind
+// a marker
+struct ReversedTrailer;
+
+const REVERSED_MARKER: &[u8] = b"REV";
+
+impl Channel for ReversedTrailer {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Custom(FIRST_CUSTOM_CHANNEL_ID)
+    }
+
+    fn name(&self) -> &'static str {
+        "reversed"
+    }
+
+    fn embed(&self, gif: &mut GIF, data: &[u8]) -> Result<(), std::io::Error> {
+        gif.trailing_data = [
+            REVERSED_MARKER,
+            &data.iter().rev().copied().collect::<Vec<_>>(),
+        ]
+        .concat();
+        Ok(())
+    }
+
+    fn extract(&self, gif: &GIF) -> Option<Vec<u8>> {
+        let data = gif.trailing_data.strip_prefix(REVERSED_MARKER)?;
+        Some(data.iter().rev().copied().collect())
+    }
+
+    fn clear(&self, gif: &mut GIF, _length: usize) -> Result<(), std::io::Error> {
+        gif.trailing_data.clear();
+        Ok(())
+    }
+}
+
+#[test]
+fn registered_channels_carry_payloads() {
+    let kind = register_channel(Box::new(ReversedTrailer)).unwrap();
+    assert_eq!(kind, ChannelKind::Custom(FIRST_CUSTOM_CHANNEL_ID));
+    assert!(register_channel(Box::new(ReversedTrailer)).is_err());
+
+    let options = Options {
+        channels: "reversed".to_string(),
+        ..Options::default()
+    };
+    proptest!(
+        ProptestConfig::with_cases(CASES / 4),
+        |(carrier in carriers_within(Shape::default()), payload in payloads())| {
+            let output = embed_bytes(&carrier, &payload, &options).unwrap();
+            let gif = parse_gif_bytes(&output).unwrap();
+            prop_assert!(gif.trailing_data.starts_with(REVERSED_MARKER));
+            let extracted = extract_bytes(&output, &options).unwrap().unwrap();
+            prop_assert_eq!(extracted.data, payload);
+        }
+    );
+}
+
+// A 64x64 frame cycling through the four colors, with its own transparent index
+fn transparent_frame(transparent_color_index: u8) -> ImageDescriptor {
+    ImageDescriptor {
+        left: 0,
+        top: 0,
+        width: 64,
+        height: 64,
+        packed_field: 0,
+        graphics_control_extension: Some(GraphicsControlExtension {
+            packed_field: 0b1,
+            delay_time: 10,
+            transparent_color_index,
+        }),
+        local_color_table: None,
+        lzw_minimum_code_size: 2,
+        image_data: (0..64 * 64).map(|pixel| (pixel % 4) as u8).collect(),
+        compressed_range: None,
+    }
+}
+
+#[test]
+fn lsb_keeps_each_frames_transparent_pixels() {
+    let mut gif = random_gif(&mut ChaCha8Rng::seed_from_u64(7), &Shape::default());
+    gif.image_descriptors = vec![transparent_frame(2), transparent_frame(0)];
+    let carrier = write_gif_bytes(&gif, None);
+
+    let options = Options {
+        channels: "lsb".to_string(),
+        ..Options::default()
+    };
+    let payload = vec![0xA5; 16];
+    let output = embed_bytes(&carrier, &payload, &options).unwrap();
+    let stego = parse_gif_bytes(&output).unwrap();
+    for (before, after) in gif.image_descriptors.iter().zip(&stego.image_descriptors) {
+        let reserved = before.transparent_color_index().unwrap() & 0xFE;
+        for (old, new) in before.image_data.iter().zip(&after.image_data) {
+            if old & 0xFE == reserved {
+                assert_eq!(old, new, "frame with transparent index {reserved}");
+            }
+        }
+    }
+    let extracted = extract_bytes(&output, &options).unwrap().unwrap();
+    assert_eq!(extracted.data, payload);
+}
+
+// The length field from inspect_json
+fn stored_length(output: &[u8]) -> usize {
+    let json = inspect_json(output, None).unwrap();
+    let (_, rest) = json.split_once("\"length\":").unwrap();
+    rest.split(',').next().unwrap().parse().unwrap()
+}
+
+#[test]
+fn default_options_pad_as_the_command_line_does() {
+    let carrier = gif_fixture(3, &Shape::default());
+    for passphrase in [None, Some("secret".to_string())] {
+        let mut lengths = Vec::new();
+        for (padding, payload) in [
+            (Some(DEFAULT_PADDING), vec![1; 5]),
+            (Some(DEFAULT_PADDING), vec![2; 100]),
+            (None, vec![1; 5]),
+            (None, vec![2; 100]),
+        ] {
+            let options = Options {
+                channels: "comment".to_string(),
+                passphrase: passphrase.clone(),
+                padding,
+                ..Options::default()
+            };
+            let output = embed_bytes(&carrier, &payload, &options).unwrap();
+            lengths.push(stored_length(&output));
+            let extracted = extract_bytes(&output, &options).unwrap().unwrap();
+            assert_eq!(extracted.data, payload);
+        }
+        // An encrypted body is padded before it is sealed, only as far as
+        // the cipher's own rounding lets it
+        match passphrase {
+            None => assert_eq!(lengths, [DEFAULT_PADDING, DEFAULT_PADDING, 50, 145]),
+            Some(_) => assert!(lengths[0] >= lengths[2] && lengths[1] >= lengths[3]),
+        }
+    }
+}
+
+// Where the slot header `passphrase` opens places its payload in the
+// container's region: (offset, length, region size)
+#[cfg(feature = "crypto")]
+fn slot_of(container: &[u8], passphrase: &str) -> (usize, usize, usize) {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+    use gifsauce::{ContainerCipher, PayloadCipher};
+
+    let key = ContainerCipher
+        .stretch(passphrase, &container[..16])
+        .unwrap();
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let region = container.len() - 16 - 2 * 24;
+    (0..2)
+        .find_map(|slot| {
+            let mut nonce = [0u8; 12];
+            nonce[0] = slot as u8;
+            let header = &container[16 + slot * 24..16 + (slot + 1) * 24];
+            let location = cipher.decrypt(Nonce::from_slice(&nonce), header).ok()?;
+            let offset = u32::from_le_bytes(location[0..4].try_into().unwrap());
+            let length = u32::from_le_bytes(location[4..8].try_into().unwrap());
+            Some((offset as usize, length as usize, region))
+        })
+        .unwrap()
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn decoys_open_as_lone_payloads_would() {
+    use gifsauce::{ContainerCipher, PayloadCipher};
+
+    let (real, decoy) = ([1u8; 200], [2u8; 200]);
+    let mut offsets = (Vec::new(), Vec::new());
+    for seed in 0..6u8 {
+        let pair = ContainerCipher
+            .seal_seeded(&[("real", &real), ("duress", &decoy)], [seed; 32])
+            .unwrap();
+        let lone = ContainerCipher
+            .seal_seeded(&[("duress", &decoy)], [seed; 32])
+            .unwrap();
+        assert_eq!(pair.len(), lone.len());
+        let (pair_offset, pair_length, region) = slot_of(&pair, "duress");
+        let (lone_offset, lone_length, _) = slot_of(&lone, "duress");
+        assert_eq!(pair_length, lone_length);
+        offsets.0.push(pair_offset * 2 / region);
+        offsets.1.push(lone_offset * 2 / region);
+        if seed == 0 {
+            assert_eq!(ContainerCipher.open(&pair, "duress").unwrap(), decoy);
+            assert_eq!(ContainerCipher.open(&pair, "real").unwrap(), real);
+        }
+    }
+    // Either kind starts in both halves of the region, not at 0 only
+    for halves in [offsets.0, offsets.1] {
+        assert!(halves.contains(&0) && halves.contains(&1), "{halves:?}");
+    }
+}
+
+// Comments that read as the first chunk of an encrypted stream, each with a
+// layout of its own, are passed over for the one the layout puts first
+#[cfg(feature = "crypto")]
+#[test]
+fn chunks_that_only_look_like_stream_starts_are_passed_over() {
+    let carrier = gif_fixture(5, &Shape::default());
+    let options = Options {
+        channels: "comment".to_string(),
+        passphrase: Some("secret".to_string()),
+        ..Options::default()
+    };
+    let payload: Vec<u8> = (0..3000u32).map(|i| (i * 31 % 251) as u8).collect();
+    let output = embed_bytes(&carrier, &payload, &options).unwrap();
+    let mut gif = parse_gif_bytes(&output).unwrap();
+    let first = gif
+        .comment_extensions
+        .iter()
+        .map(|comment| comment.comments.concat())
+        .find(|chunk| chunk.starts_with(b"GSau"))
+        .unwrap();
+    for copy in 0..500u32 {
+        let mut forged = first.clone();
+        // The container's salt, right after the header, seeds the layout
+        forged[10..14].copy_from_slice(&copy.to_le_bytes());
+        gif.comment_extensions.push(CommentExtension {
+            comments: vec![forged],
+        });
+    }
+    let forged = write_gif_bytes(&gif, None);
+
+    let extracted = extract_bytes(&forged, &options).unwrap().unwrap();
+    assert_eq!(extracted.data, payload);
+    // A wrong passphrase tries every key there is before it gives up
+    let other = Options {
+        passphrase: Some("other".to_string()),
+        ..options.clone()
+    };
+    assert!(!matches!(extract_bytes(&forged, &other), Ok(Some(_))));
+}
+
+// The low bits of the delays of `gif`
+fn delay_bits(gif: &[u8]) -> Vec<u16> {
+    parse_gif_bytes(gif)
+        .unwrap()
+        .image_descriptors
+        .iter()
+        .map(|frame| {
+            frame
+                .graphics_control_extension
+                .as_ref()
+                .unwrap()
+                .delay_time
+                & 1
+        })
+        .collect()
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn delay_jitter_is_whitened_with_the_passphrase() {
+    let mut gif = random_gif(&mut ChaCha8Rng::seed_from_u64(9), &Shape::default());
+    let mut frame = transparent_frame(0);
+    (frame.width, frame.height, frame.image_data) = (1, 1, vec![0]);
+    gif.image_descriptors = vec![frame; 4000];
+    let carrier = write_gif_bytes(&gif, None);
+
+    let mut patterns = Vec::new();
+    for passphrase in ["one", "two"] {
+        let options = Options {
+            channels: "delay".to_string(),
+            passphrase: Some(passphrase.to_string()),
+            ..Options::default()
+        };
+        let output = embed_bytes(&carrier, b"hidden", &options).unwrap();
+        // Without the passphrase the jitter reads as no payload at all
+        assert!(inspect_json(&output, None)
+            .unwrap()
+            .ends_with("\"payload\":null}"));
+        let extracted = extract_bytes(&output, &options).unwrap().unwrap();
+        assert_eq!(extracted.data, b"hidden");
+        patterns.push(delay_bits(&output));
+    }
+    // Both streams start with the same header, and still come out unalike
+    let header = 8 * 10;
+    assert_ne!(patterns[0][..header], patterns[1][..header]);
+}
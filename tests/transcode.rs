@@ -0,0 +1,633 @@
+// The JPEG and lossless WebP decoders, on images the tests encode themselves,
+// and the animations the command line tool converts and optimizes.
+// Every JPEG block is flat or carries one horizontal cosine, so the pixels
+// it decodes to are known without a reference decoder.
+#![cfg(all(feature = "cli", feature = "image-io"))]
+
+use gifsauce::frames::{canvas_frame, composite_frames};
+use gifsauce::generate::{generate_carrier, Style};
+use gifsauce::jpeg::decode_jpeg;
+use gifsauce::transcode::{animation_to_gif, still_to_gif};
+use gifsauce::{parse_gif_bytes, write_gif_bytes, Dither, GIF};
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+// Bits written most significant first, with a zero byte stuffed after 0xFF
+#[derive(Default)]
+struct JpegBits {
+    bytes: Vec<u8>,
+    current: u8,
+    count: u32,
+}
+
+impl JpegBits {
+    fn push(&mut self, value: u32, length: u32) {
+        for bit in (0..length).rev() {
+            self.current = (self.current << 1) | ((value >> bit) & 1) as u8;
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.current);
+                if self.current == 0xFF {
+                    self.bytes.push(0);
+                }
+                self.current = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // Padded with ones to a whole byte
+    fn finish(mut self) -> Vec<u8> {
+        while self.count != 0 {
+            self.push(1, 1);
+        }
+        self.bytes
+    }
+
+    // A DC difference: its size with the code of the DC table, then its bits
+    fn dc(&mut self, difference: i32) {
+        let size = 32 - difference.unsigned_abs().leading_zeros();
+        self.push(size, 4);
+        let bits = match difference < 0 {
+            true => difference + (1 << size) - 1,
+            false => difference,
+        };
+        self.push(bits as u32, size);
+    }
+
+    // The first AC coefficient, if any, then the end of the block. The AC
+    // table codes the end of block as 0 and a coefficient of 7 bits as 1.
+    fn ac(&mut self, coefficient: i32) {
+        if coefficient != 0 {
+            self.push(1, 1);
+            let bits = match coefficient < 0 {
+                true => coefficient + 127,
+                false => coefficient,
+            };
+            self.push(bits as u32, 7);
+        }
+        self.push(0, 1);
+    }
+}
+
+// A component's id, its sampling factors and its two coefficients for the
+// block at (x, y) of its stored area: the DC one and the first AC one
+struct Plane {
+    id: u8,
+    horizontal: usize,
+    vertical: usize,
+    block: fn(usize, usize) -> (i32, i32),
+}
+
+fn segment(jpeg: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    jpeg.extend_from_slice(&[0xFF, marker]);
+    jpeg.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    jpeg.extend_from_slice(data);
+}
+
+// Blocks of a plane in the order of the scans over it: MCU by MCU when it
+// shares the scan, else row by row over the blocks the image covers
+fn blocks(
+    width: usize,
+    height: usize,
+    planes: &[Plane],
+    interleaved: bool,
+) -> Vec<Vec<(usize, usize, usize)>> {
+    let max_horizontal = planes.iter().map(|plane| plane.horizontal).max().unwrap();
+    let max_vertical = planes.iter().map(|plane| plane.vertical).max().unwrap();
+    if !interleaved {
+        return planes
+            .iter()
+            .enumerate()
+            .map(|(index, plane)| {
+                let across = (width * plane.horizontal).div_ceil(max_horizontal * 8);
+                let down = (height * plane.vertical).div_ceil(max_vertical * 8);
+                (0..down)
+                    .flat_map(|y| (0..across).map(move |x| (index, x, y)))
+                    .collect()
+            })
+            .collect();
+    }
+    let mut order = Vec::new();
+    for mcu_y in 0..height.div_ceil(8 * max_vertical) {
+        for mcu_x in 0..width.div_ceil(8 * max_horizontal) {
+            for (index, plane) in planes.iter().enumerate() {
+                for v in 0..plane.vertical {
+                    for h in 0..plane.horizontal {
+                        order.push((
+                            index,
+                            mcu_x * plane.horizontal + h,
+                            mcu_y * plane.vertical + v,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    vec![order]
+}
+
+// A JPEG with every quantization step 1. Progressive ones send the DC
+// coefficients in two scans of successive approximation, then the AC ones
+// in a scan for each plane.
+fn jpeg(width: usize, height: usize, planes: &[Plane], progressive: bool) -> Vec<u8> {
+    let mut jpeg = vec![0xFF, 0xD8];
+    segment(&mut jpeg, 0xDB, &[[0].as_slice(), &[1; 64]].concat());
+
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(height as u16).to_be_bytes());
+    frame.extend_from_slice(&(width as u16).to_be_bytes());
+    frame.push(planes.len() as u8);
+    for plane in planes {
+        frame.extend_from_slice(&[plane.id, (plane.horizontal << 4 | plane.vertical) as u8, 0]);
+    }
+    segment(&mut jpeg, if progressive { 0xC2 } else { 0xC0 }, &frame);
+
+    // DC sizes 0 to 11 in four bits each, and the two AC symbols
+    let mut dc_table = vec![0x00, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    dc_table.extend(0..12);
+    segment(&mut jpeg, 0xC4, &dc_table);
+    let mut ac_table = vec![0x10, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    ac_table.extend_from_slice(&[0x00, 0x07]);
+    segment(&mut jpeg, 0xC4, &ac_table);
+
+    let scan = |jpeg: &mut Vec<u8>, planes: &[&Plane], parameters: [u8; 3], bits: JpegBits| {
+        let mut header = vec![planes.len() as u8];
+        for plane in planes {
+            header.extend_from_slice(&[plane.id, 0x00]);
+        }
+        header.extend_from_slice(&parameters);
+        segment(jpeg, 0xDA, &header);
+        jpeg.extend(bits.finish());
+    };
+    let all: Vec<&Plane> = planes.iter().collect();
+    let interleaved = &blocks(width, height, planes, true)[0];
+
+    if !progressive {
+        let mut bits = JpegBits::default();
+        let mut predictors = vec![0; planes.len()];
+        for &(index, x, y) in interleaved {
+            let (dc, ac) = (planes[index].block)(x, y);
+            bits.dc(dc - predictors[index]);
+            predictors[index] = dc;
+            bits.ac(ac);
+        }
+        scan(&mut jpeg, &all, [0, 63, 0x00], bits);
+    } else {
+        let mut bits = JpegBits::default();
+        let mut predictors = vec![0; planes.len()];
+        for &(index, x, y) in interleaved {
+            let dc = (planes[index].block)(x, y).0 >> 1;
+            bits.dc(dc - predictors[index]);
+            predictors[index] = dc;
+        }
+        scan(&mut jpeg, &all, [0, 0, 0x01], bits);
+
+        let mut bits = JpegBits::default();
+        for &(index, x, y) in interleaved {
+            bits.push(((planes[index].block)(x, y).0 & 1) as u32, 1);
+        }
+        scan(&mut jpeg, &all, [0, 0, 0x10], bits);
+
+        for (plane, order) in planes.iter().zip(blocks(width, height, planes, false)) {
+            let mut bits = JpegBits::default();
+            for (_, x, y) in order {
+                bits.ac((plane.block)(x, y).1);
+            }
+            scan(&mut jpeg, &[plane], [1, 63, 0x00], bits);
+        }
+    }
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg
+}
+
+// The sample at column `x` of a block with these coefficients
+fn sample(dc: i32, ac: i32, x: usize) -> f64 {
+    let cosine = ((2 * x + 1) as f64 * PI / 16.0).cos() / 2.0;
+    128.0 + dc as f64 / 8.0 + ac as f64 * FRAC_1_SQRT_2 / 2.0 * cosine
+}
+
+fn gray_block(x: usize, y: usize) -> (i32, i32) {
+    let dc = [-1024, -300, 0, 77, 512, 1016][(y * 3 + x) % 6];
+    let ac = if (x, y) == (1, 1) { 100 } else { 0 };
+    (dc, ac)
+}
+
+fn assert_near(actual: u8, expected: f64, tolerance: f64, at: (usize, usize)) {
+    let expected = expected.round().clamp(0.0, 255.0);
+    assert!(
+        (actual as f64 - expected).abs() <= tolerance,
+        "{actual} is not {expected} at {at:?}"
+    );
+}
+
+#[test]
+fn grayscale_jpegs_decode_to_their_blocks() {
+    let planes = [Plane {
+        id: 1,
+        horizontal: 1,
+        vertical: 1,
+        block: gray_block,
+    }];
+    for progressive in [false, true] {
+        // Not a whole number of blocks across or down
+        let (width, height, rgba) = decode_jpeg(&jpeg(21, 13, &planes, progressive)).unwrap();
+        assert_eq!((width, height), (21, 13));
+        for y in 0..height {
+            for x in 0..width {
+                let (dc, ac) = gray_block(x / 8, y / 8);
+                let pixel = &rgba[(y * width + x) * 4..][..4];
+                assert_near(pixel[0], sample(dc, ac, x % 8), 1.0, (x, y));
+                assert_eq!(pixel[0], pixel[1]);
+                assert_eq!(pixel[0], pixel[2]);
+                assert_eq!(pixel[3], 0xFF);
+            }
+        }
+    }
+}
+
+fn luma_block(x: usize, y: usize) -> (i32, i32) {
+    ((x as i32 * 3 + y as i32 * 5) % 7 * 120 - 360, 0)
+}
+
+fn blue_block(x: usize, _y: usize) -> (i32, i32) {
+    ([-400, 320][x % 2], 0)
+}
+
+fn red_block(x: usize, _y: usize) -> (i32, i32) {
+    ([240, -480][x % 2], 0)
+}
+
+#[test]
+fn color_jpegs_upsample_their_chroma() {
+    // Chroma at half the resolution both ways
+    let planes = [
+        Plane {
+            id: 1,
+            horizontal: 2,
+            vertical: 2,
+            block: luma_block,
+        },
+        Plane {
+            id: 2,
+            horizontal: 1,
+            vertical: 1,
+            block: blue_block,
+        },
+        Plane {
+            id: 3,
+            horizontal: 1,
+            vertical: 1,
+            block: red_block,
+        },
+    ];
+    for progressive in [false, true] {
+        let (width, height, rgba) = decode_jpeg(&jpeg(27, 12, &planes, progressive)).unwrap();
+        assert_eq!((width, height), (27, 12));
+        for y in 0..height {
+            for x in 0..width {
+                let luma = sample(luma_block(x / 8, y / 8).0, 0, 0);
+                let blue = sample(blue_block(x / 16, y / 16).0, 0, 0) - 128.0;
+                let red = sample(red_block(x / 16, y / 16).0, 0, 0) - 128.0;
+                let expected = [
+                    luma + 1.402 * red,
+                    luma - 0.344_136 * blue - 0.714_136 * red,
+                    luma + 1.772 * blue,
+                ];
+                let pixel = &rgba[(y * width + x) * 4..][..4];
+                for channel in 0..3 {
+                    assert_near(pixel[channel], expected[channel], 1.0, (x, y));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn malformed_jpegs_are_refused() {
+    // A frame header announcing 65535x65535 pixels, and nothing else
+    let mut huge = vec![0xFF, 0xD8];
+    segment(&mut huge, 0xC0, &[8, 0xFF, 0xFF, 0xFF, 0xFF, 1, 1, 0x44, 0]);
+    huge.extend_from_slice(&[0xFF, 0xD9]);
+    assert_eq!(
+        decode_jpeg(&huge).unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+
+    let planes = [Plane {
+        id: 1,
+        horizontal: 1,
+        vertical: 1,
+        block: gray_block,
+    }];
+    for progressive in [false, true] {
+        let good = jpeg(21, 13, &planes, progressive);
+        for length in 0..good.len() - 2 {
+            assert!(decode_jpeg(&good[..length]).is_err(), "cut at {length}");
+        }
+        // Whatever a changed byte does, the decoder returns
+        for position in 0..good.len() {
+            for flip in [0x01, 0x80, 0xFF] {
+                let mut bad = good.clone();
+                bad[position] ^= flip;
+                let _ = decode_jpeg(&bad);
+            }
+        }
+    }
+}
+
+// Bits written least significant first
+#[derive(Default)]
+struct WebpBits {
+    bytes: Vec<u8>,
+    count: usize,
+}
+
+impl WebpBits {
+    fn push(&mut self, value: u32, length: u32) {
+        for bit in 0..length {
+            if self.count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= (((value >> bit) & 1) as u8) << (self.count % 8);
+            self.count += 1;
+        }
+    }
+
+    // A prefix code, first bit of the code first
+    fn code(&mut self, code: u32, length: u32) {
+        for bit in (0..length).rev() {
+            self.push(code >> bit, 1);
+        }
+    }
+
+    // A prefix code given by its code lengths, which are coded with
+    // 0 as 0, 8 as 10 and 9 as 11
+    fn lengths(&mut self, lengths: &[u8]) {
+        self.push(0, 1);
+        self.push(13 - 4, 4);
+        // In the order of 17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9
+        for length in [0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2] {
+            self.push(length, 3);
+        }
+        self.push(0, 1); // Every length follows
+        for &length in lengths {
+            match length {
+                0 => self.code(0b0, 1),
+                8 => self.code(0b10, 2),
+                _ => self.code(0b11, 2),
+            }
+        }
+    }
+
+    // Green 0 to 254 in 8 bits, 255 and the length prefix of 8 in 9
+    fn green(&mut self, green: u8) {
+        match green {
+            255 => self.code(0b1_1111_1110, 9),
+            _ => self.code(green as u32, 8),
+        }
+    }
+
+    // Red, blue and alpha are their own 8 bit codes
+    fn literal(&mut self, [red, green, blue, alpha]: [u8; 4]) {
+        self.green(green);
+        self.code(red as u32, 8);
+        self.code(blue as u32, 8);
+        self.code(alpha as u32, 8);
+    }
+}
+
+fn webp_pixel(x: usize, y: usize) -> [u8; 4] {
+    // The second row is the first one again
+    let y = if y == 1 { 0 } else { y };
+    let alpha = if (x + y).is_multiple_of(5) { 0 } else { 0xFF };
+    [
+        (x * 40 + y) as u8,
+        255 - (x * 13) as u8,
+        (y * 70) as u8,
+        alpha,
+    ]
+}
+
+// An 8x3 lossless WebP with the subtract green transform, whose second row
+// is a back reference to the first
+fn webp() -> Vec<u8> {
+    let (width, height) = (8, 3);
+    let mut bits = WebpBits::default();
+    bits.push(0x2F, 8);
+    bits.push(width - 1, 14);
+    bits.push(height - 1, 14);
+    bits.push(1, 1);
+    bits.push(0, 3);
+    bits.push(1, 1); // Subtract green
+    bits.push(2, 2);
+    bits.push(0, 1);
+    bits.push(0, 1); // No color cache
+    bits.push(0, 1); // Nor groups of prefix codes
+
+    let mut green = vec![8; 255];
+    green.extend_from_slice(&[9, 0, 0, 0, 0, 0, 9]);
+    green.resize(256 + 24, 0);
+    bits.lengths(&green);
+    for _ in 0..3 {
+        bits.lengths(&[8; 256]);
+    }
+    // A distance code of one symbol, 0, which takes no bits
+    bits.push(1, 1);
+    bits.push(0, 1);
+    bits.push(0, 1);
+    bits.push(0, 1);
+
+    for y in 0..height as usize {
+        if y == 1 {
+            // Length 8 is prefix 5 with an extra bit of 1, distance code 1
+            // the pixel above
+            bits.code(0b1_1111_1111, 9);
+            bits.push(1, 1);
+            continue;
+        }
+        for x in 0..width as usize {
+            let [red, green, blue, alpha] = webp_pixel(x, y);
+            bits.literal([
+                red.wrapping_sub(green),
+                green,
+                blue.wrapping_sub(green),
+                alpha,
+            ]);
+        }
+    }
+
+    let data = bits.bytes;
+    let mut webp = b"RIFF".to_vec();
+    webp.extend_from_slice(&(4 + 8 + data.len() as u32).to_le_bytes());
+    webp.extend_from_slice(b"WEBPVP8L");
+    webp.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    webp.extend(data);
+    webp
+}
+
+#[test]
+fn lossless_webps_decode_exactly() {
+    let gif = still_to_gif(&webp(), Dither::None).unwrap();
+    assert_eq!(
+        (
+            gif.logical_screen_descriptor.width,
+            gif.logical_screen_descriptor.height
+        ),
+        (8, 3)
+    );
+    let frame = &gif.image_descriptors[0];
+    let colors = &frame
+        .local_color_table
+        .as_ref()
+        .or(gif.global_color_table.as_ref())
+        .unwrap()
+        .colors;
+    let transparent_index = frame.transparent_color_index();
+    for (index, pixel) in frame.image_data.iter().enumerate() {
+        let [red, green, blue, alpha] = webp_pixel(index % 8, index / 8);
+        if alpha == 0 {
+            assert_eq!(Some(*pixel), transparent_index, "pixel {index}");
+        } else {
+            assert_eq!(colors[*pixel as usize], [red, green, blue], "pixel {index}");
+        }
+    }
+}
+
+#[test]
+fn malformed_webps_are_refused() {
+    let good = webp();
+    for length in 0..good.len() {
+        assert!(
+            still_to_gif(&good[..length], Dither::None).is_err(),
+            "cut at {length}"
+        );
+    }
+    for position in 0..good.len() {
+        for flip in [0x01, 0x80, 0xFF] {
+            let mut bad = good.clone();
+            bad[position] ^= flip;
+            let _ = still_to_gif(&bad, Dither::None);
+        }
+    }
+
+    // 16384x16384 pixels announced by the bitstream, and by a canvas
+    let mut huge = good.clone();
+    huge[21..24].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+    huge[24] |= 0x0F;
+    let error = still_to_gif(&huge, Dither::None).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData, "{error}");
+    let mut canvas = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+    canvas.extend_from_slice(&[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    let error = still_to_gif(&canvas, Dither::None).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData, "{error}");
+}
+
+// What the command line tool writes to stdout for `command`, given `input`
+// on stdin
+fn piped(command: &[&str], input: &[u8]) -> Vec<u8> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_GifSauce"))
+        .args(command)
+        .arg("-q")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{command:?}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+fn delays(gif: &GIF) -> Vec<u16> {
+    gif.image_descriptors
+        .iter()
+        .map(|frame| {
+            frame
+                .graphics_control_extension
+                .as_ref()
+                .unwrap()
+                .delay_time
+        })
+        .collect()
+}
+
+#[test]
+fn converted_animations_read_back_as_the_same_frames() {
+    let gif = generate_carrier(24, 16, 4, Style::Gradient, 3, 7, Dither::None).unwrap();
+    let bytes = write_gif_bytes(&gif, None);
+    for format in ["apng", "webp"] {
+        let converted = piped(&["convert", "-", "-o", "-", "--format", format], &bytes);
+        let back = animation_to_gif(&converted, Dither::None).unwrap();
+        assert_eq!(
+            (
+                back.logical_screen_descriptor.width,
+                back.logical_screen_descriptor.height
+            ),
+            (24, 16),
+            "{format}"
+        );
+        assert_eq!(delays(&back), delays(&gif), "{format}");
+        assert_eq!(
+            composite_frames(&back).unwrap(),
+            composite_frames(&gif).unwrap(),
+            "{format}"
+        );
+    }
+}
+
+#[test]
+fn optimized_animations_show_the_same_frames_in_fewer_bytes() {
+    // A square moving over a still background
+    let mut gif = generate_carrier(48, 48, 1, Style::Noise, 5, 10, Dither::None).unwrap();
+    let background = composite_frames(&gif).unwrap().remove(0);
+    gif.image_descriptors = (0..4)
+        .map(|frame| {
+            let mut canvas = background.clone();
+            for y in 10..16 {
+                for x in frame * 8..frame * 8 + 6 {
+                    canvas[y * 48 + x] = Some([0xFF, 0, 0]);
+                }
+            }
+            canvas_frame(&gif.logical_screen_descriptor, &canvas, 10, Dither::None)
+        })
+        .collect();
+    let original = write_gif_bytes(&gif, None);
+
+    let dir = std::env::temp_dir().join(format!("gifsauce-optimize-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("in.gif"), &original).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_GifSauce"))
+        .current_dir(&dir)
+        .args(["optimize", "in.gif", "out.gif", "-q"])
+        .output()
+        .unwrap();
+    let optimized = fs::read(dir.join("out.gif"));
+    let _ = fs::remove_dir_all(&dir);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let optimized = optimized.unwrap();
+
+    let back = parse_gif_bytes(&optimized).unwrap();
+    assert_eq!(delays(&back), delays(&gif));
+    assert_eq!(
+        composite_frames(&back).unwrap(),
+        composite_frames(&gif).unwrap()
+    );
+    assert!(
+        optimized.len() < original.len(),
+        "{} -> {} bytes",
+        original.len(),
+        optimized.len()
+    );
+}
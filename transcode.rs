@@ -0,0 +1,868 @@
+use std::io::{self, Error};
+
+use crate::frames::canvas_frame;
+use crate::gif::{
+    ApplicationExtension, Dither, GIFHeader, IccProfile, KnownApplication, LogicalScreenDescriptor,
+    GIF,
+};
+use crate::jpeg::{decode_jpeg, jpeg_icc_profile, MAX_PIXELS};
+
+// APNG and animated WebP carriers are played into whole screen RGBA frames,
+// then each frame is quantized to its own palette. Pixels less than half
+// opaque become transparent, the others fully opaque. WebP images have to be
+// lossless, VP8 lossy images are not decoded. Still images, JPEGs among them,
+// make a GIF of one frame. An ICC profile comes along, so colors keep their
+// meaning.
+
+fn invalid(message: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// The frames as RGBA with their delays in hundredths of a second
+struct Animation {
+    width: usize,
+    height: usize,
+    frames: Vec<(Vec<u8>, u16)>,
+    plays: u32, // 0 for forever
+    icc_profile: Option<Vec<u8>>,
+}
+
+// Draw a frame's RGBA pixels at (left, top), over what is there when
+// blending and in its place otherwise
+fn draw(
+    canvas: &mut [u8],
+    canvas_width: usize,
+    pixels: &[u8],
+    (left, top, width, height): (usize, usize, usize, usize),
+    blend: bool,
+) {
+    let canvas_height = canvas.len() / 4 / canvas_width.max(1);
+    for y in 0..height.min(canvas_height.saturating_sub(top)) {
+        for x in 0..width.min(canvas_width.saturating_sub(left)) {
+            let source = &pixels[(y * width + x) * 4..][..4];
+            let at = ((top + y) * canvas_width + left + x) * 4;
+            let target = &mut canvas[at..at + 4];
+            if !blend || source[3] == 0xFF || target[3] == 0 {
+                target.copy_from_slice(source);
+                continue;
+            }
+            // Source over destination, with straight alpha
+            let alpha = source[3] as u32;
+            let below = target[3] as u32 * (255 - alpha) / 255;
+            let total = alpha + below;
+            if total == 0 {
+                target.copy_from_slice(&[0; 4]);
+                continue;
+            }
+            for i in 0..3 {
+                target[i] = ((source[i] as u32 * alpha + target[i] as u32 * below) / total) as u8;
+            }
+            target[3] = total as u8;
+        }
+    }
+}
+
+fn clear(
+    canvas: &mut [u8],
+    canvas_width: usize,
+    (left, top, width, height): (usize, usize, usize, usize),
+) {
+    draw(
+        canvas,
+        canvas_width,
+        &vec![0; width * height * 4],
+        (left, top, width, height),
+        false,
+    );
+}
+
+fn decode_apng(bytes: &[u8]) -> Result<Animation, Error> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let (width, height) = (reader.info().width as usize, reader.info().height as usize);
+    if width * height > MAX_PIXELS {
+        return Err(invalid("The PNG image is too big."));
+    }
+    let (frame_count, plays) = reader
+        .info()
+        .animation_control
+        .map_or((1, 1), |control| (control.num_frames, control.num_plays));
+    let icc_profile = reader
+        .info()
+        .icc_profile
+        .as_ref()
+        .map(|profile| profile.to_vec());
+    // An image before the first frame control is only for viewers that
+    // can't animate
+    if reader.info().animation_control.is_some() && reader.info().frame_control.is_none() {
+        let mut skipped = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut skipped).map_err(io::Error::other)?;
+    }
+
+    let mut canvas = vec![0; width * height * 4];
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let mut frames = Vec::new();
+    for index in 0..frame_count {
+        let output = reader.next_frame(&mut buffer).map_err(io::Error::other)?;
+        let control = reader.info().frame_control.unwrap_or_default();
+        let area = (
+            control.x_offset as usize,
+            control.y_offset as usize,
+            output.width as usize,
+            output.height as usize,
+        );
+        let rgba: Vec<u8> = buffer
+            .chunks(output.line_size)
+            .take(output.height as usize)
+            .flat_map(|line| {
+                let line = &line[..output.width as usize * output.color_type.samples()];
+                let pixels: Vec<[u8; 4]> = match output.color_type {
+                    png::ColorType::Grayscale => line.iter().map(|&v| [v, v, v, 0xFF]).collect(),
+                    png::ColorType::GrayscaleAlpha => {
+                        line.chunks(2).map(|p| [p[0], p[0], p[0], p[1]]).collect()
+                    }
+                    png::ColorType::Rgb => {
+                        line.chunks(3).map(|p| [p[0], p[1], p[2], 0xFF]).collect()
+                    }
+                    _ => line.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+                };
+                pixels.into_iter().flatten()
+            })
+            .collect();
+
+        // Disposing of the first frame as the one before leaves it cleared
+        let dispose = match control.dispose_op {
+            png::DisposeOp::Previous if index == 0 => png::DisposeOp::Background,
+            dispose => dispose,
+        };
+        let saved = (dispose == png::DisposeOp::Previous).then(|| canvas.clone());
+        draw(
+            &mut canvas,
+            width,
+            &rgba,
+            area,
+            control.blend_op == png::BlendOp::Over,
+        );
+
+        let denominator = match control.delay_den {
+            0 => 100,
+            denominator => denominator as u32,
+        };
+        let delay = (control.delay_num as u32 * 100 + denominator / 2) / denominator;
+        frames.push((canvas.clone(), delay.min(u16::MAX as u32) as u16));
+
+        match (dispose, saved) {
+            (png::DisposeOp::Background, _) => clear(&mut canvas, width, area),
+            (png::DisposeOp::Previous, Some(saved)) => canvas = saved,
+            _ => {}
+        }
+    }
+    Ok(Animation {
+        width,
+        height,
+        frames,
+        plays,
+        icc_profile,
+    })
+}
+
+// Bits are read from the least significant bit of each byte up. Reading past
+// the end gives zeros and marks the reader as overrun.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    overrun: bool,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for bit in 0..count {
+            match self.bytes.get(self.position / 8) {
+                Some(byte) => value |= (((byte >> (self.position % 8)) & 1) as u32) << bit,
+                None => self.overrun = true,
+            }
+            self.position += 1;
+        }
+        value
+    }
+}
+
+// A canonical prefix code, as the number of codes of each length and the
+// symbols in code order
+struct PrefixCode {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl PrefixCode {
+    fn new(lengths: &[u8]) -> Result<Self, Error> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::new();
+        for length in 1..16 {
+            for (symbol, _) in lengths
+                .iter()
+                .enumerate()
+                .filter(|&(_, &l)| l as usize == length)
+            {
+                symbols.push(symbol as u16);
+            }
+        }
+        // One symbol takes no bits, more have to fill the code space
+        let space = (1..16).fold(0u32, |space, length| {
+            space + ((counts[length] as u32) << (15 - length))
+        });
+        if symbols.is_empty() || (symbols.len() > 1 && space != 1 << 15) {
+            return Err(invalid("Invalid prefix code in a WebP image."));
+        }
+        Ok(PrefixCode { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> u16 {
+        if self.symbols.len() == 1 {
+            return self.symbols[0];
+        }
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.read(1) as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return self.symbols[(index + code - first) as usize];
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        reader.overrun = true; // Only a broken stream gets here
+        0
+    }
+}
+
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+fn read_prefix_code(reader: &mut BitReader, alphabet: usize) -> Result<PrefixCode, Error> {
+    let mut lengths = vec![0u8; alphabet];
+    if reader.read(1) == 1 {
+        let symbols = reader.read(1) + 1;
+        let first_bits = if reader.read(1) == 1 { 8 } else { 1 };
+        let first = reader.read(first_bits) as usize;
+        *lengths
+            .get_mut(first)
+            .ok_or_else(|| invalid("Invalid prefix code in a WebP image."))? = 1;
+        if symbols == 2 {
+            let second = reader.read(8) as usize;
+            *lengths
+                .get_mut(second)
+                .ok_or_else(|| invalid("Invalid prefix code in a WebP image."))? = 1;
+        }
+        return PrefixCode::new(&lengths);
+    }
+
+    let mut length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..reader.read(4) as usize + 4] {
+        length_lengths[symbol] = reader.read(3) as u8;
+    }
+    let length_code = PrefixCode::new(&length_lengths)?;
+    let mut remaining = match reader.read(1) {
+        1 => {
+            let bits = 2 + 2 * reader.read(3);
+            2 + reader.read(bits) as usize
+        }
+        _ => alphabet,
+    };
+    let mut symbol = 0;
+    let mut previous = 8;
+    while symbol < alphabet && remaining > 0 && !reader.overrun {
+        remaining -= 1;
+        let (length, repeat) = match length_code.decode(reader) {
+            length @ 0..=15 => (length as u8, 1),
+            16 => (previous, 3 + reader.read(2) as usize),
+            17 => (0, 3 + reader.read(3) as usize),
+            _ => (0, 11 + reader.read(7) as usize),
+        };
+        if symbol + repeat > alphabet {
+            return Err(invalid("Invalid code lengths in a WebP image."));
+        }
+        lengths[symbol..symbol + repeat].fill(length);
+        symbol += repeat;
+        if length != 0 {
+            previous = length;
+        }
+    }
+    PrefixCode::new(&lengths)
+}
+
+// Green with lengths and cached colors, red, blue, alpha and distance
+type PrefixGroup = [PrefixCode; 5];
+
+// 2D offsets, (x, y), the first 120 distance codes stand for
+#[rustfmt::skip]
+const DISTANCE_MAP: [(i8, i8); 120] = [
+    (0, 1), (1, 0), (1, 1), (-1, 1), (0, 2), (2, 0), (1, 2), (-1, 2), (2, 1), (-2, 1), (2, 2),
+    (-2, 2), (0, 3), (3, 0), (1, 3), (-1, 3), (3, 1), (-3, 1), (2, 3), (-2, 3), (3, 2), (-3, 2),
+    (0, 4), (4, 0), (1, 4), (-1, 4), (4, 1), (-4, 1), (3, 3), (-3, 3), (2, 4), (-2, 4), (4, 2),
+    (-4, 2), (0, 5), (3, 4), (-3, 4), (4, 3), (-4, 3), (5, 0), (1, 5), (-1, 5), (5, 1), (-5, 1),
+    (2, 5), (-2, 5), (5, 2), (-5, 2), (4, 4), (-4, 4), (3, 5), (-3, 5), (5, 3), (-5, 3), (0, 6),
+    (6, 0), (1, 6), (-1, 6), (6, 1), (-6, 1), (2, 6), (-2, 6), (6, 2), (-6, 2), (4, 5), (-4, 5),
+    (5, 4), (-5, 4), (3, 6), (-3, 6), (6, 3), (-6, 3), (0, 7), (7, 0), (1, 7), (-1, 7), (5, 5),
+    (-5, 5), (7, 1), (-7, 1), (4, 6), (-4, 6), (6, 4), (-6, 4), (2, 7), (-2, 7), (7, 2), (-7, 2),
+    (3, 7), (-3, 7), (7, 3), (-7, 3), (5, 6), (-5, 6), (6, 5), (-6, 5), (8, 0), (4, 7), (-4, 7),
+    (7, 4), (-7, 4), (8, 1), (8, 2), (6, 6), (-6, 6), (8, 3), (5, 7), (-5, 7), (7, 5), (-7, 5),
+    (8, 4), (6, 7), (-6, 7), (7, 6), (-7, 6), (8, 5), (7, 7), (-7, 7), (8, 6), (8, 7),
+];
+
+// A length or distance from its prefix symbol and extra bits
+fn prefix_value(reader: &mut BitReader, symbol: u16) -> usize {
+    if symbol < 4 {
+        return symbol as usize + 1;
+    }
+    let extra_bits = (symbol as u32 - 2) >> 1;
+    let offset = (2 + (symbol as usize & 1)) << extra_bits;
+    offset + reader.read(extra_bits) as usize + 1
+}
+
+// One image of ARGB pixels: the main one, or one holding transform or prefix
+// code data
+fn decode_pixels(
+    reader: &mut BitReader,
+    width: usize,
+    height: usize,
+    main: bool,
+) -> Result<Vec<u32>, Error> {
+    let cache_bits = match reader.read(1) {
+        1 => match reader.read(4) {
+            bits @ 1..=11 => bits,
+            _ => return Err(invalid("Invalid color cache in a WebP image.")),
+        },
+        _ => 0,
+    };
+    let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+
+    // Which group of prefix codes each block of pixels uses
+    let (group_bits, group_image) = if main && reader.read(1) == 1 {
+        let bits = reader.read(3) + 2;
+        let image = decode_pixels(
+            reader,
+            width.div_ceil(1 << bits),
+            height.div_ceil(1 << bits),
+            false,
+        )?;
+        (bits, image)
+    } else {
+        (0, Vec::new())
+    };
+    let group_count = group_image
+        .iter()
+        .map(|pixel| ((pixel >> 8) & 0xFFFF) as usize + 1)
+        .max()
+        .unwrap_or(1);
+    let groups: Vec<PrefixGroup> = (0..group_count)
+        .map(|_| {
+            Ok([
+                read_prefix_code(reader, 256 + 24 + cache_size)?,
+                read_prefix_code(reader, 256)?,
+                read_prefix_code(reader, 256)?,
+                read_prefix_code(reader, 256)?,
+                read_prefix_code(reader, 40)?,
+            ])
+        })
+        .collect::<Result<_, Error>>()?;
+
+    let mut cache = vec![0u32; cache_size];
+    let insert = |cache: &mut Vec<u32>, pixel: u32| {
+        if cache_bits > 0 {
+            cache[(0x1E35_A7BDu32.wrapping_mul(pixel) >> (32 - cache_bits)) as usize] = pixel;
+        }
+    };
+    let group_width = width.div_ceil(1 << group_bits);
+    let mut pixels: Vec<u32> = Vec::with_capacity(width * height);
+    while pixels.len() < width * height {
+        if reader.overrun {
+            return Err(invalid("A WebP image ends early."));
+        }
+        let (x, y) = (pixels.len() % width, pixels.len() / width);
+        let group = match group_image.is_empty() {
+            true => &groups[0],
+            false => {
+                let block = group_image[(y >> group_bits) * group_width + (x >> group_bits)];
+                &groups[((block >> 8) & 0xFFFF) as usize]
+            }
+        };
+        let green = group[0].decode(reader);
+        if green < 256 {
+            let red = group[1].decode(reader) as u32;
+            let blue = group[2].decode(reader) as u32;
+            let alpha = group[3].decode(reader) as u32;
+            let pixel = (alpha << 24) | (red << 16) | ((green as u32) << 8) | blue;
+            insert(&mut cache, pixel);
+            pixels.push(pixel);
+        } else if green < 256 + 24 {
+            let length = prefix_value(reader, green - 256);
+            let distance_symbol = group[4].decode(reader);
+            let code = prefix_value(reader, distance_symbol);
+            let distance = match code {
+                1..=120 => {
+                    let (dx, dy) = DISTANCE_MAP[code - 1];
+                    (dx as isize + dy as isize * width as isize).max(1) as usize
+                }
+                _ => code - 120,
+            };
+            if distance > pixels.len() || pixels.len() + length > width * height {
+                return Err(invalid("Invalid back reference in a WebP image."));
+            }
+            for _ in 0..length {
+                let pixel = pixels[pixels.len() - distance];
+                insert(&mut cache, pixel);
+                pixels.push(pixel);
+            }
+        } else {
+            let pixel = cache[green as usize - 256 - 24];
+            pixels.push(pixel);
+        }
+    }
+    Ok(pixels)
+}
+
+enum Transform {
+    Predictor(u32, Vec<u32>),
+    Color(u32, Vec<u32>),
+    SubtractGreen,
+    ColorIndexing(u32, Vec<u32>),
+}
+
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let bytes: [u8; 4] =
+        std::array::from_fn(|i| a.to_le_bytes()[i].wrapping_add(b.to_le_bytes()[i]));
+    u32::from_le_bytes(bytes)
+}
+
+fn average(a: u32, b: u32) -> u32 {
+    let bytes: [u8; 4] = std::array::from_fn(|i| {
+        ((a.to_le_bytes()[i] as u16 + b.to_le_bytes()[i] as u16) / 2) as u8
+    });
+    u32::from_le_bytes(bytes)
+}
+
+fn channels(pixel: u32) -> [i32; 4] {
+    pixel.to_le_bytes().map(|channel| channel as i32)
+}
+
+fn from_channels(channels: [i32; 4]) -> u32 {
+    u32::from_le_bytes(channels.map(|channel| channel.clamp(0, 255) as u8))
+}
+
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+    let to_left: i32 = (0..4).map(|i| (t[i] - tl[i]).abs()).sum();
+    let to_top: i32 = (0..4).map(|i| (l[i] - tl[i]).abs()).sum();
+    if to_left < to_top {
+        left
+    } else {
+        top
+    }
+}
+
+fn predict(mode: u32, left: u32, top: u32, top_right: u32, top_left: u32) -> u32 {
+    match mode {
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average(average(left, top_right), top),
+        6 => average(left, top_left),
+        7 => average(left, top),
+        8 => average(top_left, top),
+        9 => average(top, top_right),
+        10 => average(average(left, top_left), average(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => {
+            let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+            from_channels(std::array::from_fn(|i| l[i] + t[i] - tl[i]))
+        }
+        13 => {
+            let (a, tl) = (channels(average(left, top)), channels(top_left));
+            from_channels(std::array::from_fn(|i| a[i] + (a[i] - tl[i]) / 2))
+        }
+        _ => 0xFF00_0000,
+    }
+}
+
+fn color_delta(transform: u8, color: u8) -> u8 {
+    ((transform as i8 as i32 * color as i8 as i32) >> 5) as u8
+}
+
+impl Transform {
+    fn read(reader: &mut BitReader, width: usize, height: usize) -> Result<Self, Error> {
+        Ok(match reader.read(2) {
+            kind @ (0 | 1) => {
+                let bits = reader.read(3) + 2;
+                let data = decode_pixels(
+                    reader,
+                    width.div_ceil(1 << bits),
+                    height.div_ceil(1 << bits),
+                    false,
+                )?;
+                match kind {
+                    0 => Transform::Predictor(bits, data),
+                    _ => Transform::Color(bits, data),
+                }
+            }
+            2 => Transform::SubtractGreen,
+            _ => {
+                let size = reader.read(8) as usize + 1;
+                let mut palette = decode_pixels(reader, size, 1, false)?;
+                for index in 1..size {
+                    palette[index] = add_pixels(palette[index], palette[index - 1]);
+                }
+                let bits = match size {
+                    0..=2 => 3,
+                    3..=4 => 2,
+                    5..=16 => 1,
+                    _ => 0,
+                };
+                Transform::ColorIndexing(bits, palette)
+            }
+        })
+    }
+
+    // The width of the image the transform is applied to once decoded
+    fn coded_width(&self, width: usize) -> usize {
+        match self {
+            Transform::ColorIndexing(bits, _) => width.div_ceil(1 << bits),
+            _ => width,
+        }
+    }
+
+    fn undo(&self, pixels: &mut Vec<u32>, width: usize, height: usize) {
+        match self {
+            Transform::Predictor(bits, modes) => {
+                let blocks = width.div_ceil(1 << bits);
+                for y in 0..height {
+                    for x in 0..width {
+                        let at = y * width + x;
+                        let prediction = match (x, y) {
+                            (0, 0) => 0xFF00_0000,
+                            (_, 0) => pixels[at - 1],
+                            (0, _) => pixels[at - width],
+                            _ => {
+                                let mode = (modes[(y >> bits) * blocks + (x >> bits)] >> 8) & 0xF;
+                                // Past the right edge is the start of this row
+                                predict(
+                                    mode,
+                                    pixels[at - 1],
+                                    pixels[at - width],
+                                    pixels[at - width + 1],
+                                    pixels[at - width - 1],
+                                )
+                            }
+                        };
+                        pixels[at] = add_pixels(pixels[at], prediction);
+                    }
+                }
+            }
+            Transform::Color(bits, elements) => {
+                let blocks = width.div_ceil(1 << bits);
+                for (at, pixel) in pixels.iter_mut().enumerate() {
+                    let (x, y) = (at % width, at / width);
+                    // Green to red is held in blue, green to blue in green
+                    // and red to blue in red
+                    let [green_to_red, green_to_blue, red_to_blue, _] =
+                        elements[(y >> bits) * blocks + (x >> bits)].to_le_bytes();
+                    let [mut blue, green, mut red, alpha] = pixel.to_le_bytes();
+                    red = red.wrapping_add(color_delta(green_to_red, green));
+                    blue = blue.wrapping_add(color_delta(green_to_blue, green));
+                    blue = blue.wrapping_add(color_delta(red_to_blue, red));
+                    *pixel = u32::from_le_bytes([blue, green, red, alpha]);
+                }
+            }
+            Transform::SubtractGreen => {
+                for pixel in pixels.iter_mut() {
+                    let [blue, green, red, alpha] = pixel.to_le_bytes();
+                    *pixel = u32::from_le_bytes([
+                        blue.wrapping_add(green),
+                        green,
+                        red.wrapping_add(green),
+                        alpha,
+                    ]);
+                }
+            }
+            Transform::ColorIndexing(bits, palette) => {
+                let coded_width = width.div_ceil(1 << bits);
+                let bits_per_index = 8 >> bits;
+                let mask = (1 << bits_per_index) - 1;
+                let mut indexed = Vec::with_capacity(width * height);
+                for y in 0..height {
+                    for x in 0..width {
+                        let green = (pixels[y * coded_width + (x >> bits)] >> 8) & 0xFF;
+                        let shift = (x & ((1 << bits) - 1)) * bits_per_index;
+                        let index = (green as usize >> shift) & mask;
+                        indexed.push(palette.get(index).copied().unwrap_or(0));
+                    }
+                }
+                *pixels = indexed;
+            }
+        }
+    }
+}
+
+// A VP8L bitstream as RGBA
+fn decode_lossless(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>), Error> {
+    let mut reader = BitReader {
+        bytes,
+        position: 0,
+        overrun: false,
+    };
+    if reader.read(8) != 0x2F {
+        return Err(invalid("Not a lossless WebP image."));
+    }
+    let width = reader.read(14) as usize + 1;
+    let height = reader.read(14) as usize + 1;
+    if width * height > MAX_PIXELS {
+        return Err(invalid("The WebP image is too big."));
+    }
+    reader.read(1); // Whether alpha is used, a hint only
+    if reader.read(3) != 0 {
+        return Err(invalid("Unknown lossless WebP version."));
+    }
+
+    let mut transforms = Vec::new();
+    let mut coded_width = width;
+    while reader.read(1) == 1 {
+        if transforms.len() == 4 || reader.overrun {
+            return Err(invalid("Invalid transforms in a WebP image."));
+        }
+        let transform = Transform::read(&mut reader, coded_width, height)?;
+        coded_width = transform.coded_width(coded_width);
+        transforms.push(transform);
+    }
+    let mut pixels = decode_pixels(&mut reader, coded_width, height, true)?;
+    // Transforms are undone last to first, each on the width it was read with
+    let mut widths = vec![width];
+    for transform in &transforms {
+        widths.push(transform.coded_width(*widths.last().unwrap()));
+    }
+    for (transform, width) in transforms.iter().zip(&widths).rev() {
+        transform.undo(&mut pixels, *width, height);
+    }
+    let rgba = pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [blue, green, red, alpha] = pixel.to_le_bytes();
+            [red, green, blue, alpha]
+        })
+        .collect();
+    Ok((width, height, rgba))
+}
+
+fn u24(bytes: &[u8]) -> usize {
+    bytes[0] as usize | (bytes[1] as usize) << 8 | (bytes[2] as usize) << 16
+}
+
+// A RIFF chunk's name and data
+type Chunk<'a> = (&'a [u8], &'a [u8]);
+
+// RIFF chunks in order, skipping the padding after odd lengths
+fn chunks(mut bytes: &[u8]) -> Result<Vec<Chunk<'_>>, Error> {
+    let mut chunks = Vec::new();
+    while bytes.len() >= 8 {
+        let length = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        let data = bytes
+            .get(8..8 + length)
+            .ok_or_else(|| invalid("A WebP chunk runs past the end of the file."))?;
+        chunks.push((&bytes[..4], data));
+        bytes = &bytes[(8 + length + length % 2).min(bytes.len())..];
+    }
+    Ok(chunks)
+}
+
+fn frame_image(chunks: &[Chunk]) -> Result<(usize, usize, Vec<u8>), Error> {
+    match chunks
+        .iter()
+        .find(|(name, _)| matches!(*name, b"VP8L" | b"VP8 "))
+    {
+        Some((b"VP8L", data)) => decode_lossless(data),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Lossy WebP images can't be read, only lossless ones.",
+        )),
+        None => Err(invalid("A WebP frame has no image.")),
+    }
+}
+
+fn decode_webp(bytes: &[u8]) -> Result<Animation, Error> {
+    let file_chunks = chunks(bytes.get(12..).unwrap_or(&[]))?;
+    let icc_profile = file_chunks
+        .iter()
+        .find(|(name, _)| *name == b"ICCP")
+        .map(|(_, data)| data.to_vec());
+    let canvas_size = file_chunks
+        .iter()
+        .find(|(name, data)| *name == b"VP8X" && data.len() >= 10)
+        .map(|(_, data)| (u24(&data[4..]) + 1, u24(&data[7..]) + 1));
+    let (width, height) = match canvas_size {
+        Some(size) => size,
+        // A still image
+        None => {
+            let (width, height, rgba) = frame_image(&file_chunks)?;
+            return Ok(Animation {
+                width,
+                height,
+                frames: vec![(rgba, 0)],
+                plays: 1,
+                icc_profile,
+            });
+        }
+    };
+    if width * height > MAX_PIXELS {
+        return Err(invalid("The WebP image is too big."));
+    }
+    let plays = file_chunks
+        .iter()
+        .find(|(name, data)| *name == b"ANIM" && data.len() >= 6)
+        .map_or(1, |(_, data)| u16::from_le_bytes([data[4], data[5]]) as u32);
+
+    let mut canvas = vec![0; width * height * 4];
+    let mut frames = Vec::new();
+    for (_, data) in file_chunks
+        .iter()
+        .filter(|(name, data)| *name == b"ANMF" && data.len() >= 16)
+    {
+        let (frame_width, frame_height, rgba) = frame_image(&chunks(&data[16..])?)?;
+        let area = (
+            u24(data) * 2,
+            u24(&data[3..]) * 2,
+            frame_width,
+            frame_height,
+        );
+        let duration = u24(&data[12..]);
+        let flags = data[15];
+        draw(&mut canvas, width, &rgba, area, flags & 0b10 == 0);
+        frames.push((
+            canvas.clone(),
+            ((duration + 5) / 10).min(u16::MAX as usize) as u16,
+        ));
+        if flags & 0b1 == 1 {
+            clear(&mut canvas, width, area);
+        }
+    }
+    if frames.is_empty() {
+        // Not animated after all
+        let (frame_width, frame_height, rgba) = frame_image(&file_chunks)?;
+        draw(
+            &mut canvas,
+            width,
+            &rgba,
+            (0, 0, frame_width, frame_height),
+            false,
+        );
+        frames.push((canvas, 0));
+    }
+    Ok(Animation {
+        width,
+        height,
+        frames,
+        plays,
+        icc_profile,
+    })
+}
+
+// An APNG or WebP file as a GIF, looping as often as the original
+pub fn animation_to_gif(bytes: &[u8], dither: Dither) -> Result<GIF, Error> {
+    let animation = match bytes.starts_with(b"RIFF") {
+        true => decode_webp(bytes)?,
+        false => decode_apng(bytes)?,
+    };
+    animation_gif(&animation, dither)
+}
+
+// A JPEG, PNG or WebP image as a GIF of one frame. Of animations only the
+// first frame is kept.
+pub fn still_to_gif(bytes: &[u8], dither: Dither) -> Result<GIF, Error> {
+    let mut animation = if bytes.starts_with(&[0xFF, 0xD8]) {
+        let (width, height, rgba) = decode_jpeg(bytes)?;
+        Animation {
+            width,
+            height,
+            frames: vec![(rgba, 0)],
+            plays: 1,
+            icc_profile: jpeg_icc_profile(bytes),
+        }
+    } else if bytes.starts_with(b"RIFF") {
+        decode_webp(bytes)?
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        decode_apng(bytes)?
+    } else {
+        return Err(invalid("Not a JPEG, PNG or WebP image."));
+    };
+    animation.frames.truncate(1);
+    animation.plays = 1;
+    if let Some((_, delay)) = animation.frames.first_mut() {
+        *delay = 0;
+    }
+    animation_gif(&animation, dither)
+}
+
+fn animation_gif(animation: &Animation, dither: Dither) -> Result<GIF, Error> {
+    if animation.width > u16::MAX as usize || animation.height > u16::MAX as usize {
+        return Err(invalid("The image is too large for a GIF."));
+    }
+    let screen = LogicalScreenDescriptor {
+        width: animation.width as u16,
+        height: animation.height as u16,
+        packed_field: 0,
+        background_color_index: 0,
+        pixel_aspect_ratio: 0,
+    };
+    let image_descriptors = animation
+        .frames
+        .iter()
+        .map(|(rgba, delay)| {
+            let canvas: Vec<Option<[u8; 3]>> = rgba
+                .chunks(4)
+                .map(|pixel| (pixel[3] >= 0x80).then(|| [pixel[0], pixel[1], pixel[2]]))
+                .collect();
+            canvas_frame(&screen, &canvas, *delay, dither)
+        })
+        .collect();
+    // A GIF's loop count is the number of repeats after the first time
+    let mut application_extensions = match animation.plays {
+        1 => Vec::new(),
+        plays => {
+            let [low, high] = (plays.saturating_sub(1).min(u16::MAX as u32) as u16).to_le_bytes();
+            vec![ApplicationExtension {
+                identifier: "NETSCAPE".to_string(),
+                authentication_code: "2.0".to_string(),
+                data: vec![1, low, high],
+            }]
+        }
+    };
+    if let Some(ref profile) = animation.icc_profile {
+        let application = ApplicationExtension::from(&KnownApplication::IccProfile(IccProfile {
+            profile: profile.clone(),
+        }));
+        // Profiles that aren't ICC are left behind
+        if application.known().is_some() {
+            application_extensions.push(application);
+        }
+    }
+    Ok(GIF {
+        header: GIFHeader {
+            signature: *b"GIF",
+            version: *b"89a",
+        },
+        logical_screen_descriptor: screen,
+        global_color_table: None,
+        comment_extensions: Vec::new(),
+        application_extensions,
+        plain_text_extensions: Vec::new(),
+        image_descriptors,
+        trailing_data: Vec::new(),
+    })
+}
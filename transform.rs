@@ -0,0 +1,298 @@
+use std::io::{self, Error};
+
+use crate::gif::{
+    map_to_palette, Dither, GraphicsControlExtension, ImageDescriptor, PlainTextExtension, GIF,
+};
+
+// Frames are transformed one by one in their own palette, so no frame ever
+// needs more colors than it had. Pixels blended by bilinear filtering are
+// mapped back onto that palette, dithered if asked.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+pub fn parse_filter(name: &str) -> Option<Filter> {
+    match name {
+        "nearest" => Some(Filter::Nearest),
+        "bilinear" => Some(Filter::Bilinear),
+        _ => None,
+    }
+}
+
+// A frame's pixels in row order with what is needed to look up their colors
+struct FramePixels<'a> {
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+    colors: &'a [[u8; 3]],
+    transparent_index: Option<u8>,
+}
+
+impl<'a> FramePixels<'a> {
+    fn new(gif: &'a GIF, frame: &'a ImageDescriptor) -> Self {
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+        let mut pixels = frame.pixels_in_row_order();
+        pixels.resize(width * height, 0);
+        let colors = frame
+            .local_color_table
+            .as_ref()
+            .or(gif.global_color_table.as_ref())
+            .map_or(&[][..], |table| &table.colors[..]);
+        FramePixels {
+            pixels,
+            width,
+            height,
+            colors,
+            transparent_index: frame.transparent_color_index(),
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    fn color(&self, index: u8) -> [u8; 3] {
+        self.colors.get(index as usize).copied().unwrap_or([0; 3])
+    }
+
+    // The index of the pixel closest to a fractional position
+    fn nearest(&self, x: f64, y: f64) -> u8 {
+        let column = x.round().clamp(0.0, (self.width - 1) as f64);
+        let row = y.round().clamp(0.0, (self.height - 1) as f64);
+        self.get(column as usize, row as usize)
+    }
+
+    // The color at a fractional position, blending its four neighbours.
+    // Transparent neighbours don't add color, and make the pixel transparent
+    // when they outweigh the others.
+    fn bilinear(&self, x: f64, y: f64) -> [u8; 4] {
+        let x = x.clamp(0.0, (self.width - 1) as f64);
+        let y = y.clamp(0.0, (self.height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+        let samples = [
+            (self.get(x0, y0), (1.0 - fx) * (1.0 - fy)),
+            (self.get(x1, y0), fx * (1.0 - fy)),
+            (self.get(x0, y1), (1.0 - fx) * fy),
+            (self.get(x1, y1), fx * fy),
+        ];
+        if samples.iter().all(|(index, _)| *index == samples[0].0) {
+            let [red, green, blue] = self.color(samples[0].0);
+            let alpha = if Some(samples[0].0) == self.transparent_index {
+                0
+            } else {
+                0xFF
+            };
+            return [red, green, blue, alpha];
+        }
+
+        let mut opaque = 0.0;
+        let mut sum = [0.0; 3];
+        for (index, weight) in samples {
+            if Some(index) == self.transparent_index {
+                continue;
+            }
+            opaque += weight;
+            for (total, component) in sum.iter_mut().zip(self.color(index)) {
+                *total += weight * component as f64;
+            }
+        }
+        if opaque < 0.5 {
+            return [0; 4];
+        }
+        let [red, green, blue] = sum.map(|total| (total / opaque).round().clamp(0.0, 255.0) as u8);
+        [red, green, blue, 0xFF]
+    }
+}
+
+// A frame with nothing to show, standing in for one cropped away entirely so
+// the animation keeps its timing
+fn empty_frame(frame: &ImageDescriptor) -> ImageDescriptor {
+    let mut gce = frame
+        .graphics_control_extension
+        .clone()
+        .unwrap_or(GraphicsControlExtension {
+            packed_field: 0,
+            delay_time: 0,
+            transparent_color_index: 0,
+        });
+    if frame.transparent_color_index().is_none() {
+        gce.packed_field |= 0b1;
+        gce.transparent_color_index = 0;
+    }
+    ImageDescriptor {
+        left: 0,
+        top: 0,
+        width: 1,
+        height: 1,
+        packed_field: frame.packed_field & 0b1000_0111, // Keep only the local color table
+        image_data: vec![gce.transparent_color_index],
+        graphics_control_extension: Some(gce),
+        local_color_table: frame.local_color_table.clone(),
+        lzw_minimum_code_size: frame.lzw_minimum_code_size,
+        compressed_range: None,
+    }
+}
+
+// Keep the area of the screen from (x, y), width by height pixels. Frames
+// are clipped to it and moved with it.
+pub fn crop_gif(gif: &mut GIF, x: u16, y: u16, width: u16, height: u16) -> Result<(), Error> {
+    let screen = &gif.logical_screen_descriptor;
+    if width == 0
+        || height == 0
+        || x as usize + width as usize > screen.width as usize
+        || y as usize + height as usize > screen.height as usize
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The crop {},{},{},{} doesn't fit the {}x{} screen.",
+                x, y, width, height, screen.width, screen.height
+            ),
+        ));
+    }
+    let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+
+    let frames = std::mem::take(&mut gif.image_descriptors);
+    for frame in frames {
+        let left = (frame.left as usize).max(x);
+        let top = (frame.top as usize).max(y);
+        let right = (frame.left as usize + frame.width as usize).min(x + width);
+        let bottom = (frame.top as usize + frame.height as usize).min(y + height);
+        if left >= right || top >= bottom {
+            gif.image_descriptors.push(empty_frame(&frame));
+            continue;
+        }
+
+        let source = FramePixels::new(gif, &frame);
+        let image_data = (top..bottom)
+            .flat_map(|row| (left..right).map(move |column| (row, column)))
+            .map(|(row, column)| source.get(column - frame.left as usize, row - frame.top as usize))
+            .collect();
+        gif.image_descriptors.push(ImageDescriptor {
+            left: (left - x) as u16,
+            top: (top - y) as u16,
+            width: (right - left) as u16,
+            height: (bottom - top) as u16,
+            packed_field: frame.packed_field & !0b0100_0000, // Written without interlacing
+            image_data,
+            compressed_range: None,
+            ..frame
+        });
+    }
+
+    for plain_text in &mut gif.plain_text_extensions {
+        plain_text.text_grid_left_position =
+            plain_text.text_grid_left_position.saturating_sub(x as u16);
+        plain_text.text_grid_top_position =
+            plain_text.text_grid_top_position.saturating_sub(y as u16);
+    }
+    gif.logical_screen_descriptor.width = width as u16;
+    gif.logical_screen_descriptor.height = height as u16;
+    Ok(())
+}
+
+// Scale a position on one axis of the screen, rounding down
+fn scale(position: usize, from: usize, to: usize) -> usize {
+    (position * to / from).min(u16::MAX as usize)
+}
+
+// Scale the screen and every frame to width by height pixels
+pub fn resize_gif(
+    gif: &mut GIF,
+    width: u16,
+    height: u16,
+    filter: Filter,
+    dither: Dither,
+) -> Result<(), Error> {
+    let screen_width = gif.logical_screen_descriptor.width as usize;
+    let screen_height = gif.logical_screen_descriptor.height as usize;
+    if width == 0 || height == 0 || screen_width == 0 || screen_height == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Can't resize a {}x{} screen to {}x{}.",
+                screen_width, screen_height, width, height
+            ),
+        ));
+    }
+    let (width, height) = (width as usize, height as usize);
+    // Source position of the center of a resized pixel, relative to the frame
+    let source_x = |x: usize, left: usize| {
+        (x as f64 + 0.5) * screen_width as f64 / width as f64 - 0.5 - left as f64
+    };
+    let source_y = |y: usize, top: usize| {
+        (y as f64 + 0.5) * screen_height as f64 / height as f64 - 0.5 - top as f64
+    };
+
+    let frames = std::mem::take(&mut gif.image_descriptors);
+    for frame in frames {
+        if frame.width == 0 || frame.height == 0 {
+            gif.image_descriptors.push(frame);
+            continue;
+        }
+        let frame_left = frame.left as usize;
+        let frame_top = frame.top as usize;
+        // Frames that touch keep touching, and none shrinks to nothing
+        let left = scale(frame_left, screen_width, width);
+        let top = scale(frame_top, screen_height, height);
+        let right = scale(frame_left + frame.width as usize, screen_width, width).max(left + 1);
+        let bottom = scale(frame_top + frame.height as usize, screen_height, height).max(top + 1);
+
+        let source = FramePixels::new(gif, &frame);
+        let positions = (top..bottom).flat_map(|y| {
+            (left..right).map(move |x| (source_x(x, frame_left), source_y(y, frame_top)))
+        });
+        let image_data = match filter {
+            Filter::Nearest => positions.map(|(x, y)| source.nearest(x, y)).collect(),
+            Filter::Bilinear => {
+                let blended: Vec<[u8; 4]> = positions.map(|(x, y)| source.bilinear(x, y)).collect();
+                map_to_palette(
+                    &blended,
+                    right - left,
+                    source.colors,
+                    source.transparent_index,
+                    dither,
+                )
+            }
+        };
+        gif.image_descriptors.push(ImageDescriptor {
+            left: left as u16,
+            top: top as u16,
+            width: (right - left).min(u16::MAX as usize) as u16,
+            height: (bottom - top).min(u16::MAX as usize) as u16,
+            packed_field: frame.packed_field & !0b0100_0000, // Written without interlacing
+            image_data,
+            compressed_range: None,
+            ..frame
+        });
+    }
+
+    for plain_text in &mut gif.plain_text_extensions {
+        resize_plain_text(plain_text, (screen_width, width), (screen_height, height));
+    }
+    gif.logical_screen_descriptor.width = width as u16;
+    gif.logical_screen_descriptor.height = height as u16;
+    Ok(())
+}
+
+// Scale the text grid along with the screen, each axis given as (from, to)
+fn resize_plain_text(
+    plain_text: &mut PlainTextExtension,
+    (from_width, to_width): (usize, usize),
+    (from_height, to_height): (usize, usize),
+) {
+    let x = |value: u16| scale(value as usize, from_width, to_width) as u16;
+    let y = |value: u16| scale(value as usize, from_height, to_height) as u16;
+    plain_text.text_grid_left_position = x(plain_text.text_grid_left_position);
+    plain_text.text_grid_top_position = y(plain_text.text_grid_top_position);
+    plain_text.text_grid_width = x(plain_text.text_grid_width);
+    plain_text.text_grid_height = y(plain_text.text_grid_height);
+    plain_text.character_cell_width = x(plain_text.character_cell_width as u16).min(255) as u8;
+    plain_text.character_cell_height = y(plain_text.character_cell_height as u16).min(255) as u8;
+}
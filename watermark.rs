@@ -0,0 +1,184 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use std::io::{self, Error};
+
+use crate::frames::composite_frames;
+use crate::gif::{map_to_palette, Dither, GIF};
+
+// A watermark that outlives the file. The screen is cut into a grid of cells
+// that scale with it, and every pixel of a cell is made a little lighter or
+// darker, as a pattern drawn from the identifier and key says. No single
+// pixel carries it, so requantizing, scaling or converting the image leaves
+// it in the cell averages. Verifying takes out what the picture itself does
+// from cell to cell and correlates what is left with the pattern.
+
+// Cells across and down
+const GRID: usize = 32;
+
+// Screens smaller than this have too few pixels a cell to average
+const MIN_SIDE: usize = 64;
+
+// Correlation, in standard deviations of chance, above which the watermark
+// counts as there. Chance gets this far about once in 30000 tries.
+const THRESHOLD: f64 = 4.0;
+
+pub struct Detection {
+    pub score: f64,
+    pub found: bool,
+}
+
+// +1 or -1 for every cell, from the identifier and key alone
+fn pattern(id: &str, key: &str) -> Vec<f64> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"gifsauce watermark");
+    hasher.update((key.len() as u64).to_le_bytes());
+    hasher.update(key.as_bytes());
+    hasher.update(id.as_bytes());
+    let mut rng = ChaCha20Rng::from_seed(hasher.finalize().into());
+    (0..GRID * GRID)
+        .map(|_| if rng.gen::<bool>() { 1.0 } else { -1.0 })
+        .collect()
+}
+
+fn cell(x: usize, y: usize, width: usize, height: usize) -> usize {
+    (y * GRID / height) * GRID + x * GRID / width
+}
+
+fn check_screen(gif: &GIF) -> Result<(usize, usize), Error> {
+    let width = gif.logical_screen_descriptor.width as usize;
+    let height = gif.logical_screen_descriptor.height as usize;
+    if width < MIN_SIDE || height < MIN_SIDE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "A {}x{} screen is too small for a watermark, it takes {}x{}.",
+                width, height, MIN_SIDE, MIN_SIDE
+            ),
+        ));
+    }
+    Ok((width, height))
+}
+
+// Shift every opaque pixel by `strength` levels, up or down with its cell.
+// Frames keep their own palettes, and the shifted colors are mapped back
+// onto them, dithered so the cell averages move even where the palette has
+// no color that close.
+pub fn embed_watermark(
+    gif: &mut GIF,
+    id: &str,
+    key: &str,
+    strength: u8,
+    dither: Dither,
+) -> Result<(), Error> {
+    let (width, height) = check_screen(gif)?;
+    let pattern = pattern(id, key);
+
+    let global = gif.global_color_table.take();
+    for frame in &mut gif.image_descriptors {
+        let colors = match frame.local_color_table.as_ref().or(global.as_ref()) {
+            Some(table) => &table.colors,
+            None => continue,
+        };
+        let transparent_index = frame.transparent_color_index();
+        let frame_width = frame.width as usize;
+        let mut indices = frame.pixels_in_row_order();
+        indices.resize(frame_width * frame.height as usize, 0);
+
+        let shifted: Vec<[u8; 4]> = indices
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| {
+                if Some(index) == transparent_index {
+                    return [0; 4];
+                }
+                let x = (frame.left as usize + position % frame_width.max(1)).min(width - 1);
+                let y = (frame.top as usize + position / frame_width.max(1)).min(height - 1);
+                let shift = pattern[cell(x, y, width, height)] * strength as f64;
+                let color = colors.get(index as usize).copied().unwrap_or([0; 3]);
+                let [red, green, blue] = color
+                    .map(|component| (component as f64 + shift).round().clamp(0.0, 255.0) as u8);
+                [red, green, blue, 0xFF]
+            })
+            .collect();
+        frame.image_data = map_to_palette(&shifted, frame_width, colors, transparent_index, dither);
+        frame.packed_field &= !0b0100_0000; // Written without interlacing
+        frame.compressed_range = None;
+    }
+    gif.global_color_table = global;
+    Ok(())
+}
+
+// What each cell has over its neighbours in the frames as shown, which the
+// picture itself mostly doesn't explain. Worked out once, it can be checked
+// against any number of patterns.
+pub fn cell_residuals(gif: &GIF) -> Result<Vec<(usize, f64)>, Error> {
+    let (width, height) = check_screen(gif)?;
+    let mut sums = vec![0.0; GRID * GRID];
+    let mut counts = vec![0usize; GRID * GRID];
+    for canvas in composite_frames(gif)? {
+        for (position, pixel) in canvas.iter().enumerate() {
+            if let Some([red, green, blue]) = pixel {
+                let index = cell(position % width, position / width, width, height);
+                sums[index] += 0.299 * *red as f64 + 0.587 * *green as f64 + 0.114 * *blue as f64;
+                counts[index] += 1;
+            }
+        }
+    }
+    let means: Vec<Option<f64>> = sums
+        .iter()
+        .zip(&counts)
+        .map(|(sum, &count)| (count > 0).then(|| sum / count as f64))
+        .collect();
+
+    let mut residuals = Vec::new();
+    for row in 0..GRID {
+        for column in 0..GRID {
+            let mean = match means[row * GRID + column] {
+                Some(mean) => mean,
+                None => continue,
+            };
+            let neighbours: Vec<f64> = (row.saturating_sub(1)..(row + 2).min(GRID))
+                .flat_map(|y| {
+                    (column.saturating_sub(1)..(column + 2).min(GRID)).map(move |x| (x, y))
+                })
+                .filter(|&(x, y)| (x, y) != (column, row))
+                .filter_map(|(x, y)| means[y * GRID + x])
+                .collect();
+            if neighbours.is_empty() {
+                continue;
+            }
+            let average = neighbours.iter().sum::<f64>() / neighbours.len() as f64;
+            residuals.push((row * GRID + column, mean - average));
+        }
+    }
+    Ok(residuals)
+}
+
+// Correlate the residuals with the pattern of an identifier
+pub fn detect_watermark(residuals: &[(usize, f64)], id: &str, key: &str) -> Detection {
+    let pattern = pattern(id, key);
+    let correlation: f64 = residuals
+        .iter()
+        .map(|&(cell, residual)| residual * pattern[cell])
+        .sum();
+    let energy: f64 = residuals
+        .iter()
+        .map(|(_, residual)| residual * residual)
+        .sum();
+
+    // Unrelated residuals give a correlation around 0 with a deviation of
+    // sqrt(energy), so this counts deviations
+    let score = match energy > 0.0 {
+        true => correlation / energy.sqrt(),
+        false => 0.0,
+    };
+    Detection {
+        score,
+        found: score > THRESHOLD,
+    }
+}
+
+pub fn verify_watermark(gif: &GIF, id: &str, key: &str) -> Result<Detection, Error> {
+    Ok(detect_watermark(&cell_residuals(gif)?, id, key))
+}
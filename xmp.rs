@@ -0,0 +1,50 @@
+use std::io::{self, Error};
+
+use crate::gif::{ApplicationExtension, KnownApplication, XmpPacket, GIF};
+
+// XMP Data application extensions, as Adobe's tools write them. The packet is
+// stored raw with a trailer after it, which the core model keeps as it is, so
+// a file edited here reads the same in other metadata editors.
+
+fn is_xmp(application: &ApplicationExtension) -> bool {
+    application.identifier == "XMP Data" && application.authentication_code == "XMP"
+}
+
+// The XML of the first XMP block, None when there is none
+pub fn xmp_packet(gif: &GIF) -> Result<Option<String>, Error> {
+    let application = match gif.application_extensions.iter().find(|a| is_xmp(a)) {
+        Some(application) => application,
+        None => return Ok(None),
+    };
+    match application.known() {
+        Some(KnownApplication::Xmp(packet)) => Ok(Some(packet.xml)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The XMP block is damaged, its trailer is missing or its text isn't UTF-8.",
+        )),
+    }
+}
+
+// Put the XML in the first XMP block, or a new one, dropping any others
+pub fn set_xmp(gif: &mut GIF, xml: &str) -> Result<(), Error> {
+    // A NUL would end the packet's sub-blocks early
+    if xml.contains('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "XMP can't hold NUL characters.",
+        ));
+    }
+    let application = ApplicationExtension::from(&KnownApplication::Xmp(XmpPacket {
+        xml: xml.to_string(),
+    }));
+    gif.set_application(application);
+    Ok(())
+}
+
+// Remove every XMP block, returning how many there were
+pub fn delete_xmp(gif: &mut GIF) -> usize {
+    let before = gif.application_extensions.len();
+    gif.application_extensions
+        .retain(|application| !is_xmp(application));
+    before - gif.application_extensions.len()
+}